@@ -0,0 +1,106 @@
+use crossterm::event::KeyCode;
+
+/// How far a held navigation key moves per press, growing the longer it is held
+#[derive(Clone, Copy)]
+pub struct Acceleration {
+    /// Step of a key that isn't held, in lines or columns
+    pub base_step: usize,
+    /// Repeats of the key between each doubling of the step
+    pub repeats_per_doubling: usize,
+    /// Largest step, in lines or columns
+    pub max_step: usize,
+}
+
+impl Acceleration {
+    /// The step for a key pressed `repeat_count` times in a row before
+    pub fn step(&self, repeat_count: usize) -> usize {
+        let doublings = repeat_count / self.repeats_per_doubling;
+
+        // The base step is never capped below itself
+        self.base_step
+            .saturating_mul(1usize.checked_shl(doublings as u32).unwrap_or(usize::MAX))
+            .min(self.max_step.max(self.base_step))
+    }
+}
+
+/// Counts how many times in a row the same key has been pressed
+#[derive(Default)]
+pub struct KeyRepeat {
+    last_key: Option<KeyCode>,
+    count: usize,
+}
+
+impl KeyRepeat {
+    /// Records a press of `key`, returning how many times in a row it was pressed before
+    pub fn press(&mut self, key: KeyCode) -> usize {
+        if self.last_key == Some(key) {
+            self.count += 1;
+        } else {
+            self.last_key = Some(key);
+            self.count = 0;
+        }
+
+        self.count
+    }
+
+    pub fn reset(&mut self) {
+        self.last_key = None;
+        self.count = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_doubles_every_few_repeats_up_to_the_cap() {
+        let acceleration = Acceleration {
+            base_step: 1,
+            repeats_per_doubling: 5,
+            max_step: 32,
+        };
+
+        let steps: Vec<_> = (0..40)
+            .step_by(5)
+            .map(|repeats| acceleration.step(repeats))
+            .collect();
+
+        assert_eq!(steps, [1, 2, 4, 8, 16, 32, 32, 32]);
+        assert_eq!(acceleration.step(4), 1);
+        assert_eq!(acceleration.step(usize::MAX), 32);
+    }
+
+    #[test]
+    fn cap_below_the_base_step_keeps_the_base_step() {
+        let acceleration = Acceleration {
+            base_step: 10,
+            repeats_per_doubling: 1,
+            max_step: 4,
+        };
+
+        assert_eq!(acceleration.step(0), 10);
+        assert_eq!(acceleration.step(3), 10);
+    }
+
+    #[test]
+    fn repeats_count_presses_of_the_same_key_in_a_row() {
+        let mut repeat = KeyRepeat::default();
+
+        let counts: Vec<_> = [
+            KeyCode::Down,
+            KeyCode::Down,
+            KeyCode::Down,
+            KeyCode::Up,
+            KeyCode::Up,
+        ]
+        .into_iter()
+        .map(|key| repeat.press(key))
+        .collect();
+
+        assert_eq!(counts, [0, 1, 2, 0, 1]);
+
+        repeat.reset();
+        assert_eq!(repeat.press(KeyCode::Up), 0);
+    }
+}
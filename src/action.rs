@@ -0,0 +1,323 @@
+use crossterm::event::KeyCode;
+
+/// Everything the viewer can do in response to a key press
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    ScrollRight,
+    ScrollLeft,
+    Down,
+    Up,
+    PageDown,
+    PageUp,
+    Top,
+    Bottom,
+    NextDiff,
+    PrevDiff,
+    FirstDiff,
+    LastDiff,
+    LineEnd,
+    LineStart,
+    ToggleChangeCounts,
+    ToggleOrigin,
+    ToggleBothNumbers,
+    ToggleOffsets,
+    ToggleCompact,
+    ToggleChangesOnly,
+    ToggleLineHighlight,
+    ToggleWhitespace,
+    ToggleRuler,
+    ToggleMinimap,
+    ToggleLinkedChanges,
+    ToggleFold,
+    ToggleOnlyDiffs,
+    ToggleWrap,
+    HexDump,
+    ExpandRepeat,
+    ExpandFold,
+    CollapseFold,
+    TogglePin,
+    InvertRegion,
+    SyncToSimilar,
+    Anchor,
+    ClearAnchors,
+    JumpToLine,
+    Search,
+    SearchNext,
+    ToggleChangeIndex,
+    FocusChangeIndex,
+    ToggleIndependentScroll,
+    FocusPane,
+    PaneDown,
+    PaneUp,
+    MarkRangeStart,
+    MarkRangeEnd,
+    ExportRange,
+    CopyLinePatch,
+    CopyLinePair,
+    DumpViewState,
+    Reload,
+    NextPair,
+    PrevPair,
+    Help,
+    Quit,
+}
+
+/// Every key binding, in the order they are listed in the help
+const BINDINGS: &[(KeyCode, Action)] = &[
+    (KeyCode::Up, Action::Up),
+    (KeyCode::Down, Action::Down),
+    (KeyCode::PageDown, Action::PageDown),
+    (KeyCode::PageUp, Action::PageUp),
+    (KeyCode::Home, Action::Top),
+    (KeyCode::End, Action::Bottom),
+    (KeyCode::Left, Action::ScrollLeft),
+    (KeyCode::Right, Action::ScrollRight),
+    (KeyCode::Char('^'), Action::LineStart),
+    (KeyCode::Char('$'), Action::LineEnd),
+    (KeyCode::Char('n'), Action::NextDiff),
+    (KeyCode::Char('N'), Action::PrevDiff),
+    (KeyCode::Char('g'), Action::FirstDiff),
+    (KeyCode::Char('G'), Action::LastDiff),
+    (KeyCode::Char('r'), Action::Reload),
+    (KeyCode::Char(']'), Action::NextPair),
+    (KeyCode::Char('['), Action::PrevPair),
+    (KeyCode::Char('c'), Action::ToggleChangeCounts),
+    (KeyCode::Char('o'), Action::ToggleOrigin),
+    (KeyCode::Char('b'), Action::ToggleBothNumbers),
+    (KeyCode::Char('O'), Action::ToggleOffsets),
+    (KeyCode::Char('z'), Action::ToggleCompact),
+    (KeyCode::Char('d'), Action::ToggleChangesOnly),
+    (KeyCode::Char('l'), Action::ToggleLineHighlight),
+    (KeyCode::Char('_'), Action::ToggleWhitespace),
+    (KeyCode::Char('#'), Action::ToggleRuler),
+    (KeyCode::Char('v'), Action::ToggleMinimap),
+    (KeyCode::Char('L'), Action::ToggleLinkedChanges),
+    (KeyCode::Char('f'), Action::ToggleFold),
+    (KeyCode::Char('h'), Action::ToggleOnlyDiffs),
+    (KeyCode::Char('W'), Action::ToggleWrap),
+    (KeyCode::Char('x'), Action::HexDump),
+    (KeyCode::Char('e'), Action::ExpandRepeat),
+    (KeyCode::Enter, Action::ExpandFold),
+    (KeyCode::Char('F'), Action::CollapseFold),
+    (KeyCode::Char('p'), Action::TogglePin),
+    (KeyCode::Char('i'), Action::InvertRegion),
+    (KeyCode::Char('s'), Action::SyncToSimilar),
+    (KeyCode::Char('a'), Action::Anchor),
+    (KeyCode::Char('A'), Action::ClearAnchors),
+    (KeyCode::Char(':'), Action::JumpToLine),
+    (KeyCode::Char('/'), Action::Search),
+    (KeyCode::Char('.'), Action::SearchNext),
+    (KeyCode::Char('t'), Action::ToggleChangeIndex),
+    (KeyCode::Tab, Action::FocusChangeIndex),
+    (KeyCode::Char('u'), Action::ToggleIndependentScroll),
+    (KeyCode::Char('P'), Action::FocusPane),
+    (KeyCode::Char('j'), Action::PaneDown),
+    (KeyCode::Char('k'), Action::PaneUp),
+    (KeyCode::Char('m'), Action::MarkRangeStart),
+    (KeyCode::Char('M'), Action::MarkRangeEnd),
+    (KeyCode::Char('w'), Action::ExportRange),
+    (KeyCode::Char('y'), Action::CopyLinePatch),
+    (KeyCode::Char('Y'), Action::CopyLinePair),
+    (KeyCode::Char('D'), Action::DumpViewState),
+    (KeyCode::Char('?'), Action::Help),
+    (KeyCode::Esc, Action::Quit),
+];
+
+/// What the viewer is currently showing, which decides the actions that are available
+pub struct Context {
+    pub pair_count: usize,
+    pub collapse_repeats: bool,
+    /// Runs of identical lines are folded
+    pub folding: bool,
+    /// Both ends of the export range are marked
+    pub range_marked: bool,
+    /// Columns of the shown lines match the columns of the files
+    pub full_lines: bool,
+    pub change_index_shown: bool,
+    /// A search was made that can be repeated
+    pub searched: bool,
+    /// The panes can be scrolled apart
+    pub independent_scroll: bool,
+    /// Long lines wrap rather than scrolling sideways
+    pub wrapped: bool,
+}
+
+impl Action {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::ScrollRight => "Scroll right",
+            Action::ScrollLeft => "Scroll left",
+            Action::Down => "Select next line",
+            Action::Up => "Select previous line",
+            Action::PageDown => "Select the line a screen down",
+            Action::PageUp => "Select the line a screen up",
+            Action::Top => "Jump to the first line",
+            Action::Bottom => "Jump to the last line",
+            Action::NextDiff => "Jump to next diff",
+            Action::PrevDiff => "Jump to previous diff",
+            Action::FirstDiff => "Jump to the first diff",
+            Action::LastDiff => "Jump to the last diff",
+            Action::LineEnd => "Scroll to end of line",
+            Action::LineStart => "Scroll to start of line",
+            Action::ToggleChangeCounts => "Toggle change counts in the gutter",
+            Action::ToggleOrigin => "Toggle origin tags in the gutter",
+            Action::ToggleBothNumbers => "Toggle both files' line numbers in each gutter",
+            Action::ToggleOffsets => "Toggle byte offsets in the gutters",
+            Action::ToggleCompact => "Toggle borders and gutter",
+            Action::ToggleChangesOnly => "Toggle showing only the changed characters",
+            Action::ToggleLineHighlight => "Toggle highlighting whole changed lines",
+            Action::ToggleWhitespace => "Toggle showing spaces and tabs",
+            Action::ToggleRuler => "Toggle a ruler of column numbers above the panes",
+            Action::ToggleMinimap => "Toggle a map of where the diffs are beside the panes",
+            Action::ToggleLinkedChanges => "Toggle brightening the selected change in both panes",
+            Action::ToggleFold => "Toggle folding the identical start and end of changed lines",
+            Action::ToggleOnlyDiffs => "Toggle hiding the lines away from any diff",
+            Action::ToggleWrap => "Toggle wrapping long lines instead of scrolling sideways",
+            Action::HexDump => "Show the bytes of the selected line",
+            Action::ExpandRepeat => "Expand the selected repeated block",
+            Action::ExpandFold => "Expand the selected fold of identical lines",
+            Action::CollapseFold => "Fold the identical lines around the selection again",
+            Action::TogglePin => "Pin the selected line to the top of the panes, or unpin it",
+            Action::InvertRegion => "Highlight the change under the selection until the next key",
+            Action::SyncToSimilar => "Line file 2 up on its line most like the selected one",
+            Action::Anchor => {
+                "Anchor the selected file 1 line, then the file 2 line to keep it paired with"
+            }
+            Action::ClearAnchors => "Drop every anchor",
+            Action::JumpToLine => "Jump to the line number typed next, confirmed with Enter",
+            Action::Search => "Search forward for the text typed next, confirmed with Enter",
+            Action::SearchNext => "Repeat the last search",
+            Action::ToggleChangeIndex => "Toggle a third pane listing the changed lines",
+            Action::FocusChangeIndex => "Move Up and Down between the panes and the change list",
+            Action::ToggleIndependentScroll => "Toggle scrolling the panes apart with j and k",
+            Action::FocusPane => "Switch the pane j and k scroll",
+            Action::PaneDown => "Scroll the focused pane down without the other",
+            Action::PaneUp => "Scroll the focused pane up without the other",
+            Action::MarkRangeStart => "Mark the selected line as the start of the export range",
+            Action::MarkRangeEnd => "Mark the selected line as the end of the export range",
+            Action::ExportRange => "Write the marked range to a patch file",
+            Action::CopyLinePatch => "Copy the selected line pair to the clipboard as a diff",
+            Action::CopyLinePair => "Copy the selected line pair to the clipboard as they are",
+            Action::DumpViewState => "Write the view state to a file to attach to bug reports",
+            Action::Reload => "Reload both files from disk",
+            Action::NextPair => "Open next file pair",
+            Action::PrevPair => "Open previous file pair",
+            Action::Help => "Show this help",
+            Action::Quit => "Quit",
+        }
+    }
+
+    /// Whether the action can do anything in `context`
+    pub fn applies(&self, context: &Context) -> bool {
+        match self {
+            Action::ScrollRight | Action::ScrollLeft | Action::LineEnd | Action::LineStart => {
+                !context.wrapped
+            }
+            Action::NextPair | Action::PrevPair => context.pair_count > 1,
+            Action::ExpandRepeat => context.collapse_repeats,
+            Action::ExpandFold | Action::CollapseFold => context.folding,
+            Action::ExportRange => context.range_marked,
+            // Changed sections are no longer at their columns in the line
+            Action::InvertRegion => context.full_lines,
+            Action::FocusChangeIndex => context.change_index_shown,
+            Action::SearchNext => context.searched,
+            Action::FocusPane | Action::PaneDown | Action::PaneUp => context.independent_scroll,
+            _ => true,
+        }
+    }
+}
+
+/// The key bound to each action
+pub struct Keymap {
+    bindings: Vec<(KeyCode, Action)>,
+}
+
+impl Keymap {
+    /// The default bindings, with quitting moved to `quit_key`. Whatever `quit_key` did before
+    /// is unbound
+    pub fn new(quit_key: KeyCode) -> Self {
+        let bindings = BINDINGS
+            .iter()
+            .copied()
+            .filter(|(key, action)| *action != Action::Quit && *key != quit_key)
+            .chain([(quit_key, Action::Quit)])
+            .collect();
+
+        Keymap { bindings }
+    }
+
+    /// The first action bound to `code` that applies in `context`
+    pub fn action(&self, code: KeyCode, context: &Context) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(key, action)| *key == code && action.applies(context))
+            .map(|(_, action)| *action)
+    }
+
+    /// The bindings of every action that applies in `context`, as `(key, description)`
+    pub fn bindings(&self, context: &Context) -> Vec<(String, &'static str)> {
+        self.bindings
+            .iter()
+            .filter(|(_, action)| action.applies(context))
+            .map(|(key, action)| (key_name(*key), action.description()))
+            .collect()
+    }
+}
+
+/// Parses a key name for binding: a single character, or a name like `Esc` or `Tab`
+pub fn parse_key(value: &str) -> Result<KeyCode, String> {
+    let mut chars = value.chars();
+
+    if let (Some(char), None) = (chars.next(), chars.next()) {
+        return Ok(KeyCode::Char(char));
+    }
+
+    match value.to_lowercase().as_str() {
+        "esc" => Ok(KeyCode::Esc),
+        "tab" => Ok(KeyCode::Tab),
+        "enter" => Ok(KeyCode::Enter),
+        "backspace" => Ok(KeyCode::Backspace),
+        "delete" => Ok(KeyCode::Delete),
+        _ => Err(format!("unknown key `{}`", value)),
+    }
+}
+
+fn key_name(code: KeyCode) -> String {
+    match code {
+        KeyCode::Char(char) => char.to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn no_key_is_bound_twice() {
+        let mut keys = HashSet::new();
+
+        for (key, action) in BINDINGS {
+            assert!(
+                keys.insert(key),
+                "{:?} is bound again for {:?}",
+                key,
+                action
+            );
+        }
+    }
+
+    #[test]
+    fn quit_key_takes_over_whatever_it_did() {
+        let keymap = Keymap::new(KeyCode::Char('n'));
+        let quit_keys: Vec<_> = keymap
+            .bindings
+            .iter()
+            .filter(|(key, _)| *key == KeyCode::Char('n'))
+            .collect();
+
+        assert_eq!(quit_keys, [&(KeyCode::Char('n'), Action::Quit)]);
+    }
+}
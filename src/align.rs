@@ -0,0 +1,491 @@
+use std::ops::Range;
+
+use clap::ValueEnum;
+
+use crate::compare::DiffOptions;
+
+/// How lines from the two files are paired into rows before they are diffed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Alignment {
+    /// Pair lines by index, like `zip_longest`. With a resync window, re-pair after a run of
+    /// differing lines on the nearest matching line within the window
+    None,
+    /// Pair lines along the longest common subsequence of identical lines, so an inserted or
+    /// removed line gets a row of its own instead of shifting every line after it
+    #[default]
+    Lcs,
+    /// Like `lcs`, but lines only need to share their first whitespace separated field
+    Key,
+}
+
+/// One row of the view. Each side is an index into that file's loaded lines, or `None` if the
+/// file has no line in this row
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Row {
+    pub file1: Option<usize>,
+    pub file2: Option<usize>,
+}
+
+impl Alignment {
+    /// Pairs up the lines. `resync_window` only applies to `None`, as the other alignments
+    /// always resynchronize
+    pub fn align(
+        &self,
+        file1_lines: &[String],
+        file2_lines: &[String],
+        resync_window: Option<usize>,
+        options: &DiffOptions,
+    ) -> Vec<Row> {
+        let lines_equal = |line1: &str, line2: &str| options.lines_equal(line1, line2);
+
+        match self {
+            Alignment::None => match resync_window {
+                Some(window) => resync(file1_lines, file2_lines, window, lines_equal),
+                None => positional(file1_lines.len(), file2_lines.len()),
+            },
+            Alignment::Lcs => lcs(file1_lines, file2_lines, lines_equal),
+            Alignment::Key => lcs(file1_lines, file2_lines, |line1, line2| {
+                lines_equal(line_key(line1), line_key(line2))
+            }),
+        }
+    }
+}
+
+fn positional(file1_count: usize, file2_count: usize) -> Vec<Row> {
+    (0..file1_count.max(file2_count))
+        .map(|index| Row {
+            file1: (index < file1_count).then_some(index),
+            file2: (index < file2_count).then_some(index),
+        })
+        .collect()
+}
+
+/// Pairs line `i` of file 1 with line `i + shift` of file 2, giving the lines without a partner
+/// rows of their own
+pub fn shifted(file1_count: usize, file2_count: usize, shift: isize) -> Vec<Row> {
+    let file1_start = shift.max(0).unsigned_abs();
+    let file2_start = shift.min(0).unsigned_abs();
+
+    (0..(file1_count + file1_start).max(file2_count + file2_start))
+        .map(|index| Row {
+            file1: index
+                .checked_sub(file1_start)
+                .filter(|&index| index < file1_count),
+            file2: index
+                .checked_sub(file2_start)
+                .filter(|&index| index < file2_count),
+        })
+        .collect()
+}
+
+/// Pairs lines by index until they differ, then looks up to `window` lines ahead in each file for
+/// the closest pair of matching lines to continue from. The skipped lines are paired up
+/// positionally. If there is no such pair, the differing lines are paired and the walk goes on
+fn resync<F>(file1_lines: &[String], file2_lines: &[String], window: usize, matches: F) -> Vec<Row>
+where
+    F: Fn(&str, &str) -> bool,
+{
+    let file1_count = file1_lines.len();
+    let file2_count = file2_lines.len();
+
+    let mut rows = vec![];
+
+    let mut i = 0;
+    let mut j = 0;
+
+    while i < file1_count && j < file2_count {
+        if matches(&file1_lines[i], &file2_lines[j]) {
+            rows.push(Row {
+                file1: Some(i),
+                file2: Some(j),
+            });
+
+            i += 1;
+            j += 1;
+
+            continue;
+        }
+
+        // Nearest anchor first, so a single inserted line is skipped rather than a long run
+        let anchor = (1..=window * 2)
+            .flat_map(|distance| {
+                (0..=distance.min(window))
+                    .filter(move |skip1| distance - skip1 <= window)
+                    .map(move |skip1| (i + skip1, j + distance - skip1))
+            })
+            .find(|&(anchor1, anchor2)| {
+                anchor1 < file1_count
+                    && anchor2 < file2_count
+                    && matches(&file1_lines[anchor1], &file2_lines[anchor2])
+            });
+
+        match anchor {
+            Some((anchor1, anchor2)) => {
+                let mut file1_gap: Vec<usize> = (i..anchor1).collect();
+                let mut file2_gap: Vec<usize> = (j..anchor2).collect();
+
+                flush_gap(&mut rows, &mut file1_gap, &mut file2_gap);
+
+                i = anchor1;
+                j = anchor2;
+            }
+            None => {
+                rows.push(Row {
+                    file1: Some(i),
+                    file2: Some(j),
+                });
+
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    let mut file1_gap: Vec<usize> = (i..file1_count).collect();
+    let mut file2_gap: Vec<usize> = (j..file2_count).collect();
+
+    flush_gap(&mut rows, &mut file1_gap, &mut file2_gap);
+
+    rows
+}
+
+/// Pairs the lines `matches` considers equal along their longest common subsequence. Lines in
+/// between matches are paired up positionally, so a changed line still diffs against its
+/// counterpart, and any leftovers become one sided rows
+fn lcs<F>(file1_lines: &[String], file2_lines: &[String], matches: F) -> Vec<Row>
+where
+    F: Fn(&str, &str) -> bool,
+{
+    // Matching lines at the start and end are always part of a longest common subsequence.
+    // Leaving them out of the table keeps it small for files that only differ in the middle
+    let shorter_count = file1_lines.len().min(file2_lines.len());
+
+    let prefix = (0..shorter_count)
+        .take_while(|&index| matches(&file1_lines[index], &file2_lines[index]))
+        .count();
+    let suffix = (0..shorter_count - prefix)
+        .take_while(|&index| {
+            matches(
+                &file1_lines[file1_lines.len() - 1 - index],
+                &file2_lines[file2_lines.len() - 1 - index],
+            )
+        })
+        .count();
+
+    let file1_middle = &file1_lines[prefix..file1_lines.len() - suffix];
+    let file2_middle = &file2_lines[prefix..file2_lines.len() - suffix];
+
+    let same = |index: usize| Row {
+        file1: Some(index),
+        file2: Some(index),
+    };
+
+    let mut rows: Vec<Row> = (0..prefix).map(same).collect();
+
+    rows.extend(
+        middle_lcs(file1_middle, file2_middle, matches)
+            .into_iter()
+            .map(|row| Row {
+                file1: row.file1.map(|index| index + prefix),
+                file2: row.file2.map(|index| index + prefix),
+            }),
+    );
+
+    rows.extend((0..suffix).map(|index| Row {
+        file1: Some(file1_lines.len() - suffix + index),
+        file2: Some(file2_lines.len() - suffix + index),
+    }));
+
+    rows
+}
+
+/// `lcs` of the lines between the matching start and end
+fn middle_lcs<F>(file1_lines: &[String], file2_lines: &[String], matches: F) -> Vec<Row>
+where
+    F: Fn(&str, &str) -> bool,
+{
+    let mut matched = vec![];
+
+    Myers::new(file1_lines, file2_lines, &matches).matches(
+        0..file1_lines.len(),
+        0..file2_lines.len(),
+        &mut matched,
+    );
+
+    let mut rows = vec![];
+
+    // The first line of each file after the last match
+    let mut i = 0;
+    let mut j = 0;
+
+    for (match1, match2) in matched
+        .into_iter()
+        .chain([(file1_lines.len(), file2_lines.len())])
+    {
+        let mut file1_gap: Vec<usize> = (i..match1).collect();
+        let mut file2_gap: Vec<usize> = (j..match2).collect();
+
+        flush_gap(&mut rows, &mut file1_gap, &mut file2_gap);
+
+        if match1 < file1_lines.len() {
+            rows.push(Row {
+                file1: Some(match1),
+                file2: Some(match2),
+            });
+        }
+
+        i = match1 + 1;
+        j = match2 + 1;
+    }
+
+    rows
+}
+
+/// Myers' O((N + M) D) diff in linear space, finding a longest common subsequence by splitting
+/// the lines at the middle snake of the shortest edit script and recursing into both halves. A
+/// table of every pair of lines would take gigabytes on files with changes far apart
+struct Myers<'l, F> {
+    file1_lines: &'l [String],
+    file2_lines: &'l [String],
+    matches: F,
+    /// The furthest file 1 line reached on each diagonal searching forwards, and backwards from
+    /// the ends. Diagonal `k` is at index `k + offset`
+    forward: Vec<usize>,
+    backward: Vec<usize>,
+    offset: isize,
+}
+
+impl<'l, F> Myers<'l, F>
+where
+    F: Fn(&str, &str) -> bool,
+{
+    fn new(file1_lines: &'l [String], file2_lines: &'l [String], matches: F) -> Self {
+        let offset = max_edits(file1_lines.len(), file2_lines.len()) as isize + 1;
+        let diagonals = 2 * offset as usize + 1;
+
+        Myers {
+            file1_lines,
+            file2_lines,
+            matches,
+            forward: vec![0; diagonals],
+            backward: vec![0; diagonals],
+            offset,
+        }
+    }
+
+    fn lines_match(&self, file1_index: usize, file2_index: usize) -> bool {
+        (self.matches)(
+            &self.file1_lines[file1_index],
+            &self.file2_lines[file2_index],
+        )
+    }
+
+    /// Pushes the matching pairs of lines of a longest common subsequence of `file1` and `file2`
+    /// onto `matched`, in order
+    fn matches(
+        &mut self,
+        mut file1: Range<usize>,
+        mut file2: Range<usize>,
+        matched: &mut Vec<(usize, usize)>,
+    ) {
+        while !file1.is_empty() && !file2.is_empty() && self.lines_match(file1.start, file2.start) {
+            matched.push((file1.start, file2.start));
+            file1.start += 1;
+            file2.start += 1;
+        }
+
+        let mut suffix = vec![];
+
+        while !file1.is_empty()
+            && !file2.is_empty()
+            && self.lines_match(file1.end - 1, file2.end - 1)
+        {
+            file1.end -= 1;
+            file2.end -= 1;
+            suffix.push((file1.end, file2.end));
+        }
+
+        if !file1.is_empty() && !file2.is_empty() {
+            let (middle1, middle2) = self.middle_snake(file1.clone(), file2.clone());
+
+            self.matches(file1.start..middle1, file2.start..middle2, matched);
+            self.matches(middle1..file1.end, middle2..file2.end, matched);
+        }
+
+        matched.extend(suffix.into_iter().rev());
+    }
+
+    /// Where the middle snake of the shortest edit script between `file1` and `file2` starts.
+    /// Both ranges are non-empty and differ in their first and last lines, so the edit script
+    /// is at least two long and the point splits it into two shorter ones
+    fn middle_snake(&mut self, file1: Range<usize>, file2: Range<usize>) -> (usize, usize) {
+        let n = file1.len() as isize;
+        let m = file2.len() as isize;
+        let delta = n - m;
+        let odd = delta % 2 != 0;
+        let offset = self.offset;
+
+        self.forward[(1 + offset) as usize] = 0;
+        self.backward[(1 + offset) as usize] = 0;
+
+        for d in 0..=max_edits(file1.len(), file2.len()) as isize {
+            for k in (-d..=d).rev().step_by(2) {
+                let index = (k + offset) as usize;
+
+                let mut x =
+                    if k == -d || (k != d && self.forward[index - 1] < self.forward[index + 1]) {
+                        self.forward[index + 1]
+                    } else {
+                        self.forward[index - 1] + 1
+                    };
+                let start = x;
+                let start_y = (x as isize - k) as usize;
+                let mut y = start_y;
+
+                while (x as isize) < n
+                    && (y as isize) < m
+                    && self.lines_match(file1.start + x, file2.start + y)
+                {
+                    x += 1;
+                    y += 1;
+                }
+
+                self.forward[index] = x;
+
+                let reverse_k = delta - k;
+
+                if odd
+                    && reverse_k.abs() < d
+                    && x + self.backward[(reverse_k + offset) as usize] >= n as usize
+                {
+                    return (file1.start + start, file2.start + start_y);
+                }
+            }
+
+            for k in (-d..=d).rev().step_by(2) {
+                let index = (k + offset) as usize;
+
+                // Counted back from the ends of the ranges
+                let mut x =
+                    if k == -d || (k != d && self.backward[index - 1] < self.backward[index + 1]) {
+                        self.backward[index + 1]
+                    } else {
+                        self.backward[index - 1] + 1
+                    };
+                let mut y = (x as isize - k) as usize;
+
+                while (x as isize) < n
+                    && (y as isize) < m
+                    && self.lines_match(file1.end - x - 1, file2.end - y - 1)
+                {
+                    x += 1;
+                    y += 1;
+                }
+
+                self.backward[index] = x;
+
+                let forward_k = delta - k;
+
+                if !odd
+                    && forward_k.abs() <= d
+                    && x + self.forward[(forward_k + offset) as usize] >= n as usize
+                {
+                    return (file1.end - x, file2.end - y);
+                }
+            }
+        }
+
+        unreachable!("the forward and backward searches always meet")
+    }
+}
+
+/// Edits the search for the middle snake needs at most, for files of these lengths
+fn max_edits(file1_count: usize, file2_count: usize) -> usize {
+    (file1_count + file2_count).div_ceil(2) + 1
+}
+
+fn flush_gap(rows: &mut Vec<Row>, file1_gap: &mut Vec<usize>, file2_gap: &mut Vec<usize>) {
+    let count = file1_gap.len().max(file2_gap.len());
+
+    rows.extend((0..count).map(|index| Row {
+        file1: file1_gap.get(index).copied(),
+        file2: file2_gap.get(index).copied(),
+    }));
+
+    file1_gap.clear();
+    file2_gap.clear();
+}
+
+fn line_key(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Length of the longest common subsequence, from the full table the alignment avoids
+    fn lcs_length(file1_lines: &[String], file2_lines: &[String]) -> usize {
+        let mut lengths = vec![vec![0; file2_lines.len() + 1]; file1_lines.len() + 1];
+
+        for i in (0..file1_lines.len()).rev() {
+            for j in (0..file2_lines.len()).rev() {
+                lengths[i][j] = if file1_lines[i] == file2_lines[j] {
+                    lengths[i + 1][j + 1] + 1
+                } else {
+                    lengths[i + 1][j].max(lengths[i][j + 1])
+                };
+            }
+        }
+
+        lengths[0][0]
+    }
+
+    /// Lines drawn from a few values, so they match often and in many ways
+    fn random_lines(seed: &mut u64, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|_| {
+                *seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                ((*seed >> 33) % 4).to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lcs_rows_pair_a_longest_common_subsequence() {
+        let mut seed = 7;
+
+        for _ in 0..300 {
+            let count1 = (seed % 23) as usize;
+            let file1_lines = random_lines(&mut seed, count1);
+            let count2 = (seed % 19) as usize;
+            let file2_lines = random_lines(&mut seed, count2);
+
+            let rows = lcs(&file1_lines, &file2_lines, |line1, line2| line1 == line2);
+
+            // Every line shows up once, in order
+            let file1_indices: Vec<usize> = rows.iter().filter_map(|row| row.file1).collect();
+            let file2_indices: Vec<usize> = rows.iter().filter_map(|row| row.file2).collect();
+            assert_eq!(file1_indices, (0..file1_lines.len()).collect::<Vec<_>>());
+            assert_eq!(file2_indices, (0..file2_lines.len()).collect::<Vec<_>>());
+
+            let matching = rows
+                .iter()
+                .filter(|row| match (row.file1, row.file2) {
+                    (Some(index1), Some(index2)) => file1_lines[index1] == file2_lines[index2],
+                    _ => false,
+                })
+                .count();
+
+            assert_eq!(
+                matching,
+                lcs_length(&file1_lines, &file2_lines),
+                "{:?} {:?}",
+                file1_lines,
+                file2_lines
+            );
+        }
+    }
+}
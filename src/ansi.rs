@@ -0,0 +1,92 @@
+use tui::{
+    style::{Color, Modifier, Style},
+    text::Spans,
+};
+
+const RESET: &str = "\x1b[0m";
+
+/// Renders styled text for a terminal with ANSI escape codes, resetting the style at the end
+pub fn render_ansi(spans: &Spans) -> String {
+    let mut output = String::new();
+
+    for span in &spans.0 {
+        // Line terminators are kept in the loaded lines, but would break up the output
+        let text: String = span
+            .content
+            .chars()
+            .filter(|char| !char.is_control())
+            .collect();
+
+        if text.is_empty() {
+            continue;
+        }
+
+        output.push_str(RESET);
+        output.push_str(&style_codes(&span.style));
+        output.push_str(&text);
+    }
+
+    output.push_str(RESET);
+
+    output
+}
+
+fn style_codes(style: &Style) -> String {
+    let mut codes = vec![];
+
+    for (modifier, code) in [
+        (Modifier::BOLD, "1"),
+        (Modifier::DIM, "2"),
+        (Modifier::ITALIC, "3"),
+        (Modifier::UNDERLINED, "4"),
+        (Modifier::REVERSED, "7"),
+        (Modifier::CROSSED_OUT, "9"),
+    ] {
+        if style.add_modifier.contains(modifier) {
+            codes.push(code.to_string());
+        }
+    }
+
+    if let Some(code) = style.fg.and_then(|color| color_code(color, false)) {
+        codes.push(code);
+    }
+
+    if let Some(code) = style.bg.and_then(|color| color_code(color, true)) {
+        codes.push(code);
+    }
+
+    if codes.is_empty() {
+        String::new()
+    } else {
+        format!("\x1b[{}m", codes.join(";"))
+    }
+}
+
+/// The SGR parameters selecting `color` as the foreground, or background when `background`
+fn color_code(color: Color, background: bool) -> Option<String> {
+    let base = if background { 40 } else { 30 };
+
+    let code = match color {
+        Color::Reset => return None,
+        Color::Black => base,
+        Color::Red => base + 1,
+        Color::Green => base + 2,
+        Color::Yellow => base + 3,
+        Color::Blue => base + 4,
+        Color::Magenta => base + 5,
+        Color::Cyan => base + 6,
+        Color::Gray => base + 7,
+        Color::DarkGray => base + 60,
+        Color::LightRed => base + 61,
+        Color::LightGreen => base + 62,
+        Color::LightYellow => base + 63,
+        Color::LightBlue => base + 64,
+        Color::LightMagenta => base + 65,
+        Color::LightCyan => base + 66,
+        Color::White => base + 67,
+        Color::Rgb(r, g, b) => return Some(format!("{};2;{};{};{}", base + 8, r, g, b)),
+        Color::Indexed(index) => return Some(format!("{};5;{}", base + 8, index)),
+    };
+
+    Some(code.to_string())
+}
@@ -0,0 +1,555 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use crossterm::event::KeyCode;
+use regex::Regex;
+use tui::style::Color;
+
+use trace_log_comparer::{
+    align::Alignment,
+    compare::{CharDiff, DiffOptions, InputMode},
+    filter::TimeFilter,
+    mask::Masks,
+    prefix::{Prefix, PrefixFiles, StripPrefix},
+    scan, state,
+    style::{self, ColorDepth, DiffStyle, Emphasis},
+    theme::Theme,
+};
+
+use crate::action;
+
+/// An inclusive range of 1-based line numbers, written `START:END`
+#[derive(Clone, Copy)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+fn parse_line_range(value: &str) -> Result<LineRange, String> {
+    let (start, end) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected START:END, got `{}`", value))?;
+
+    let parse = |number: &str| {
+        number
+            .trim()
+            .parse::<usize>()
+            .map_err(|err| format!("invalid line number `{}`: {}", number, err))
+    };
+
+    let range = LineRange {
+        start: parse(start)?,
+        end: parse(end)?,
+    };
+
+    if range.start == 0 || range.end < range.start {
+        return Err(format!(
+            "`{}` is not a range of 1-based line numbers with START <= END",
+            value
+        ));
+    }
+
+    Ok(range)
+}
+
+fn parse_line_pair(value: &str) -> Result<(usize, usize), String> {
+    let (line1, line2) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected LINE1:LINE2, got `{}`", value))?;
+
+    Ok((parse_positive(line1.trim())?, parse_positive(line2.trim())?))
+}
+
+fn parse_time_range(value: &str) -> Result<(f64, f64), String> {
+    let (start, end) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected START:END, got `{}`", value))?;
+
+    let parse = |time: &str| {
+        time.trim()
+            .parse::<f64>()
+            .map_err(|err| format!("invalid timestamp `{}`: {}", time, err))
+    };
+
+    let (start, end) = (parse(start)?, parse(end)?);
+
+    if end < start {
+        return Err(format!("`{}` ends before it starts", value));
+    }
+
+    Ok((start, end))
+}
+
+fn parse_regex(value: &str) -> Result<Regex, String> {
+    Regex::new(value).map_err(|err| err.to_string())
+}
+
+fn parse_positive(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(number) => Ok(number),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Non-interactive formats the diff can be written in
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    /// A unified diff, like `diff -u`
+    Unified,
+    /// A JSON object with the sections of every aligned line pair
+    Json,
+    /// A standalone HTML page with the files side by side, colored like the viewer, and every
+    /// line linkable as `#file1-L<N>` or `#file2-L<N>`
+    Html,
+}
+
+/// Side-by-side viewer for finding where two trace logs diverge
+#[derive(Parser)]
+#[command(version)]
+pub struct Args {
+    /// First log file, or `-` to read it from stdin. Gzip files are decompressed
+    #[arg(required_unless_present_any = ["dir", "manifest"])]
+    pub file1: Option<PathBuf>,
+
+    /// Second log file, or `-` to read it from stdin. Gzip files are decompressed
+    #[arg(required_unless_present_any = ["dir", "manifest"])]
+    pub file2: Option<PathBuf>,
+
+    /// Only look for the first difference after this (1-based) line number
+    pub skip_past_line: Option<usize>,
+
+    /// Open at the first differing line containing this text instead of the first diff
+    #[arg(long, value_name = "TEXT")]
+    pub open_at: Option<String>,
+
+    /// Open at this (1-based) line instead of the first diff. Takes precedence over
+    /// `--start-diff`
+    #[arg(long, value_name = "LINE")]
+    pub start_line: Option<usize>,
+
+    /// Open at the Kth (1-based) differing line instead of the first diff, or at the first diff
+    /// when there are fewer
+    #[arg(long, value_name = "K")]
+    pub start_diff: Option<usize>,
+
+    /// Only index and compare lines START to END (1-based, inclusive) of both files, still
+    /// numbered as in the whole files
+    #[arg(
+        long,
+        value_name = "START:END",
+        value_parser = parse_line_range,
+        conflicts_with_all = ["sort", "lines"]
+    )]
+    pub range: Option<LineRange>,
+
+    /// Treat file 2 as a pattern, where `?` matches any character and `*` any run of characters
+    #[arg(long)]
+    pub pattern: bool,
+
+    /// What the lines of the files are. Combine `patch` with `--align lcs` to pair up the
+    /// lines of two patches
+    #[arg(long, value_enum, default_value_t = InputMode::Trace, conflicts_with = "pattern")]
+    pub mode: InputMode,
+
+    /// How the characters of two differing lines are matched up
+    #[arg(long, value_enum, default_value_t = CharDiff::Lockstep)]
+    pub char_diff: CharDiff,
+
+    /// Skip these characters when comparing, while still showing them, e.g. `,` to compare
+    /// `1,000` equal to `1000`
+    #[arg(long, value_name = "CHARS", conflicts_with = "pattern")]
+    pub ignore_chars: Option<String>,
+
+    /// Leave text matching this regular expression out of the comparison, like `0x[0-9a-f]+` for
+    /// addresses that vary between runs. Can be given more than once
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    pub mask: Vec<Regex>,
+
+    /// Compare runs of whitespace equal to each other and ignore whitespace at the end of lines,
+    /// while still showing it
+    #[arg(long)]
+    pub ignore_whitespace: bool,
+
+    /// Only ignore whitespace at the end of lines
+    #[arg(long)]
+    pub ignore_trailing_whitespace: bool,
+
+    /// Compare line endings, so a line ending in `\r\n` differs from the same line ending in
+    /// `\n`. They are ignored by default
+    #[arg(long)]
+    pub preserve_eol: bool,
+
+    /// Compare the files as sorted multisets of lines, listing the lines only one file has
+    /// regardless of their order
+    #[arg(long, conflicts_with_all = ["svg", "resume", "pattern"])]
+    pub sort: bool,
+
+    /// Split the files into fixed-width records of N bytes instead of lines
+    #[arg(long, value_name = "N", value_parser = parse_positive)]
+    pub record_width: Option<usize>,
+
+    /// Compare the files byte by byte, as rows of a hex dump of 16 bytes each. Needed to compare
+    /// binary files, which are refused otherwise
+    #[arg(long, conflicts_with_all = ["record_width", "sort", "lines", "pattern", "time_range", "strip_prefix", "ignore_prefix"])]
+    pub hex: bool,
+
+    /// Compare only the lines with a timestamp between START and END, inclusive. Lines without
+    /// a timestamp are left out
+    #[arg(long, value_name = "START:END", value_parser = parse_time_range)]
+    pub time_range: Option<(f64, f64)>,
+
+    /// The 1-based whitespace separated field holding each line's timestamp
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = parse_positive,
+        requires = "time_range"
+    )]
+    pub time_column: usize,
+
+    /// Strip text matching this regular expression from the start of each line before comparing,
+    /// like `^\d+: ` for line numbers only one tool writes
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    pub strip_prefix: Option<Regex>,
+
+    /// The files `--strip-prefix` applies to
+    #[arg(long, value_enum, default_value_t = PrefixFiles::Both, requires = "strip_prefix")]
+    pub strip_prefix_from: PrefixFiles,
+
+    /// Keep showing the stripped prefixes in front of the lines, dimmed, without comparing them
+    #[arg(long, requires = "strip_prefix")]
+    pub keep_prefix: bool,
+
+    /// Leave the first N characters of each line out of the comparison, like sequence numbers
+    /// and timestamps that differ between runs. They are still shown, dimmed. Lines of N
+    /// characters or fewer are left out entirely
+    #[arg(long, value_name = "N", conflicts_with = "strip_prefix", value_parser = parse_positive)]
+    pub ignore_prefix: Option<usize>,
+
+    /// Print the diff of file 1's line LINE1 against file 2's line LINE2 and exit
+    #[arg(
+        long,
+        value_name = "LINE1:LINE2",
+        value_parser = parse_line_pair,
+        conflicts_with_all = ["dir", "manifest", "svg", "sort"]
+    )]
+    pub lines: Option<(usize, usize)>,
+
+    /// Print the numbers of the differing lines, merging consecutive ones into `START-END`
+    /// ranges, and exit
+    #[arg(long, conflicts_with_all = ["svg", "sort", "lines"])]
+    pub quiet_identical: bool,
+
+    /// Only report whether the files differ, like `diff -q`, exiting with status 1 if they do
+    /// and 0 if they don't. A file missing the final newline of the other differs from it
+    #[arg(short, long, conflicts_with_all = ["svg", "sort", "lines", "quiet_identical"])]
+    pub quiet: bool,
+
+    /// Write the whole diff to stdout in this format and exit, with status 1 if the files differ
+    /// and 0 if they don't
+    #[arg(long, value_enum, conflicts_with_all = ["svg", "sort", "lines", "quiet_identical", "quiet"])]
+    pub output: Option<OutputFormat>,
+
+    /// Unchanged lines around each change in `--output unified` hunks
+    #[arg(long, value_name = "N", default_value_t = 3, requires = "output")]
+    pub hunk_context: usize,
+
+    /// Write a JUnit XML report to this path, with a test case per file pair that fails when
+    /// the files differ, and exit
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["svg", "sort", "lines", "quiet_identical"])]
+    pub junit: Option<PathBuf>,
+
+    /// Print a TAP report with a test per file pair that fails when the files differ, and exit
+    #[arg(long, conflicts_with_all = ["svg", "sort", "lines", "quiet_identical"])]
+    pub tap: bool,
+
+    /// Compare every pair of identically named files in two directories
+    #[arg(long, num_args = 2, value_names = ["DIR1", "DIR2"], conflicts_with_all = ["file1", "file2"])]
+    pub dir: Option<Vec<PathBuf>>,
+
+    /// Compare the file pairs listed in this file, one `FILE1<TAB>FILE2` pair per line
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["file1", "file2", "dir"])]
+    pub manifest: Option<PathBuf>,
+
+    /// Render the side-by-side diff to an SVG image at this path instead of opening the viewer
+    #[arg(long, value_name = "PATH")]
+    pub svg: Option<PathBuf>,
+
+    /// Lines to include in the SVG image. Defaults to the lines around the first diff
+    #[arg(long, value_name = "START:END", value_parser = parse_line_range, requires = "svg")]
+    pub svg_range: Option<LineRange>,
+
+    /// Save scan progress to this checkpoint file, and resume from it if it already exists. The
+    /// offsets of the scanned lines are appended to a `.lines` file next to it
+    #[arg(long, value_name = "STATE", conflicts_with_all = ["dir", "manifest"])]
+    pub resume: Option<PathBuf>,
+
+    /// Map the files into memory rather than reading each shown line from them, which is faster
+    /// for large files. It is opt-in, not picked by file size, because a file truncated while it
+    /// is mapped crashes the viewer with SIGBUS on the next read of the lost part, where reading
+    /// the file itself only finds it shorter. Avoid it when reloading files that are regenerated
+    /// in place. Stdin is always read into memory
+    #[arg(long)]
+    pub mmap: bool,
+
+    /// Store the line offsets of files with more than N lines compactly, in about a quarter of
+    /// the memory, at the cost of slightly slower reads of each line. 0 stores every file's
+    /// compactly. Defaults to 10000000 lines, whose offsets take 80 MB stored in full
+    #[arg(long, value_name = "N")]
+    pub compact_index_above: Option<usize>,
+
+    /// Compare a second variant of file 1 alongside file 2, in a third pane. Lines are paired by
+    /// index, and each variant is diffed against file 1
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["dir", "manifest", "output", "quiet", "quiet_identical", "junit", "tap", "sort", "lines", "svg", "resume"]
+    )]
+    pub third: Option<PathBuf>,
+
+    /// Save the selected line and column of the files on quit, and open there on the next run
+    /// rather than at the first diff. Views are kept in `trace-log-comparer/views.json` under
+    /// `$XDG_CACHE_HOME` or `~/.cache`
+    #[arg(long)]
+    pub remember_view: bool,
+
+    /// Reload the files when they change on disk, once they have stopped changing for a moment,
+    /// keeping the selected line
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Lines to scan past the end of the shorter file, and to load on either side of the first
+    /// diff
+    #[arg(short = 'C', long, value_name = "N", default_value_t = scan::EXTRA_LINE_COUNT)]
+    pub context: usize,
+
+    /// Keep at most this many rows loaded in the viewer at once, to bound memory use
+    #[arg(long, value_name = "N", value_parser = parse_positive)]
+    pub max_rows: Option<usize>,
+
+    /// Show at most N columns of each line in each pane, cutting longer lines off with `›`
+    #[arg(long, value_name = "N", value_parser = parse_positive)]
+    pub max_col: Option<usize>,
+
+    /// Cut lines off after N characters when loading them into the viewer, ending them with `…`,
+    /// so a runaway line takes neither the memory nor the horizontal scrolling of all of it, and
+    /// only its first N characters are diffed. This only changes what is shown: the scan still
+    /// compares whole lines, and marks the `…` of a line differing past it as changed, unless
+    /// --truncate-comparisons is given too
+    #[arg(long, value_name = "N", value_parser = parse_positive)]
+    pub max_line_width: Option<usize>,
+
+    /// Compare only the first --max-line-width characters of each line as well, so differences
+    /// past them are ignored
+    #[arg(long, requires = "max_line_width")]
+    pub truncate_comparisons: bool,
+
+    /// How lines are paired up between the files before comparing them
+    #[arg(long, value_enum, default_value_t = Alignment::Lcs)]
+    pub align: Alignment,
+
+    /// With `--align none`, look up to N lines ahead for a matching line to re-pair on after
+    /// the files diverge
+    #[arg(long, value_name = "N", value_parser = parse_positive)]
+    pub resync_window: Option<usize>,
+
+    /// Collapse runs of a repeated block of lines, like an unrolled loop, to a `× N` summary
+    #[arg(long)]
+    pub collapse_repeats: bool,
+
+    /// Show only the differing lines and CONTEXT lines around them, 3 if not given, hiding the
+    /// rest behind a separator
+    #[arg(long, value_name = "CONTEXT", num_args = 0..=1, default_missing_value = "3")]
+    pub only_diffs: Option<usize>,
+
+    /// Fold runs of more than N identical lines, 10 if not given, into a row that Enter expands
+    /// and F folds again
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "10")]
+    pub fold_identical: Option<usize>,
+
+    /// Columns between tab stops. Tabs are expanded to the next one when drawing the lines, but
+    /// compared as single characters
+    #[arg(long, value_name = "N", default_value_t = state::DEFAULT_TAB_WIDTH, value_parser = parse_positive)]
+    pub tab_width: usize,
+
+    /// Draw spaces as a dim `·` and tabs as a dim `→` out to the next tab stop, so whitespace
+    /// differences can be seen
+    #[arg(long)]
+    pub show_whitespace: bool,
+
+    /// Characters to show left of a diff when scrolling to it horizontally. By default the first
+    /// diff opens 5 characters from the edge, and jumping to a diff centers it
+    #[arg(long, value_name = "N")]
+    pub context_chars: Option<usize>,
+
+    /// Lines or columns a navigation key moves per press, before holding it speeds it up
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = parse_positive)]
+    pub scroll_step: usize,
+
+    /// Double the scroll step every N repeats while a navigation key is held
+    #[arg(long, value_name = "N", default_value_t = 5, value_parser = parse_positive)]
+    pub scroll_acceleration: usize,
+
+    /// Largest number of lines or columns a held navigation key moves at once
+    #[arg(long, value_name = "N", default_value_t = 32, value_parser = parse_positive)]
+    pub max_scroll_step: usize,
+
+    /// Key that quits the viewer: a single character, or `Esc`, `Tab`, `Enter`, `Backspace`, or
+    /// `Delete`
+    #[arg(long, value_name = "KEY", default_value = "Esc", value_parser = action::parse_key)]
+    pub quit_key: KeyCode,
+
+    /// Carry on searching from the start of the files after reaching their end
+    #[arg(long)]
+    pub search_wrap: bool,
+
+    /// Jump from the last diff to the first one when going to the next diff, and from the first
+    /// to the last when going to the previous one, instead of staying put
+    #[arg(long)]
+    pub diff_wrap: bool,
+
+    /// Ask for confirmation before quitting the viewer
+    #[arg(long)]
+    pub confirm_quit: bool,
+
+    /// Hide the status line, giving the panes the full height. It still shows while typing a
+    /// line number or search
+    #[arg(long)]
+    pub no_status: bool,
+
+    /// Don't brighten the selected change in both panes to match up what each file has there.
+    /// `L` turns it back on
+    #[arg(long)]
+    pub no_link_changes: bool,
+
+    /// Use diff colors told apart under red-green color blindness, and mark changed lines with
+    /// `+`, `-`, or `~` in the gutter
+    #[arg(long)]
+    pub colorblind: bool,
+
+    /// Show the byte offset of each line in its file, in hex, after its line number in the
+    /// gutters
+    #[arg(long)]
+    pub show_offsets: bool,
+
+    /// Base of the line numbers in the gutters
+    #[arg(long, value_enum, default_value_t = state::NumberBase::Decimal)]
+    pub number_base: state::NumberBase,
+
+    /// Theme file of `kind = "color"` lines for the `added`, `removed`, `modified`, and `same` text
+    /// and the `selected` row. Read from `~/.config/trace-log-comparer/theme.toml` by default, if
+    /// it exists. The color flags take precedence over it
+    #[arg(long, value_name = "FILE")]
+    pub theme: Option<PathBuf>,
+
+    /// Color of added text: a name like `green`, a `#rrggbb` or `rgb(r, g, b)` color, or a 0-255
+    /// palette index
+    #[arg(long, value_name = "COLOR", value_parser = style::parse_color)]
+    pub added_color: Option<Color>,
+
+    /// Color of removed text
+    #[arg(long, value_name = "COLOR", value_parser = style::parse_color)]
+    pub removed_color: Option<Color>,
+
+    /// Color of modified text
+    #[arg(long, value_name = "COLOR", value_parser = style::parse_color)]
+    pub modified_color: Option<Color>,
+
+    /// Color of the selected row
+    #[arg(long, value_name = "COLOR", value_parser = style::parse_color)]
+    pub selected_color: Option<Color>,
+
+    /// Mark the selected row with this before its line number, e.g. `> `, so it stands out
+    /// without color. The other rows are moved over by its width
+    #[arg(long, value_name = "TEXT")]
+    pub selected_symbol: Option<String>,
+
+    /// Colors the terminal can show, to fall back to the nearest when 24-bit colors aren't
+    /// available. Detected from `COLORTERM` and `TERM` by default
+    #[arg(long, value_enum)]
+    pub color_depth: Option<ColorDepth>,
+
+    /// How changed text stands out
+    #[arg(long, value_enum, default_value_t = Emphasis::Fill)]
+    pub emphasis: Emphasis,
+
+    /// Write debug logs to this file. Verbosity is controlled by `RUST_LOG` (default `info`)
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+}
+
+impl Args {
+    pub fn diff_options(&self) -> DiffOptions {
+        DiffOptions {
+            pattern: self.pattern,
+            char_diff: self.char_diff,
+            ignore_chars: self.ignore_chars.clone().unwrap_or_default(),
+            mode: self.mode,
+            ignore_whitespace: self.ignore_whitespace,
+            ignore_trailing_whitespace: self.ignore_trailing_whitespace,
+            masks: Masks(self.mask.clone()),
+            preserve_eol: self.preserve_eol,
+            compare_width: self.max_line_width.filter(|_| self.truncate_comparisons),
+        }
+    }
+
+    pub fn diff_style(&self, theme: &Theme) -> DiffStyle {
+        let style = if self.colorblind {
+            DiffStyle::colorblind()
+        } else {
+            DiffStyle::default()
+        };
+
+        let mut style = style.with_theme(theme).with_colors(
+            self.added_color,
+            self.removed_color,
+            self.modified_color,
+        );
+
+        if let Some(color) = self.selected_color {
+            style.selected.fg = Some(color);
+        }
+
+        style
+            .with_emphasis(self.emphasis)
+            .for_color_depth(self.color_depth())
+    }
+
+    fn color_depth(&self) -> ColorDepth {
+        if self.svg.is_some() {
+            // Images aren't limited by the terminal
+            return ColorDepth::Truecolor;
+        }
+
+        self.color_depth.unwrap_or_else(ColorDepth::detect)
+    }
+
+    pub fn strip_prefix(&self) -> Option<StripPrefix> {
+        if let Some(length) = self.ignore_prefix {
+            return Some(StripPrefix {
+                prefix: Prefix::Characters(length),
+                files: PrefixFiles::Both,
+            });
+        }
+
+        self.strip_prefix.clone().map(|pattern| StripPrefix {
+            prefix: Prefix::Pattern(pattern),
+            files: self.strip_prefix_from,
+        })
+    }
+
+    /// Whether stripped prefixes are still shown, which ignored prefixes always are
+    pub fn keep_prefix(&self) -> bool {
+        self.keep_prefix || self.ignore_prefix.is_some()
+    }
+
+    pub fn time_filter(&self) -> Option<TimeFilter> {
+        self.time_range.map(|(start, end)| TimeFilter {
+            column: self.time_column,
+            start,
+            end,
+        })
+    }
+}
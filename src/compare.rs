@@ -0,0 +1,521 @@
+use std::borrow::Cow;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    align::Alignment,
+    mask::Masks,
+    state::{self, DiffSection, LineDiff},
+    string::StringUtils,
+};
+
+/// Options changing when two lines are considered equal
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffOptions {
+    /// Treat file 2 as a pattern, where `?` matches any character and `*` any run of characters
+    pub pattern: bool,
+    /// How the characters of two differing lines are matched up
+    #[serde(default)]
+    pub char_diff: CharDiff,
+    /// Characters skipped when comparing, though still shown, so `1,000` equals `1000` when
+    /// this has `,`
+    #[serde(default)]
+    pub ignore_chars: String,
+    /// What the lines of the files are
+    #[serde(default)]
+    pub mode: InputMode,
+    /// Compare runs of whitespace equal to each other, and ignore whitespace at the end of lines
+    #[serde(default)]
+    pub ignore_whitespace: bool,
+    /// Ignore whitespace at the end of lines
+    #[serde(default)]
+    pub ignore_trailing_whitespace: bool,
+    /// Text left out of the comparison, though still shown
+    #[serde(default)]
+    pub masks: Masks,
+    /// Compare line endings too, so a `\r\n` line differs from the same `\n` line
+    #[serde(default)]
+    pub preserve_eol: bool,
+    /// Compare only the first this many characters of each line
+    #[serde(default)]
+    pub compare_width: Option<usize>,
+}
+
+/// What the lines of the files are
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum InputMode {
+    /// Plain lines of text, like trace logs
+    #[default]
+    Trace,
+    /// Lines of unified diffs, compared as a `+`, `-`, or ` ` marker and the content after it.
+    /// Hunk headers compare equal, as their line numbers shift with unrelated changes
+    Patch,
+}
+
+/// A line of a unified diff
+pub enum PatchLine<'l> {
+    /// A `@@ -a,b +c,d @@` hunk header
+    HunkHeader,
+    /// A line starting with a `+`, `-`, or ` ` marker
+    Content { marker: &'l str, content: &'l str },
+    /// Anything else, like `---`/`+++` file headers or `\ No newline at end of file`
+    Other,
+}
+
+impl<'l> PatchLine<'l> {
+    pub fn parse(line: &'l str) -> Self {
+        if line.starts_with("@@") {
+            PatchLine::HunkHeader
+        } else if line.starts_with("---") || line.starts_with("+++") {
+            PatchLine::Other
+        } else if line.starts_with(['+', '-', ' ']) {
+            let (marker, content) = line.split_at(1);
+            PatchLine::Content { marker, content }
+        } else {
+            PatchLine::Other
+        }
+    }
+}
+
+/// How the characters of a pair of differing lines are matched up
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum CharDiff {
+    /// Compare the characters in the same column
+    #[default]
+    Lockstep,
+    /// When most columns of a line pair differ, keep the lines' longest common substring as the
+    /// same text and show the text around it as removed and added
+    Substring,
+}
+
+impl DiffOptions {
+    /// Short labels of the options that are on, for the status bar
+    pub fn active_labels(&self) -> Vec<&'static str> {
+        [
+            (self.pattern, "pattern"),
+            (self.char_diff == CharDiff::Substring, "substring"),
+            (!self.ignore_chars.is_empty(), "ignore-chars"),
+            (self.mode == InputMode::Patch, "patch"),
+            (self.ignore_whitespace, "ignore-whitespace"),
+            (
+                self.ignore_trailing_whitespace && !self.ignore_whitespace,
+                "ignore-trailing-whitespace",
+            ),
+            (!self.masks.is_empty(), "mask"),
+            (self.preserve_eol, "preserve-eol"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, label)| enabled.then_some(label))
+        .collect()
+    }
+
+    pub fn lines_equal(&self, line1: &str, line2: &str) -> bool {
+        if self.mode == InputMode::Patch {
+            if let (PatchLine::HunkHeader, PatchLine::HunkHeader) =
+                (PatchLine::parse(line1), PatchLine::parse(line2))
+            {
+                return true;
+            }
+        }
+
+        let (line1, line2) = match self.compare_width {
+            Some(width) => (line1.slice(..width), line2.slice(..width)),
+            None => (line1, line2),
+        };
+
+        let (masked1, masked2) = (self.masks.apply(line1), self.masks.apply(line2));
+        let (line1, line2) = (
+            self.normalize_whitespace(&masked1),
+            self.normalize_whitespace(&masked2),
+        );
+        let (line1, line2) = (line1.as_ref(), line2.as_ref());
+
+        if self.pattern {
+            pattern_sections(line1, line2).is_some()
+        } else if !self.ignore_chars.is_empty() {
+            line1
+                .chars()
+                .filter(|char| !self.is_ignored(*char))
+                .eq(line2.chars().filter(|char| !self.is_ignored(*char)))
+        } else {
+            line1 == line2
+        }
+    }
+
+    pub fn is_ignored(&self, char: char) -> bool {
+        self.ignore_chars.contains(char)
+    }
+
+    /// Whether whitespace at the end of lines is left out of the comparison
+    pub fn ignores_trailing_whitespace(&self) -> bool {
+        self.ignore_whitespace || self.ignore_trailing_whitespace
+    }
+
+    /// The line without its `\n` or `\r\n` ending, unless line endings are compared
+    pub fn strip_eol<'l>(&self, line: &'l str) -> &'l str {
+        if self.preserve_eol {
+            return line;
+        }
+
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        line.strip_suffix('\r').unwrap_or(line)
+    }
+
+    /// The line as it is compared: without its line ending unless that is compared, without
+    /// trailing whitespace when that is ignored, and with each run of whitespace as a single
+    /// space when whitespace is ignored
+    fn normalize_whitespace<'l>(&self, line: &'l str) -> Cow<'l, str> {
+        let line = if self.ignores_trailing_whitespace() {
+            line.trim_end()
+        } else {
+            self.strip_eol(line)
+        };
+
+        if !self.ignore_whitespace {
+            return Cow::Borrowed(line);
+        }
+
+        let mut normalized = String::with_capacity(line.len());
+        let mut in_whitespace = false;
+
+        for char in line.chars() {
+            if char.is_whitespace() {
+                if !in_whitespace {
+                    normalized.push(' ');
+                }
+            } else {
+                normalized.push(char);
+            }
+
+            in_whitespace = char.is_whitespace();
+        }
+
+        Cow::Owned(normalized)
+    }
+}
+
+/// How alike two lines are, from 0 to 1: the length of their longest common substring relative to
+/// the longer line
+pub fn similarity(line1: &str, line2: &str) -> f64 {
+    let line1: Vec<char> = line1.trim_end().chars().collect();
+    let line2: Vec<char> = line2.trim_end().chars().collect();
+
+    let longer_length = line1.len().max(line2.len());
+
+    if longer_length == 0 {
+        return 1.0;
+    }
+
+    let (_, _, longest) = longest_common_substring(&line1, &line2);
+
+    longest as f64 / longer_length as f64
+}
+
+/// Diffs the lines around their longest common substring: the text before and after it is
+/// removed from `line1` and added in `line2`. `None` if the lines share no characters
+pub fn substring_sections(line1: &str, line2: &str) -> Option<LineDiff> {
+    let line1: Vec<char> = line1.chars().collect();
+    let line2: Vec<char> = line2.chars().collect();
+
+    let (end1, end2, length) = longest_common_substring(&line1, &line2);
+
+    if length == 0 {
+        return None;
+    }
+
+    let (start1, start2) = (end1 - length, end2 - length);
+
+    let sections = [
+        DiffSection::Removed(line1[..start1].iter().collect()),
+        DiffSection::Added(line2[..start2].iter().collect()),
+        DiffSection::Same(line1[start1..end1].iter().collect()),
+        DiffSection::Removed(line1[end1..].iter().collect()),
+        DiffSection::Added(line2[end2..].iter().collect()),
+    ];
+
+    let mut diffs = LineDiff::default();
+
+    for section in sections {
+        if !section.is_empty() {
+            diffs.push(section);
+        }
+    }
+
+    Some(diffs)
+}
+
+/// The end of the longest common substring in each of the lines, and its length
+fn longest_common_substring(line1: &[char], line2: &[char]) -> (usize, usize, usize) {
+    // Length of the common substring ending at the previous and current char of `line1`
+    let mut previous = vec![0; line2.len() + 1];
+    let mut current = vec![0; line2.len() + 1];
+    let mut longest = (0, 0, 0);
+
+    for (i, char1) in line1.iter().enumerate() {
+        for (j, char2) in line2.iter().enumerate() {
+            current[j + 1] = if char1 == char2 { previous[j] + 1 } else { 0 };
+
+            if current[j + 1] > longest.2 {
+                longest = (i + 1, j + 1, current[j + 1]);
+            }
+        }
+
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    longest
+}
+
+/// Matches `text` against `pattern`, returning the sections of the match. `None` if it doesn't
+/// match
+pub fn pattern_sections(text: &str, pattern: &str) -> Option<LineDiff> {
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+
+    // matches[i][j] is whether text[i..] matches pattern[j..]
+    let mut matches = vec![vec![false; pattern.len() + 1]; text.len() + 1];
+    matches[text.len()][pattern.len()] = true;
+
+    for i in (0..=text.len()).rev() {
+        for j in (0..pattern.len()).rev() {
+            matches[i][j] = match pattern[j] {
+                '*' => matches[i][j + 1] || (i < text.len() && matches[i + 1][j]),
+                '?' => i < text.len() && matches[i + 1][j + 1],
+                char => i < text.len() && text[i] == char && matches[i + 1][j + 1],
+            };
+        }
+    }
+
+    if !matches[0][0] {
+        return None;
+    }
+
+    let mut diffs = LineDiff::default();
+
+    let mut i = 0;
+
+    for (j, &pattern_char) in pattern.iter().enumerate() {
+        match pattern_char {
+            '*' => {
+                // Consume as little as possible, leaving the rest to the remaining pattern
+                let length = (0..=text.len() - i)
+                    .find(|length| matches[i + length][j + 1])
+                    .unwrap_or(0);
+
+                diffs.push(DiffSection::Equivalent {
+                    left: text[i..i + length].iter().collect(),
+                    right: pattern_char.to_string(),
+                });
+
+                i += length;
+            }
+            '?' => {
+                diffs.push(DiffSection::Equivalent {
+                    left: text[i].to_string(),
+                    right: pattern_char.to_string(),
+                });
+
+                i += 1;
+            }
+            char => {
+                diffs.push(DiffSection::Same(char.to_string()));
+
+                i += 1;
+            }
+        }
+    }
+
+    Some(diffs)
+}
+
+/// Splits a line into the tokens its diff is made of. Joining the tokens must give back the line
+pub type Tokenizer = fn(&str) -> Vec<&str>;
+
+/// Every character is a token, as in the viewer's own diff
+pub fn char_tokens(line: &str) -> Vec<&str> {
+    line.char_indices()
+        .map(|(index, char)| &line[index..index + char.len_utf8()])
+        .collect()
+}
+
+/// Runs of whitespace and of everything else are tokens, for columnar formats
+pub fn word_tokens(line: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut start = 0;
+    let mut previous_is_whitespace = None;
+
+    for (index, char) in line.char_indices() {
+        let is_whitespace = char.is_whitespace();
+
+        if previous_is_whitespace.is_some_and(|previous| previous != is_whitespace) {
+            tokens.push(&line[start..index]);
+            start = index;
+        }
+
+        previous_is_whitespace = Some(is_whitespace);
+    }
+
+    if start < line.len() {
+        tokens.push(&line[start..]);
+    }
+
+    tokens
+}
+
+/// Diffs the lines of two files like the viewer does by default: the lines are paired along their
+/// longest common subsequence, and each pair is diffed character by character. Lines only one
+/// file has are entirely `Removed` or `Added`
+///
+/// ```
+/// use trace_log_comparer::{diff_lines, DiffSection};
+///
+/// let file1 = vec!["pc=1 r1=0x0f".to_string(), "pc=2".to_string()];
+/// let file2 = vec!["pc=1 r1=0x1f".to_string(), "pc=2".to_string()];
+///
+/// let diffs = diff_lines(&file1, &file2);
+///
+/// assert!(matches!(
+///     diffs[0].sections(),
+///     [
+///         DiffSection::Same(before),
+///         DiffSection::Modified { left, right },
+///         DiffSection::Same(after),
+///     ] if before == "pc=1 r1=0x" && left == "0" && right == "1" && after == "f"
+/// ));
+/// assert!(matches!(diffs[1].sections(), [DiffSection::Same(line)] if line == "pc=2"));
+/// ```
+pub fn diff_lines(file1_lines: &[String], file2_lines: &[String]) -> Vec<LineDiff> {
+    let options = DiffOptions::default();
+    let rows = Alignment::Lcs.align(file1_lines, file2_lines, None, &options);
+
+    state::calculate_diffs(&rows, file1_lines, file2_lines, &options)
+}
+
+/// Diffs the lines of two files like `diff_lines`, pairing them along their longest common
+/// subsequence, but splitting the changed lines of file 1 with `tokenize1` and of file 2 with
+/// `tokenize2`. The tokens of each pair are paired along their own longest common subsequence, so
+/// a token only one line has shows as `Added` or `Removed` instead of shifting the rest
+///
+/// ```
+/// use trace_log_comparer::compare::{char_tokens, diff_lines_with, word_tokens};
+/// use trace_log_comparer::DiffSection;
+///
+/// let file1 = vec!["pc=1 r1=0f".to_string()];
+/// let file2 = vec!["pc=1 r1=1f".to_string()];
+///
+/// let diffs = diff_lines_with(&file1, &file2, word_tokens, word_tokens);
+///
+/// assert!(matches!(
+///     diffs[0].sections(),
+///     [DiffSection::Same(same), DiffSection::Modified { left, right }]
+///         if same == "pc=1 " && left == "r1=0f" && right == "r1=1f"
+/// ));
+///
+/// let diffs = diff_lines_with(&file1, &file2, char_tokens, char_tokens);
+///
+/// assert!(matches!(diffs[0].sections()[1], DiffSection::Modified { ref left, .. } if left == "0"));
+/// ```
+pub fn diff_lines_with(
+    file1_lines: &[String],
+    file2_lines: &[String],
+    tokenize1: Tokenizer,
+    tokenize2: Tokenizer,
+) -> Vec<LineDiff> {
+    let options = DiffOptions::default();
+
+    Alignment::Lcs
+        .align(file1_lines, file2_lines, None, &options)
+        .into_iter()
+        .map(|row| match (row.file1, row.file2) {
+            (Some(index1), Some(index2)) if file1_lines[index1] == file2_lines[index2] => {
+                DiffSection::Same(file1_lines[index1].clone()).into()
+            }
+            (Some(index1), Some(index2)) => diff_tokens(
+                tokenize1(&file1_lines[index1]),
+                tokenize2(&file2_lines[index2]),
+            ),
+            (Some(index1), None) => DiffSection::Removed(file1_lines[index1].clone()).into(),
+            (None, Some(index2)) => DiffSection::Added(file2_lines[index2].clone()).into(),
+            (None, None) => LineDiff::default(),
+        })
+        .collect()
+}
+
+/// Diffs two lines already split into tokens. Equal tokens are paired along the longest common
+/// subsequence, and the tokens between them are `Modified` where both lines have some left
+fn diff_tokens(tokens1: Vec<&str>, tokens2: Vec<&str>) -> LineDiff {
+    let tokens1: Vec<String> = tokens1.into_iter().map(String::from).collect();
+    let tokens2: Vec<String> = tokens2.into_iter().map(String::from).collect();
+    // Keeping line endings makes tokens only match when they are exactly the same
+    let exact = DiffOptions {
+        preserve_eol: true,
+        ..DiffOptions::default()
+    };
+    let mut diffs = LineDiff::default();
+
+    for row in Alignment::Lcs.align(&tokens1, &tokens2, None, &exact) {
+        diffs.push(match (row.file1, row.file2) {
+            (Some(index1), Some(index2)) if tokens1[index1] == tokens2[index2] => {
+                DiffSection::Same(tokens1[index1].clone())
+            }
+            (Some(index1), Some(index2)) => DiffSection::Modified {
+                left: tokens1[index1].clone(),
+                right: tokens2[index2].clone(),
+            },
+            (Some(index1), None) => DiffSection::Removed(tokens1[index1].clone()),
+            (None, Some(index2)) => DiffSection::Added(tokens2[index2].clone()),
+            (None, None) => continue,
+        });
+    }
+
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    /// Fields and the commas between them are tokens
+    fn comma_tokens(line: &str) -> Vec<&str> {
+        let mut tokens = vec![];
+        let mut start = 0;
+
+        for (index, _) in line.match_indices(',') {
+            tokens.extend([&line[start..index], &line[index..index + 1]]);
+            start = index + 1;
+        }
+
+        tokens.push(&line[start..]);
+        tokens
+    }
+
+    #[test]
+    fn inserted_lines_and_tokens_do_not_shift_the_rest() {
+        let file1 = lines(&["pc=1 r1=0", "pc=2 r1=0"]);
+        let file2 = lines(&["irq", "pc=1 r1=0", "pc=2,r2=5,r1=0"]);
+
+        let diffs = diff_lines_with(&file1, &file2, word_tokens, comma_tokens);
+
+        assert_eq!(diffs.len(), 3);
+        assert_eq!(diffs[0].sections(), [DiffSection::Added("irq".into())]);
+        assert_eq!(diffs[1].sections(), [DiffSection::Same("pc=1 r1=0".into())]);
+        // The extra `r2=5` field is added, and `r1=0` still lines up with file 1's
+        assert_eq!(
+            diffs[2].sections(),
+            [
+                DiffSection::Same("pc=2".into()),
+                DiffSection::Modified {
+                    left: " ".into(),
+                    right: ",".into(),
+                },
+                DiffSection::Added("r2=5,".into()),
+                DiffSection::Same("r1=0".into()),
+            ]
+        );
+    }
+}
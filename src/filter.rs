@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+
+/// Keeps only the lines whose timestamp falls within a range
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimeFilter {
+    /// 1-based whitespace separated field holding the timestamp
+    pub column: usize,
+    /// Inclusive bounds of the timestamps to keep
+    pub start: f64,
+    pub end: f64,
+}
+
+impl TimeFilter {
+    /// Whether `line` has a timestamp in range. Lines without a readable timestamp are dropped
+    pub fn keeps(&self, line: &str) -> bool {
+        self.timestamp(line)
+            .is_some_and(|timestamp| self.start <= timestamp && timestamp <= self.end)
+    }
+
+    fn timestamp(&self, line: &str) -> Option<f64> {
+        let field = line.split_whitespace().nth(self.column.checked_sub(1)?)?;
+
+        // Allow decorations around the number, like `[12.5]` or `12.5:`
+        field
+            .trim_matches(|char: char| !(char.is_ascii_digit() || char == '.'))
+            .parse()
+            .ok()
+    }
+}
@@ -0,0 +1,528 @@
+use std::io::{self, BufRead, Read, Write};
+
+/// The first two bytes of every gzip member
+pub const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Back references reach at most this many bytes behind the end of the output
+const WINDOW_SIZE: usize = 32 * 1024;
+
+/// Output collected before the part behind the window is written out
+const FLUSH_SIZE: usize = 4 * WINDOW_SIZE;
+
+/// Base length and extra bits of length symbols 257 to 285
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distance and extra bits of distance symbols 0 to 29
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order the code length code lengths of a dynamic block are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+// Header flags
+const FLAG_HEADER_CRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+
+/// Whether `bytes` start like gzip data
+pub fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Decompresses the gzip data of `reader` into `out`, member after member until the end of the
+/// input. Each member's checksum and length are checked
+pub fn decompress<R: BufRead, W: Write>(reader: R, out: W) -> io::Result<()> {
+    let mut bits = BitReader::new(reader);
+    let mut output = Output::new(out);
+
+    loop {
+        read_header(&mut bits)?;
+
+        output.start_member();
+        inflate(&mut bits, &mut output)?;
+
+        bits.align_to_byte();
+        let crc = bits.u32_le()?;
+        let length = bits.u32_le()?;
+
+        if crc != output.crc || length != output.member_length as u32 {
+            return Err(invalid_data("gzip checksum mismatch"));
+        }
+
+        // Concatenated files are more members
+        if bits.reader.fill_buf()?.is_empty() {
+            break;
+        }
+    }
+
+    output.finish()
+}
+
+fn read_header<R: BufRead>(bits: &mut BitReader<R>) -> io::Result<()> {
+    let mut header = [0; 10];
+    bits.reader.read_exact(&mut header)?;
+
+    if !is_gzip(&header) {
+        return Err(invalid_data("not gzip data"));
+    }
+
+    if header[2] != 8 {
+        return Err(invalid_data("unsupported gzip compression method"));
+    }
+
+    let flags = header[3];
+
+    if flags & FLAG_EXTRA != 0 {
+        let mut length = [0; 2];
+        bits.reader.read_exact(&mut length)?;
+
+        io::copy(
+            &mut (&mut bits.reader).take(u16::from_le_bytes(length) as u64),
+            &mut io::sink(),
+        )?;
+    }
+
+    for flag in [FLAG_NAME, FLAG_COMMENT] {
+        if flag & flags != 0 {
+            // Zero terminated
+            bits.reader.read_until(0, &mut vec![])?;
+        }
+    }
+
+    if flags & FLAG_HEADER_CRC != 0 {
+        bits.reader.read_exact(&mut [0; 2])?;
+    }
+
+    Ok(())
+}
+
+/// Decodes deflate blocks up to the final one
+fn inflate<R: BufRead, W: Write>(
+    bits: &mut BitReader<R>,
+    output: &mut Output<W>,
+) -> io::Result<()> {
+    loop {
+        let last = bits.bits(1)? == 1;
+
+        match bits.bits(2)? {
+            0 => stored_block(bits, output)?,
+            1 => {
+                let (literals, distances) = fixed_codes();
+                compressed_block(bits, output, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(bits)?;
+                compressed_block(bits, output, &literals, &distances)?;
+            }
+            _ => return Err(invalid_data("invalid deflate block type")),
+        }
+
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+fn stored_block<R: BufRead, W: Write>(
+    bits: &mut BitReader<R>,
+    output: &mut Output<W>,
+) -> io::Result<()> {
+    bits.align_to_byte();
+
+    let length = bits.bits(16)? as u16;
+    let complement = bits.bits(16)? as u16;
+
+    if length != !complement {
+        return Err(invalid_data("corrupt stored deflate block"));
+    }
+
+    for _ in 0..length {
+        output.push(bits.bits(8)? as u8)?;
+    }
+
+    Ok(())
+}
+
+fn compressed_block<R: BufRead, W: Write>(
+    bits: &mut BitReader<R>,
+    output: &mut Output<W>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> io::Result<()> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+
+        match symbol {
+            0..=255 => output.push(symbol as u8)?,
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+
+                if index >= LENGTH_BASE.len() {
+                    return Err(invalid_data("invalid deflate length"));
+                }
+
+                let length =
+                    LENGTH_BASE[index] as usize + bits.bits(LENGTH_EXTRA[index] as u32)? as usize;
+
+                let index = distances.decode(bits)? as usize;
+
+                if index >= DISTANCE_BASE.len() {
+                    return Err(invalid_data("invalid deflate distance"));
+                }
+
+                let distance = DISTANCE_BASE[index] as usize
+                    + bits.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+
+                output.copy(distance, length)?;
+            }
+        }
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes<R: BufRead>(bits: &mut BitReader<R>) -> io::Result<(Huffman, Huffman)> {
+    let literal_count = bits.bits(5)? as usize + 257;
+    let distance_count = bits.bits(5)? as usize + 1;
+    let code_length_count = bits.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0; 19];
+
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[index] = bits.bits(3)? as u8;
+    }
+
+    let code_length_code = Huffman::new(&code_lengths);
+
+    // The literal and distance code lengths are one sequence, and repeats can cross between them
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_length_code.decode(bits)? {
+            length @ 0..=15 => (length as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| invalid_data("deflate length repeat with no length"))?;
+                (previous, 3 + bits.bits(2)?)
+            }
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+
+        lengths.extend((0..repeat).map(|_| length));
+    }
+
+    if lengths.len() > literal_count + distance_count {
+        return Err(invalid_data("deflate code lengths overrun"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+/// A canonical Huffman code, as the number of codes of each length and the symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+
+        counts[0] = 0;
+
+        // Where the symbols of each length start
+        let mut offsets = [0; 16];
+
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    /// Reads one code, a bit at a time from the most significant end
+    fn decode<R: BufRead>(&self, bits: &mut BitReader<R>) -> io::Result<u16> {
+        // The first code of the current length, and the index of its symbol
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+
+            let count = count as i32;
+
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(invalid_data("invalid deflate code"))
+    }
+}
+
+/// Reads deflate's least significant bit first bit stream
+struct BitReader<R> {
+    reader: R,
+    buffer: u32,
+    count: u32,
+}
+
+impl<R: BufRead> BitReader<R> {
+    fn new(reader: R) -> Self {
+        BitReader {
+            reader,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// The next `count` bits, up to 16
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.count < count {
+            let mut byte = [0];
+            self.reader.read_exact(&mut byte)?;
+
+            self.buffer |= (byte[0] as u32) << self.count;
+            self.count += 8;
+        }
+
+        let value = self.buffer & ((1 << count) - 1);
+
+        self.buffer >>= count;
+        self.count -= count;
+
+        Ok(value)
+    }
+
+    /// Drops the rest of the partly read byte
+    fn align_to_byte(&mut self) {
+        let partial = self.count % 8;
+
+        self.buffer >>= partial;
+        self.count -= partial;
+    }
+
+    fn u32_le(&mut self) -> io::Result<u32> {
+        Ok(self.bits(16)? | self.bits(16)? << 16)
+    }
+}
+
+/// Decompressed bytes, kept for back references until they fall behind the window
+struct Output<W> {
+    out: W,
+    buffer: Vec<u8>,
+    /// CRC-32 and length of the current member
+    crc: u32,
+    member_length: usize,
+    crc_table: [u32; 256],
+}
+
+impl<W: Write> Output<W> {
+    fn new(out: W) -> Self {
+        let mut crc_table = [0; 256];
+
+        for (index, entry) in crc_table.iter_mut().enumerate() {
+            *entry = (0..8).fold(index as u32, |crc, _| {
+                if crc & 1 == 1 {
+                    0xedb8_8320 ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                }
+            });
+        }
+
+        Output {
+            out,
+            buffer: Vec::with_capacity(FLUSH_SIZE + WINDOW_SIZE),
+            crc: 0,
+            member_length: 0,
+            crc_table,
+        }
+    }
+
+    fn start_member(&mut self) {
+        self.crc = 0;
+        self.member_length = 0;
+    }
+
+    fn push(&mut self, byte: u8) -> io::Result<()> {
+        self.buffer.push(byte);
+
+        let crc = !self.crc;
+        self.crc = !(self.crc_table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8));
+        self.member_length += 1;
+
+        if self.buffer.len() >= FLUSH_SIZE + WINDOW_SIZE {
+            self.out.write_all(&self.buffer[..FLUSH_SIZE])?;
+            self.buffer.drain(..FLUSH_SIZE);
+        }
+
+        Ok(())
+    }
+
+    /// Repeats the `length` bytes starting `distance` back, which can overlap what they add
+    fn copy(&mut self, distance: usize, length: usize) -> io::Result<()> {
+        if distance > self.buffer.len() {
+            return Err(invalid_data("deflate distance too far back"));
+        }
+
+        for _ in 0..length {
+            self.push(self.buffer[self.buffer.len() - distance])?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.out.write_all(&self.buffer)?;
+        self.out.flush()
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `stored line\n` in a stored block
+    const STORED: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x04\x03\x01\x0c\x00\xf3\xff\
+        stored line\n\xb8\x71\xe7\xd8\x0c\x00\x00\x00";
+
+    /// `fixed line, fixed line\n` in a block with the fixed codes
+    const FIXED: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x4b\xcb\xac\x48\x4d\x51\
+        \xc8\xc9\xcc\x4b\xd5\x51\x48\x83\xb3\xb9\x00\x2f\x86\xa1\xa8\x17\x00\x00\x00";
+
+    /// `line 0\n` to `line 17\n` in a block with codes of its own
+    const DYNAMIC: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x35\xcc\xb9\x0d\x00\x20\
+        \x10\x03\xc1\x9c\x2a\x28\x01\xf3\x1d\x14\x44\x80\x74\xa2\xff\x10\x21\x9b\x68\xa2\x5d\xdf\
+        \x67\xc5\x14\xfc\x01\x92\x49\x21\x95\x34\xd2\x89\x91\x41\xa6\xf2\xbf\xd1\x07\x1a\x41\x27\
+        \x68\x05\xbd\xa0\x19\x2c\x5c\x04\x67\x8d\x02\x86\x00\x00\x00";
+
+    fn decompressed(gzip: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = vec![];
+        decompress(gzip, &mut out)?;
+
+        Ok(out)
+    }
+
+    #[test]
+    fn stored_block_is_copied_out() {
+        assert_eq!(decompressed(STORED).unwrap(), b"stored line\n");
+    }
+
+    #[test]
+    fn fixed_codes_block_is_decoded() {
+        assert_eq!(decompressed(FIXED).unwrap(), b"fixed line, fixed line\n");
+    }
+
+    #[test]
+    fn dynamic_codes_block_is_decoded() {
+        let lines: String = (0..18).map(|i| format!("line {}\n", i)).collect();
+
+        assert_eq!(decompressed(DYNAMIC).unwrap(), lines.as_bytes());
+    }
+
+    #[test]
+    fn concatenated_members_are_decompressed_in_turn() {
+        let gzip = [STORED, FIXED].concat();
+
+        assert_eq!(
+            decompressed(&gzip).unwrap(),
+            b"stored line\nfixed line, fixed line\n"
+        );
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        for gzip in [STORED, FIXED, DYNAMIC] {
+            for length in [5, 12, gzip.len() / 2, gzip.len() - 1] {
+                let err = decompressed(&gzip[..length]).unwrap_err();
+
+                assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "{} bytes", length);
+            }
+        }
+    }
+
+    #[test]
+    fn checksum_or_length_mismatch_is_an_error() {
+        // The CRC-32 and the length are the last 8 bytes
+        for byte in [DYNAMIC.len() - 8, DYNAMIC.len() - 4] {
+            let mut gzip = DYNAMIC.to_vec();
+            gzip[byte] ^= 1;
+
+            let err = decompressed(&gzip).unwrap_err();
+
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(err.to_string(), "gzip checksum mismatch");
+        }
+    }
+
+    #[test]
+    fn corrupt_stored_block_length_is_an_error() {
+        let mut gzip = STORED.to_vec();
+        // The complement of the block's length
+        gzip[13] ^= 1;
+
+        let err = decompressed(&gzip).unwrap_err();
+
+        assert_eq!(err.to_string(), "corrupt stored deflate block");
+    }
+
+    #[test]
+    fn data_that_isnt_gzip_is_an_error() {
+        let err = decompressed(b"plain text, not compressed\n").unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
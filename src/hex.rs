@@ -0,0 +1,59 @@
+use std::fmt::Write;
+
+/// Bytes in each row of a dump, and in each record of `--hex` comparisons
+pub const BYTES_PER_ROW: usize = 16;
+
+/// Formats `bytes` as a classic hex dump, 16 bytes per row with the offset on the left and the
+/// printable ASCII on the right
+pub fn hex_dump(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(BYTES_PER_ROW)
+        .enumerate()
+        .map(|(row, chunk)| hex_row(row * BYTES_PER_ROW, chunk))
+        .collect()
+}
+
+/// Formats up to 16 `bytes` found at `offset` as a row of a hex dump
+pub fn hex_row(offset: usize, bytes: &[u8]) -> String {
+    let mut line = format!("{:08x}  ", offset);
+
+    for index in 0..BYTES_PER_ROW {
+        match bytes.get(index) {
+            Some(byte) => write!(line, "{:02x} ", byte).unwrap(),
+            None => line.push_str("   "),
+        }
+
+        if index == BYTES_PER_ROW / 2 - 1 {
+            line.push(' ');
+        }
+    }
+
+    line.push_str(" |");
+    line.extend(bytes.iter().map(|&byte| {
+        if byte.is_ascii_graphic() || byte == b' ' {
+            byte as char
+        } else {
+            '.'
+        }
+    }));
+    line.push('|');
+
+    line
+}
+
+/// Whether `bytes` from the start of a file look like they aren't text: they have a NUL, or
+/// more than a tenth of them are control characters other than whitespace and escapes
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    if bytes.contains(&0) {
+        return true;
+    }
+
+    let control_count = bytes
+        .iter()
+        .filter(|&&byte| {
+            (byte < 0x20 || byte == 0x7f) && !matches!(byte, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)
+        })
+        .count();
+
+    control_count * 10 > bytes.len()
+}
@@ -0,0 +1,153 @@
+use std::{io, path::Path};
+
+use tui::{
+    style::{Modifier, Style},
+    text::Spans,
+};
+
+use crate::svg::{color_to_hex, escape, BACKGROUND, FOREGROUND};
+
+/// The two files go in a table of a row per aligned line pair, so long lines of one file don't
+/// shift the other's rows
+const STYLESHEET: &str = "
+body { margin: 0; background: BACKGROUND; color: FOREGROUND; font-family: monospace; }
+table { border-collapse: collapse; }
+th { position: sticky; top: 0; background: BACKGROUND; text-align: left; padding: 4px 8px; }
+td { padding: 0 8px; vertical-align: top; white-space: pre; }
+td.number { text-align: right; opacity: 0.5; user-select: none; }
+td.number a { color: inherit; text-decoration: none; }
+td.number a:target { opacity: 1; outline: 1px solid FOREGROUND; }
+td.file1 { border-right: 1px solid rgba(212, 212, 212, 0.3); }
+";
+
+/// Writes the start of the page comparing `file1_path` with `file2_path`, up to the first row
+pub fn write_header(
+    out: &mut impl io::Write,
+    file1_path: &Path,
+    file2_path: &Path,
+) -> io::Result<()> {
+    let file1 = escape(&file1_path.display().to_string());
+    let file2 = escape(&file2_path.display().to_string());
+
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, r#"<html><head><meta charset="utf-8">"#)?;
+    writeln!(out, "<title>{} &lt;&gt; {}</title>", file1, file2)?;
+    writeln!(
+        out,
+        "<style>{}</style>",
+        STYLESHEET
+            .replace("BACKGROUND", BACKGROUND)
+            .replace("FOREGROUND", FOREGROUND)
+    )?;
+    writeln!(out, "</head><body><table>")?;
+    writeln!(
+        out,
+        r#"<thead><tr><th colspan="2">{}</th><th colspan="2">{}</th></tr></thead><tbody>"#,
+        file1, file2
+    )
+}
+
+/// Writes a row of the table: each file's 1-based line number, linked to by `#file1-L<number>`
+/// or `#file2-L<number>`, and its styled text. A file without a line in the row gets empty cells
+pub fn write_row(
+    out: &mut impl io::Write,
+    line_numbers: [Option<usize>; 2],
+    spans: [&Spans; 2],
+) -> io::Result<()> {
+    out.write_all(b"<tr>")?;
+
+    for (file, (line_number, spans)) in ["file1", "file2"]
+        .iter()
+        .zip(line_numbers.iter().zip(spans))
+    {
+        match line_number {
+            Some(number) => write!(
+                out,
+                r##"<td class="number"><a id="{file}-L{number}" href="#{file}-L{number}">{number}</a></td>"##
+            )?,
+            None => write!(out, r#"<td class="number"></td>"#)?,
+        }
+
+        write!(out, r#"<td class="{}">"#, file)?;
+
+        if line_number.is_some() {
+            for span in &spans.0 {
+                let text = escape(&displayed_text(&span.content));
+
+                if text.is_empty() {
+                    continue;
+                }
+
+                match css(&span.style) {
+                    Some(css) => write!(out, r#"<span style="{}">{}</span>"#, css, text)?,
+                    None => out.write_all(text.as_bytes())?,
+                }
+            }
+        }
+
+        out.write_all(b"</td>")?;
+    }
+
+    out.write_all(b"</tr>\n")
+}
+
+/// Writes the end of the page, after the last row
+pub fn write_footer(out: &mut impl io::Write) -> io::Result<()> {
+    writeln!(out, "</tbody></table></body></html>")
+}
+
+/// Line terminators have no place in a cell. Unlike the image export, tabs are kept, as the
+/// browser lines them up
+fn displayed_text(text: &str) -> String {
+    text.chars()
+        .filter(|&char| char == '\t' || !char.is_control())
+        .collect()
+}
+
+/// The inline CSS drawing text in `style`, `None` for the page's own style
+fn css(style: &Style) -> Option<String> {
+    let mut fg = style.fg.and_then(color_to_hex);
+    let mut bg = style.bg.and_then(color_to_hex);
+
+    if style.add_modifier.contains(Modifier::REVERSED) {
+        (fg, bg) = (
+            Some(bg.unwrap_or_else(|| BACKGROUND.to_string())),
+            Some(fg.unwrap_or_else(|| FOREGROUND.to_string())),
+        );
+    }
+
+    let mut properties = vec![];
+
+    if let Some(fg) = fg {
+        properties.push(format!("color: {}", fg));
+    }
+
+    if let Some(bg) = bg {
+        properties.push(format!("background: {}", bg));
+    }
+
+    for (modifier, property) in [
+        (Modifier::BOLD, "font-weight: bold"),
+        (Modifier::DIM, "opacity: 0.5"),
+        (Modifier::ITALIC, "font-style: italic"),
+    ] {
+        if style.add_modifier.contains(modifier) {
+            properties.push(property.to_string());
+        }
+    }
+
+    let decorations: Vec<&str> = [
+        (Modifier::UNDERLINED, "underline"),
+        (Modifier::CROSSED_OUT, "line-through"),
+    ]
+    .into_iter()
+    .filter(|(modifier, _)| style.add_modifier.contains(*modifier))
+    .map(|(_, decoration)| decoration)
+    .collect();
+
+    if !decorations.is_empty() {
+        properties.push(format!("text-decoration: {}", decorations.join(" ")));
+    }
+
+    (!properties.is_empty()).then(|| properties.join("; "))
+}
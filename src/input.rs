@@ -0,0 +1,317 @@
+use std::{
+    collections::hash_map::RandomState,
+    env,
+    fs::{self, File, OpenOptions},
+    hash::{BuildHasher, Hasher},
+    io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process,
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use crate::{gzip, hex, mmap::Mmap};
+
+/// Path that reads from stdin instead of a file
+pub const STDIN_PATH: &str = "-";
+
+/// Bytes from the start of an input checked for whether it is text
+const BINARY_SAMPLE_LENGTH: usize = 8 * 1024;
+
+/// Names tried for a temporary file before giving up, should they all be taken
+const TEMP_ATTEMPTS: usize = 16;
+
+/// Stdin can only be read once, so it is kept in memory for every later open
+static STDIN_CONTENTS: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Each compressed file opened so far, and the temporary file it was decompressed to
+static DECOMPRESSED: Mutex<Vec<Decompressed>> = Mutex::new(Vec::new());
+
+struct Decompressed {
+    source: PathBuf,
+    /// The source's length and modification time when it was decompressed
+    stamp: (u64, Option<SystemTime>),
+    temp_path: PathBuf,
+}
+
+/// An input log. Files are read in place, or from a memory map of them, while stdin can't seek
+/// and is read into memory first. Gzip files can't seek either, and are read from a decompressed
+/// temporary copy
+pub enum Input {
+    File(File),
+    Memory(Cursor<Vec<u8>>),
+    Mapped(Cursor<Mmap>),
+}
+
+impl Input {
+    /// Opens the file at `path`, or stdin when it is `-`. Gzip files are decompressed on the
+    /// first open
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Input::open_unlabeled(path).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("Could not open {}: {}", path.display(), err),
+            )
+        })
+    }
+
+    /// `open`, with errors that don't say which file they are about
+    fn open_unlabeled(path: &Path) -> io::Result<Self> {
+        if is_stdin(path) {
+            return Ok(Input::Memory(Cursor::new(stdin_contents()?.clone())));
+        }
+
+        let mut file = File::open(path)?;
+
+        if !is_gzip_file(path, &mut file)? {
+            return Ok(Input::File(file));
+        }
+
+        Ok(Input::File(File::open(decompressed_path(path, file)?)?))
+    }
+
+    /// Like `open`, but maps files into memory when they can be. A file changed while it is
+    /// mapped can fail reads of it with `SIGBUS`, so this is only done when asked for
+    pub fn open_mapped(path: &Path) -> io::Result<Self> {
+        let input = Input::open(path)?;
+
+        let Input::File(file) = &input else {
+            return Ok(input);
+        };
+
+        Ok(match Mmap::map(file) {
+            Some(mmap) => Input::Mapped(Cursor::new(mmap)),
+            None => input,
+        })
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::File(file) => file.read(buf),
+            Input::Memory(cursor) => cursor.read(buf),
+            Input::Mapped(cursor) => cursor.read(buf),
+        }
+    }
+}
+
+impl Seek for Input {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Input::File(file) => file.seek(pos),
+            Input::Memory(cursor) => cursor.seek(pos),
+            Input::Mapped(cursor) => cursor.seek(pos),
+        }
+    }
+}
+
+pub fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == STDIN_PATH
+}
+
+/// Length in bytes of the input at `path`, 0 if it can't be read
+pub fn length(path: &Path) -> u64 {
+    if is_stdin(path) {
+        return stdin_contents().map_or(0, |contents| contents.len() as u64);
+    }
+
+    fs::metadata(path).map_or(0, |metadata| metadata.len())
+}
+
+/// Whether the input at `path` ends in a newline. Empty inputs don't
+pub fn ends_with_newline(path: &Path) -> io::Result<bool> {
+    let mut input = Input::open(path)?;
+
+    if input.seek(SeekFrom::End(0))? == 0 {
+        return Ok(false);
+    }
+
+    let mut last = [0];
+    input.seek(SeekFrom::End(-1))?;
+    input.read_exact(&mut last)?;
+
+    Ok(last[0] == b'\n')
+}
+
+/// When the input at `path` was last modified. `None` for stdin, or if it can't be read
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    if is_stdin(path) {
+        return None;
+    }
+
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Whether the input at `path` looks like it isn't text, from its first few KB
+pub fn is_binary(path: &Path) -> io::Result<bool> {
+    let mut sample = Vec::with_capacity(BINARY_SAMPLE_LENGTH);
+
+    Input::open(path)?
+        .take(BINARY_SAMPLE_LENGTH as u64)
+        .read_to_end(&mut sample)?;
+
+    Ok(hex::looks_binary(&sample))
+}
+
+/// Deletes the decompressed copies of gzip files, which are kept until exit for later opens
+pub fn remove_decompressed() {
+    let decompressed = DECOMPRESSED.lock().unwrap_or_else(|err| err.into_inner());
+
+    for entry in decompressed.iter() {
+        let _ = fs::remove_file(&entry.temp_path);
+    }
+}
+
+/// Whether `file` is gzip compressed, by its extension or its first bytes. Leaves the file at
+/// its start
+fn is_gzip_file(path: &Path, file: &mut File) -> io::Result<bool> {
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        return Ok(true);
+    }
+
+    let mut magic = [0; 2];
+    let is_gzip = file.read_exact(&mut magic).is_ok() && gzip::is_gzip(&magic);
+
+    file.seek(SeekFrom::Start(0))?;
+
+    Ok(is_gzip)
+}
+
+/// The temporary file the gzip `file` at `path` is decompressed to, decompressing it if it
+/// hasn't been yet or has changed since
+fn decompressed_path(path: &Path, file: File) -> io::Result<PathBuf> {
+    let metadata = file.metadata()?;
+    let stamp = (metadata.len(), metadata.modified().ok());
+
+    let mut decompressed = DECOMPRESSED.lock().unwrap_or_else(|err| err.into_inner());
+    let previous = decompressed.iter().position(|entry| entry.source == path);
+
+    if let Some(entry) = previous.map(|index| &decompressed[index]) {
+        if entry.stamp == stamp {
+            return Ok(entry.temp_path.clone());
+        }
+    }
+
+    let (temp_path, temp_file) = create_temp_file()?;
+    let result = gzip::decompress(BufReader::new(file), BufWriter::new(temp_file));
+
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path);
+
+        return Err(io::Error::new(
+            err.kind(),
+            format!("could not decompress: {}", err),
+        ));
+    }
+
+    // Readers still open on the old copy keep reading it after it is removed, where the OS
+    // allows removing it
+    if let Some(index) = previous {
+        let _ = fs::remove_file(decompressed.remove(index).temp_path);
+    }
+
+    decompressed.push(Decompressed {
+        source: path.to_path_buf(),
+        stamp,
+        temp_path: temp_path.clone(),
+    });
+
+    Ok(temp_path)
+}
+
+/// Creates a temporary file only the user can read, under a random name so nothing can be
+/// waiting at it
+fn create_temp_file() -> io::Result<(PathBuf, File)> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+
+    let mut attempt = 0;
+
+    loop {
+        let temp_path = env::temp_dir().join(format!(
+            "trace-log-comparer-{}-{:016x}.log",
+            process::id(),
+            RandomState::new().build_hasher().finish()
+        ));
+
+        match options.open(&temp_path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < TEMP_ATTEMPTS => {
+                attempt += 1;
+            }
+            result => return result.map(|file| (temp_path, file)),
+        }
+    }
+}
+
+fn stdin_contents() -> io::Result<&'static Vec<u8>> {
+    if let Some(contents) = STDIN_CONTENTS.get() {
+        return Ok(contents);
+    }
+
+    let mut contents = Vec::new();
+    io::stdin().lock().read_to_end(&mut contents)?;
+
+    if gzip::is_gzip(&contents) {
+        let mut decompressed = Vec::new();
+        gzip::decompress(contents.as_slice(), &mut decompressed)?;
+
+        contents = decompressed;
+    }
+
+    Ok(STDIN_CONTENTS.get_or_init(|| contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `stored line\n` and `first member\n` compressed
+    const STORED_LINE: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x04\x03\x01\x0c\x00\xf3\xff\
+        stored line\n\xb8\x71\xe7\xd8\x0c\x00\x00\x00";
+    const FIRST_MEMBER: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x4b\xcb\x2c\x2a\x2e\x51\
+        \xc8\x4d\xcd\x4d\x4a\x2d\xe2\x02\x00\xa7\xf4\x85\x0a\x0d\x00\x00\x00";
+
+    fn read_input(path: &Path) -> String {
+        let mut text = String::new();
+        Input::open(path)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+
+        text
+    }
+
+    #[test]
+    fn gzip_file_is_decompressed_again_after_it_changes() {
+        let path = env::temp_dir().join(format!("trace-log-comparer-test-{}.gz", process::id()));
+
+        fs::write(&path, STORED_LINE).unwrap();
+        assert_eq!(read_input(&path), "stored line\n");
+
+        fs::write(&path, FIRST_MEMBER).unwrap();
+        assert_eq!(read_input(&path), "first member\n");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let temp_path = decompressed_path(&path, File::open(&path).unwrap()).unwrap();
+            let mode = fs::metadata(temp_path).unwrap().permissions().mode();
+
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        remove_decompressed();
+        fs::remove_file(&path).unwrap();
+    }
+}
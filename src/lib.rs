@@ -0,0 +1,30 @@
+//! Scanning, aligning, and diffing of two trace logs, shared by the viewer binary
+
+pub mod align;
+pub mod ansi;
+pub mod compare;
+pub mod filter;
+pub mod gzip;
+pub mod hex;
+pub mod html;
+pub mod input;
+pub mod mask;
+pub mod mmap;
+pub mod multiset;
+pub mod pairs;
+pub mod patch;
+pub mod positions;
+pub mod prefix;
+pub mod record;
+pub mod repeats;
+pub mod report;
+pub mod scan;
+pub mod state;
+pub mod string;
+pub mod style;
+pub mod svg;
+pub mod theme;
+pub mod view;
+
+pub use compare::diff_lines;
+pub use state::{DiffPosition, DiffSection, LineDiff};
@@ -1,151 +1,609 @@
 use std::{
-    env,
-    fs::File,
-    io::{self, BufRead, BufReader},
+    fs::{self, File},
+    io::{self, BufReader, Write},
     path::Path,
+    process,
 };
 
-use itertools::{EitherOrBoth, Itertools};
-use state::{DiffPosition, State};
-use ui::build_app;
+use acceleration::Acceleration;
+use action::Keymap;
+use args::{Args, OutputFormat};
+use clap::Parser;
+use trace_log_comparer::{
+    align::Alignment,
+    ansi, hex,
+    input::{self, Input},
+    multiset,
+    pairs::{self, FilePair},
+    prefix::StripPrefix,
+    record,
+    report::{self, PairResult},
+    scan::{self, index_files, DiffSummary, ScanOptions},
+    state, svg,
+    theme::{self, Theme},
+    view,
+};
+use ui::{build_app, Controls};
 
-mod state;
-mod string;
+mod acceleration;
+mod action;
+mod args;
+mod clipboard;
+mod minimap;
+mod three_way;
 mod ui;
+mod watch;
 
-fn main() -> Result<(), io::Error> {
-    let args: Vec<String> = env::args().collect();
+/// Number of lines rendered around the first diff when exporting without an explicit range
+const EXPORT_LINE_COUNT: usize = 40;
 
-    if args.len() < 3 {
-        println!("trace-log-comparer expects two arguments, one for each file.");
-        println!("Received {} arguments.", args.len());
-        return Ok(());
+fn main() {
+    let result = run();
+
+    input::remove_decompressed();
+
+    // Exit with 2 on trouble, like `diff`, as 1 means the files differ
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        process::exit(2);
+    }
+}
+
+fn run() -> io::Result<()> {
+    let args = Args::parse();
+
+    if let Some(log_file) = &args.log_file {
+        init_logging(log_file)?;
     }
 
-    let file1_path = &args[1];
-    let file2_path = &args[2];
-    let skip_past_line = if args.len() > 3 {
-        args[3]
-            .parse::<usize>()
-            .expect("Could not parse line number.")
+    let pairs = if let Some(dirs) = &args.dir {
+        let pairing = pairs::pair_directories(&dirs[0], &dirs[1])?;
+
+        for (dir, paths) in [
+            (&dirs[0], &pairing.only_in_first),
+            (&dirs[1], &pairing.only_in_second),
+        ] {
+            for path in paths {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                println!("Only in {}: {}", dir.display(), name);
+            }
+        }
+
+        pairing.pairs
+    } else if let Some(manifest_path) = &args.manifest {
+        let manifest = pairs::read_manifest(manifest_path)?;
+
+        for (line_number, line) in &manifest.malformed_lines {
+            eprintln!(
+                "Skipping line {} of {}, which is not FILE1<TAB>FILE2: {}",
+                line_number,
+                manifest_path.display(),
+                line
+            );
+        }
+
+        manifest.pairs
     } else {
-        0
+        // Both positionals are required by clap when `--dir` and `--manifest` are absent
+        vec![FilePair {
+            file1: args.file1.clone().unwrap(),
+            file2: args.file2.clone().unwrap(),
+        }]
     };
 
-    let mut file1_reader = buf_reader(file1_path).expect("Could not open file 1");
-    let mut file2_reader = buf_reader(file2_path).expect("Could not open file 2");
+    if pairs.is_empty() {
+        println!("No file pairs to compare");
+        return Ok(());
+    }
 
-    let mut line_index = 0;
+    // Lines of binary files are whatever falls between newline bytes, and usually not UTF-8
+    if !args.hex && args.record_width.is_none() {
+        for path in pairs.iter().flat_map(|pair| [&pair.file1, &pair.file2]) {
+            if input::is_binary(path)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} looks like a binary file. Compare it with --hex, or split it into \
+                         fixed-width records with --record-width",
+                        path.display()
+                    ),
+                ));
+            }
+        }
+    }
 
-    let mut file1_line_positions = Vec::new();
-    let mut file2_line_positions = Vec::new();
+    let theme = read_theme(&args);
 
-    let mut first_diff_positions = None;
+    if let Some((line1, line2)) = args.lines {
+        return print_line_pair(&pairs[0], line1, line2, &args, &theme);
+    }
 
-    let mut line1 = String::new();
-    let mut line2 = String::new();
+    if args.sort {
+        for pair in &pairs {
+            print_multiset_diff(pair, &args, pairs.len() > 1)?;
+        }
 
-    let mut file1_offset = 0;
-    let mut file2_offset = 0;
+        return Ok(());
+    }
 
-    let mut extra_line_count = 20;
+    let scan_options = ScanOptions {
+        skip_past_line: args.skip_past_line.unwrap_or(0),
+        open_at: args.open_at.clone(),
+        diff_options: args.diff_options(),
+        record_width: args.record_width.or(args.hex.then_some(hex::BYTES_PER_ROW)),
+        hex: args.hex,
+        time_filter: args.time_filter(),
+        strip_prefix: args.strip_prefix(),
+        context: args.context,
+        resume_path: args.resume.clone(),
+        memory_map: args.mmap,
+        compact_index_above: args.compact_index_above,
+        line_range: args.range.map(|range| range.start - 1..range.end),
+    };
 
-    let mut file1_result = file1_reader.read_line(&mut line1);
-    let mut file2_result = file2_reader.read_line(&mut line2);
+    if args.quiet {
+        let mut differ = false;
 
-    while let (Ok(line1_length), Ok(line2_length)) = (file1_result.as_ref(), file2_result.as_ref())
-    {
-        if *line1_length == 0 || *line2_length == 0 {
-            if extra_line_count > 0 {
-                // Load extra lines after the end of the shorter file
-                extra_line_count -= 1;
-            } else {
-                break;
+        for pair in &pairs {
+            if scan::first_divergence(&pair.file1, &pair.file2, &scan_options)?.is_some()
+                || !scan::final_newlines_match(&pair.file1, &pair.file2, &scan_options)?
+            {
+                println!(
+                    "Files {} and {} differ",
+                    pair.file1.display(),
+                    pair.file2.display()
+                );
+                differ = true;
             }
         }
 
-        if line_index + 1 > skip_past_line && line1 != line2 && first_diff_positions.is_none() {
-            let find_offset = || -> usize {
-                for (offset, combined_chars) in line1.chars().zip_longest(line2.chars()).enumerate()
-                {
-                    match combined_chars {
-                        EitherOrBoth::Both(char1, char2) => {
-                            if char1 != char2 {
-                                return offset;
-                            }
-                        }
-                        EitherOrBoth::Left(_) | EitherOrBoth::Right(_) => {
-                            return offset;
-                        }
+        if differ {
+            exit_differ()?;
+        }
+
+        return Ok(());
+    }
+
+    if args.junit.is_some() || args.tap {
+        let results = pairs
+            .iter()
+            .map(|pair| {
+                let mut first_diff =
+                    scan::first_divergence(&pair.file1, &pair.file2, &scan_options)?;
+
+                // Reported with the line numbers of the whole files
+                if let (Some(diff), Some(range)) = (&mut first_diff, &scan_options.line_range) {
+                    diff.position.line_index += range.start;
+                }
+
+                Ok(PairResult { pair, first_diff })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        if let Some(junit_path) = &args.junit {
+            fs::write(junit_path, report::junit(&results))?;
+        }
+
+        if args.tap {
+            print!("{}", report::tap(&results));
+        }
+
+        return Ok(());
+    }
+
+    let load_pair = |pair: &FilePair, scan_options: &ScanOptions| {
+        let (mut state, summary) = index_files(&pair.file1, &pair.file2, scan_options)?;
+
+        state.record_width = scan_options.record_width;
+        state.skipped_lines = scan_options
+            .line_range
+            .as_ref()
+            .map_or(0, |range| range.start);
+        state.hex = args.hex;
+        state.max_rows = args.max_rows;
+        state.context = args.context;
+        state.search_wraps = args.search_wrap;
+        state.max_columns = args.max_col;
+        state.max_line_width = args.max_line_width;
+        state.diff_options = args.diff_options();
+        state.alignment = args.align;
+        state.resync_window = args.resync_window;
+        state.collapse_repeats = args.collapse_repeats;
+        state.only_diffs = args.only_diffs.is_some();
+        state.only_diffs_context = args.only_diffs.unwrap_or(state::DEFAULT_ONLY_DIFFS_CONTEXT);
+        state.fold_identical = args.fold_identical;
+        state.start_line = args
+            .start_line
+            .map(|line| line.saturating_sub(state.line_number(0)));
+        state.start_diff = args.start_diff;
+        state.show_whitespace = args.show_whitespace;
+        state.tab_width = args.tab_width;
+        state.context_chars = args.context_chars;
+        state.strip_prefix = args.strip_prefix();
+        state.keep_prefix = args.keep_prefix();
+        state.style = args.diff_style(&theme);
+        state.gutter.markers = args.colorblind;
+        state.gutter.offsets = args.show_offsets;
+        state.gutter.number_base = args.number_base;
+
+        io::Result::Ok((state, summary))
+    };
+
+    // Writing out the whole diff needs every line of the longer file indexed, not just the first
+    // few past the end of the shorter one
+    let whole_files = ScanOptions {
+        context: usize::MAX,
+        ..scan_options.clone()
+    };
+
+    if let Some(format) = args.output {
+        let mut differ = false;
+        let mut stdout = io::stdout().lock();
+
+        for pair in &pairs {
+            let (mut state, _) = load_pair(pair, &whole_files)?;
+
+            match format {
+                OutputFormat::Unified => {
+                    if let Some(patch) =
+                        state.unified_diff(&pair.file1, &pair.file2, args.hunk_context)?
+                    {
+                        write!(stdout, "{}", patch)?;
+                        differ = true;
                     }
                 }
+                OutputFormat::Json => {
+                    let mut out = io::BufWriter::new(&mut stdout);
 
-                return 0;
-            };
+                    differ |= !state.write_json(&mut out, &pair.file1, &pair.file2)?;
+                    out.flush()?;
+                }
+                OutputFormat::Html => {
+                    let mut out = io::BufWriter::new(&mut stdout);
 
-            first_diff_positions = Some(DiffPosition {
-                line_index,
-                line_offset: find_offset(),
-                file1_offset,
-                file2_offset,
-            });
+                    differ |= !state.write_html(&mut out, &pair.file1, &pair.file2)?;
+                    out.flush()?;
+                }
+            }
         }
 
-        if *line1_length > 0 {
-            file1_line_positions.push(file1_offset);
+        if differ {
+            drop(stdout);
+            exit_differ()?;
         }
 
-        if *line2_length > 0 {
-            file2_line_positions.push(file2_offset);
+        return Ok(());
+    }
+
+    if args.quiet_identical {
+        for pair in &pairs {
+            let (mut state, _) = load_pair(pair, &whole_files)?;
+
+            if pairs.len() > 1 {
+                println!("{} <> {}", pair.file1.display(), pair.file2.display());
+            }
+
+            let mut ranges = LineRanges::default();
+
+            state.visit_changed_line_numbers(|line_number| {
+                if let Some(range) = ranges.push(line_number) {
+                    println!("{}", range);
+                }
+
+                Ok(())
+            })?;
+
+            if let Some(range) = ranges.finish() {
+                println!("{}", range);
+            }
+        }
+
+        return Ok(());
+    }
+
+    let controls = Controls {
+        acceleration: Acceleration {
+            base_step: args.scroll_step,
+            repeats_per_doubling: args.scroll_acceleration,
+            max_step: args.max_scroll_step,
+        },
+        keymap: Keymap::new(args.quit_key),
+        confirm_quit: args.confirm_quit,
+        status: !args.no_status,
+        diff_wrap: args.diff_wrap,
+        watch: args.watch,
+        saved_views: args.remember_view.then(view::default_path).flatten(),
+        link_changes: !args.no_link_changes,
+        selected_symbol: args
+            .selected_symbol
+            .clone()
+            .filter(|symbol| !symbol.is_empty()),
+    };
+
+    if let Some(third) = &args.third {
+        let load_variant = |file2: &Path| {
+            let (mut state, _) = load_pair(
+                &FilePair {
+                    file1: pairs[0].file1.clone(),
+                    file2: file2.to_path_buf(),
+                },
+                &scan_options,
+            )?;
+
+            // Rows of both variants have to line up with the same base lines
+            state.alignment = Alignment::None;
+            state.resync_window = None;
+            state.collapse_repeats = false;
+            state.only_diffs = false;
+            state.fold_identical = None;
+
+            io::Result::Ok(state)
+        };
+
+        let first = load_variant(&pairs[0].file2)?;
+        let second = load_variant(third)?;
+
+        return three_way::run_three_way(
+            first,
+            second,
+            [&pairs[0].file1, &pairs[0].file2, third],
+            controls,
+        );
+    }
+
+    let (mut state, summary) = load_pair(&pairs[0], &scan_options)?;
+
+    if summary.open_at_missed {
+        if let Some(query) = &args.open_at {
+            eprintln!(
+                "Warning: no differing line contains `{}`, opening at the first diff",
+                query
+            );
         }
+    }
 
-        file1_offset += line1_length;
-        file2_offset += line2_length;
+    if let Some(svg_path) = &args.svg {
+        let (center_line_index, line_count) = match args.svg_range {
+            Some(range) => {
+                let line_count = range.end - range.start + 1;
+                (
+                    range.start.saturating_sub(state.line_number(0)) + line_count / 2,
+                    line_count,
+                )
+            }
+            None => (
+                state.first_diff().map_or(0, |diff| diff.line_index),
+                EXPORT_LINE_COUNT,
+            ),
+        };
+
+        state.load_lines_around_line(center_line_index, line_count);
 
-        line_index += 1;
+        let (file1_spans, file2_spans) = state.spans();
+        let (file1_numbers, file2_numbers) = state.line_numbers();
 
-        line1.clear();
-        line2.clear();
+        fs::write(
+            svg_path,
+            svg::render_svg([file1_spans, file2_spans], [&file1_numbers, &file2_numbers]),
+        )?;
 
-        file1_result = file1_reader.read_line(&mut line1);
-        file2_result = file2_reader.read_line(&mut line2);
+        return Ok(());
     }
 
-    let line1_length = if let Ok(length) = file1_result {
-        length
+    state.align_files(scan_options.skip_past_line)?;
+
+    println!("{}", summary.longer_file.message());
+
+    if let Some(hint) = summary.line_balance.swap_hint() {
+        println!("{}", hint);
+    }
+
+    let state = build_app(state, &pairs, controls, |pair| {
+        let (mut state, _) = load_pair(pair, &scan_options)?;
+        state.align_files(scan_options.skip_past_line)?;
+
+        Ok(state)
+    })?;
+
+    print_diff_summary(&state.diff_summary, state.skipped_lines);
+
+    Ok(())
+}
+
+/// Prints how many lines of the files differ, and where they first do. `skipped_lines` come
+/// before the indexed ones in the files
+fn print_diff_summary(summary: &DiffSummary, skipped_lines: usize) {
+    if summary.differing() == 0 {
+        println!("Files are identical");
+        return;
+    }
+
+    println!(
+        "{} lines differ: {} modified, {} added, {} removed",
+        summary.differing(),
+        summary.modified,
+        summary.added,
+        summary.removed
+    );
+
+    // Unset when the lines of the longer file past the shorter one weren't indexed
+    if let Some(first_diff) = &summary.first_diff {
+        println!(
+            "First divergence at line {} column {} (file offsets {}, {})",
+            skipped_lines + first_diff.line_index + 1,
+            first_diff.line_offset + 1,
+            first_diff.file1_offset,
+            first_diff.file2_offset
+        );
+    }
+}
+
+/// Exits with status 1 like `diff` does when the files differ, so scripts can tell the outcome
+/// apart
+fn exit_differ() -> io::Result<()> {
+    io::stdout().flush()?;
+    input::remove_decompressed();
+
+    process::exit(1);
+}
+
+/// Gathers ascending line numbers into runs of consecutive lines, formatted `N` or `START-END`
+#[derive(Default)]
+struct LineRanges {
+    run: Option<(usize, usize)>,
+}
+
+impl LineRanges {
+    /// Adds `line_number`, returning the run it ends if it doesn't continue one
+    fn push(&mut self, line_number: usize) -> Option<String> {
+        match &mut self.run {
+            Some((_, end)) if *end + 1 == line_number => {
+                *end = line_number;
+                None
+            }
+            run => run
+                .replace((line_number, line_number))
+                .map(|(start, end)| format_range(start, end)),
+        }
+    }
+
+    /// The last run, if any line was added
+    fn finish(self) -> Option<String> {
+        self.run.map(|(start, end)| format_range(start, end))
+    }
+}
+
+fn format_range(start: usize, end: usize) -> String {
+    if start == end {
+        start.to_string()
     } else {
-        0
+        format!("{}-{}", start, end)
+    }
+}
+
+/// Prints the diff of one line from each file of `pair`, in the viewer's colors
+fn print_line_pair(
+    pair: &FilePair,
+    line1: usize,
+    line2: usize,
+    args: &Args,
+    theme: &Theme,
+) -> io::Result<()> {
+    let read_line = |path: &Path, line_number: usize| -> io::Result<String> {
+        let mut reader = BufReader::new(Input::open(path)?);
+
+        record::read_nth_record(&mut reader, line_number - 1, args.record_width)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} has no line {}", path.display(), line_number),
+            )
+        })
     };
 
-    let line2_length = if let Ok(length) = file2_result {
-        length
+    let (text1, text2) = (
+        read_line(&pair.file1, line1)?,
+        read_line(&pair.file2, line2)?,
+    );
+
+    let strip_prefix = args.strip_prefix();
+
+    let (prefix1, rest1) = StripPrefix::split_optional(strip_prefix.as_ref(), &text1, true);
+    let (prefix2, rest2) = StripPrefix::split_optional(strip_prefix.as_ref(), &text2, false);
+
+    let (file1_spans, file2_spans) =
+        state::line_pair_spans(rest1, rest2, &args.diff_options(), &args.diff_style(theme));
+
+    let (prefix1, prefix2) = if args.keep_prefix() {
+        (prefix1, prefix2)
     } else {
-        0
+        ("", "")
     };
 
-    if line1_length == 0 && line2_length == 0 {
-        println!("Both files are the same length");
-    } else if line1_length == 0 {
-        println!("File 2 is longer");
-    } else {
-        println!("File 1 is longer");
+    let width = line1.max(line2).to_string().len();
+
+    println!(
+        "{:>width$} {}{}",
+        line1,
+        prefix1,
+        ansi::render_ansi(&file1_spans)
+    );
+    println!(
+        "{:>width$} {}{}",
+        line2,
+        prefix2,
+        ansi::render_ansi(&file2_spans)
+    );
+
+    Ok(())
+}
+
+/// Prints the lines each file of `pair` has more of than the other, `diff` style
+fn print_multiset_diff(pair: &FilePair, args: &Args, print_header: bool) -> io::Result<()> {
+    let diff = multiset::multiset_diff(
+        &pair.file1,
+        &pair.file2,
+        args.record_width,
+        args.strip_prefix().as_ref(),
+    )?;
+
+    if print_header {
+        println!("{} <> {}", pair.file1.display(), pair.file2.display());
     }
 
-    build_app(State::new(
-        first_diff_positions,
-        file1_line_positions,
-        file2_line_positions,
-        file1_reader,
-        file2_reader,
-    ))?;
+    if diff.is_empty() {
+        println!("Files contain the same lines");
+        return Ok(());
+    }
+
+    for (marker, lines) in [("<", &diff.only_in_file1), (">", &diff.only_in_file2)] {
+        for (line, count) in lines {
+            if *count > 1 {
+                println!("{} {} (× {})", marker, line, count);
+            } else {
+                println!("{} {}", marker, line);
+            }
+        }
+    }
 
     Ok(())
 }
 
-fn buf_reader<'a, P>(filename: P) -> io::Result<BufReader<File>>
-where
-    P: AsRef<Path>,
-{
-    let file = File::open(filename)?;
-    Ok(BufReader::new(file))
+/// Reads the `--theme` file, or the default one, reporting anything wrong with it. The theme is
+/// empty, using the default colors, when there's no file
+fn read_theme(args: &Args) -> Theme {
+    let Some(path) = args.theme.clone().or_else(theme::default_path) else {
+        return Theme::default();
+    };
+
+    match theme::read_theme(&path) {
+        Ok(Some((theme, problems))) => {
+            for problem in problems {
+                eprintln!("Ignoring {} {}", path.display(), problem);
+            }
+
+            theme
+        }
+        Ok(None) => {
+            if args.theme.is_some() {
+                eprintln!("Theme {} does not exist", path.display());
+            }
+
+            Theme::default()
+        }
+        Err(err) => {
+            eprintln!("Could not read theme {}: {}", path.display(), err);
+
+            Theme::default()
+        }
+    }
+}
+
+fn init_logging(path: &Path) -> io::Result<()> {
+    let file = File::create(path)?;
+
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .target(env_logger::Target::Pipe(Box::new(file)))
+        .init();
+
+    Ok(())
 }
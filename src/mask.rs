@@ -0,0 +1,99 @@
+use std::{borrow::Cow, ops::Range};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// What masked text is compared as, so any two masked runs are equal
+const PLACEHOLDER: char = '\u{fffc}';
+
+/// Patterns for text that is expected to vary between runs, like cycle counts or addresses, and
+/// is left out of the comparison
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(into = "Vec<String>", try_from = "Vec<String>")]
+pub struct Masks(pub Vec<Regex>);
+
+impl Masks {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Byte ranges of the masked text of `line`, in order. Matches of every pattern are merged
+    /// where they overlap or touch, so the result doesn't depend on the order of the patterns
+    pub fn ranges(&self, line: &str) -> Vec<Range<usize>> {
+        let mut matches: Vec<Range<usize>> = self
+            .0
+            .iter()
+            .flat_map(|pattern| pattern.find_iter(line).map(|found| found.range()))
+            .filter(|range| !range.is_empty())
+            .collect();
+
+        matches.sort_by_key(|range| (range.start, range.end));
+
+        let mut ranges: Vec<Range<usize>> = vec![];
+
+        for range in matches {
+            match ranges.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => ranges.push(range),
+            }
+        }
+
+        ranges
+    }
+
+    /// `line` with each run of masked text replaced by a placeholder, as it is compared
+    pub fn apply<'l>(&self, line: &'l str) -> Cow<'l, str> {
+        let ranges = self.ranges(line);
+
+        if ranges.is_empty() {
+            return Cow::Borrowed(line);
+        }
+
+        let mut masked = String::with_capacity(line.len());
+        let mut end = 0;
+
+        for range in ranges {
+            masked.push_str(&line[end..range.start]);
+            masked.push(PLACEHOLDER);
+            end = range.end;
+        }
+
+        masked.push_str(&line[end..]);
+
+        Cow::Owned(masked)
+    }
+}
+
+/// Masks are the same when their patterns are
+impl PartialEq for Masks {
+    fn eq(&self, other: &Self) -> bool {
+        self.0
+            .iter()
+            .map(Regex::as_str)
+            .eq(other.0.iter().map(Regex::as_str))
+    }
+}
+
+impl Eq for Masks {}
+
+impl From<Masks> for Vec<String> {
+    fn from(masks: Masks) -> Self {
+        masks
+            .0
+            .iter()
+            .map(|pattern| pattern.as_str().to_string())
+            .collect()
+    }
+}
+
+impl TryFrom<Vec<String>> for Masks {
+    type Error = regex::Error;
+
+    fn try_from(patterns: Vec<String>) -> Result<Self, Self::Error> {
+        patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Masks)
+    }
+}
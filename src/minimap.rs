@@ -0,0 +1,71 @@
+use std::ops::Range;
+
+use tui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    widgets::Widget,
+};
+
+/// A column standing for the whole length of the files, top to bottom, with a tick on every row
+/// covering a differing line and the lines on screen highlighted
+pub struct Minimap<'a> {
+    /// Indices of the differing lines, ascending
+    pub diff_lines: &'a [usize],
+    pub line_count: usize,
+    /// Lines shown in the panes
+    pub viewport: Range<usize>,
+}
+
+impl Minimap<'_> {
+    /// The lines covered by `row` of a map `height` rows tall
+    fn row_lines(&self, row: usize, height: usize) -> Range<usize> {
+        let start = row * self.line_count / height;
+        // Every row covers a line at least, when the files are shorter than the map
+        let end = ((row + 1) * self.line_count / height).max(start + 1);
+
+        start..end
+    }
+}
+
+impl Widget for Minimap<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let height = area.height as usize;
+
+        if height == 0 || self.line_count == 0 {
+            return;
+        }
+
+        for row in 0..height {
+            let lines = self.row_lines(row, height);
+
+            if lines.start >= self.line_count {
+                break;
+            }
+
+            let first_diff = self.diff_lines.partition_point(|&line| line < lines.start);
+            let has_diff = self
+                .diff_lines
+                .get(first_diff)
+                .is_some_and(|&line| line < lines.end);
+
+            let on_screen = lines.start < self.viewport.end && self.viewport.start < lines.end;
+
+            let (symbol, mut style) = if has_diff {
+                ("■", Style::default().fg(Color::Red))
+            } else {
+                ("│", Style::default().add_modifier(Modifier::DIM))
+            };
+
+            if on_screen {
+                style = style.bg(Color::DarkGray);
+            }
+
+            for x in area.left()..area.right() {
+                buf.get_mut(x, area.top() + row as u16)
+                    .set_symbol(symbol)
+                    .set_style(style);
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    io::{self, BufReader},
+    path::Path,
+};
+
+use crate::{input::Input, prefix::StripPrefix, record::read_record};
+
+/// Lines that occur more often in one file than the other, ignoring their order
+pub struct MultisetDiff {
+    /// Each line and how many more times it occurs in file 1, sorted by line
+    pub only_in_file1: Vec<(String, usize)>,
+    /// Each line and how many more times it occurs in file 2, sorted by line
+    pub only_in_file2: Vec<(String, usize)>,
+}
+
+impl MultisetDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_file1.is_empty() && self.only_in_file2.is_empty()
+    }
+}
+
+/// Compares the files as multisets of lines, so two files with the same lines in any order are
+/// equal. Lines are compared without their `strip_prefix`
+pub fn multiset_diff(
+    file1_path: &Path,
+    file2_path: &Path,
+    record_width: Option<usize>,
+    strip_prefix: Option<&StripPrefix>,
+) -> io::Result<MultisetDiff> {
+    // Occurrences in file 1 less occurrences in file 2
+    let mut counts: BTreeMap<String, isize> = BTreeMap::new();
+
+    for (path, delta, file1) in [(file1_path, 1, true), (file2_path, -1, false)] {
+        let mut reader = BufReader::new(Input::open(path)?);
+        let mut line = String::new();
+
+        while read_record(&mut reader, &mut line, record_width)? > 0 {
+            // A missing newline on the last line doesn't make it a different line
+            let trimmed = if record_width.is_none() {
+                line.trim_end_matches(['\n', '\r'])
+            } else {
+                &line
+            };
+
+            let (_, trimmed) = StripPrefix::split_optional(strip_prefix, trimmed, file1);
+
+            *counts.entry(trimmed.to_string()).or_default() += delta;
+            line.clear();
+        }
+    }
+
+    let mut diff = MultisetDiff {
+        only_in_file1: vec![],
+        only_in_file2: vec![],
+    };
+
+    for (line, count) in counts {
+        match count.cmp(&0) {
+            Ordering::Greater => diff.only_in_file1.push((line, count as usize)),
+            Ordering::Less => diff.only_in_file2.push((line, count.unsigned_abs())),
+            Ordering::Equal => {}
+        }
+    }
+
+    Ok(diff)
+}
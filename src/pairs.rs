@@ -0,0 +1,91 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub struct FilePair {
+    pub file1: PathBuf,
+    pub file2: PathBuf,
+}
+
+pub struct DirectoryPairing {
+    pub pairs: Vec<FilePair>,
+    pub only_in_first: Vec<PathBuf>,
+    pub only_in_second: Vec<PathBuf>,
+}
+
+/// Pairs up files with the same name in both directories, sorted by name
+pub fn pair_directories(dir1: &Path, dir2: &Path) -> io::Result<DirectoryPairing> {
+    let mut files1 = list_files(dir1)?;
+    let files2 = list_files(dir2)?;
+
+    let mut pairs = vec![];
+    let mut only_in_second = vec![];
+
+    for (name, file2) in files2 {
+        if let Some(file1) = files1.remove(&name) {
+            pairs.push(FilePair { file1, file2 });
+        } else {
+            only_in_second.push(file2);
+        }
+    }
+
+    Ok(DirectoryPairing {
+        pairs,
+        only_in_first: files1.into_values().collect(),
+        only_in_second,
+    })
+}
+
+pub struct Manifest {
+    pub pairs: Vec<FilePair>,
+    /// 1-based line number and contents of each line that isn't a pair
+    pub malformed_lines: Vec<(usize, String)>,
+}
+
+/// Reads a manifest with one `file1<TAB>file2` pair per line. Blank lines are ignored, and
+/// relative paths are relative to the manifest's directory
+pub fn read_manifest(path: &Path) -> io::Result<Manifest> {
+    let contents = fs::read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut pairs = vec![];
+    let mut malformed_lines = vec![];
+
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+
+        match fields[..] {
+            [file1, file2] if !file1.is_empty() && !file2.is_empty() => pairs.push(FilePair {
+                file1: base.join(file1),
+                file2: base.join(file2),
+            }),
+            _ => malformed_lines.push((index + 1, line.to_string())),
+        }
+    }
+
+    Ok(Manifest {
+        pairs,
+        malformed_lines,
+    })
+}
+
+fn list_files(dir: &Path) -> io::Result<BTreeMap<OsString, PathBuf>> {
+    let mut files = BTreeMap::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+
+        if entry.file_type()?.is_file() {
+            files.insert(entry.file_name(), entry.path());
+        }
+    }
+
+    Ok(files)
+}
@@ -0,0 +1,207 @@
+use std::{collections::VecDeque, fmt::Write};
+
+use crate::{align::Row, state::LineDiff};
+
+/// One row of a unified diff: the whole line of each file that has one in it, and their diff
+pub struct PatchRow {
+    pub file1_line: Option<String>,
+    pub file2_line: Option<String>,
+    pub line_diffs: LineDiff,
+}
+
+impl PatchRow {
+    fn is_change(&self) -> bool {
+        self.file1_line.is_none()
+            || self.file2_line.is_none()
+            || self
+                .line_diffs
+                .sections()
+                .iter()
+                .any(|diff| diff.is_change())
+    }
+}
+
+/// Builds the hunks of a unified diff from its rows, pushed in order, with up to `context`
+/// unchanged rows around the changed ones. Changes whose context would overlap or touch share a
+/// hunk. Only the rows of the hunk being built are kept
+pub struct Hunks {
+    context: usize,
+    out: String,
+    /// The rows of the hunk being built, or before its first change, the unchanged rows that
+    /// would lead up to it
+    rows: VecDeque<PatchRow>,
+    /// Set once `rows` has a change
+    changed: bool,
+    /// Unchanged rows at the end of `rows`
+    unchanged_run: usize,
+    /// 1-based line numbers of the first line of each file in `rows`
+    file1_start: usize,
+    file2_start: usize,
+}
+
+impl Hunks {
+    /// Hunks of rows starting at the 1-based lines `file1_start` and `file2_start`
+    pub fn new(context: usize, file1_start: usize, file2_start: usize) -> Self {
+        Hunks {
+            context,
+            out: String::new(),
+            rows: VecDeque::new(),
+            changed: false,
+            unchanged_run: 0,
+            file1_start,
+            file2_start,
+        }
+    }
+
+    pub fn push(&mut self, row: PatchRow) {
+        let is_change = row.is_change();
+
+        self.rows.push_back(row);
+
+        if is_change {
+            self.changed = true;
+            self.unchanged_run = 0;
+
+            return;
+        }
+
+        self.unchanged_run += 1;
+
+        if self.changed && self.unchanged_run > 2 * self.context {
+            // No later change can reach this hunk with its context anymore
+            self.write_hunk(self.rows.len() - (self.unchanged_run - self.context));
+        }
+
+        while !self.changed && self.rows.len() > self.context {
+            self.drop_front();
+        }
+    }
+
+    /// The hunks written, `None` if no row was changed
+    pub fn finish(mut self) -> Option<String> {
+        if self.changed {
+            let trailing = self.unchanged_run.min(self.context);
+
+            self.write_hunk(self.rows.len() - (self.unchanged_run - trailing));
+        }
+
+        (!self.out.is_empty()).then_some(self.out)
+    }
+
+    /// Writes the first `count` rows as a hunk, dropping them
+    fn write_hunk(&mut self, count: usize) {
+        let mut rows = vec![];
+        let mut line_diffs = vec![];
+        let mut file1_lines = vec![];
+        let mut file2_lines = vec![];
+
+        for row in self.rows.iter().take(count) {
+            rows.push(Row {
+                file1: row.file1_line.as_ref().map(|_| file1_lines.len()),
+                file2: row.file2_line.as_ref().map(|_| file2_lines.len()),
+            });
+            line_diffs.push(row.line_diffs.clone());
+            file1_lines.extend(row.file1_line.clone());
+            file2_lines.extend(row.file2_line.clone());
+        }
+
+        write_hunk(
+            &mut self.out,
+            &rows,
+            &line_diffs,
+            &file1_lines,
+            &file2_lines,
+            self.file1_start,
+            self.file2_start,
+        );
+
+        for _ in 0..count {
+            self.drop_front();
+        }
+
+        self.changed = false;
+    }
+
+    fn drop_front(&mut self) {
+        if let Some(row) = self.rows.pop_front() {
+            self.file1_start += usize::from(row.file1_line.is_some());
+            self.file2_start += usize::from(row.file2_line.is_some());
+        }
+    }
+}
+
+/// Writes the aligned `rows` as one unified diff hunk, with `file1_start` and `file2_start` being
+/// the 1-based line numbers of the first lines in `file1_lines` and `file2_lines`
+pub fn write_hunk(
+    out: &mut String,
+    rows: &[Row],
+    line_diffs: &[LineDiff],
+    file1_lines: &[String],
+    file2_lines: &[String],
+    file1_start: usize,
+    file2_start: usize,
+) {
+    let file1_count = rows.iter().filter(|row| row.file1.is_some()).count();
+    let file2_count = rows.iter().filter(|row| row.file2.is_some()).count();
+
+    let _ = writeln!(
+        out,
+        "@@ -{} +{} @@",
+        hunk_range(file1_start, file1_count),
+        hunk_range(file2_start, file2_count)
+    );
+
+    // Changed lines are buffered so each run lists all its removals before its additions
+    let mut removed = vec![];
+    let mut added = vec![];
+
+    for (row, line_diffs) in rows.iter().zip(line_diffs) {
+        let unchanged = row.file1.is_some()
+            && row.file2.is_some()
+            && !line_diffs.sections().iter().any(|diff| diff.is_change());
+
+        if unchanged {
+            flush_changes(out, &mut removed, &mut added);
+
+            if let Some(index) = row.file1 {
+                write_line(out, ' ', &file1_lines[index]);
+            }
+        } else {
+            removed.extend(row.file1.map(|index| &file1_lines[index]));
+            added.extend(row.file2.map(|index| &file2_lines[index]));
+        }
+    }
+
+    flush_changes(out, &mut removed, &mut added);
+}
+
+fn flush_changes(out: &mut String, removed: &mut Vec<&String>, added: &mut Vec<&String>) {
+    for line in removed.drain(..) {
+        write_line(out, '-', line);
+    }
+
+    for line in added.drain(..) {
+        write_line(out, '+', line);
+    }
+}
+
+fn write_line(out: &mut String, marker: char, line: &str) {
+    out.push(marker);
+
+    if line.ends_with('\n') {
+        out.push_str(line);
+    } else {
+        out.push_str(line);
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// `start,count` as written in a hunk header. An empty range is written as starting at the line
+/// before it
+fn hunk_range(start: usize, count: usize) -> String {
+    if count == 0 {
+        format!("{},0", start.saturating_sub(1))
+    } else {
+        format!("{},{}", start, count)
+    }
+}
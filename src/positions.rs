@@ -0,0 +1,230 @@
+use std::{fmt, iter, mem};
+
+use serde::{
+    de::{SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+/// Lines of a file above which its offsets are stored compactly by default. As many dense
+/// offsets take 80 MB
+pub const DEFAULT_COMPACT_ABOVE: usize = 10_000_000;
+
+/// Lines in each block of a compact index, of which only the first has its offset kept whole
+const BLOCK_LINES: usize = 64;
+
+/// The byte offset of each line of a file. Up to `compact_above` lines every offset is kept
+/// whole, in 8 bytes a line. Past that the index turns compact: it keeps the offset of every 64th
+/// line, and the length of each line in between in as few bytes as it fits in. Lines under
+/// 16 KB then take at most 2 bytes each, a quarter of the memory, but looking up an offset adds
+/// up to 63 lengths instead of reading it directly
+#[derive(Clone, Debug)]
+pub struct LinePositions {
+    /// Every offset, while the index is dense
+    dense: Vec<usize>,
+    /// The offset of the first line of each block, and where the lengths of the rest of its
+    /// lines start in `lengths`, once the index is compact
+    blocks: Vec<(usize, usize)>,
+    /// LEB128 lengths of the lines of each block after its first
+    lengths: Vec<u8>,
+    compact: bool,
+    len: usize,
+    /// Offset of the last line, which the next line's length is counted from
+    last: Option<usize>,
+    compact_above: usize,
+}
+
+impl Default for LinePositions {
+    fn default() -> Self {
+        LinePositions::new(DEFAULT_COMPACT_ABOVE)
+    }
+}
+
+impl LinePositions {
+    /// A dense index turning compact once it has more than `compact_above` lines
+    pub fn new(compact_above: usize) -> Self {
+        LinePositions {
+            dense: Vec::new(),
+            blocks: Vec::new(),
+            lengths: Vec::new(),
+            compact: false,
+            len: 0,
+            last: None,
+            compact_above,
+        }
+    }
+
+    /// Changes the line count above which the index is compact, turning it compact now if it
+    /// already has more lines
+    pub fn set_compact_above(&mut self, compact_above: usize) {
+        self.compact_above = compact_above;
+        self.compact_if_large();
+    }
+
+    /// Adds the offset of the next line, which comes after every offset already added
+    pub fn push(&mut self, offset: usize) {
+        if self.compact {
+            self.push_compact(offset);
+        } else {
+            self.dense.push(offset);
+            self.len += 1;
+            self.last = Some(offset);
+
+            self.compact_if_large();
+        }
+    }
+
+    /// The offset of 0-based line `index`
+    pub fn get(&self, index: usize) -> Option<usize> {
+        if !self.compact {
+            return self.dense.get(index).copied();
+        }
+
+        if index >= self.len {
+            return None;
+        }
+
+        let (start, lengths_start) = self.blocks[index / BLOCK_LINES];
+        let mut lengths = &self.lengths[lengths_start..];
+
+        let distance: usize = (0..index % BLOCK_LINES)
+            .map(|_| read_length(&mut lengths))
+            .sum();
+
+        Some(start + distance)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn last(&self) -> Option<usize> {
+        self.last
+    }
+
+    pub fn is_compact(&self) -> bool {
+        self.compact
+    }
+
+    /// Every offset, in order
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.iter_from(0)
+    }
+
+    /// The offsets from 0-based line `start` on, in order. Only the lengths in the block of
+    /// `start` are read to get to it
+    pub fn iter_from(&self, start: usize) -> impl Iterator<Item = usize> + '_ {
+        let compact = self
+            .blocks
+            .iter()
+            .enumerate()
+            .skip(start / BLOCK_LINES)
+            .flat_map(move |(block, &(start, lengths_start))| {
+                let mut lengths = &self.lengths[lengths_start..];
+                let line_count = (self.len - block * BLOCK_LINES).min(BLOCK_LINES);
+
+                iter::successors(Some(start), move |offset| {
+                    Some(offset + read_length(&mut lengths))
+                })
+                .take(line_count)
+            })
+            .skip(start % BLOCK_LINES);
+
+        self.dense.iter().skip(start).copied().chain(compact)
+    }
+
+    fn push_compact(&mut self, offset: usize) {
+        match self.last {
+            Some(last) if !self.len.is_multiple_of(BLOCK_LINES) => {
+                write_length(&mut self.lengths, offset - last);
+            }
+            _ => self.blocks.push((offset, self.lengths.len())),
+        }
+
+        self.len += 1;
+        self.last = Some(offset);
+    }
+
+    fn compact_if_large(&mut self) {
+        if self.compact || self.len <= self.compact_above {
+            return;
+        }
+
+        let dense = mem::take(&mut self.dense);
+
+        self.compact = true;
+        self.len = 0;
+        self.last = None;
+
+        for offset in dense {
+            self.push_compact(offset);
+        }
+
+        self.blocks.shrink_to_fit();
+    }
+}
+
+/// Appends `length` 7 bits a byte, lowest first, with the top bit set on every byte but the last
+fn write_length(lengths: &mut Vec<u8>, mut length: usize) {
+    while length >= 0x80 {
+        lengths.push((length & 0x7f) as u8 | 0x80);
+        length >>= 7;
+    }
+
+    lengths.push(length as u8);
+}
+
+/// Reads a length written by `write_length` off the front of `lengths`
+fn read_length(lengths: &mut &[u8]) -> usize {
+    let mut length = 0;
+    let mut shift = 0;
+
+    while let Some((&byte, rest)) = lengths.split_first() {
+        *lengths = rest;
+        length |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    length
+}
+
+/// Written as the list of offsets, like the `Vec` the index used to be, so checkpoints don't
+/// depend on whether it is compact
+impl Serialize for LinePositions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for LinePositions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OffsetsVisitor;
+
+        impl<'de> Visitor<'de> for OffsetsVisitor {
+            type Value = LinePositions;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a list of line offsets")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<LinePositions, A::Error> {
+                let mut positions = LinePositions::default();
+
+                while let Some(offset) = seq.next_element()? {
+                    positions.push(offset);
+                }
+
+                Ok(positions)
+            }
+        }
+
+        deserializer.deserialize_seq(OffsetsVisitor)
+    }
+}
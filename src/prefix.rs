@@ -0,0 +1,86 @@
+use clap::ValueEnum;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// The files a prefix is stripped from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum PrefixFiles {
+    #[default]
+    Both,
+    File1,
+    File2,
+}
+
+/// What makes up the prefix of a line
+#[derive(Clone, Debug)]
+pub enum Prefix {
+    /// Text matching the pattern at the start of the line
+    Pattern(Regex),
+    /// The first this many characters, or all of a shorter line but its ending
+    Characters(usize),
+}
+
+/// Strips a prefix from the start of each line of one or both files, like line numbers only one
+/// tool writes, before the lines are compared
+#[derive(Clone, Debug)]
+pub struct StripPrefix {
+    pub prefix: Prefix,
+    pub files: PrefixFiles,
+}
+
+impl StripPrefix {
+    /// Splits `line` into its prefix and the rest. The prefix is empty when `line` isn't from a
+    /// stripped file or the pattern doesn't match at its start
+    pub fn split<'l>(&self, line: &'l str, file1: bool) -> (&'l str, &'l str) {
+        let applies = match self.files {
+            PrefixFiles::Both => true,
+            PrefixFiles::File1 => file1,
+            PrefixFiles::File2 => !file1,
+        };
+
+        if !applies {
+            return ("", line);
+        }
+
+        match &self.prefix {
+            Prefix::Pattern(pattern) => match pattern.find(line) {
+                Some(prefix) if prefix.start() == 0 => line.split_at(prefix.end()),
+                _ => ("", line),
+            },
+            Prefix::Characters(count) => {
+                let text_length = line.trim_end_matches(['\n', '\r']).len();
+                let end = line[..text_length]
+                    .char_indices()
+                    .nth(*count)
+                    .map_or(text_length, |(index, _)| index);
+
+                line.split_at(end)
+            }
+        }
+    }
+
+    /// Removes the prefix from `line` in place
+    pub fn strip(&self, line: &mut String, file1: bool) {
+        let prefix_length = self.split(line, file1).0.len();
+        line.drain(..prefix_length);
+    }
+
+    /// Like `split`, but `line` has no prefix without a `strip_prefix`
+    pub fn split_optional<'l>(
+        strip_prefix: Option<&Self>,
+        line: &'l str,
+        file1: bool,
+    ) -> (&'l str, &'l str) {
+        strip_prefix.map_or(("", line), |strip_prefix| strip_prefix.split(line, file1))
+    }
+
+    /// What a scan checkpoint records to tell whether it stripped the same prefixes
+    pub fn key(&self) -> (String, PrefixFiles) {
+        let prefix = match &self.prefix {
+            Prefix::Pattern(pattern) => pattern.as_str().to_string(),
+            Prefix::Characters(count) => format!("first {} characters", count),
+        };
+
+        (prefix, self.files)
+    }
+}
@@ -0,0 +1,84 @@
+use std::io::{self, BufRead, Read};
+
+use crate::hex::{hex_row, BYTES_PER_ROW};
+
+/// Reads the next record's bytes into `buffer`: a line, or `record_width` bytes when set.
+/// Returns the number of bytes read, 0 at the end of the file
+pub fn read_record_bytes<R: BufRead>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+    record_width: Option<usize>,
+) -> io::Result<usize> {
+    match record_width {
+        Some(width) => reader.take(width as u64).read_to_end(buffer),
+        None => reader.read_until(b'\n', buffer),
+    }
+}
+
+/// Reads the next 16 bytes into `buffer` as a row of a hex dump, with `offset` as the offset of
+/// the row. Returns the number of bytes read, 0 at the end of the file
+pub fn read_hex_record<R: BufRead>(
+    reader: &mut R,
+    buffer: &mut String,
+    offset: usize,
+) -> io::Result<usize> {
+    let mut bytes = Vec::new();
+    let length = read_record_bytes(reader, &mut bytes, Some(BYTES_PER_ROW))?;
+
+    if length > 0 {
+        buffer.push_str(&hex_record_text(offset, &bytes));
+    }
+
+    Ok(length)
+}
+
+/// The text of the hex record of `bytes` at `offset`: its hex dump row, newline terminated like a
+/// line so it is written out as one
+pub fn hex_record_text(offset: usize, bytes: &[u8]) -> String {
+    let mut text = hex_row(offset, bytes);
+    text.push('\n');
+
+    text
+}
+
+/// Reads record `index` from the start of `reader` as text. `None` if there are too few records
+pub fn read_nth_record<R: BufRead>(
+    reader: &mut R,
+    index: usize,
+    record_width: Option<usize>,
+) -> io::Result<Option<String>> {
+    let mut buffer = String::new();
+
+    for _ in 0..index {
+        if read_record(reader, &mut buffer, record_width)? == 0 {
+            return Ok(None);
+        }
+
+        buffer.clear();
+    }
+
+    match read_record(reader, &mut buffer, record_width)? {
+        0 => Ok(None),
+        _ => Ok(Some(buffer)),
+    }
+}
+
+/// Like `read_record_bytes`, but appends the record to `buffer` as text. Lines must be UTF-8, but
+/// fixed-width records may hold arbitrary bytes and are converted lossily
+pub fn read_record<R: BufRead>(
+    reader: &mut R,
+    buffer: &mut String,
+    record_width: Option<usize>,
+) -> io::Result<usize> {
+    match record_width {
+        Some(_) => {
+            let mut bytes = Vec::new();
+            let length = read_record_bytes(reader, &mut bytes, record_width)?;
+
+            buffer.push_str(&String::from_utf8_lossy(&bytes));
+
+            Ok(length)
+        }
+        None => reader.read_line(buffer),
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+
+/// Longest block of lines checked for repetition
+const MAX_PERIOD: usize = 16;
+/// Fewest back-to-back copies of a block that are collapsed
+const MIN_REPEATS: usize = 3;
+
+/// Where a displayed line comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineSource {
+    /// The loaded line at this index
+    Line(usize),
+    /// The summary of `count` copies of the `period` lines above, the first starting at the loaded
+    /// line `start`
+    Repeat {
+        start: usize,
+        period: usize,
+        count: usize,
+    },
+}
+
+/// Replaces each run of repeated blocks in `lines` with its first copy and a `× N` summary line.
+/// Runs whose first line (offset by `first_line_index`) is in `expanded` are left alone
+pub fn collapse_repeats(
+    lines: Vec<String>,
+    first_line_index: usize,
+    expanded: &HashSet<usize>,
+) -> (Vec<String>, Vec<LineSource>) {
+    let mut collapsed_lines = vec![];
+    let mut sources = vec![];
+
+    let mut index = 0;
+
+    while index < lines.len() {
+        let repeat = longest_repeat(&lines[index..]);
+        let is_expanded = expanded.contains(&(first_line_index + index));
+
+        match repeat {
+            Some((period, count)) if is_expanded => {
+                // Keep the whole run, so its later copies aren't collapsed on their own
+                for offset in 0..period * count {
+                    collapsed_lines.push(lines[index + offset].clone());
+                    sources.push(LineSource::Line(index + offset));
+                }
+
+                index += period * count;
+            }
+            Some((period, count)) => {
+                for offset in 0..period {
+                    collapsed_lines.push(lines[index + offset].clone());
+                    sources.push(LineSource::Line(index + offset));
+                }
+
+                collapsed_lines.push(format!("× {} (block of {} lines)\n", count, period));
+                sources.push(LineSource::Repeat {
+                    start: index,
+                    period,
+                    count,
+                });
+
+                index += period * count;
+            }
+            None => {
+                collapsed_lines.push(lines[index].clone());
+                sources.push(LineSource::Line(index));
+
+                index += 1;
+            }
+        }
+    }
+
+    (collapsed_lines, sources)
+}
+
+/// Every line unchanged, for when repeats aren't collapsed
+pub fn uncollapsed(line_count: usize) -> Vec<LineSource> {
+    (0..line_count).map(LineSource::Line).collect()
+}
+
+/// The `(period, count)` of the repeated block at the start of `lines` covering the most lines
+fn longest_repeat(lines: &[String]) -> Option<(usize, usize)> {
+    (1..=MAX_PERIOD.min(lines.len() / MIN_REPEATS))
+        .filter_map(|period| {
+            let mut count = 1;
+
+            while (count + 1) * period <= lines.len()
+                && lines[count * period..(count + 1) * period] == lines[..period]
+            {
+                count += 1;
+            }
+
+            (count >= MIN_REPEATS).then_some((period, count))
+        })
+        .max_by_key(|(period, count)| (period * count, usize::MAX - period))
+}
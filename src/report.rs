@@ -0,0 +1,94 @@
+use std::fmt::Write;
+
+use crate::{pairs::FilePair, scan::FirstDiff};
+
+/// The outcome of comparing one pair of files, as a test case that passes when they're identical
+pub struct PairResult<'a> {
+    pub pair: &'a FilePair,
+    pub first_diff: Option<FirstDiff>,
+}
+
+impl PairResult<'_> {
+    fn name(&self) -> String {
+        format!(
+            "{} <> {}",
+            self.pair.file1.display(),
+            self.pair.file2.display()
+        )
+    }
+
+    /// Where the files diverge, `None` when they pass
+    fn failure_message(&self) -> Option<String> {
+        self.first_diff.as_ref().map(|diff| {
+            let mut message = format!("files differ at line {}", diff.position.line_index + 1);
+
+            if let Some(field_name) = &diff.field_name {
+                let _ = write!(message, " in field {}", field_name);
+            }
+
+            message
+        })
+    }
+}
+
+/// Formats the results as a Test Anything Protocol stream
+pub fn tap(results: &[PairResult]) -> String {
+    let mut output = format!("TAP version 13\n1..{}\n", results.len());
+
+    for (index, result) in results.iter().enumerate() {
+        match result.failure_message() {
+            None => {
+                let _ = writeln!(output, "ok {} - {}", index + 1, result.name());
+            }
+            Some(message) => {
+                let _ = writeln!(output, "not ok {} - {}", index + 1, result.name());
+                let _ = writeln!(output, "  ---\n  message: '{}'\n  ...", message);
+            }
+        }
+    }
+
+    output
+}
+
+/// Formats the results as a JUnit XML report with one test case per pair
+pub fn junit(results: &[PairResult]) -> String {
+    let failures = results
+        .iter()
+        .filter(|result| result.first_diff.is_some())
+        .count();
+
+    let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+
+    let _ = writeln!(
+        output,
+        r#"<testsuite name="trace-log-comparer" tests="{}" failures="{}">"#,
+        results.len(),
+        failures
+    );
+
+    for result in results {
+        let name = escape(&result.name());
+
+        match result.failure_message() {
+            None => {
+                let _ = writeln!(output, r#"  <testcase name="{}"/>"#, name);
+            }
+            Some(message) => {
+                let _ = writeln!(output, r#"  <testcase name="{}">"#, name);
+                let _ = writeln!(output, r#"    <failure message="{}"/>"#, escape(&message));
+                let _ = writeln!(output, "  </testcase>");
+            }
+        }
+    }
+
+    output.push_str("</testsuite>\n");
+
+    output
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
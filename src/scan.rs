@@ -0,0 +1,1370 @@
+use std::{
+    cmp::Ordering,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    mem,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread::{self, JoinHandle},
+    time::SystemTime,
+};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compare::DiffOptions,
+    filter::TimeFilter,
+    input::{self, Input},
+    positions::{self, LinePositions},
+    prefix::{PrefixFiles, StripPrefix},
+    record::{read_hex_record, read_record, read_record_bytes},
+    state::{self, DiffPosition, State},
+    string::StringUtils,
+};
+
+/// Lines scanned between writes of the resume checkpoint
+const CHECKPOINT_INTERVAL: usize = 1_000_000;
+
+/// Extension of the file next to the resume checkpoint that line offsets and differing lines are
+/// appended to, so each checkpoint only writes the lines scanned since the last one
+const CHECKPOINT_LINES_EXTENSION: &str = "lines";
+
+/// Bytes of each entry of the checkpoint's lines file: which list it's in, then the value
+const CHECKPOINT_ENTRY_LENGTH: usize = 9;
+
+/// Default lines of the longer file indexed past the end of the shorter one. The first of them is
+/// where the files diverge, if they didn't before
+pub const EXTRA_LINE_COUNT: usize = 20;
+
+/// Records a reader thread sends at once, so the channel isn't touched for every line
+const READ_BATCH_SIZE: usize = 1024;
+
+/// Batches a reader thread may read ahead of the comparison
+const READ_AHEAD_BATCHES: usize = 16;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum LongerFile {
+    Neither,
+    File1,
+    File2,
+}
+
+impl LongerFile {
+    pub fn message(&self) -> &'static str {
+        match self {
+            LongerFile::Neither => "Both files are the same length",
+            LongerFile::File1 => "File 1 is longer",
+            LongerFile::File2 => "File 2 is longer",
+        }
+    }
+}
+
+/// Differing lines needed before `LineBalance::swap_hint` draws any conclusion
+const MIN_SWAP_HINT_LINES: usize = 10;
+
+/// How many differing lines are longer in each file, a rough measure of which file adds text to
+/// the other
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct LineBalance {
+    pub longer_in_file1: usize,
+    pub longer_in_file2: usize,
+}
+
+impl LineBalance {
+    fn count(&mut self, line1: &str, line2: &str) {
+        match line1.len().cmp(&line2.len()) {
+            Ordering::Less => self.longer_in_file2 += 1,
+            Ordering::Equal => {}
+            Ordering::Greater => self.longer_in_file1 += 1,
+        }
+    }
+
+    /// A hint that the files were passed the wrong way around, when file 1's lines are longer
+    /// nearly every time the lines differ. Newer traces usually add to the older ones
+    pub fn swap_hint(&self) -> Option<&'static str> {
+        (self.longer_in_file1 >= MIN_SWAP_HINT_LINES
+            && self.longer_in_file1 >= 4 * self.longer_in_file2)
+            .then_some("File 1 looks like a superset of file 2, did you mean to swap them?")
+    }
+}
+
+/// How the whole files differ, line by line. Lines of the longer file past the end of the
+/// shorter one are counted even when they aren't indexed
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct DiffSummary {
+    /// Lines both files have, with different text
+    pub modified: usize,
+    /// Lines only file 2 has
+    pub added: usize,
+    /// Lines only file 1 has
+    pub removed: usize,
+    /// Of `added` and `removed`, the lines of the longer file past the indexed ones
+    #[serde(default)]
+    pub unindexed_added: usize,
+    #[serde(default)]
+    pub unindexed_removed: usize,
+    /// Where the files first diverge, even when they were opened at another diff. Filled in from
+    /// the scan's first diff when it finishes
+    #[serde(skip)]
+    pub first_diff: Option<DiffPosition>,
+}
+
+impl DiffSummary {
+    fn count(&mut self, line1_length: usize, line2_length: usize) {
+        if line1_length == 0 {
+            self.added += 1;
+        } else if line2_length == 0 {
+            self.removed += 1;
+        } else {
+            self.modified += 1;
+        }
+    }
+
+    pub fn differing(&self) -> usize {
+        self.modified + self.added + self.removed
+    }
+}
+
+/// What the scan found besides the line index
+pub struct ScanSummary {
+    pub longer_file: LongerFile,
+    /// Set when `ScanOptions::open_at` was given but no differing line contained it
+    pub open_at_missed: bool,
+    pub line_balance: LineBalance,
+}
+
+#[derive(Clone, Default)]
+pub struct ScanOptions {
+    pub skip_past_line: usize,
+    /// Open at the first differing line containing this text instead of the first diff
+    pub open_at: Option<String>,
+    pub diff_options: DiffOptions,
+    /// Split the files into records of this many bytes instead of lines
+    pub record_width: Option<usize>,
+    /// Compare the records as rows of a hex dump, so differing bytes never read as the same
+    /// character
+    pub hex: bool,
+    /// Compare only the lines with a timestamp in this range
+    pub time_filter: Option<TimeFilter>,
+    /// Prefix stripped from the lines before they are compared
+    pub strip_prefix: Option<StripPrefix>,
+    /// Lines of the longer file indexed past the end of the shorter one
+    pub context: usize,
+    /// Checkpoint file to resume from and periodically save progress to
+    pub resume_path: Option<PathBuf>,
+    /// Map the files into memory, instead of reading them with a system call per line
+    pub memory_map: bool,
+    /// Lines of a file above which its line offsets are stored compactly, instead of
+    /// `positions::DEFAULT_COMPACT_ABOVE`
+    pub compact_index_above: Option<usize>,
+    /// 0-based indices of the only lines of both files to index and compare. Line indices in
+    /// the scan's results count from its start
+    pub line_range: Option<Range<usize>>,
+}
+
+impl ScanOptions {
+    fn compact_index_above(&self) -> usize {
+        self.compact_index_above
+            .unwrap_or(positions::DEFAULT_COMPACT_ABOVE)
+    }
+
+    /// Whether the scan goes on to the line with index `line_index`, counted from the start of
+    /// `line_range`
+    fn in_range(&self, line_index: usize) -> bool {
+        self.line_range
+            .as_ref()
+            .is_none_or(|range| line_index < range.len())
+    }
+}
+
+/// Everything the scan has learned so far. Saved to the resume checkpoint so an interrupted scan
+/// can continue from `file1_offset`/`file2_offset` instead of the start of both files
+#[derive(Serialize, Deserialize)]
+struct ScanProgress {
+    file1_path: PathBuf,
+    file2_path: PathBuf,
+    skip_past_line: usize,
+    #[serde(default)]
+    open_at: Option<String>,
+    #[serde(default)]
+    diff_options: DiffOptions,
+    #[serde(default)]
+    record_width: Option<usize>,
+    #[serde(default)]
+    hex: bool,
+    #[serde(default)]
+    time_filter: Option<TimeFilter>,
+    #[serde(default)]
+    strip_prefix: Option<(String, PrefixFiles)>,
+    #[serde(default = "default_context")]
+    context: usize,
+    #[serde(default)]
+    line_range: Option<Range<usize>>,
+    /// Sizes of the files when the checkpoint was made, to notice them being regenerated
+    #[serde(default)]
+    file_lengths: (u64, u64),
+    /// When the files were last modified as the checkpoint was made, to notice them being
+    /// regenerated at the same size
+    #[serde(default)]
+    file_modified: (Option<SystemTime>, Option<SystemTime>),
+
+    line_index: usize,
+    file1_offset: usize,
+    file2_offset: usize,
+    extra_line_count: usize,
+    #[serde(default)]
+    line_balance: LineBalance,
+    /// Indices of the lines that differ from the other file's line with the same index
+    #[serde(default, skip_serializing)]
+    diff_lines: Vec<usize>,
+    #[serde(default)]
+    diff_summary: DiffSummary,
+
+    /// Kept in the lines file, though checkpoints from before it have them here
+    #[serde(default, skip_serializing)]
+    file1_line_positions: LinePositions,
+    #[serde(default, skip_serializing)]
+    file2_line_positions: LinePositions,
+    /// Bytes of the lines file the checkpoint covers. Anything after them was appended by a save
+    /// interrupted before its checkpoint was written. `None` in checkpoints from before the
+    /// lines file
+    #[serde(default)]
+    lines_length: Option<u64>,
+    /// Line offsets of file 1 and file 2, and differing lines, already in the lines file
+    #[serde(skip)]
+    saved_counts: (usize, usize, usize),
+
+    first_diff: Option<DiffPosition>,
+    /// First diff on a line containing `open_at`
+    #[serde(default)]
+    open_at_diff: Option<DiffPosition>,
+
+    /// Set once the scan has finished
+    longer_file: Option<LongerFile>,
+}
+
+impl ScanProgress {
+    fn new(file1_path: &Path, file2_path: &Path, options: &ScanOptions) -> Self {
+        ScanProgress {
+            file1_path: file1_path.to_path_buf(),
+            file2_path: file2_path.to_path_buf(),
+            skip_past_line: options.skip_past_line,
+            open_at: options.open_at.clone(),
+            diff_options: options.diff_options.clone(),
+            record_width: options.record_width,
+            hex: options.hex,
+            time_filter: options.time_filter.clone(),
+            strip_prefix: options.strip_prefix.as_ref().map(StripPrefix::key),
+            context: options.context,
+            line_range: options.line_range.clone(),
+            file_lengths: file_lengths(file1_path, file2_path),
+            file_modified: file_modified(file1_path, file2_path),
+
+            line_index: 0,
+            file1_offset: 0,
+            file2_offset: 0,
+            extra_line_count: options.context,
+            line_balance: LineBalance::default(),
+            diff_lines: Vec::new(),
+            diff_summary: DiffSummary::default(),
+
+            file1_line_positions: LinePositions::new(options.compact_index_above()),
+            file2_line_positions: LinePositions::new(options.compact_index_above()),
+            lines_length: None,
+            saved_counts: (0, 0, 0),
+
+            first_diff: None,
+            open_at_diff: None,
+
+            longer_file: None,
+        }
+    }
+
+    /// Loads the checkpoint at `path`, if it exists and was made for the same comparison
+    fn load(
+        path: &Path,
+        file1_path: &Path,
+        file2_path: &Path,
+        options: &ScanOptions,
+    ) -> Option<Self> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+            Err(err) => {
+                warn!("Could not read checkpoint {}: {}", path.display(), err);
+                return None;
+            }
+        };
+
+        let mut progress: ScanProgress = match serde_json::from_slice(&contents) {
+            Ok(progress) => progress,
+            Err(err) => {
+                warn!("Ignoring malformed checkpoint {}: {}", path.display(), err);
+                return None;
+            }
+        };
+
+        if progress.file1_path != file1_path
+            || progress.file2_path != file2_path
+            || progress.skip_past_line != options.skip_past_line
+            || progress.open_at != options.open_at
+            || progress.diff_options != options.diff_options
+            || progress.record_width != options.record_width
+            || progress.hex != options.hex
+            || progress.time_filter != options.time_filter
+            || progress.strip_prefix != options.strip_prefix.as_ref().map(StripPrefix::key)
+            || progress.context != options.context
+            || progress.line_range != options.line_range
+            || progress.file_lengths != file_lengths(file1_path, file2_path)
+            || progress.file_modified != file_modified(file1_path, file2_path)
+        {
+            warn!(
+                "Ignoring checkpoint {}, which was made for a different comparison",
+                path.display()
+            );
+            return None;
+        }
+
+        // Made compact at the default size when read from the checkpoint itself
+        progress
+            .file1_line_positions
+            .set_compact_above(options.compact_index_above());
+        progress
+            .file2_line_positions
+            .set_compact_above(options.compact_index_above());
+
+        if let Some(length) = progress.lines_length {
+            if let Err(err) =
+                progress.load_lines(&path.with_extension(CHECKPOINT_LINES_EXTENSION), length)
+            {
+                warn!(
+                    "Ignoring checkpoint {} without its lines: {}",
+                    path.display(),
+                    err
+                );
+                return None;
+            }
+        }
+
+        Some(progress)
+    }
+
+    /// Reads the first `length` bytes of the lines file at `lines_path` into the lists they were
+    /// saved from
+    fn load_lines(&mut self, lines_path: &Path, length: u64) -> io::Result<()> {
+        let contents = fs::read(lines_path)?;
+        let entries = contents
+            .get(..length as usize)
+            .filter(|entries| entries.len() % CHECKPOINT_ENTRY_LENGTH == 0)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "lines file is cut short")
+            })?;
+
+        for entry in entries.chunks_exact(CHECKPOINT_ENTRY_LENGTH) {
+            let value =
+                u64::from_le_bytes(entry[1..].try_into().expect("Entry is 9 bytes")) as usize;
+
+            match entry[0] {
+                b'1' => self.file1_line_positions.push(value),
+                b'2' => self.file2_line_positions.push(value),
+                b'd' => self.diff_lines.push(value),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "lines file has an unknown entry",
+                    ))
+                }
+            }
+        }
+
+        self.saved_counts = (
+            self.file1_line_positions.len(),
+            self.file2_line_positions.len(),
+            self.diff_lines.len(),
+        );
+
+        Ok(())
+    }
+
+    /// Appends the lines scanned since the last save to the lines file, then writes the rest of
+    /// the progress to the checkpoint at `path`
+    fn save(&mut self, path: &Path) -> io::Result<()> {
+        let lines_file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path.with_extension(CHECKPOINT_LINES_EXTENSION))?;
+
+        // Drops what an interrupted save appended past the checkpoint
+        let mut length = self.lines_length.unwrap_or(0);
+        lines_file.set_len(length)?;
+
+        let mut writer = BufWriter::new(lines_file);
+        writer.seek(SeekFrom::Start(length))?;
+
+        let (file1_count, file2_count, diff_count) = self.saved_counts;
+        let entries = (self
+            .file1_line_positions
+            .iter_from(file1_count)
+            .map(|offset| (b'1', offset)))
+        .chain(
+            self.file2_line_positions
+                .iter_from(file2_count)
+                .map(|offset| (b'2', offset)),
+        )
+        .chain(
+            self.diff_lines[diff_count..]
+                .iter()
+                .map(|&line_index| (b'd', line_index)),
+        );
+
+        for (list, value) in entries {
+            writer.write_all(&[list])?;
+            writer.write_all(&(value as u64).to_le_bytes())?;
+            length += CHECKPOINT_ENTRY_LENGTH as u64;
+        }
+
+        writer.flush()?;
+
+        self.lines_length = Some(length);
+        self.saved_counts = (
+            self.file1_line_positions.len(),
+            self.file2_line_positions.len(),
+            self.diff_lines.len(),
+        );
+
+        // Write next to the checkpoint and rename over it, so an interruption mid-write leaves
+        // the previous checkpoint intact
+        let temp_path = path.with_extension("tmp");
+
+        fs::write(&temp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&temp_path, path)?;
+
+        debug!(
+            "Saved checkpoint at line {} to {}",
+            self.line_index,
+            path.display()
+        );
+
+        Ok(())
+    }
+}
+
+/// Scans both files for the first differing line, recording the byte offset of every line so
+/// `State` can seek back to them
+pub fn index_files(
+    file1_path: &Path,
+    file2_path: &Path,
+    options: &ScanOptions,
+) -> io::Result<(State<'static, Input>, ScanSummary)> {
+    let mut file1_reader = buf_reader(file1_path, options.memory_map)?;
+    let mut file2_reader = buf_reader(file2_path, options.memory_map)?;
+
+    let resume_path = options.resume_path.as_deref();
+
+    let mut progress = resume_path
+        .and_then(|path| ScanProgress::load(path, file1_path, file2_path, options))
+        .unwrap_or_else(|| ScanProgress::new(file1_path, file2_path, options));
+
+    if let Some(longer_file) = progress.longer_file {
+        info!("Using completed scan from checkpoint");
+
+        return Ok(finish(progress, longer_file, file1_reader, file2_reader));
+    }
+
+    info!(
+        "Indexing {} and {} from line {}",
+        file1_path.display(),
+        file2_path.display(),
+        progress.line_index + 1
+    );
+
+    file1_reader.seek(SeekFrom::Start(progress.file1_offset as u64))?;
+    file2_reader.seek(SeekFrom::Start(progress.file2_offset as u64))?;
+
+    let fresh =
+        progress.line_index == 0 && progress.file1_offset == 0 && progress.file2_offset == 0;
+
+    if let Some(range) = options.line_range.as_ref().filter(|_| fresh) {
+        let (file1_offset, file1_reached) = skip_records(&mut file1_reader, range.start, options)?;
+        let (file2_offset, file2_reached) = skip_records(&mut file2_reader, range.start, options)?;
+
+        if !file1_reached && !file2_reached {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} and {} both end before line {}, where the range starts",
+                    file1_path.display(),
+                    file2_path.display(),
+                    range.start + 1
+                ),
+            ));
+        }
+
+        // A file ending before the range is left with no lines in it
+        progress.file1_offset = file1_offset;
+        progress.file2_offset = file2_offset;
+    }
+
+    // With more than one CPU, both files are read at once on their own threads while their
+    // records are compared in order here
+    let mut file1_records =
+        RecordReader::spawn(file1_reader, true, options, progress.file1_offset)?;
+    let mut file2_records =
+        RecordReader::spawn(file2_reader, false, options, progress.file2_offset)?;
+
+    let mut line1 = String::new();
+    let mut line2 = String::new();
+
+    let mut file1_result = file1_records.read(&mut line1, &mut progress.file1_offset);
+    let mut file2_result = file2_records.read(&mut line2, &mut progress.file2_offset);
+
+    // Set when the scan stops at the end of `options.line_range`, with lines past it left
+    let mut range_ended = false;
+
+    while let (Ok(line1_length), Ok(line2_length)) = (file1_result.as_ref(), file2_result.as_ref())
+    {
+        if !options.in_range(progress.line_index) {
+            range_ended = true;
+            break;
+        }
+
+        if *line1_length == 0 && *line2_length == 0 {
+            // Both files have ended, within the extra lines of the longer one
+            break;
+        }
+
+        if *line1_length == 0 || *line2_length == 0 {
+            if progress.extra_line_count > 0 {
+                // Load extra lines after the end of the shorter file
+                progress.extra_line_count -= 1;
+            } else {
+                break;
+            }
+        }
+
+        let matches_open_at = |query: &String| line1.contains(query) || line2.contains(query);
+
+        let wants_diff = progress.first_diff.is_none()
+            || (progress.open_at_diff.is_none()
+                && progress.open_at.as_ref().is_some_and(matches_open_at));
+
+        let differs = progress.line_index + 1 > progress.skip_past_line
+            && !options.diff_options.lines_equal(&line1, &line2);
+
+        if differs {
+            progress.diff_lines.push(progress.line_index);
+            progress.diff_summary.count(*line1_length, *line2_length);
+        }
+
+        if differs && wants_diff {
+            let diff = DiffPosition {
+                line_index: progress.line_index,
+                line_offset: state::first_change_column(&line1, &line2, &options.diff_options),
+                file1_offset: progress.file1_offset,
+                file2_offset: progress.file2_offset,
+            };
+
+            debug!(
+                "Diff at line {} offset {} (file offsets {}, {})",
+                diff.line_index + 1,
+                diff.line_offset,
+                diff.file1_offset,
+                diff.file2_offset
+            );
+
+            if progress.open_at.as_ref().is_some_and(matches_open_at) {
+                progress.open_at_diff = Some(diff.clone());
+            }
+
+            if progress.first_diff.is_none() {
+                progress.first_diff = Some(diff);
+            }
+        }
+
+        if !options.diff_options.pattern {
+            // Lines of different lengths always differ, and equal lengths say nothing about
+            // the direction. Patterns aren't comparable by length
+            progress.line_balance.count(&line1, &line2);
+        }
+
+        if *line1_length > 0 {
+            progress.file1_line_positions.push(progress.file1_offset);
+        }
+
+        if *line2_length > 0 {
+            progress.file2_line_positions.push(progress.file2_offset);
+        }
+
+        progress.file1_offset += line1_length;
+        progress.file2_offset += line2_length;
+
+        progress.line_index += 1;
+
+        if let Some(path) = resume_path {
+            if progress.line_index.is_multiple_of(CHECKPOINT_INTERVAL) {
+                progress.save(path)?;
+            }
+        }
+
+        file1_result = file1_records.read(&mut line1, &mut progress.file1_offset);
+        file2_result = file2_records.read(&mut line2, &mut progress.file2_offset);
+    }
+
+    info!(
+        "Indexed {} lines in file 1 and {} lines in file 2{}",
+        progress.file1_line_positions.len(),
+        progress.file2_line_positions.len(),
+        if progress.file1_line_positions.is_compact() || progress.file2_line_positions.is_compact()
+        {
+            ", compactly"
+        } else {
+            ""
+        }
+    );
+
+    // Past the range both files count as ended
+    let (line1_length, line2_length) = if range_ended {
+        (0, 0)
+    } else {
+        (
+            file1_result.unwrap_or_default(),
+            file2_result.unwrap_or_default(),
+        )
+    };
+
+    let longer_file = match (line1_length, line2_length) {
+        // Both were read to the end, so every line is indexed
+        (0, 0) => match progress
+            .file1_line_positions
+            .len()
+            .cmp(&progress.file2_line_positions.len())
+        {
+            Ordering::Less => LongerFile::File2,
+            Ordering::Equal => LongerFile::Neither,
+            Ordering::Greater => LongerFile::File1,
+        },
+        (0, _) => LongerFile::File2,
+        _ => LongerFile::File1,
+    };
+
+    // The rest of the longer file isn't indexed, but every line of it is added or removed
+    if line1_length > 0 || line2_length > 0 {
+        let file1 = line1_length > 0;
+        let (records, line, offset) = if file1 {
+            (&mut file1_records, &mut line1, &mut progress.file1_offset)
+        } else {
+            (&mut file2_records, &mut line2, &mut progress.file2_offset)
+        };
+
+        let mut length = line1_length.max(line2_length);
+        let mut line_index = progress.line_index;
+
+        while length > 0 && options.in_range(line_index) {
+            if line_index + 1 > progress.skip_past_line {
+                let summary = &mut progress.diff_summary;
+
+                if file1 {
+                    summary.removed += 1;
+                    summary.unindexed_removed += 1;
+                } else {
+                    summary.added += 1;
+                    summary.unindexed_added += 1;
+                }
+            }
+
+            *offset += length;
+            line_index += 1;
+
+            length = records.read(line, offset)?;
+        }
+    }
+
+    progress.longer_file = Some(longer_file);
+
+    if let Some(path) = resume_path {
+        progress.save(path)?;
+    }
+
+    Ok(finish(
+        progress,
+        longer_file,
+        file1_records.finish(),
+        file2_records.finish(),
+    ))
+}
+
+/// Where two files first diverge
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FirstDiff {
+    /// The differing line, and the column of its first change
+    pub position: DiffPosition,
+    /// Name of the `name=value` field the first differing character is in, if any
+    pub field_name: Option<String>,
+}
+
+/// Reads both files up to the first line that differs after `options.skip_past_line`, without
+/// indexing the rest of the files. `None` if they don't diverge
+pub fn first_divergence(
+    file1_path: &Path,
+    file2_path: &Path,
+    options: &ScanOptions,
+) -> io::Result<Option<FirstDiff>> {
+    find_first_diff(
+        buf_reader(file1_path, options.memory_map)?,
+        buf_reader(file2_path, options.memory_map)?,
+        options,
+    )
+}
+
+/// Whether both files end in a newline, or neither does. Lines are compared without their
+/// endings, so this is the one way files of the same lines can differ that `first_divergence`
+/// doesn't see. Always true for records, and for a line range, which may end before the files do
+pub fn final_newlines_match(
+    file1_path: &Path,
+    file2_path: &Path,
+    options: &ScanOptions,
+) -> io::Result<bool> {
+    if options.record_width.is_some()
+        || options.hex
+        || options.line_range.is_some()
+        || options.diff_options.preserve_eol
+    {
+        return Ok(true);
+    }
+
+    Ok(input::ends_with_newline(file1_path)? == input::ends_with_newline(file2_path)?)
+}
+
+/// Streams the records of both readers up to the first line that differs after
+/// `options.skip_past_line`, finding it at the position `index_files` does but without keeping
+/// the offset of every line. The first line past the end of the shorter file always differs,
+/// however many lines `options.context` lets `index_files` compare there. `None` if the readers
+/// don't diverge
+pub fn find_first_diff(
+    mut file1_reader: impl BufRead,
+    mut file2_reader: impl BufRead,
+    options: &ScanOptions,
+) -> io::Result<Option<FirstDiff>> {
+    let mut line1 = String::new();
+    let mut line2 = String::new();
+
+    let mut line_index = 0;
+    let mut file1_offset = 0;
+    let mut file2_offset = 0;
+
+    if let Some(range) = &options.line_range {
+        let file1_reached;
+        let file2_reached;
+
+        (file1_offset, file1_reached) = skip_records(&mut file1_reader, range.start, options)?;
+        (file2_offset, file2_reached) = skip_records(&mut file2_reader, range.start, options)?;
+
+        if !file1_reached && !file2_reached {
+            return Ok(None);
+        }
+    }
+
+    while options.in_range(line_index) {
+        line1.clear();
+        line2.clear();
+
+        let line1_length = read_kept_record(
+            &mut file1_reader,
+            &mut line1,
+            true,
+            options,
+            &mut file1_offset,
+        )?;
+        let line2_length = read_kept_record(
+            &mut file2_reader,
+            &mut line2,
+            false,
+            options,
+            &mut file2_offset,
+        )?;
+
+        if line1_length == 0 && line2_length == 0 {
+            return Ok(None);
+        }
+
+        if line_index + 1 > options.skip_past_line
+            && !options.diff_options.lines_equal(&line1, &line2)
+        {
+            let line_offset = state::first_change_column(&line1, &line2, &options.diff_options);
+
+            return Ok(Some(FirstDiff {
+                position: DiffPosition {
+                    line_index,
+                    line_offset,
+                    file1_offset,
+                    file2_offset,
+                },
+                field_name: field_name(&line1, line_offset)
+                    .or_else(|| field_name(&line2, line_offset)),
+            }));
+        }
+
+        file1_offset += line1_length;
+        file2_offset += line2_length;
+
+        line_index += 1;
+    }
+
+    Ok(None)
+}
+
+/// Reads past the first `count` records of `reader`, before any filtering. Returns the offset it
+/// is left at, and whether the reader had that many records
+fn skip_records(
+    reader: &mut impl BufRead,
+    count: usize,
+    options: &ScanOptions,
+) -> io::Result<(usize, bool)> {
+    let mut offset = 0;
+    let mut buffer = Vec::new();
+
+    for _ in 0..count {
+        buffer.clear();
+
+        match read_record_bytes(reader, &mut buffer, options.record_width)? {
+            0 => return Ok((offset, false)),
+            length => offset += length,
+        }
+    }
+
+    Ok((offset, true))
+}
+
+/// A record read ahead by a reader thread, with what `read_kept_record` returned for it and the
+/// offset it moved to
+struct ReadRecord {
+    line: String,
+    result: io::Result<usize>,
+    offset: usize,
+}
+
+/// Reads the kept records of one file for the scan. With more than one CPU each file is read on
+/// its own thread, so the two files are read at the same time while the scan compares them. The
+/// records come out exactly as `read_kept_record` returns them either way
+enum RecordReader {
+    Inline {
+        reader: BufReader<Input>,
+        file1: bool,
+        options: Box<ScanOptions>,
+    },
+    Threaded {
+        batches: Receiver<Vec<ReadRecord>>,
+        batch: Vec<ReadRecord>,
+        /// Index of the next record of `batch`
+        next: usize,
+        /// Batches given back once they are read, so the thread can reuse their lines' buffers
+        spent: SyncSender<Vec<ReadRecord>>,
+        thread: JoinHandle<BufReader<Input>>,
+    },
+}
+
+impl RecordReader {
+    /// Starts reading file 1 (or file 2) from `reader`, which is at `offset`
+    fn spawn(
+        reader: BufReader<Input>,
+        file1: bool,
+        options: &ScanOptions,
+        offset: usize,
+    ) -> io::Result<Self> {
+        let options = options.clone();
+
+        let parallel = thread::available_parallelism().is_ok_and(|count| count.get() > 1);
+
+        if !parallel {
+            return Ok(RecordReader::Inline {
+                reader,
+                file1,
+                options: Box::new(options),
+            });
+        }
+
+        let (sender, batches) = mpsc::sync_channel(READ_AHEAD_BATCHES);
+        let (spent, spent_batches) = mpsc::sync_channel(READ_AHEAD_BATCHES);
+
+        let thread = thread::Builder::new()
+            .name(format!("read file {}", if file1 { 1 } else { 2 }))
+            .spawn(move || read_batches(reader, file1, &options, offset, sender, spent_batches))?;
+
+        Ok(RecordReader::Threaded {
+            batches,
+            batch: Vec::new(),
+            next: 0,
+            spent,
+            thread,
+        })
+    }
+
+    /// Reads the next record into `line`, replacing it, and moves `offset` past the records that
+    /// were skipped like `read_kept_record`. Past the end of the file, `line` is empty and the
+    /// length 0
+    fn read(&mut self, line: &mut String, offset: &mut usize) -> io::Result<usize> {
+        line.clear();
+
+        let (batches, batch, next, spent) = match self {
+            RecordReader::Inline {
+                reader,
+                file1,
+                options,
+            } => return read_kept_record(reader, line, *file1, options, offset),
+            RecordReader::Threaded {
+                batches,
+                batch,
+                next,
+                spent,
+                ..
+            } => (batches, batch, next, spent),
+        };
+
+        if *next == batch.len() {
+            // The thread stops at the end of the file
+            let Ok(new_batch) = batches.recv() else {
+                return Ok(0);
+            };
+
+            // The thread has enough buffers when it can't take them back
+            let _ = spent.try_send(mem::replace(batch, new_batch));
+            *next = 0;
+        }
+
+        let record = &mut batch[*next];
+        *next += 1;
+
+        // Swapped rather than moved, so the buffer of `line` goes back to the thread
+        mem::swap(line, &mut record.line);
+        *offset = record.offset;
+
+        mem::replace(&mut record.result, Ok(0))
+    }
+
+    /// Stops reading, and gives back the reader
+    fn finish(self) -> BufReader<Input> {
+        match self {
+            RecordReader::Inline { reader, .. } => reader,
+            RecordReader::Threaded {
+                batches, thread, ..
+            } => {
+                // Unblocks the thread if it is waiting to send more
+                mem::drop(batches);
+
+                thread
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            }
+        }
+    }
+}
+
+/// Reads the records of file 1 (or file 2) from `reader` at `offset`, sending them in batches until
+/// the end of the file, an error, or the scan no longer wanting them. Gives back the reader
+fn read_batches(
+    mut reader: BufReader<Input>,
+    file1: bool,
+    options: &ScanOptions,
+    mut offset: usize,
+    sender: SyncSender<Vec<ReadRecord>>,
+    spent_batches: Receiver<Vec<ReadRecord>>,
+) -> BufReader<Input> {
+    let mut spare_lines = Vec::new();
+
+    loop {
+        spare_lines.extend(spent_batches.try_iter().flatten().map(|record| record.line));
+
+        let mut batch = Vec::with_capacity(READ_BATCH_SIZE);
+        let mut ended = false;
+
+        while batch.len() < READ_BATCH_SIZE && !ended {
+            let mut line: String = spare_lines.pop().unwrap_or_default();
+            line.clear();
+
+            let result = read_kept_record(&mut reader, &mut line, file1, options, &mut offset);
+            let record_offset = offset;
+
+            // The scan stops reading a file at its end, or at an error
+            match result {
+                Ok(length) if length > 0 => offset += length,
+                _ => ended = true,
+            }
+
+            batch.push(ReadRecord {
+                line,
+                result,
+                offset: record_offset,
+            });
+        }
+
+        if sender.send(batch).is_err() || ended {
+            return reader;
+        }
+    }
+}
+
+/// Reads the next record of file 1 (or file 2) the `options.time_filter` keeps, if any, skipping
+/// the others, and strips its prefix. `offset` is moved past the skipped records, so it stays the
+/// offset of the record that is returned. The returned length includes the stripped prefix
+fn read_kept_record(
+    reader: &mut impl BufRead,
+    line: &mut String,
+    file1: bool,
+    options: &ScanOptions,
+    offset: &mut usize,
+) -> io::Result<usize> {
+    loop {
+        let length = if options.hex {
+            read_hex_record(reader, line, *offset)?
+        } else {
+            read_record(reader, line, options.record_width)?
+        };
+
+        if let Some(strip_prefix) = &options.strip_prefix {
+            strip_prefix.strip(line, file1);
+        }
+
+        let kept = options
+            .time_filter
+            .as_ref()
+            .is_none_or(|filter| filter.keeps(line));
+
+        if length == 0 || kept {
+            return Ok(length);
+        }
+
+        *offset += length;
+        line.clear();
+    }
+}
+
+/// The name of the whitespace separated `name=value` field containing column `offset`
+fn field_name(line: &str, offset: usize) -> Option<String> {
+    let mut start = 0;
+
+    for field in line.split(char::is_whitespace) {
+        let length = field.columns();
+
+        if offset < start + length {
+            return field
+                .split_once('=')
+                .map(|(name, _)| name.to_string())
+                .filter(|name| !name.is_empty());
+        }
+
+        // Skip the separator too
+        start += length + 1;
+    }
+
+    None
+}
+
+fn finish(
+    progress: ScanProgress,
+    longer_file: LongerFile,
+    file1_reader: BufReader<Input>,
+    file2_reader: BufReader<Input>,
+) -> (State<'static, Input>, ScanSummary) {
+    let open_at_missed = progress.open_at.is_some() && progress.open_at_diff.is_none();
+
+    if open_at_missed {
+        warn!("No differing line matched the open-at text, using the first diff");
+    }
+
+    let diff_summary = DiffSummary {
+        first_diff: progress.first_diff.clone(),
+        ..progress.diff_summary
+    };
+
+    // Open at the line matching `open_at` when there is one
+    let first_diff = progress.open_at_diff.or(progress.first_diff);
+
+    let line_balance = progress.line_balance;
+
+    let mut state = State::new(
+        first_diff,
+        progress.file1_line_positions,
+        progress.file2_line_positions,
+        file1_reader,
+        file2_reader,
+    );
+
+    state.diff_lines = progress.diff_lines;
+    state.diff_summary = diff_summary;
+
+    (
+        state,
+        ScanSummary {
+            longer_file,
+            open_at_missed,
+            line_balance,
+        },
+    )
+}
+
+/// Checkpoints from before `--context` used the default
+fn default_context() -> usize {
+    EXTRA_LINE_COUNT
+}
+
+fn file_lengths(file1_path: &Path, file2_path: &Path) -> (u64, u64) {
+    (input::length(file1_path), input::length(file2_path))
+}
+
+fn file_modified(file1_path: &Path, file2_path: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+    (input::modified(file1_path), input::modified(file2_path))
+}
+
+fn buf_reader(path: &Path, memory_map: bool) -> io::Result<BufReader<Input>> {
+    let file = if memory_map {
+        Input::open_mapped(path)
+    } else {
+        Input::open(path)
+    }?;
+
+    Ok(BufReader::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs::File, io::Cursor, process, time::Duration};
+
+    use super::*;
+
+    /// `count` lines numbered from 1
+    fn numbered_lines(count: usize) -> String {
+        (1..=count).map(|i| format!("line {}\n", i)).collect()
+    }
+
+    fn first_diff(text1: &str, text2: &str) -> Option<FirstDiff> {
+        find_first_diff(
+            Cursor::new(text1.as_bytes()),
+            Cursor::new(text2.as_bytes()),
+            &ScanOptions::default(),
+        )
+        .expect("Could not read")
+    }
+
+    #[test]
+    fn identical_files_dont_diverge() {
+        assert_eq!(first_diff("a=1\nb=2\n", "a=1\nb=2\n"), None);
+    }
+
+    #[test]
+    fn first_line_differing_is_found_at_its_first_change() {
+        assert_eq!(
+            first_diff("id=7 value=10\nend\n", "id=7 value=12\nend\n"),
+            Some(FirstDiff {
+                position: DiffPosition {
+                    line_index: 0,
+                    line_offset: 12,
+                    file1_offset: 0,
+                    file2_offset: 0,
+                },
+                field_name: Some("value".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn extra_line_of_the_longer_file_differs() {
+        assert_eq!(
+            first_diff("a\nb\n", "a\nb\nc\n"),
+            Some(FirstDiff {
+                position: DiffPosition {
+                    line_index: 2,
+                    line_offset: 0,
+                    file1_offset: 4,
+                    file2_offset: 4,
+                },
+                field_name: None,
+            })
+        );
+    }
+
+    #[test]
+    fn longer_file_is_indexed_only_up_to_the_extra_lines() {
+        let options = ScanOptions {
+            context: EXTRA_LINE_COUNT,
+            ..ScanOptions::default()
+        };
+        let path = |name: &str| {
+            env::temp_dir().join(format!(
+                "trace-log-comparer-test-{}-{}",
+                process::id(),
+                name
+            ))
+        };
+        let (file1_path, file2_path) = (path("extra1"), path("extra2"));
+
+        fs::write(&file1_path, numbered_lines(3)).unwrap();
+
+        // Fewer, exactly as many, and more extra lines than are indexed
+        for extra_count in [EXTRA_LINE_COUNT - 1, EXTRA_LINE_COUNT, EXTRA_LINE_COUNT + 1] {
+            fs::write(&file2_path, numbered_lines(3 + extra_count)).unwrap();
+
+            let (state, summary) = index_files(&file1_path, &file2_path, &options).unwrap();
+
+            assert_eq!(
+                state.line_counts(),
+                (3, 3 + extra_count.min(EXTRA_LINE_COUNT))
+            );
+            assert_eq!(state.diff_summary.added, extra_count);
+            assert_eq!(state.first_diff().map(|diff| diff.line_index), Some(3));
+            assert!(matches!(summary.longer_file, LongerFile::File2));
+        }
+
+        fs::remove_file(file1_path).unwrap();
+        fs::remove_file(file2_path).unwrap();
+    }
+
+    #[test]
+    fn first_diff_past_the_shorter_file_ignores_the_extra_lines() {
+        let shorter = numbered_lines(3);
+        let longer = numbered_lines(3 + EXTRA_LINE_COUNT + 5);
+
+        for context in [0, EXTRA_LINE_COUNT] {
+            let options = ScanOptions {
+                context,
+                ..ScanOptions::default()
+            };
+            let diff = find_first_diff(
+                Cursor::new(longer.as_bytes()),
+                Cursor::new(shorter.as_bytes()),
+                &options,
+            )
+            .unwrap();
+
+            assert_eq!(
+                diff.map(|diff| diff.position),
+                Some(DiffPosition {
+                    line_index: 3,
+                    line_offset: 0,
+                    file1_offset: shorter.len(),
+                    file2_offset: shorter.len(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn crlf_and_lf_files_of_the_same_lines_dont_diverge() {
+        let crlf = "pc=1\r\npc=2\r\n";
+        let lf = "pc=1\npc=2\n";
+
+        assert_eq!(first_diff(crlf, lf), None);
+
+        let options = ScanOptions {
+            diff_options: DiffOptions {
+                preserve_eol: true,
+                ..DiffOptions::default()
+            },
+            ..ScanOptions::default()
+        };
+        let diff = find_first_diff(
+            Cursor::new(crlf.as_bytes()),
+            Cursor::new(lf.as_bytes()),
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(diff.map(|diff| diff.position.line_index), Some(0));
+    }
+
+    #[test]
+    fn checkpoint_appends_the_lines_scanned_since_the_last_save() {
+        let path = |name: &str| {
+            env::temp_dir().join(format!(
+                "trace-log-comparer-test-{}-{}",
+                process::id(),
+                name
+            ))
+        };
+        let (file1_path, file2_path) = (path("resume1"), path("resume2"));
+        let checkpoint_path = path("resume.json");
+        let lines_path = checkpoint_path.with_extension(CHECKPOINT_LINES_EXTENSION);
+
+        fs::write(&file1_path, "a\nb\nc\n").unwrap();
+        fs::write(&file2_path, "a\nx\nc\n").unwrap();
+
+        let options = ScanOptions::default();
+        let mut progress = ScanProgress::new(&file1_path, &file2_path, &options);
+
+        progress.file1_line_positions.push(0);
+        progress.file2_line_positions.push(0);
+        progress.save(&checkpoint_path).unwrap();
+
+        progress.file1_line_positions.push(2);
+        progress.file2_line_positions.push(2);
+        progress.diff_lines.push(1);
+        progress.save(&checkpoint_path).unwrap();
+
+        // Each line was written once, and what an interrupted save appends after is dropped
+        assert_eq!(fs::metadata(&lines_path).unwrap().len(), 5 * 9);
+        OpenOptions::new()
+            .append(true)
+            .open(&lines_path)
+            .unwrap()
+            .write_all(b"1\0\0\0\0")
+            .unwrap();
+
+        let loaded = ScanProgress::load(&checkpoint_path, &file1_path, &file2_path, &options)
+            .expect("Checkpoint was ignored");
+
+        assert_eq!(
+            loaded.file1_line_positions.iter().collect::<Vec<_>>(),
+            [0, 2]
+        );
+        assert_eq!(
+            loaded.file2_line_positions.iter().collect::<Vec<_>>(),
+            [0, 2]
+        );
+        assert_eq!(loaded.diff_lines, [1]);
+        assert_eq!(loaded.saved_counts, (2, 2, 1));
+
+        // A file regenerated at the same length is noticed by its modification time
+        let file1 = File::options().write(true).open(&file1_path).unwrap();
+        file1
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1))
+            .unwrap();
+
+        assert!(ScanProgress::load(&checkpoint_path, &file1_path, &file2_path, &options).is_none());
+
+        for path in [file1_path, file2_path, checkpoint_path, lines_path] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn missing_final_newline_is_a_difference_lines_dont_show() {
+        let path = |name: &str| {
+            env::temp_dir().join(format!(
+                "trace-log-comparer-test-{}-{}",
+                process::id(),
+                name
+            ))
+        };
+        let (file1_path, file2_path) = (path("eof1"), path("eof2"));
+        let options = ScanOptions::default();
+
+        fs::write(&file1_path, "a\r\nb\r\n").unwrap();
+
+        for (text2, newlines_match) in [("a\nb\n", true), ("a\nb", false)] {
+            fs::write(&file2_path, text2).unwrap();
+
+            assert!(first_divergence(&file1_path, &file2_path, &options)
+                .unwrap()
+                .is_none());
+            assert_eq!(
+                final_newlines_match(&file1_path, &file2_path, &options).unwrap(),
+                newlines_match
+            );
+        }
+
+        fs::remove_file(file1_path).unwrap();
+        fs::remove_file(file2_path).unwrap();
+    }
+}
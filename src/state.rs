@@ -1,31 +1,185 @@
 use std::{
+    collections::HashSet,
     fs::File,
-    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    ops::{Range, RangeInclusive},
+    path::Path,
+    slice,
+    time::Instant,
 };
 
-use itertools::{Diff, EitherOrBoth, Itertools};
+use clap::ValueEnum;
+use log::{debug, trace, warn};
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
 use tui::{
-    style::{Color, Modifier, Style},
-    text::{Span, Spans},
+    style::{Modifier, Style},
+    text::{Span, Spans, Text},
     widgets::ListItem,
 };
+use unicode_width::UnicodeWidthChar;
 
-use crate::string::StringUtils;
+use crate::{
+    align::{self, Alignment, Row},
+    compare::{self, CharDiff, DiffOptions, InputMode, PatchLine},
+    html,
+    patch::{self, Hunks, PatchRow},
+    positions::LinePositions,
+    prefix::StripPrefix,
+    record::{hex_record_text, read_record_bytes},
+    repeats::{self, LineSource},
+    scan::DiffSummary,
+    string::StringUtils,
+    style::DiffStyle,
+};
+
+/// Lines of each file diffed at a time when writing the whole diff out
+const DIFF_CHUNK_LINES: usize = 10_000;
+
+/// Characters of identical text kept next to a change when folding
+const FOLD_CONTEXT: usize = 8;
+
+/// Characters shown left of the first diff when opening, without `State::context_chars`
+const DEFAULT_CONTEXT_CHARS: usize = 5;
 
-pub struct State<'a> {
+/// Rows shown around each changed row when showing only the diffs, without a context given
+pub const DEFAULT_ONLY_DIFFS_CONTEXT: usize = 3;
+
+/// Columns between tab stops by default
+pub const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// The loaded window of two files and how it's shown. The files are read through `R`, which is a
+/// `File` except in memory, e.g. over a `Cursor`
+pub struct State<'a, R = File> {
     first_diff: Option<DiffPosition>,
 
-    file1_line_positions: Vec<usize>,
-    file2_line_positions: Vec<usize>,
+    /// Byte offset of each line of each file. The files can have different line counts, so the
+    /// two are indexed independently: a line index past the end of one only has a line in the
+    /// other, and every lookup must go through `get`
+    file1_line_positions: LinePositions,
+    file2_line_positions: LinePositions,
+
+    /// Every line read seeks its reader to the line's offset first, as the readers are shared by
+    /// every read and the scan leaves them at the end of the files
+    file1_reader: BufReader<R>,
+    file2_reader: BufReader<R>,
+
+    /// Where each reader was left by its last read. A read starting there skips the seek, which
+    /// would drop the reader's buffer even when it doesn't move. `None` until the first read,
+    /// after a reload, and after a failed one
+    file1_reader_position: Option<u64>,
+    file2_reader_position: Option<u64>,
+
+    /// The first error reading a loaded window since it was last taken, whose lines from there
+    /// on are left out
+    read_error: Option<io::Error>,
+
+    /// The `(line_index, line_count)` of the last load, to reload the same window
+    window: (usize, usize),
+
+    /// Where each aligned line of the loaded window comes from
+    file1_sources: Vec<LineSource>,
+    file2_sources: Vec<LineSource>,
+
+    /// Start lines of repeated blocks that are shown expanded
+    file1_expanded: HashSet<usize>,
+    file2_expanded: HashSet<usize>,
+
+    /// The aligned text of the loaded window, after collapsing repeats
+    file1_lines: Vec<String>,
+    file2_lines: Vec<String>,
+
+    /// The prefix stripped from each loaded line, before collapsing repeats
+    file1_prefixes: Vec<String>,
+    file2_prefixes: Vec<String>,
+
+    rows: Vec<Row>,
+    line_diffs: Vec<LineDiff>,
+    /// Rows standing in for a run of hidden unchanged rows
+    separator_rows: Vec<usize>,
+    /// Each separator row folding identical rows, with the file 1 lines it hides
+    folds: Vec<(usize, Range<usize>)>,
+    /// File 1 lines of the folds that were expanded. A later fold overlapping one stays expanded,
+    /// as the same run of lines can be folded differently once the window moves
+    expanded_folds: Vec<Range<usize>>,
+
+    pub diff_options: DiffOptions,
+    pub alignment: Alignment,
+    /// Pair the lines of the window `file2_shift` lines apart instead of using `alignment`, after
+    /// re-syncing by hand
+    pub file2_shift: Option<isize>,
+    /// 0-based line indexes of file 1 and file 2 the alignment keeps paired up, sorted by the
+    /// file 1 line. Only anchors with both lines in the window apply
+    anchors: Vec<(usize, usize)>,
+    /// Lines to look ahead for a line to resynchronize on with positional alignment
+    pub resync_window: Option<usize>,
+    /// Collapse runs of repeated blocks of lines to a `× N` summary
+    pub collapse_repeats: bool,
+    /// Prefix stripped from the lines before they are compared
+    pub strip_prefix: Option<StripPrefix>,
+    /// Show the stripped prefixes in front of the lines, as text that doesn't count as a change
+    pub keep_prefix: bool,
+    pub gutter: GutterOptions,
+    pub style: DiffStyle,
+    /// Render only the changed sections of each line
+    pub changes_only: bool,
+    /// Shorten long identical starts and ends of changed lines to `…`
+    pub fold_same: bool,
+    /// Fill the background of every line with a change, under the character highlights
+    pub highlight_changed_lines: bool,
+    /// Draw spaces as `·` and tabs as `→` out to the next tab stop
+    pub show_whitespace: bool,
+    /// Columns between tab stops. Tabs are expanded to them only when drawing the lines and
+    /// working out their columns, and are compared as they are
+    pub tab_width: usize,
+    /// Show only the changed rows and the `only_diffs_context` rows around them
+    pub only_diffs: bool,
+    pub only_diffs_context: usize,
+    /// Fold each run of more than this many identical rows into a single row, which can be
+    /// expanded again
+    pub fold_identical: Option<usize>,
+    /// 0-based line `build_state` opens at instead of the first diff, clamped to the files
+    pub start_line: Option<usize>,
+    /// 1-based number of the diff among `diff_marks` that `build_state` opens at when there is
+    /// no `start_line`, or the first diff when there are fewer
+    pub start_diff: Option<usize>,
+    /// Lines before the start of the files that were indexed, which line numbers still count
+    pub skipped_lines: usize,
 
-    file1_reader: BufReader<File>,
-    file2_reader: BufReader<File>,
+    /// Lines are records of this many bytes rather than newline terminated
+    pub record_width: Option<usize>,
+    /// Records are shown as rows of a hex dump
+    pub hex: bool,
 
-    line_diffs: Vec<Vec<DiffSection>>,
+    /// Upper bound on the number of rows loaded into the viewer at once
+    pub max_rows: Option<usize>,
+    /// Indices of the lines the scan found differing from the other file's line with the same
+    /// index, ascending
+    pub diff_lines: Vec<usize>,
+    /// The rows of the whole files differing once aligned. Left `None` by `align_files` when
+    /// `diff_lines` are the same
+    aligned_diffs: Option<AlignedDiffs>,
+    /// How the whole files differ, shown in the status bar and printed on exit. Counted from
+    /// the aligned rows once `align_files` has run
+    pub diff_summary: DiffSummary,
+    /// Lines loaded on either side of the first diff at least
+    pub context: usize,
+    /// Searches carry on from the start of the files after reaching their end
+    pub search_wraps: bool,
+    /// Columns of text shown per pane at most, past the horizontal offset
+    pub max_columns: Option<usize>,
+    /// Lines longer than this many characters are cut off there when loaded, ending in `…`
+    pub max_line_width: Option<usize>,
+    /// Wrap lines at this pane width, gutter included, instead of scrolling them sideways
+    pub wrap_width: Option<usize>,
 
     pub longest_line_length: usize,
+    /// Columns taken by the widest gutter in the built lines
+    pub gutter_width: usize,
     pub selected_line: usize,
     pub initial_horizontal_offset: usize,
+    /// Characters shown left of a diff when scrolling to it horizontally. When unset, the first
+    /// diff opens `DEFAULT_CONTEXT_CHARS` from the edge and jumps center the diff
+    pub context_chars: Option<usize>,
     pub first_line_index: usize,
 
     file1_spans: Vec<Spans<'a>>,
@@ -33,39 +187,492 @@ pub struct State<'a> {
 
     pub file1_list_lines: Vec<ListItem<'a>>,
     pub file2_list_lines: Vec<ListItem<'a>>,
+
+    /// Row kept at the top of both panes while scrolling
+    pinned: Option<PinnedRow<'a>>,
+    pub file1_pinned_line: Option<ListItem<'a>>,
+    pub file2_pinned_line: Option<ListItem<'a>>,
+}
+
+/// A copy of a row taken when it was pinned, so it stays available after scrolling reloads the
+/// window without it
+struct PinnedRow<'a> {
+    file1_spans: Spans<'a>,
+    file2_spans: Spans<'a>,
+    line_diffs: LineDiff,
+    file1_number: Option<usize>,
+    file2_number: Option<usize>,
+    file1_offset: Option<usize>,
+    file2_offset: Option<usize>,
+}
+
+/// One row of the JSON diff
+#[derive(Serialize)]
+struct JsonRow<'d> {
+    /// 1-based line numbers
+    file1_line: Option<usize>,
+    file2_line: Option<usize>,
+    /// Byte offsets of the lines in the files
+    file1_offset: Option<usize>,
+    file2_offset: Option<usize>,
+    /// How the lines compare: `equal` when they are the same byte for byte, `equivalent` when
+    /// they only match once normalized or masked, `modified` when they differ, and `added` or
+    /// `removed` when only one file has a line in the row
+    kind: &'static str,
+    sections: &'d LineDiff,
+}
+
+/// Lines of each file aligned at once by `State::visit_chunks`, with the lines past the chunk's
+/// last matching row
+struct Chunk {
+    /// The first line of each file in the chunk
+    file1_start: usize,
+    file2_start: usize,
+    /// The chunk's lines, without the prefixes stripped from them
+    file1_lines: Vec<String>,
+    file2_lines: Vec<String>,
+    /// The stripped prefixes, empty without `strip_prefix`
+    file1_prefixes: Vec<String>,
+    file2_prefixes: Vec<String>,
+    rows: Vec<Row>,
+    /// Set when both files end in the chunk
+    at_end: bool,
+}
+
+impl Chunk {
+    /// Rows up to the last one pairing lines `is_same` holds for. Lines after the chunk may pair
+    /// up with the lines after it, so the rest are left for the next chunk, unless both files
+    /// end here or there is no such row
+    fn settled_rows(&self, is_same: impl Fn(usize) -> bool) -> usize {
+        if self.at_end {
+            return self.rows.len();
+        }
+
+        (0..self.rows.len())
+            .rposition(is_same)
+            .map_or(self.rows.len(), |row| row + 1)
+    }
+}
+
+/// The rows of the whole files that differ once aligned, as `State::align_files` finds them
+#[derive(Default)]
+struct AlignedDiffs {
+    /// A line of each row, ascending: the later of its two lines, or its one line
+    marks: Vec<usize>,
+    /// The lines of each file in the rows, ascending
+    file1_lines: Vec<usize>,
+    file2_lines: Vec<usize>,
+}
+
+/// A row of the whole files, as `State::visit_rows` passes it
+struct WholeRow<'r> {
+    /// The 0-based line of each file in the row
+    file1_index: Option<usize>,
+    file2_index: Option<usize>,
+    /// Prefixes stripped from the lines before they were diffed
+    file1_prefix: &'r str,
+    file2_prefix: &'r str,
+    line_diffs: &'r LineDiff,
 }
 
+impl WholeRow<'_> {
+    /// The row's `JsonRow::kind`
+    fn kind(&self) -> &'static str {
+        match (self.file1_index, self.file2_index) {
+            (Some(_), None) => "removed",
+            (None, Some(_)) => "added",
+            _ if self.file1_line() == self.file2_line() => "equal",
+            _ if self
+                .line_diffs
+                .sections()
+                .iter()
+                .any(DiffSection::is_change) =>
+            {
+                "modified"
+            }
+            _ => "equivalent",
+        }
+    }
+
+    /// File 1's whole line, prefix included
+    fn file1_line(&self) -> Option<String> {
+        self.file1_index
+            .map(|_| format!("{}{}", self.file1_prefix, self.line_diffs.left_text()))
+    }
+
+    /// File 2's whole line, prefix included
+    fn file2_line(&self) -> Option<String> {
+        self.file2_index
+            .map(|_| format!("{}{}", self.file2_prefix, self.line_diffs.right_text()))
+    }
+}
+
+/// One changed row of the loaded window, as listed in the change index pane
+pub struct IndexEntry<'a> {
+    pub row: usize,
+    /// File 1 column of the row's first change
+    pub offset: usize,
+    /// The line number, then the changes in file 1 and in file 2
+    pub spans: Spans<'a>,
+}
+
+/// The raw bytes of one row from file 1 and file 2
+pub type RowBytes = (Option<Vec<u8>>, Option<Vec<u8>>);
+
+/// Extra information rendered next to each line number
+#[derive(Clone, Copy, Default)]
+pub struct GutterOptions {
+    /// Show the number of differing sections on each line, e.g. `4821(3)`
+    pub change_counts: bool,
+    /// Tag changed lines with the file they differ in: `1>` for lines only file 1 contributes
+    /// to, `<2` for file 2, and `<>` for lines changed on both sides
+    pub origin: bool,
+    /// Mark changed lines with `+` when file 2 only adds text, `-` when it only removes text,
+    /// and `~` otherwise, so changes aren't told apart by color alone
+    pub markers: bool,
+    /// Show the line numbers of both files on every row, like `4821 | 4822`, to match up the
+    /// rows around an insertion
+    pub both_numbers: bool,
+    /// Show the byte offset of each line in its file, in hex, after its line number
+    pub offsets: bool,
+    pub number_base: NumberBase,
+    /// Leave out the gutter entirely, overriding the other options
+    pub hidden: bool,
+}
+
+/// The base line numbers are written in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum NumberBase {
+    #[default]
+    Decimal,
+    /// Lowercase hex without a `0x` prefix, to match addresses in the logs
+    Hex,
+}
+
+impl NumberBase {
+    pub fn format(self, number: usize) -> String {
+        match self {
+            NumberBase::Decimal => number.to_string(),
+            NumberBase::Hex => format!("{:x}", number),
+        }
+    }
+
+    /// Columns line numbers are padded to. Hex fits as many lines in fewer digits
+    fn width(self) -> usize {
+        match self {
+            NumberBase::Decimal => 8,
+            NumberBase::Hex => 6,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DiffPosition {
     pub line_index: usize,
+    /// Column of the first differing character in file 1's line
     pub line_offset: usize,
     pub file1_offset: usize,
     pub file2_offset: usize,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum DiffSection {
     Added(String),
-    Modified { left: String, right: String },
+    Modified {
+        left: String,
+        right: String,
+    },
     Same(String),
     Removed(String),
+    /// Text that differs between the files but compares equal under the `DiffOptions`
+    Equivalent {
+        left: String,
+        right: String,
+    },
+    /// Text matched by a `--mask` in both files, which isn't compared
+    Masked {
+        left: String,
+        right: String,
+    },
 }
 
 impl DiffSection {
+    /// Columns the section takes up in file 1's pane. `Added` text only appears in file 2's, so
+    /// it takes none
     pub fn left_len(&self) -> usize {
         match self {
-            DiffSection::Added(a) | DiffSection::Same(a) | DiffSection::Removed(a) => a.len(),
-            DiffSection::Modified { left, right } => left.len(),
+            DiffSection::Added(_) => 0,
+            DiffSection::Same(left)
+            | DiffSection::Removed(left)
+            | DiffSection::Modified { left, .. }
+            | DiffSection::Equivalent { left, .. }
+            | DiffSection::Masked { left, .. } => left.columns(),
+        }
+    }
+
+    /// Columns the section takes up in file 2's pane. `Removed` text only appears in file 1's
+    pub fn right_len(&self) -> usize {
+        match self {
+            DiffSection::Removed(_) => 0,
+            DiffSection::Same(right)
+            | DiffSection::Added(right)
+            | DiffSection::Modified { right, .. }
+            | DiffSection::Equivalent { right, .. }
+            | DiffSection::Masked { right, .. } => right.columns(),
+        }
+    }
+
+    /// Characters of the section in file 1's line
+    fn left_chars(&self) -> usize {
+        match self {
+            DiffSection::Added(_) => 0,
+            DiffSection::Same(left)
+            | DiffSection::Removed(left)
+            | DiffSection::Modified { left, .. }
+            | DiffSection::Equivalent { left, .. }
+            | DiffSection::Masked { left, .. } => left.chars().count(),
+        }
+    }
+
+    /// Characters of the section in file 2's line
+    fn right_chars(&self) -> usize {
+        match self {
+            DiffSection::Removed(_) => 0,
+            DiffSection::Same(right)
+            | DiffSection::Added(right)
+            | DiffSection::Modified { right, .. }
+            | DiffSection::Equivalent { right, .. }
+            | DiffSection::Masked { right, .. } => right.chars().count(),
+        }
+    }
+
+    /// Whether the section has no text in either file
+    pub fn is_empty(&self) -> bool {
+        match self {
+            DiffSection::Added(text) | DiffSection::Same(text) | DiffSection::Removed(text) => {
+                text.is_empty()
+            }
+            DiffSection::Modified { left, right }
+            | DiffSection::Equivalent { left, right }
+            | DiffSection::Masked { left, right } => left.is_empty() && right.is_empty(),
+        }
+    }
+
+    /// Whether the section differs between the files, rather than being the same or equivalent
+    pub fn is_change(&self) -> bool {
+        !matches!(
+            self,
+            DiffSection::Same(_) | DiffSection::Equivalent { .. } | DiffSection::Masked { .. }
+        )
+    }
+}
+
+/// Written as `{"kind": "added", "text": ...}` for text of one file or both, and as
+/// `{"kind": "modified", "left": ..., "right": ...}` for text that differs between them
+impl Serialize for DiffSection {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (kind, text, sides) = match self {
+            DiffSection::Added(text) => ("added", Some(text), None),
+            DiffSection::Same(text) => ("same", Some(text), None),
+            DiffSection::Removed(text) => ("removed", Some(text), None),
+            DiffSection::Modified { left, right } => ("modified", None, Some((left, right))),
+            DiffSection::Equivalent { left, right } => ("equivalent", None, Some((left, right))),
+            DiffSection::Masked { left, right } => ("masked", None, Some((left, right))),
+        };
+
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("kind", kind)?;
+
+        if let Some(text) = text {
+            map.serialize_entry("text", text)?;
+        }
+
+        if let Some((left, right)) = sides {
+            map.serialize_entry("left", left)?;
+            map.serialize_entry("right", right)?;
+        }
+
+        map.end()
+    }
+}
+
+/// The sections making up the diff of one pair of lines.
+///
+/// Adjacent sections never share a variant: pushing a section of the same kind as the last one
+/// extends it instead, so consumers can treat each section as a maximal run
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct LineDiff {
+    sections: Vec<DiffSection>,
+}
+
+impl LineDiff {
+    pub fn sections(&self) -> &[DiffSection] {
+        &self.sections
+    }
+
+    /// The text of file 1's line, put back together from the sections
+    pub fn left_text(&self) -> String {
+        self.sections
+            .iter()
+            .filter_map(|section| match section {
+                DiffSection::Added(_) => None,
+                DiffSection::Same(left)
+                | DiffSection::Removed(left)
+                | DiffSection::Modified { left, .. }
+                | DiffSection::Equivalent { left, .. }
+                | DiffSection::Masked { left, .. } => Some(left.as_str()),
+            })
+            .collect()
+    }
+
+    /// The text of file 2's line, put back together from the sections
+    pub fn right_text(&self) -> String {
+        self.sections
+            .iter()
+            .filter_map(|section| match section {
+                DiffSection::Removed(_) => None,
+                DiffSection::Same(right)
+                | DiffSection::Added(right)
+                | DiffSection::Modified { right, .. }
+                | DiffSection::Equivalent { right, .. }
+                | DiffSection::Masked { right, .. } => Some(right.as_str()),
+            })
+            .collect()
+    }
+
+    /// The sections without the `\n` or `\r\n` ending each line, as the lines are written one
+    /// to a row
+    pub fn without_line_endings(&self) -> LineDiff {
+        let ending_len = |text: String| {
+            if text.ends_with("\r\n") {
+                2
+            } else {
+                text.ends_with('\n') as usize
+            }
+        };
+        let (mut left_ending, mut right_ending) =
+            (ending_len(self.left_text()), ending_len(self.right_text()));
+
+        let mut sections = self.sections.clone();
+
+        // The endings are the last bytes of each side, so come off the sections at the end
+        for section in sections.iter_mut().rev() {
+            if left_ending == 0 && right_ending == 0 {
+                break;
+            }
+
+            let cut = |text: &mut String, ending: &mut usize| {
+                let length = (*ending).min(text.len());
+                text.truncate(text.len() - length);
+                *ending -= length;
+            };
+
+            match section {
+                DiffSection::Same(text) => {
+                    let mut ending = left_ending.min(right_ending);
+                    let before = ending;
+                    cut(text, &mut ending);
+
+                    left_ending -= before - ending;
+                    right_ending -= before - ending;
+                }
+                DiffSection::Added(right) => cut(right, &mut right_ending),
+                DiffSection::Removed(left) => cut(left, &mut left_ending),
+                DiffSection::Modified { left, right }
+                | DiffSection::Equivalent { left, right }
+                | DiffSection::Masked { left, right } => {
+                    cut(left, &mut left_ending);
+                    cut(right, &mut right_ending);
+                }
+            }
+        }
+
+        let mut line_diffs = LineDiff::default();
+        sections
+            .into_iter()
+            .filter(|section| !section.is_empty())
+            .for_each(|section| line_diffs.push(section));
+
+        line_diffs
+    }
+
+    /// Appends `section`, merging it into the last section if they are the same kind
+    pub fn push(&mut self, section: DiffSection) {
+        let last_section = match self.sections.last_mut() {
+            Some(last_section) => last_section,
+            None => {
+                self.sections.push(section);
+                return;
+            }
+        };
+
+        match (last_section, &section) {
+            (DiffSection::Added(ref mut a), DiffSection::Added(ref b))
+            | (DiffSection::Same(ref mut a), DiffSection::Same(ref b))
+            | (DiffSection::Removed(ref mut a), DiffSection::Removed(ref b)) => {
+                a.push_str(b);
+            }
+            (
+                DiffSection::Modified {
+                    left: ref mut left_a,
+                    right: ref mut right_a,
+                },
+                DiffSection::Modified {
+                    left: ref left_b,
+                    right: ref right_b,
+                },
+            ) => {
+                // Combine both sides
+                left_a.push_str(left_b);
+                right_a.push_str(right_b);
+            }
+            (
+                DiffSection::Equivalent {
+                    left: ref mut left_a,
+                    right: ref mut right_a,
+                },
+                DiffSection::Equivalent {
+                    left: ref left_b,
+                    right: ref right_b,
+                },
+            )
+            | (
+                DiffSection::Masked {
+                    left: ref mut left_a,
+                    right: ref mut right_a,
+                },
+                DiffSection::Masked {
+                    left: ref left_b,
+                    right: ref right_b,
+                },
+            ) => {
+                left_a.push_str(left_b);
+                right_a.push_str(right_b);
+            }
+            _ => {
+                // They don't match. Last section is completed
+                self.sections.push(section);
+            }
+        }
+    }
+}
+
+impl From<DiffSection> for LineDiff {
+    fn from(section: DiffSection) -> Self {
+        LineDiff {
+            sections: vec![section],
         }
     }
 }
 
-impl<'a> State<'a> {
+impl<'a, R: Read + Seek> State<'a, R> {
     pub fn new(
         first_diff: Option<DiffPosition>,
-        file1_line_positions: Vec<usize>,
-        file2_line_positions: Vec<usize>,
-        file1_reader: BufReader<File>,
-        file2_reader: BufReader<File>,
+        file1_line_positions: LinePositions,
+        file2_line_positions: LinePositions,
+        file1_reader: BufReader<R>,
+        file2_reader: BufReader<R>,
     ) -> Self {
         State {
             first_diff,
@@ -75,12 +682,69 @@ impl<'a> State<'a> {
 
             file1_reader,
             file2_reader,
+            file1_reader_position: None,
+            file2_reader_position: None,
+            read_error: None,
+
+            diff_options: DiffOptions::default(),
+            alignment: Alignment::default(),
+            file2_shift: None,
+            anchors: vec![],
+            resync_window: None,
+            collapse_repeats: false,
+            strip_prefix: None,
+            keep_prefix: false,
+            gutter: GutterOptions::default(),
+            style: DiffStyle::default(),
+            changes_only: false,
+            fold_same: false,
+            highlight_changed_lines: false,
+            show_whitespace: false,
+            tab_width: DEFAULT_TAB_WIDTH,
+            only_diffs: false,
+            only_diffs_context: DEFAULT_ONLY_DIFFS_CONTEXT,
+            fold_identical: None,
+            start_line: None,
+            start_diff: None,
+            skipped_lines: 0,
+
+            record_width: None,
+            hex: false,
+            max_rows: None,
+            diff_lines: vec![],
+            aligned_diffs: None,
+            diff_summary: DiffSummary::default(),
+            context: 0,
+            search_wraps: false,
+            max_columns: None,
+            max_line_width: None,
+            wrap_width: None,
 
             longest_line_length: 0,
+            gutter_width: 0,
             selected_line: 0,
             initial_horizontal_offset: 0,
+            context_chars: None,
             first_line_index: 0,
 
+            window: (0, 0),
+
+            file1_sources: vec![],
+            file2_sources: vec![],
+
+            file1_expanded: HashSet::new(),
+            file2_expanded: HashSet::new(),
+
+            file1_lines: vec![],
+            file2_lines: vec![],
+
+            file1_prefixes: vec![],
+            file2_prefixes: vec![],
+
+            rows: vec![],
+            separator_rows: vec![],
+            folds: vec![],
+            expanded_folds: vec![],
             line_diffs: vec![],
 
             file1_spans: vec![],
@@ -88,396 +752,4095 @@ impl<'a> State<'a> {
 
             file1_list_lines: vec![],
             file2_list_lines: vec![],
+
+            pinned: None,
+            file1_pinned_line: None,
+            file2_pinned_line: None,
         }
     }
 
     pub fn build_state(&mut self, lines_to_load: usize) {
-        let (file1_raw_lines, file2_raw_lines) = if let Some(diff) = &self.first_diff {
-            let diff_line_index = diff.line_index;
-            self.initial_horizontal_offset = diff.line_offset;
+        // Load at least the context lines on either side of the diff
+        let lines_to_load = self.capped_line_count(lines_to_load.max(self.context * 2 + 1));
 
-            let lines = self.get_lines_around_line(diff_line_index, lines_to_load);
+        let start_diff = self
+            .start_diff
+            .and_then(|diff| self.diff_marks().get(diff.saturating_sub(1)).copied());
 
-            self.selected_line = diff_line_index - self.first_line_index;
+        if let Some(line_index) = self.start_line {
+            let line_index = line_index.min(self.line_count().saturating_sub(1));
 
-            lines
-        } else {
-            self.get_lines_around_line(0, lines_to_load)
-        };
+            self.load_lines_around_line(line_index, lines_to_load);
 
-        self.longest_line_length = longest_line_length(&file1_raw_lines, &file2_raw_lines);
+            self.selected_line = self.row_for_line(line_index);
 
-        self.line_diffs = self.calculate_diffs(&file1_raw_lines, &file2_raw_lines);
+            let changed = self
+                .line_diffs
+                .get(self.selected_line)
+                .is_some_and(|line_diffs| line_diffs.sections().iter().any(DiffSection::is_change));
 
-        let (file1_spans, file2_spans) = build_spans(&self.line_diffs);
+            // A changed line opens at its first change, like the first diff
+            if let Some((_, diff_offset)) = changed
+                .then(|| self.first_diff_from(self.selected_line))
+                .flatten()
+            {
+                self.initial_horizontal_offset = self.diff_column(self.selected_line, diff_offset);
+            }
+        } else if let Some(line_index) = start_diff {
+            self.load_lines_around_line(line_index, lines_to_load);
 
-        self.file1_spans = file1_spans;
-        self.file2_spans = file2_spans;
+            // A row before the diff's can have the other file's line with the same index
+            let row = self.row_for_line(line_index);
+
+            match self.first_diff_from(row) {
+                Some((row, diff_offset)) => {
+                    self.selected_line = row;
+                    self.initial_horizontal_offset = self.diff_column(row, diff_offset);
+                }
+                None => self.selected_line = row,
+            }
+        } else if let Some(diff) = &self.first_diff {
+            let diff_line_index = diff.line_index;
+            let diff_offset = diff.line_offset;
+
+            self.load_lines_around_line(diff_line_index, lines_to_load);
 
-        self.initial_horizontal_offset = if self.initial_horizontal_offset > 5 {
-            self.initial_horizontal_offset - 5
+            self.selected_line = self.row_for_line(diff_line_index);
+
+            let diff_offset = self.past_kept_prefix(self.selected_line, diff_offset);
+            self.initial_horizontal_offset = self.diff_column(self.selected_line, diff_offset);
         } else {
-            0
-        };
+            self.load_lines_around_line(0, lines_to_load);
+        }
+
+        self.initial_horizontal_offset = self
+            .initial_horizontal_offset
+            .saturating_sub(self.context_chars.unwrap_or(DEFAULT_CONTEXT_CHARS));
 
         // Index into line number
         self.build_lines(self.initial_horizontal_offset, self.first_line_index + 1);
     }
 
-    pub fn build_lines(&mut self, horizontal_offset: usize, start_line_number: usize) {
-        let (file1_list_lines, file2_list_lines) = build_lines(
-            &self.file1_spans,
-            &self.file2_spans,
-            horizontal_offset,
-            start_line_number,
-        );
+    /// Reloads the window with `lines_to_load` lines around the selected line, keeping it
+    /// selected
+    pub fn resize_window(&mut self, lines_to_load: usize) {
+        let lines_to_load = self.capped_line_count(lines_to_load);
 
-        self.file1_list_lines = file1_list_lines;
-        self.file2_list_lines = file2_list_lines;
-    }
+        let selected_line_index = self.selected_line_index();
 
-    pub fn get_lines_around_line(
-        &mut self,
-        line_index: usize,
-        line_count: usize,
-    ) -> (Vec<String>, Vec<String>) {
-        let bottom_line_index = (line_index as i32) - ((line_count / 2) as i32);
+        self.load_lines_around_line(selected_line_index, lines_to_load);
 
-        let bottom_line_index = if bottom_line_index > 0 {
-            bottom_line_index as usize
-        } else {
-            0
-        };
+        self.selected_line = self.row_for_line(selected_line_index);
+    }
 
-        let top_line_index = bottom_line_index + line_count;
+    /// Swaps in the line index and readers of `indexed`, a new scan of the same files, keeping
+    /// the view settings. The selected line stays selected if the files still have it
+    pub fn reload(&mut self, indexed: State<'a, R>) {
+        let selected_line_index = self.selected_line_index();
 
-        let mut file1_lines = vec![];
-        let mut file2_lines = vec![];
+        self.first_diff = indexed.first_diff;
+        self.file1_line_positions = indexed.file1_line_positions;
+        self.file2_line_positions = indexed.file2_line_positions;
+        self.file1_reader = indexed.file1_reader;
+        self.file2_reader = indexed.file2_reader;
+        self.file1_reader_position = None;
+        self.file2_reader_position = None;
+        self.diff_lines = indexed.diff_lines;
+        self.aligned_diffs = indexed.aligned_diffs;
+        self.diff_summary = indexed.diff_summary;
 
-        for i in bottom_line_index..top_line_index {
-            let line1_offset = self.file1_line_positions.get(i);
-            if let Some(line1_offset) = line1_offset {
-                let line1 = self
-                    .read_line_at_offset(true, *line1_offset as u64)
-                    .expect("Could not read line");
-                file1_lines.push(line1);
-            }
+        // Repeats, folds, and the pinned row may not be there anymore
+        self.file1_expanded.clear();
+        self.file2_expanded.clear();
+        self.expanded_folds.clear();
+        self.pinned = None;
 
-            let line2_offset = self.file2_line_positions.get(i);
-            if let Some(line2_offset) = line2_offset {
-                let line2 = self
-                    .read_line_at_offset(false, *line2_offset as u64)
-                    .expect("Could not read line");
-                file2_lines.push(line2);
-            }
-        }
+        let line_index = selected_line_index.min(self.line_count().saturating_sub(1));
 
-        self.first_line_index = bottom_line_index;
+        self.load_lines_around_line(line_index, self.window.1);
 
-        (file1_lines, file2_lines)
+        self.selected_line = self.row_for_line(line_index);
     }
 
-    pub fn calculate_diffs(
-        &mut self,
-        file1_lines: &Vec<String>,
-        file2_lines: &Vec<String>,
-    ) -> Vec<Vec<DiffSection>> {
-        file1_lines
-            .iter()
-            .zip_longest(file2_lines)
-            .map(|line| match line {
-                EitherOrBoth::Both(line1, line2) => self.calculate_line_diffs(line1, line2),
-                EitherOrBoth::Left(line1) => vec![DiffSection::Removed(line1.clone())],
-                EitherOrBoth::Right(line2) => vec![DiffSection::Added(line2.clone())],
+    /// The line index shown in the selected row, from file 1 if it has a line there
+    fn selected_line_index(&self) -> usize {
+        if let Some(lines) = self.fold_lines(self.selected_line) {
+            return lines.start;
+        }
+
+        self.rows
+            .get(self.selected_line)
+            .and_then(|row| {
+                let (file1_index, file2_index) = self.row_window_lines(row);
+                file1_index.or(file2_index)
             })
-            .collect()
+            .map_or(self.window.0, |index| self.first_line_index + index)
     }
 
-    fn calculate_line_diffs(&self, line1: &String, line2: &String) -> Vec<DiffSection> {
-        let mut last_diff: Option<DiffSection> = None;
-
-        let mut diffs: Vec<DiffSection> = vec![];
+    /// The first line from 0-based `start` on containing `needle` in either file, as its line
+    /// index and the character offset of the match in the shown text. Lines are read from the files, so
+    /// lines outside the loaded window are found too. With `search_wraps`, the search carries on
+    /// from the first line after the last
+    pub fn find_line_matching(&mut self, start: usize, needle: &str) -> Option<(usize, usize)> {
+        let line_count = self.line_count();
 
-        let mut merge_diff = |last_diff: &mut Option<DiffSection>, new_diff: DiffSection| {
-            if let Some(mut inner_last_diff) = last_diff.take() {
-                match (&mut inner_last_diff, &new_diff) {
-                    (DiffSection::Added(ref mut a), &DiffSection::Added(ref b))
-                    | (DiffSection::Same(ref mut a), &DiffSection::Same(ref b))
-                    | (DiffSection::Removed(ref mut a), &DiffSection::Removed(ref b)) => {
-                        a.push_str(&b);
-                        // We consumed the Option, so we have to re-place the value
-                        *last_diff = Some(inner_last_diff);
-                    }
-                    (
-                        DiffSection::Modified {
-                            left: ref mut left_a,
-                            right: ref mut right_a,
-                        },
-                        DiffSection::Modified {
-                            left: ref left_b,
-                            right: ref right_b,
-                        },
-                    ) => {
-                        // Combine both sides
-                        left_a.push_str(&left_b);
-                        right_a.push_str(&right_b);
-                        *last_diff = Some(inner_last_diff);
-                    }
-                    _ => {
-                        // They don't match. Last diff is completed. Push new diff
-                        diffs.push(inner_last_diff);
-                        *last_diff = Some(new_diff);
-                    }
-                }
-            } else {
-                // Directly push new diff
-                *last_diff = Some(new_diff);
-            }
+        let wrapped = if self.search_wraps {
+            0..start.min(line_count)
+        } else {
+            0..0
         };
 
-        for combined_chars in line1.chars().zip_longest(line2.chars()) {
-            match combined_chars {
-                EitherOrBoth::Both(char1, char2) => {
-                    if char1 == char2 {
-                        merge_diff(&mut last_diff, DiffSection::Same(char1.to_string()));
-                    } else {
-                        merge_diff(
-                            &mut last_diff,
-                            DiffSection::Modified {
-                                left: char1.to_string(),
-                                right: char2.to_string(),
-                            },
-                        );
-                    }
-                }
-                EitherOrBoth::Left(char) => {
-                    merge_diff(&mut last_diff, DiffSection::Removed(char.to_string()))
-                }
-                EitherOrBoth::Right(char) => {
-                    merge_diff(&mut last_diff, DiffSection::Added(char.to_string()))
+        for line_index in (start..line_count).chain(wrapped) {
+            for file1 in [true, false] {
+                let positions = if file1 {
+                    &self.file1_line_positions
+                } else {
+                    &self.file2_line_positions
+                };
+
+                let Some(offset) = positions.get(line_index) else {
+                    continue;
+                };
+
+                let Ok(line) = self.read_line_at_offset(file1, offset as u64) else {
+                    continue;
+                };
+
+                let shown = if self.keep_prefix {
+                    line.as_str()
+                } else {
+                    StripPrefix::split_optional(self.strip_prefix.as_ref(), &line, file1).1
+                };
+
+                if let Some(byte_index) = shown.find(needle) {
+                    return Some((line_index, shown[..byte_index].chars().count()));
                 }
             }
         }
 
-        if let Some(last_diff) = last_diff {
-            diffs.push(last_diff);
-        }
+        None
+    }
 
-        diffs
+    /// Reloads the window around 0-based `line_index`, clamped to the lines of the longer file,
+    /// and selects it. Returns the line index jumped to
+    pub fn jump_to_line(&mut self, line_index: usize) -> usize {
+        let line_index = line_index.min(self.line_count().saturating_sub(1));
+
+        self.load_lines_around_line(line_index, self.window.1);
+        self.selected_line = self.row_for_line(line_index);
+
+        line_index
     }
 
-    fn read_line_at_offset(&mut self, file1: bool, offset: u64) -> io::Result<String> {
-        let reader = if file1 {
-            &mut self.file1_reader
+    /// Moves the selection `step` rows down, or up. When that comes within a quarter of the
+    /// window of its edge and the files have lines past it, the window is reloaded around the
+    /// selected line, so scrolling walks the whole file
+    pub fn scroll(&mut self, down: bool, step: usize) {
+        let last_row = self.rows.len().saturating_sub(1);
+
+        let (row, overshoot) = if down {
+            let row = self.selected_line + step;
+            (row.min(last_row), row.saturating_sub(last_row))
         } else {
-            &mut self.file2_reader
+            (
+                self.selected_line.saturating_sub(step),
+                step.saturating_sub(self.selected_line),
+            )
         };
 
-        let mut buffer = String::new();
+        self.selected_line = row;
 
-        reader.seek(SeekFrom::Start(offset))?;
-        reader.read_line(&mut buffer)?;
+        let margin = self.rows.len() / 4;
 
-        Ok(buffer)
-    }
-
-    pub fn find_next_diff(&self, match_line: usize, match_offset: usize) -> Option<(usize, usize)> {
-        for (line_number, line_diffs) in self.line_diffs[match_line..].iter().enumerate() {
-            // Make sure index is actually to the start of the lines
-            let line_number = line_number + match_line;
-            let mut line_offset = 0;
+        // Folds at the edges of the window hide the lines past them too
+        let window_start = self
+            .folds
+            .first()
+            .map_or(self.first_line_index, |(_, lines)| {
+                lines.start.min(self.first_line_index)
+            });
+        let window_end = self
+            .folds
+            .last()
+            .map_or(self.first_line_index + self.window.1, |(_, lines)| {
+                lines.end.max(self.first_line_index + self.window.1)
+            });
 
-            for diff in line_diffs {
-                match diff {
-                    DiffSection::Added(_)
-                    | DiffSection::Modified { left: _, right: _ }
-                    | DiffSection::Removed(_) => {
-                        if line_offset > match_offset || line_number > match_line {
-                            // This is the next diff
-                            return Some((line_number, line_offset));
-                        }
-                    }
-                    _ => {}
-                }
+        let reload = if down {
+            row + margin >= last_row && window_end < self.line_count()
+        } else {
+            row <= margin && window_start > 0
+        };
 
-                line_offset += diff.left_len();
-            }
+        if !reload {
+            return;
         }
 
-        None
+        // Steps past the edge of the window carry on into the newly loaded lines, and off a fold
+        // past all of its lines
+        let line_index = match self.fold_lines(self.selected_line) {
+            Some(lines) if down && overshoot > 0 => lines.end - 1 + overshoot,
+            _ if down => self.selected_line_index() + overshoot,
+            _ => self.selected_line_index().saturating_sub(overshoot),
+        };
+
+        self.jump_to_line(line_index);
     }
 
-    pub fn find_prev_diff(&self, match_line: usize, match_offset: usize) -> Option<(usize, usize)> {
-        for (line_number, line_diffs) in self.line_diffs[..match_line + 1].iter().enumerate().rev()
-        {
-            let line_width = line_diffs
-                .iter()
-                .map(|diff| diff.left_len())
-                .reduce(|acc, width| acc + width)
-                .unwrap_or(0);
+    /// Reloads the window around the first diff, returning its row and column. `None` when the
+    /// files don't differ
+    pub fn jump_to_first_diff(&mut self) -> Option<(usize, usize)> {
+        let diff = self.first_diff.clone()?;
 
-            let mut line_offset = line_width;
+        self.jump_to_line(diff.line_index);
 
-            for diff in line_diffs.iter().rev() {
-                line_offset -= diff.left_len();
+        Some((
+            self.selected_line,
+            self.past_kept_prefix(self.selected_line, diff.line_offset),
+        ))
+    }
 
-                match diff {
-                    DiffSection::Added(_)
-                    | DiffSection::Modified { left: _, right: _ }
-                    | DiffSection::Removed(_) => {
-                        if line_offset < match_offset || line_number < match_line {
-                            // This is the prev diff
-                            return Some((line_number, line_offset));
-                        }
-                    }
-                    _ => {}
-                }
-            }
+    /// `offset` into a line without its prefix, as the scan compares it, moved past the prefix
+    /// `keep_prefix` shows in front of the line in `row`
+    fn past_kept_prefix(&self, row: usize, offset: usize) -> usize {
+        let prefix = self
+            .line_diffs
+            .get(row)
+            .and_then(|line_diffs| line_diffs.sections().first())
+            .filter(|_| self.keep_prefix);
+
+        match prefix {
+            Some(prefix @ DiffSection::Masked { .. }) => offset + self.offset_len(row, prefix),
+            _ => offset,
         }
+    }
 
-        None
+    /// Reloads the window around the last diff, returning its row and column. The search starts
+    /// at the last line marked differing and walks back a window at a time, as aligning
+    /// the lines can pair that one up. `None`, with the window left alone, when there is no diff
+    pub fn jump_to_last_diff(&mut self) -> Option<(usize, usize)> {
+        let mut line_index = *self.diff_marks().last()?;
+        let selected_line_index = self.selected_line_index();
+
+        loop {
+            self.jump_to_line(line_index);
+
+            let last_row = self.rows.len().saturating_sub(1);
+
+            if let Some(diff) = self.find_prev_diff(last_row, usize::MAX) {
+                return Some(diff);
+            }
+
+            if self.first_line_index == 0 {
+                self.jump_to_line(selected_line_index);
+
+                return None;
+            }
+
+            line_index = self.first_line_index - 1;
+        }
     }
-}
 
-fn longest_line_length(file1_lines: &Vec<String>, file2_lines: &Vec<String>) -> usize {
-    let mut longest_length = 0;
+    /// The next diff after column `offset` of `row`, reloading the window around it when it is
+    /// past the loaded lines, as its row and column. Diffs past the window are looked for from
+    /// `diff_marks`, a window at a time as the window may not pair the lines up like the whole
+    /// files. `None`, with the window left alone, when there is no later diff
+    pub fn jump_to_next_diff(&mut self, row: usize, offset: usize) -> Option<(usize, usize)> {
+        if let Some(diff) = self.find_next_diff(row, offset) {
+            return Some(diff);
+        }
 
-    for line in file1_lines.iter().chain(file2_lines.iter()) {
-        if line.len() > longest_length {
-            longest_length = line.len();
+        let selected_line_index = self.selected_line_index();
+
+        loop {
+            let window_end = self.first_line_index + self.window_line_count();
+            let next = self.diff_marks().partition_point(|&line| line < window_end);
+
+            let Some(&line_index) = self.diff_marks().get(next) else {
+                self.jump_to_line(selected_line_index);
+
+                return None;
+            };
+
+            self.jump_to_line(line_index);
+
+            // Rows of lines that were already loaded were searched already
+            let start_row = if window_end > self.first_line_index {
+                self.row_for_line(window_end)
+            } else {
+                0
+            };
+
+            if let Some(diff) = self.first_diff_from(start_row) {
+                return Some(diff);
+            }
         }
     }
 
-    longest_length
-}
+    /// Like `jump_to_next_diff`, the diff before column `offset` of `row`
+    pub fn jump_to_prev_diff(&mut self, row: usize, offset: usize) -> Option<(usize, usize)> {
+        if let Some(diff) = self.find_prev_diff(row, offset) {
+            return Some(diff);
+        }
 
-fn build_spans<'a, 'b>(diffs: &'a Vec<Vec<DiffSection>>) -> (Vec<Spans<'b>>, Vec<Spans<'b>>) {
-    diffs
-        .iter()
-        .map(|line_diffs| {
-            let mut line1 = Spans::default();
-            let mut line2 = Spans::default();
+        let selected_line_index = self.selected_line_index();
 
-            for diff in line_diffs.iter() {
-                match diff {
-                    DiffSection::Added(string) => line2.0.push(Span::styled(
-                        string.clone(),
-                        Style::default().bg(Color::Rgb(0, 100, 0)),
-                    )),
-                    DiffSection::Modified { left, right } => {
-                        line1.0.push(Span::styled(
-                            left.clone(),
-                            Style::default()
-                                .add_modifier(Modifier::BOLD)
-                                .bg(Color::Blue),
-                        ));
-
-                        line2.0.push(Span::styled(
-                            right.clone(),
-                            Style::default()
-                                .add_modifier(Modifier::BOLD)
-                                .bg(Color::Blue),
-                        ));
-                    }
-                    DiffSection::Same(string) => {
-                        let span = Span::raw(string.clone());
+        loop {
+            let window_start = self.first_line_index;
+            let prev = self
+                .diff_marks()
+                .partition_point(|&line| line < window_start);
 
-                        line1.0.push(span.clone());
-                        line2.0.push(span);
-                    }
-                    DiffSection::Removed(string) => line1.0.push(Span::styled(
-                        string.clone(),
-                        Style::default().bg(Color::Red),
-                    )),
+            let Some(&line_index) = prev.checked_sub(1).map(|prev| &self.diff_marks()[prev]) else {
+                self.jump_to_line(selected_line_index);
+
+                return None;
+            };
+
+            self.jump_to_line(line_index);
+
+            let end_row = if window_start < self.first_line_index + self.window_line_count() {
+                self.row_for_line(window_start)
+            } else {
+                self.rows.len()
+            };
+
+            if let Some(diff) = self.find_prev_diff(end_row, 0) {
+                return Some(diff);
+            }
+        }
+    }
+
+    /// Lines of the longer file in the loaded window
+    fn window_line_count(&self) -> usize {
+        self.file1_lines.len().max(self.file2_lines.len())
+    }
+
+    /// The first diff in `row` or a later one
+    fn first_diff_from(&self, row: usize) -> Option<(usize, usize)> {
+        let mut offset = 0;
+        let line_diffs = self.line_diffs.get(row)?;
+
+        if self.row_differs(row) {
+            for section in line_diffs.sections() {
+                if section.is_change() {
+                    return Some((row, offset));
                 }
+
+                offset += self.offset_len(row, section);
             }
+        }
 
-            (line1, line2)
-        })
-        .unzip()
-}
+        self.find_next_diff(row, usize::MAX)
+    }
 
-fn build_lines<'a>(
-    file1_spans: &Vec<Spans<'a>>,
-    file2_spans: &Vec<Spans<'a>>,
-    horizontal_offset: usize,
-    start_line_number: usize,
-) -> (Vec<ListItem<'a>>, Vec<ListItem<'a>>) {
-    let add_left_placeholder = |spans: Spans<'a>, original_length: usize| -> Spans<'a> {
-        if original_length == 0 {
-            // String was empty to begin with. EOF
-            Spans::from(Span::styled(
-                "EOF",
-                Style::default().add_modifier(Modifier::DIM),
-            ))
-        } else if spans.width() == 0 {
-            Spans::from(Span::styled(
-                "<==",
-                Style::default().add_modifier(Modifier::DIM),
-            ))
+    fn capped_line_count(&self, lines_to_load: usize) -> usize {
+        self.max_rows
+            .map_or(lines_to_load, |max_rows| lines_to_load.min(max_rows))
+    }
+
+    /// Reads, diffs, and styles the window of lines around `line_index`
+    pub fn load_lines_around_line(&mut self, line_index: usize, line_count: usize) {
+        self.window = (line_index, line_count);
+
+        let (mut file1_raw_lines, mut file2_raw_lines) =
+            self.get_lines_around_line(line_index, line_count);
+
+        self.file1_prefixes = self.strip_prefixes(&mut file1_raw_lines, true);
+        self.file2_prefixes = self.strip_prefixes(&mut file2_raw_lines, false);
+
+        let (file1_raw_lines, file2_raw_lines) = if self.collapse_repeats {
+            let (file1_lines, file1_sources) = repeats::collapse_repeats(
+                file1_raw_lines,
+                self.first_line_index,
+                &self.file1_expanded,
+            );
+            let (file2_lines, file2_sources) = repeats::collapse_repeats(
+                file2_raw_lines,
+                self.first_line_index,
+                &self.file2_expanded,
+            );
+
+            self.file1_sources = file1_sources;
+            self.file2_sources = file2_sources;
+
+            (file1_lines, file2_lines)
         } else {
-            spans
+            self.file1_sources = repeats::uncollapsed(file1_raw_lines.len());
+            self.file2_sources = repeats::uncollapsed(file2_raw_lines.len());
+
+            (file1_raw_lines, file2_raw_lines)
+        };
+
+        self.longest_line_length =
+            longest_line_length(&file1_raw_lines, &file2_raw_lines, self.tab_width);
+
+        if self.keep_prefix {
+            self.longest_line_length += self
+                .file1_prefixes
+                .iter()
+                .chain(&self.file2_prefixes)
+                .map(|prefix| prefix.columns())
+                .max()
+                .unwrap_or(0);
         }
-    };
 
-    let process_spans_into_lines = |spans: &Vec<Spans<'a>>| -> Vec<ListItem<'a>> {
-        spans
+        self.file1_lines = file1_raw_lines;
+        self.file2_lines = file2_raw_lines;
+
+        self.align_window();
+    }
+
+    /// Pairs up, diffs, and styles the lines of the loaded window
+    fn align_window(&mut self) {
+        self.rows = match self.file2_shift {
+            Some(shift) => align::shifted(self.file1_lines.len(), self.file2_lines.len(), shift),
+            None => self.anchored_rows(),
+        };
+        // Re-pairing can leave fewer rows than before, so keep the selection on one of them
+        self.selected_line = self.selected_line.min(self.rows.len().saturating_sub(1));
+        self.line_diffs = calculate_diffs(
+            &self.rows,
+            &self.file1_lines,
+            &self.file2_lines,
+            &self.diff_options,
+        );
+
+        if self.max_line_width.is_some() && self.diff_options.compare_width.is_none() {
+            self.mark_cut_changes();
+        }
+
+        if self.keep_prefix {
+            for (row, line_diffs) in self.rows.iter().zip(self.line_diffs.iter_mut()) {
+                let left = row_prefix(row.file1, &self.file1_sources, &self.file1_prefixes);
+                let right = row_prefix(row.file2, &self.file2_sources, &self.file2_prefixes);
+
+                if !left.is_empty() || !right.is_empty() {
+                    // Shown like masked text, as neither is compared
+                    let mut prefixed = LineDiff::from(DiffSection::Masked {
+                        left: left.to_string(),
+                        right: right.to_string(),
+                    });
+
+                    for section in line_diffs.sections.drain(..) {
+                        prefixed.push(section);
+                    }
+
+                    *line_diffs = prefixed;
+                }
+            }
+        }
+
+        self.separator_rows.clear();
+        self.folds.clear();
+
+        if self.only_diffs {
+            self.hide_unchanged_rows();
+        }
+
+        if let Some(threshold) = self.fold_identical {
+            self.fold_identical_rows(threshold);
+        }
+
+        self.rebuild_spans();
+    }
+
+    /// Marks the `…` ending lines cut off by `max_line_width` as changed in the rows where nothing
+    /// before it is, but the scan found the whole lines differing
+    fn mark_cut_changes(&mut self) {
+        for row in 0..self.rows.len() {
+            let (Some(line1), Some(line2)) = self.row_lines(row) else {
+                continue;
+            };
+
+            // The scan only compares lines with the same index
+            if line1 != line2 || self.diff_lines.binary_search(&line1).is_err() {
+                continue;
+            }
+
+            let line_diffs = &mut self.line_diffs[row];
+
+            if line_diffs.sections().iter().any(|diff| diff.is_change()) {
+                continue;
+            }
+
+            let Some(DiffSection::Same(text)) = line_diffs.sections.last_mut() else {
+                continue;
+            };
+
+            let Some(uncut) = text.strip_suffix('…') else {
+                continue;
+            };
+
+            text.truncate(uncut.len());
+
+            if text.is_empty() {
+                line_diffs.sections.pop();
+            }
+
+            line_diffs.push(DiffSection::Modified {
+                left: "…".to_string(),
+                right: "…".to_string(),
+            });
+        }
+    }
+
+    /// Replaces each run of rows further than `only_diffs_context` rows from a changed row with a
+    /// separator row. The rows left keep their own line numbers
+    fn hide_unchanged_rows(&mut self) {
+        let mut shown = vec![false; self.rows.len()];
+
+        for (index, line_diffs) in self.line_diffs.iter().enumerate() {
+            if line_diffs.sections().iter().any(|diff| diff.is_change()) {
+                let start = index.saturating_sub(self.only_diffs_context);
+                let end = (index + self.only_diffs_context + 1).min(shown.len());
+
+                shown[start..end].fill(true);
+            }
+        }
+
+        let rows = std::mem::take(&mut self.rows);
+        let line_diffs = std::mem::take(&mut self.line_diffs);
+
+        let mut hidden_count = 0;
+
+        for ((row, line_diffs), shown) in rows.into_iter().zip(line_diffs).zip(shown) {
+            if !shown {
+                hidden_count += 1;
+                continue;
+            }
+
+            self.push_separator(hidden_count);
+            hidden_count = 0;
+
+            self.rows.push(row);
+            self.line_diffs.push(line_diffs);
+        }
+
+        self.push_separator(hidden_count);
+    }
+
+    /// Replaces each run of more than `threshold` identical lines in both files with a fold row,
+    /// unless it overlaps a fold that was expanded. A run reaching the edge of the window folds
+    /// the lines past it the scan found identical too, so scrolling steps over it in one row
+    fn fold_identical_rows(&mut self, threshold: usize) {
+        // The lines of each row that can be folded
+        let identical: Vec<Option<(usize, usize)>> = (0..self.rows.len())
+            .map(|row| {
+                let (file1_line, file2_line) = self.row_lines(row);
+                let unchanged = !self.line_diffs[row]
+                    .sections()
+                    .iter()
+                    .any(|diff| diff.is_change());
+
+                file1_line
+                    .zip(file2_line)
+                    .filter(|_| unchanged && !self.separator_rows.contains(&row))
+            })
+            .collect();
+
+        let rows = std::mem::take(&mut self.rows);
+        let mut line_diffs = std::mem::take(&mut self.line_diffs).into_iter();
+        let separator_rows = std::mem::take(&mut self.separator_rows);
+
+        let mut index = 0;
+
+        while index < rows.len() {
+            let run_length = identical[index..]
+                .iter()
+                .take_while(|line| line.is_some())
+                .count();
+
+            let folded_lines = identical[index]
+                .zip(identical[(index + run_length).saturating_sub(1)])
+                .map(|(first, last)| {
+                    // Only lines paired with the same line of the other file were compared by
+                    // the scan
+                    let start = match first {
+                        (line1, line2) if index == 0 && line1 == line2 => {
+                            self.identical_run_start(line1)
+                        }
+                        (line1, _) => line1,
+                    };
+
+                    let end = match last {
+                        (line1, line2) if index + run_length == rows.len() && line1 == line2 => {
+                            self.identical_run_end(line1)
+                        }
+                        (line1, _) => line1 + 1,
+                    };
+
+                    start..end
+                })
+                .filter(|lines| {
+                    lines.len() > threshold
+                        && !self.expanded_folds.iter().any(|expanded| {
+                            expanded.start < lines.end && lines.start < expanded.end
+                        })
+                });
+
+            if let Some(lines) = folded_lines {
+                let line_count = lines.len();
+
+                self.folds.push((self.rows.len(), lines));
+                self.separator_rows.push(self.rows.len());
+                self.rows.push(Row {
+                    file1: None,
+                    file2: None,
+                });
+                self.line_diffs
+                    .push(LineDiff::from(DiffSection::Same(format!(
+                        "⋯ {} identical lines ⋯",
+                        line_count
+                    ))));
+
+                line_diffs.by_ref().take(run_length).for_each(drop);
+                index += run_length;
+                continue;
+            }
+
+            let kept = index..index + run_length.max(1);
+
+            for (row, &kept_row) in kept.clone().zip(&rows[kept]) {
+                if separator_rows.contains(&row) {
+                    self.separator_rows.push(self.rows.len());
+                }
+
+                self.rows.push(kept_row);
+                self.line_diffs.extend(line_diffs.next());
+            }
+
+            index += run_length.max(1);
+        }
+    }
+
+    /// The first line of the run of lines the scan found identical that `line_index` is in
+    fn identical_run_start(&self, line_index: usize) -> usize {
+        let prev = self.diff_lines.partition_point(|&line| line < line_index);
+
+        prev.checked_sub(1)
+            .map_or(0, |prev| self.diff_lines[prev] + 1)
+    }
+
+    /// The line after the run of lines the scan found identical that `line_index` is in, at most
+    /// the end of the shorter file
+    fn identical_run_end(&self, line_index: usize) -> usize {
+        let next = self.diff_lines.partition_point(|&line| line <= line_index);
+        let shorter_length = self
+            .file1_line_positions
+            .len()
+            .min(self.file2_line_positions.len());
+
+        self.diff_lines
+            .get(next)
+            .map_or(shorter_length, |&line| line.min(shorter_length))
+    }
+
+    /// The file 1 lines folded into `row`, if it is a fold
+    fn fold_lines(&self, row: usize) -> Option<Range<usize>> {
+        self.folds
             .iter()
-            .enumerate()
-            .map(|(index, spans)| {
-                let original_length = spans.width();
+            .find(|(fold_row, _)| *fold_row == row)
+            .map(|(_, lines)| lines.clone())
+    }
 
-                let mut spans = add_left_placeholder(
-                    spans_substring(spans.clone(), horizontal_offset),
-                    original_length,
-                );
+    /// Shows the lines folded into `row` in its place, selecting the first of them in the window.
+    /// Returns whether `row` was a fold
+    pub fn expand_fold(&mut self, row: usize) -> bool {
+        let Some(lines) = self.fold_lines(row) else {
+            return false;
+        };
 
-                let full_sized_number_string = format!("{} ", start_line_number + index);
+        self.expanded_folds.push(lines.clone());
+        self.align_window();
 
-                let number_string = if full_sized_number_string.len() <= 9 {
-                    format!("{:8} ", start_line_number + index)
-                } else {
-                    full_sized_number_string
-                };
+        self.selected_line = self.row_for_line(lines.start.max(self.first_line_index));
 
-                spans.0.insert(
-                    0,
-                    Span::styled(number_string, Style::default().add_modifier(Modifier::DIM)),
-                );
+        true
+    }
+
+    /// Folds the expanded run of identical lines `row` is in again, selecting the fold. Returns
+    /// whether `row` was in one
+    pub fn collapse_fold(&mut self, row: usize) -> bool {
+        let Some(line_index) = self.row_lines(row).0 else {
+            return false;
+        };
+
+        let count = self.expanded_folds.len();
 
-                ListItem::new(spans)
+        self.expanded_folds
+            .retain(|lines| !lines.contains(&line_index));
+
+        if self.expanded_folds.len() == count {
+            return false;
+        }
+
+        self.align_window();
+        self.selected_line = self.row_for_line(line_index);
+
+        true
+    }
+
+    /// Adds a separator row for `hidden_count` hidden rows, if there are any
+    fn push_separator(&mut self, hidden_count: usize) {
+        if hidden_count == 0 {
+            return;
+        }
+
+        let plural = if hidden_count == 1 { "" } else { "s" };
+
+        self.separator_rows.push(self.rows.len());
+        self.rows.push(Row {
+            file1: None,
+            file2: None,
+        });
+        self.line_diffs
+            .push(LineDiff::from(DiffSection::Same(format!(
+                "⋯ {} line{} hidden ⋯",
+                hidden_count, plural
+            ))));
+    }
+
+    /// Strips the prefix from each of `lines` of file 1 (or file 2), returning the prefixes
+    fn strip_prefixes(&self, lines: &mut [String], file1: bool) -> Vec<String> {
+        let Some(strip_prefix) = &self.strip_prefix else {
+            return vec![];
+        };
+
+        lines
+            .iter_mut()
+            .map(|line| {
+                let prefix = strip_prefix.split(line, file1).0.to_string();
+                line.drain(..prefix.len());
+
+                prefix
             })
             .collect()
-    };
+    }
 
-    (
-        process_spans_into_lines(file1_spans),
-        process_spans_into_lines(file2_spans),
-    )
-}
+    /// Aligns the window with `alignment` between the anchors, which are kept paired up
+    fn anchored_rows(&self) -> Vec<Row> {
+        let window_index = |sources: &[LineSource], line_index: usize| {
+            let loaded_index = line_index.checked_sub(self.first_line_index)?;
 
-fn spans_substring<'a>(spans: Spans<'a>, horizontal_offset: usize) -> Spans<'a> {
-    let mut required_offset = horizontal_offset;
+            sources
+                .iter()
+                .position(|source| *source == LineSource::Line(loaded_index))
+        };
 
-    let spans: Vec<Span<'_>> = spans
-        .0
-        .into_iter()
-        .filter_map(|span| {
-            if required_offset == 0 {
-                // Consume this span
-                Some(span)
-            } else if required_offset < span.width() {
-                // Offset is within this span
-                let text = span.content.slice(required_offset..).to_string().clone();
-                required_offset = 0;
-                Some(Span::styled(text, span.style.clone()))
-            } else {
-                // Offset is not within this span. Skip it
-                required_offset -= span.width();
-                None
+        // Anchors that cross an earlier one can't both hold, so the earlier one wins
+        let mut fixed_rows: Vec<(usize, usize)> = vec![];
+
+        for &(file1_line, file2_line) in &self.anchors {
+            if let (Some(file1_index), Some(file2_index)) = (
+                window_index(&self.file1_sources, file1_line),
+                window_index(&self.file2_sources, file2_line),
+            ) {
+                let after_last = fixed_rows
+                    .last()
+                    .is_none_or(|&(last1, last2)| file1_index > last1 && file2_index > last2);
+
+                if after_last {
+                    fixed_rows.push((file1_index, file2_index));
+                }
             }
-        })
-        .collect();
+        }
 
-    Spans::from(spans)
+        let mut rows = vec![];
+        let (mut start1, mut start2) = (0, 0);
+
+        for anchor in fixed_rows.into_iter().map(Some).chain([None]) {
+            let (end1, end2) = anchor.unwrap_or((self.file1_lines.len(), self.file2_lines.len()));
+
+            let segment = self.alignment.align(
+                &self.file1_lines[start1..end1],
+                &self.file2_lines[start2..end2],
+                self.resync_window,
+                &self.diff_options,
+            );
+
+            rows.extend(segment.into_iter().map(|row| Row {
+                file1: row.file1.map(|index| start1 + index),
+                file2: row.file2.map(|index| start2 + index),
+            }));
+
+            if anchor.is_some() {
+                rows.push(Row {
+                    file1: Some(end1),
+                    file2: Some(end2),
+                });
+
+                (start1, start2) = (end1 + 1, end2 + 1);
+            }
+        }
+
+        rows
+    }
+
+    /// Keeps the 0-based `file1_line` and `file2_line` paired up, replacing any anchor on either
+    /// line and any shift from re-syncing, and selects the anchored row
+    pub fn add_anchor(&mut self, file1_line: usize, file2_line: usize) {
+        self.anchors
+            .retain(|&(line1, line2)| line1 != file1_line && line2 != file2_line);
+        self.anchors.push((file1_line, file2_line));
+        self.anchors.sort();
+
+        self.file2_shift = None;
+        self.align_window();
+
+        let window_index = file1_line.checked_sub(self.first_line_index);
+
+        if let Some(row) = self
+            .rows
+            .iter()
+            .position(|row| self.row_window_lines(row).0 == window_index)
+        {
+            self.selected_line = row;
+        }
+    }
+
+    /// Hides the rows away from any change, or shows them again, keeping the selected line
+    /// selected. A hidden selected line leaves its separator selected
+    pub fn toggle_only_diffs(&mut self) {
+        let line_index = self.selected_line_index();
+
+        self.only_diffs = !self.only_diffs;
+        self.align_window();
+
+        self.selected_line = self.row_for_line(line_index);
+    }
+
+    /// Drops every anchor, returning how many there were
+    pub fn clear_anchors(&mut self) -> usize {
+        let count = self.anchors.len();
+
+        self.anchors.clear();
+        self.align_window();
+
+        count
+    }
+
+    /// Re-pairs the window so the file 1 line in `row` sits next to the most similar file 2 line
+    /// of the window, selecting it. Returns the similarity, and the 1-based number of the file 2
+    /// line when it is a line rather than a repeat summary
+    pub fn sync_to_similar(&mut self, row: usize) -> Option<(f64, Option<usize>)> {
+        let file1_index = self.rows.get(row)?.file1?;
+        let file1_line = &self.file1_lines[file1_index];
+
+        let (file2_index, score) = self
+            .file2_lines
+            .iter()
+            .map(|line| compare::similarity(file1_line, line))
+            .enumerate()
+            // Prefer the closest line among equally similar ones
+            .min_by(|(index_a, score_a), (index_b, score_b)| {
+                score_b.total_cmp(score_a).then(
+                    index_a
+                        .abs_diff(file1_index)
+                        .cmp(&index_b.abs_diff(file1_index)),
+                )
+            })?;
+
+        self.file2_shift = Some(file2_index as isize - file1_index as isize);
+        self.align_window();
+
+        self.selected_line = self
+            .rows
+            .iter()
+            .position(|row| row.file1 == Some(file1_index))
+            .unwrap_or(row);
+
+        let (_, file2_numbers) = self.line_numbers();
+
+        Some((score, file2_numbers[self.selected_line]))
+    }
+
+    /// Restyles the loaded window, after changing how it is rendered
+    pub fn rebuild_spans(&mut self) {
+        let (file1_spans, file2_spans) = if self.changes_only {
+            build_change_spans(&self.line_diffs, &self.style)
+        } else if self.fold_same {
+            let folded: Vec<LineDiff> = self.line_diffs.iter().map(fold_same).collect();
+
+            build_spans(&folded, &self.style)
+        } else {
+            build_spans(&self.line_diffs, &self.style)
+        };
+
+        self.file1_spans = file1_spans;
+        self.file2_spans = file2_spans;
+
+        let shown_whitespace = self
+            .show_whitespace
+            .then_some(self.style.trailing_whitespace);
+
+        for spans in self.file1_spans.iter_mut().chain(&mut self.file2_spans) {
+            *spans = expand_whitespace(std::mem::take(spans), self.tab_width, shown_whitespace);
+        }
+
+        for &row in &self.separator_rows {
+            let separator = Spans::from(Span::styled(
+                self.line_diffs[row].left_text(),
+                Style::default().add_modifier(Modifier::DIM),
+            ));
+
+            self.file1_spans[row] = separator.clone();
+            self.file2_spans[row] = separator;
+        }
+    }
+
+    /// Whether the spans show every character of the lines, so `shown_column` and `diff_column`
+    /// find the `DiffSection` offsets in them
+    pub fn shows_full_lines(&self) -> bool {
+        !self.changes_only && !self.fold_same
+    }
+
+    /// The column the character `offset` characters into `row`'s file 1 line, or file 2 line
+    /// without one, is drawn at once tabs are expanded
+    pub fn shown_column(&self, row: usize, offset: usize) -> usize {
+        let Some(line_diffs) = self.line_diffs.get(row) else {
+            return offset;
+        };
+
+        let text = if self.rows[row].file1.is_some() {
+            line_diffs.left_text()
+        } else {
+            line_diffs.right_text()
+        };
+
+        text.tab_column_of(offset, self.tab_width)
+    }
+
+    /// The column the change starting at column `offset` of `row`, as `find_next_diff` gives
+    /// them, is drawn at once tabs are expanded. Added text only shows in file 2's pane and
+    /// is placed by its column there, removed text by its column in file 1's, and text in both by
+    /// whichever starts first, so the change is on screen however the lines before it differ.
+    /// Offsets that don't start a change are placed like `shown_column`
+    pub fn diff_column(&self, row: usize, offset: usize) -> usize {
+        let Some(line_diffs) = self.line_diffs.get(row) else {
+            return offset;
+        };
+
+        let (left_text, right_text) = (line_diffs.left_text(), line_diffs.right_text());
+
+        let mut position = 0;
+        let (mut left_chars, mut right_chars) = (0, 0);
+
+        for section in line_diffs.sections() {
+            if position == offset && section.is_change() {
+                let left_column = left_text.tab_column_of(left_chars, self.tab_width);
+                let right_column = right_text.tab_column_of(right_chars, self.tab_width);
+
+                return match section {
+                    DiffSection::Added(_) => right_column,
+                    DiffSection::Removed(_) => left_column,
+                    _ => left_column.min(right_column),
+                };
+            }
+
+            position += self.offset_len(row, section);
+            left_chars += section.left_chars();
+            right_chars += section.right_chars();
+        }
+
+        self.shown_column(row, offset)
+    }
+
+    /// The styled contents of the loaded window, without line numbers or placeholders
+    pub fn spans(&self) -> (&[Spans<'a>], &[Spans<'a>]) {
+        (&self.file1_spans, &self.file2_spans)
+    }
+
+    pub fn first_diff(&self) -> Option<&DiffPosition> {
+        self.first_diff.as_ref()
+    }
+
+    /// The 1-based number in its file of the line with index `line_index`, counting the skipped
+    /// lines
+    pub fn line_number(&self, line_index: usize) -> usize {
+        self.skipped_lines + line_index + 1
+    }
+
+    /// The 1-based line number each row of the loaded window shows from file 1 and file 2
+    pub fn line_numbers(&self) -> (Vec<Option<usize>>, Vec<Option<usize>>) {
+        self.row_line_numbers(self.first_line_index + 1)
+    }
+
+    fn row_line_numbers(
+        &self,
+        start_line_number: usize,
+    ) -> (Vec<Option<usize>>, Vec<Option<usize>>) {
+        self.rows
+            .iter()
+            .map(|row| {
+                let (file1_index, file2_index) = self.row_window_lines(row);
+
+                (
+                    file1_index.map(|index| start_line_number + self.skipped_lines + index),
+                    file2_index.map(|index| start_line_number + self.skipped_lines + index),
+                )
+            })
+            .unzip()
+    }
+
+    /// The index into the loaded window of the line each file shows in `row`. `None` for
+    /// missing lines and repeat summaries
+    fn row_window_lines(&self, row: &Row) -> (Option<usize>, Option<usize>) {
+        let window_line = |sources: &[LineSource], index: Option<usize>| match index
+            .and_then(|index| sources.get(index))
+        {
+            Some(LineSource::Line(window_index)) => Some(*window_index),
+            _ => None,
+        };
+
+        (
+            window_line(&self.file1_sources, row.file1),
+            window_line(&self.file2_sources, row.file2),
+        )
+    }
+
+    /// The 0-based line index each file shows in `row` of the loaded window
+    pub fn row_lines(&self, row: usize) -> (Option<usize>, Option<usize>) {
+        let (file1_index, file2_index) = self
+            .rows
+            .get(row)
+            .map_or((None, None), |row| self.row_window_lines(row));
+
+        (
+            file1_index.map(|index| self.first_line_index + index),
+            file2_index.map(|index| self.first_line_index + index),
+        )
+    }
+
+    /// Diffs the given inclusive ranges of 0-based line indexes on their own, and formats them as
+    /// a unified diff with a single hunk
+    pub fn range_patch(
+        &mut self,
+        file1_range: RangeInclusive<usize>,
+        file2_range: RangeInclusive<usize>,
+        file1_path: &Path,
+        file2_path: &Path,
+    ) -> io::Result<String> {
+        let file1_lines = self.read_lines(true, file1_range.clone())?;
+        let file2_lines = self.read_lines(false, file2_range.clone())?;
+
+        let rows = self.alignment.align(
+            &file1_lines,
+            &file2_lines,
+            self.resync_window,
+            &self.diff_options,
+        );
+        let line_diffs = calculate_diffs(&rows, &file1_lines, &file2_lines, &self.diff_options);
+
+        let mut patch = format!(
+            "--- {}\n+++ {}\n",
+            file1_path.display(),
+            file2_path.display()
+        );
+
+        patch::write_hunk(
+            &mut patch,
+            &rows,
+            &line_diffs,
+            &file1_lines,
+            &file2_lines,
+            file1_range.start() + 1,
+            file2_range.start() + 1,
+        );
+
+        Ok(patch)
+    }
+
+    /// Diffs the whole files as a unified diff, with hunks of the changed lines and up to
+    /// `context` unchanged lines around them. `None` if the files don't differ
+    pub fn unified_diff(
+        &mut self,
+        file1_path: &Path,
+        file2_path: &Path,
+        context: usize,
+    ) -> io::Result<Option<String>> {
+        let mut hunks = Hunks::new(context, self.line_number(0), self.line_number(0));
+
+        // Compared without their prefixes, but written out whole
+        self.visit_rows(|_, row| {
+            hunks.push(PatchRow {
+                file1_line: row.file1_line(),
+                file2_line: row.file2_line(),
+                line_diffs: row.line_diffs.clone(),
+            });
+
+            Ok(())
+        })?;
+
+        Ok(hunks.finish().map(|hunks| {
+            format!(
+                "--- {}\n+++ {}\n{}",
+                file1_path.display(),
+                file2_path.display(),
+                hunks
+            )
+        }))
+    }
+
+    /// Writes the diff of the whole files to `out` as a JSON object with the `first_diff` and a
+    /// row per aligned line pair, returning whether the files are identical. The files are
+    /// aligned and written a chunk at a time, so they're never all in memory
+    pub fn write_json(
+        &mut self,
+        out: &mut impl io::Write,
+        file1_path: &Path,
+        file2_path: &Path,
+    ) -> io::Result<bool> {
+        write!(
+            out,
+            "{{\"file1\":{},\"file2\":{},\"first_diff\":{},\"lines\":[",
+            serde_json::to_string(&file1_path)?,
+            serde_json::to_string(&file2_path)?,
+            serde_json::to_string(&self.first_diff.clone().map(|diff| DiffPosition {
+                line_index: self.skipped_lines + diff.line_index,
+                ..diff
+            }))?
+        )?;
+
+        let mut first_row = true;
+
+        let identical = self.visit_rows(|state, row| {
+            // Line endings compared with `preserve_eol` are part of the diff
+            let sections = if state.diff_options.preserve_eol {
+                row.line_diffs.clone()
+            } else {
+                row.line_diffs.without_line_endings()
+            };
+            let json_row = JsonRow {
+                file1_line: row.file1_index.map(|index| state.line_number(index)),
+                file2_line: row.file2_index.map(|index| state.line_number(index)),
+                file1_offset: row
+                    .file1_index
+                    .and_then(|index| state.file1_line_positions.get(index)),
+                file2_offset: row
+                    .file2_index
+                    .and_then(|index| state.file2_line_positions.get(index)),
+                kind: row.kind(),
+                sections: &sections,
+            };
+
+            if !first_row {
+                out.write_all(b",")?;
+            }
+
+            first_row = false;
+
+            out.write_all(b"\n")?;
+            serde_json::to_writer(&mut *out, &json_row)?;
+
+            Ok(())
+        })?;
+
+        writeln!(out, "\n],\"identical\":{}}}", identical)?;
+
+        Ok(identical)
+    }
+
+    /// Writes the diff of the whole files to `out` as a standalone HTML page showing them side
+    /// by side, styled like the viewer, returning whether the files are identical. Like
+    /// `write_json`, the files are written a chunk at a time
+    pub fn write_html(
+        &mut self,
+        out: &mut impl io::Write,
+        file1_path: &Path,
+        file2_path: &Path,
+    ) -> io::Result<bool> {
+        html::write_header(out, file1_path, file2_path)?;
+
+        let identical = self.visit_rows(|state, row| {
+            let (file1_spans, file2_spans) =
+                build_spans(slice::from_ref(row.line_diffs), &state.style);
+
+            html::write_row(
+                out,
+                [
+                    row.file1_index.map(|index| state.line_number(index)),
+                    row.file2_index.map(|index| state.line_number(index)),
+                ],
+                [&file1_spans[0], &file2_spans[0]],
+            )
+        })?;
+
+        html::write_footer(out)?;
+
+        Ok(identical)
+    }
+
+    /// Finds the rows of the whole files that differ, aligned a chunk at a time, filling in
+    /// `aligned_diffs` and recounting `diff_summary` from them. The scan compares lines by
+    /// index, so every line after an inserted one reads as changed. Rows before the line with
+    /// index `skip_past_line` aren't counted, like the scan's. Lines are only compared, not
+    /// diffed, and nothing is done when the scan's pairing is the alignment's or found no
+    /// indexed line differing
+    pub fn align_files(&mut self, skip_past_line: usize) -> io::Result<()> {
+        let positional = self.alignment == Alignment::None && self.resync_window.is_none();
+
+        if positional || self.diff_lines.is_empty() {
+            self.aligned_diffs = None;
+
+            return Ok(());
+        }
+
+        let mut aligned = AlignedDiffs::default();
+        let mut summary = DiffSummary {
+            modified: 0,
+            added: self.diff_summary.unindexed_added,
+            removed: self.diff_summary.unindexed_removed,
+            ..self.diff_summary.clone()
+        };
+
+        self.visit_chunks(|state, chunk| {
+            let is_same = |index: usize| match chunk.rows[index] {
+                Row {
+                    file1: Some(line1),
+                    file2: Some(line2),
+                } => state
+                    .diff_options
+                    .lines_equal(&chunk.file1_lines[line1], &chunk.file2_lines[line2]),
+                _ => false,
+            };
+
+            let visited_rows = chunk.settled_rows(is_same);
+
+            for (index, row) in chunk.rows[..visited_rows].iter().enumerate() {
+                let file1_index = row.file1.map(|line| chunk.file1_start + line);
+                let file2_index = row.file2.map(|line| chunk.file2_start + line);
+
+                if is_same(index) || file1_index.or(file2_index) < Some(skip_past_line) {
+                    continue;
+                }
+
+                aligned.file1_lines.extend(file1_index);
+                aligned.file2_lines.extend(file2_index);
+                // The later of the two lines, which no row before this one has
+                aligned.marks.extend(file1_index.max(file2_index));
+
+                match (row.file1, row.file2) {
+                    (Some(_), Some(_)) => summary.modified += 1,
+                    (None, _) => summary.added += 1,
+                    (_, None) => summary.removed += 1,
+                }
+            }
+
+            Ok(visited_rows)
+        })?;
+
+        // The rows' lines can be out of order when only one file has a line in some
+        aligned.marks.sort_unstable();
+        aligned.marks.dedup();
+
+        self.aligned_diffs = Some(aligned);
+        self.diff_summary = summary;
+
+        Ok(())
+    }
+
+    /// The lines to mark and jump to as differing: those `align_files` found, or the scan's
+    pub fn diff_marks(&self) -> &[usize] {
+        self.aligned_diffs
+            .as_ref()
+            .map_or(&self.diff_lines, |aligned| &aligned.marks)
+    }
+
+    /// Whether the lines of `row` are in a row of the whole files that differs once aligned.
+    /// Rows at the edges of the window can leave a line unpaired when the line it goes with
+    /// isn't loaded. Rows are taken as they are when they aren't paired the way the whole files
+    /// are, and for repeat summaries
+    fn row_differs(&self, row: usize) -> bool {
+        let Some(aligned) = self
+            .aligned_diffs
+            .as_ref()
+            .filter(|_| self.file2_shift.is_none() && self.anchors.is_empty())
+        else {
+            return true;
+        };
+
+        let differs = |lines: &[usize], index: Option<usize>| {
+            index.is_some_and(|index| lines.binary_search(&index).is_ok())
+        };
+
+        match self.row_lines(row) {
+            (None, None) => true,
+            (file1_index, file2_index) => {
+                differs(&aligned.file1_lines, file1_index)
+                    || differs(&aligned.file2_lines, file2_index)
+            }
+        }
+    }
+
+    /// Aligns and diffs the whole files a chunk at a time, calling `visit` with each row, in
+    /// order. Returns whether the files are identical
+    fn visit_rows(
+        &mut self,
+        mut visit: impl FnMut(&Self, WholeRow) -> io::Result<()>,
+    ) -> io::Result<bool> {
+        let mut identical = true;
+
+        self.visit_chunks(|state, chunk| {
+            let line_diffs = calculate_diffs(
+                &chunk.rows,
+                &chunk.file1_lines,
+                &chunk.file2_lines,
+                &state.diff_options,
+            );
+
+            let is_same = |index: usize| {
+                let row = &chunk.rows[index];
+
+                row.file1.is_some()
+                    && row.file2.is_some()
+                    && !line_diffs[index]
+                        .sections()
+                        .iter()
+                        .any(|diff| diff.is_change())
+            };
+
+            let visited_rows = chunk.settled_rows(is_same);
+
+            for (index, (row, line_diffs)) in chunk.rows.iter().zip(&line_diffs).enumerate() {
+                if index == visited_rows {
+                    break;
+                }
+
+                identical &= is_same(index);
+
+                fn prefix(prefixes: &[String], index: Option<usize>) -> &str {
+                    index
+                        .and_then(|index| prefixes.get(index))
+                        .map_or("", String::as_str)
+                }
+
+                visit(
+                    state,
+                    WholeRow {
+                        file1_index: row.file1.map(|index| chunk.file1_start + index),
+                        file2_index: row.file2.map(|index| chunk.file2_start + index),
+                        file1_prefix: prefix(&chunk.file1_prefixes, row.file1),
+                        file2_prefix: prefix(&chunk.file2_prefixes, row.file2),
+                        line_diffs,
+                    },
+                )?;
+            }
+
+            Ok(visited_rows)
+        })?;
+
+        Ok(identical)
+    }
+
+    /// Aligns the whole files `DIFF_CHUNK_LINES` lines at a time, calling `visit` with each chunk
+    /// in order. `visit` returns how many of the chunk's rows it took, and the lines of the rest
+    /// start the next chunk
+    fn visit_chunks(
+        &mut self,
+        mut visit: impl FnMut(&Self, &Chunk) -> io::Result<usize>,
+    ) -> io::Result<()> {
+        let (file1_count, file2_count) = self.line_counts();
+
+        // The first line of each file not visited yet
+        let mut file1_start = 0;
+        let mut file2_start = 0;
+
+        while file1_start < file1_count || file2_start < file2_count {
+            let file1_end = (file1_start + DIFF_CHUNK_LINES).min(file1_count);
+            let file2_end = (file2_start + DIFF_CHUNK_LINES).min(file2_count);
+
+            let mut file1_lines = self.read_line_range(true, file1_start..file1_end)?;
+            let mut file2_lines = self.read_line_range(false, file2_start..file2_end)?;
+            let file1_prefixes = self.strip_prefixes(&mut file1_lines, true);
+            let file2_prefixes = self.strip_prefixes(&mut file2_lines, false);
+
+            let rows = self.alignment.align(
+                &file1_lines,
+                &file2_lines,
+                self.resync_window,
+                &self.diff_options,
+            );
+
+            let chunk = Chunk {
+                file1_start,
+                file2_start,
+                file1_lines,
+                file2_lines,
+                file1_prefixes,
+                file2_prefixes,
+                rows,
+                at_end: file1_end == file1_count && file2_end == file2_count,
+            };
+
+            let visited = &chunk.rows[..visit(self, &chunk)?];
+            file1_start += visited.iter().filter(|row| row.file1.is_some()).count();
+            file2_start += visited.iter().filter(|row| row.file2.is_some()).count();
+        }
+
+        Ok(())
+    }
+
+    /// Formats `row` of the loaded window as a unified diff hunk of its own. `None` past the
+    /// last row
+    pub fn row_patch(&self, row: usize) -> Option<String> {
+        let aligned = self.rows.get(row)?;
+        let line_diffs = self.line_diffs.get(row)?;
+
+        let (file1_index, file2_index) = self.row_lines(row);
+        // A file without a line in the row gets the other file's line number
+        let start =
+            |index: Option<usize>, other: Option<usize>| index.or(other).map_or(1, |i| i + 1);
+
+        let mut patch = String::new();
+
+        patch::write_hunk(
+            &mut patch,
+            &[Row {
+                file1: aligned.file1.map(|_| 0),
+                file2: aligned.file2.map(|_| 0),
+            }],
+            slice::from_ref(line_diffs),
+            &[line_diffs.left_text()],
+            &[line_diffs.right_text()],
+            start(file1_index, file2_index),
+            start(file2_index, file1_index),
+        );
+
+        Some(patch)
+    }
+
+    /// Reads the lines in `range` that exist in the file
+    fn read_line_range(&mut self, file1: bool, range: Range<usize>) -> io::Result<Vec<String>> {
+        if range.is_empty() {
+            return Ok(vec![]);
+        }
+
+        self.read_lines(file1, range.start..=range.end - 1)
+    }
+
+    fn read_lines(&mut self, file1: bool, range: RangeInclusive<usize>) -> io::Result<Vec<String>> {
+        let positions = if file1 {
+            &self.file1_line_positions
+        } else {
+            &self.file2_line_positions
+        };
+
+        let offsets: Vec<usize> = range.filter_map(|index| positions.get(index)).collect();
+
+        offsets
+            .into_iter()
+            .map(|offset| self.read_line_at_offset(file1, offset as u64))
+            .collect()
+    }
+
+    /// Expands any collapsed repeat in `row` and reloads the window
+    pub fn expand_repeats(&mut self, row: usize) {
+        let Some(row) = self.rows.get(row).copied() else {
+            return;
+        };
+
+        let mut expanded = false;
+
+        for (index, sources, expanded_starts) in [
+            (row.file1, &self.file1_sources, &mut self.file1_expanded),
+            (row.file2, &self.file2_sources, &mut self.file2_expanded),
+        ] {
+            if let Some(LineSource::Repeat { start, .. }) =
+                index.and_then(|index| sources.get(index))
+            {
+                expanded_starts.insert(self.first_line_index + start);
+                expanded = true;
+            }
+        }
+
+        if expanded {
+            let (line_index, line_count) = self.window;
+            self.load_lines_around_line(line_index, line_count);
+        }
+    }
+
+    /// The row of the loaded window showing `line_index` from either file, or the closest loaded
+    /// row
+    fn row_for_line(&self, line_index: usize) -> usize {
+        let window_index = line_index.saturating_sub(self.first_line_index);
+
+        let exact = self.rows.iter().position(|row| {
+            let (file1_index, file2_index) = self.row_window_lines(row);
+
+            file1_index == Some(window_index) || file2_index == Some(window_index)
+        });
+
+        // A hidden line is behind the separator before the next row that's shown
+        let separator = || {
+            let next_row = self
+                .rows
+                .iter()
+                .position(|row| {
+                    let (file1_index, file2_index) = self.row_window_lines(row);
+
+                    file1_index.or(file2_index) > Some(window_index)
+                })
+                .unwrap_or(self.rows.len());
+
+            let row = next_row.checked_sub(1)?;
+
+            self.separator_rows.contains(&row).then_some(row)
+        };
+
+        exact
+            .or_else(separator)
+            // The line may not have been indexed, so keep the selection on a loaded row
+            .unwrap_or(window_index.min(self.rows.len().saturating_sub(1)))
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.pinned.is_some()
+    }
+
+    /// Hex digits of the largest line offset in either file
+    fn offset_width(&self) -> usize {
+        let largest = self
+            .file1_line_positions
+            .last()
+            .max(self.file2_line_positions.last())
+            .unwrap_or(0);
+
+        format!("{:x}", largest).len()
+    }
+
+    /// Pins `row` of the loaded window, or unpins the current pinned row
+    pub fn toggle_pin(&mut self, row: usize) {
+        if self.pinned.take().is_some() {
+            return;
+        }
+
+        let Some(line_diffs) = self.line_diffs.get(row) else {
+            return;
+        };
+
+        let (file1_numbers, file2_numbers) = self.line_numbers();
+        let (file1_offset, file2_offset) = self.row_offsets(row);
+
+        self.pinned = Some(PinnedRow {
+            file1_spans: self.file1_spans.get(row).cloned().unwrap_or_default(),
+            file2_spans: self.file2_spans.get(row).cloned().unwrap_or_default(),
+            line_diffs: line_diffs.clone(),
+            file1_number: file1_numbers[row],
+            file2_number: file2_numbers[row],
+            file1_offset,
+            file2_offset,
+        });
+    }
+
+    pub fn build_lines(&mut self, horizontal_offset: usize, start_line_number: usize) {
+        let (file1_numbers, file2_numbers) = self.row_line_numbers(start_line_number);
+
+        let gutters = |file1: bool| -> Vec<String> {
+            self.line_diffs
+                .iter()
+                .zip(file1_numbers.iter().zip(&file2_numbers))
+                .enumerate()
+                .map(|(row, (line_diffs, (number1, number2)))| {
+                    let offsets = self.row_offsets(row);
+                    let offset = if file1 { offsets.0 } else { offsets.1 };
+
+                    self.gutter_text(line_diffs, (*number1, *number2), offset, file1)
+                })
+                .collect()
+        };
+
+        let file1_gutters = gutters(true);
+        let file2_gutters = gutters(false);
+
+        self.gutter_width = file1_gutters
+            .iter()
+            .chain(&file2_gutters)
+            .map(|gutter| gutter.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        let (file1_past_end, file2_past_end) = self.past_end_rows();
+
+        let columns = self.shown_columns(horizontal_offset);
+
+        let (file1_list_lines, file2_list_lines) = build_lines(
+            &self.file1_spans,
+            &self.file2_spans,
+            &file1_gutters,
+            &file2_gutters,
+            (&file1_past_end, &file2_past_end),
+            &self.separator_rows,
+            columns,
+        );
+
+        self.file1_list_lines = self.highlight_lines(file1_list_lines);
+        self.file2_list_lines = self.highlight_lines(file2_list_lines);
+
+        let pinned_lines = self.pinned.as_ref().map(|pinned| {
+            let numbers = (pinned.file1_number, pinned.file2_number);
+
+            build_lines(
+                slice::from_ref(&pinned.file1_spans),
+                slice::from_ref(&pinned.file2_spans),
+                &[self.gutter_text(&pinned.line_diffs, numbers, pinned.file1_offset, true)],
+                &[self.gutter_text(&pinned.line_diffs, numbers, pinned.file2_offset, false)],
+                (&[], &[]),
+                &[],
+                // The pinned line is one row high
+                ShownColumns {
+                    wrap_columns: None,
+                    ..columns
+                },
+            )
+        });
+
+        (self.file1_pinned_line, self.file2_pinned_line) = match pinned_lines {
+            Some((mut file1_lines, mut file2_lines)) => (file1_lines.pop(), file2_lines.pop()),
+            None => (None, None),
+        };
+    }
+
+    /// The columns of the lines shown from `horizontal_offset`. Wrapped lines are shown from
+    /// their start, in rows of the wrap width less the gutter
+    fn shown_columns(&self, horizontal_offset: usize) -> ShownColumns {
+        ShownColumns {
+            horizontal_offset: if self.wrap_width.is_some() {
+                0
+            } else {
+                horizontal_offset
+            },
+            max_columns: self.max_columns,
+            wrap_columns: self
+                .wrap_width
+                .map(|width| width.saturating_sub(self.gutter_width).max(1)),
+        }
+    }
+
+    /// Applies the changed line background to the lines of changed rows, when enabled
+    fn highlight_lines(&self, lines: Vec<ListItem<'a>>) -> Vec<ListItem<'a>> {
+        if !self.highlight_changed_lines {
+            return lines;
+        }
+
+        lines
+            .into_iter()
+            .zip(&self.line_diffs)
+            .map(|(line, line_diffs)| {
+                if line_diffs.sections().iter().any(|diff| diff.is_change()) {
+                    line.style(self.style.changed_line)
+                } else {
+                    line
+                }
+            })
+            .collect()
+    }
+
+    /// Lines in the longer file
+    pub fn line_count(&self) -> usize {
+        let (file1_count, file2_count) = self.line_counts();
+
+        file1_count.max(file2_count)
+    }
+
+    /// The 1-based number of the selected line, and how far through the longer file it is as a
+    /// percentage
+    pub fn position(&self) -> (usize, usize) {
+        let line_index = self.selected_line_index();
+        let line_count = self.line_count();
+
+        let percent = if line_count <= 1 {
+            100
+        } else {
+            (line_index * 100 / (line_count - 1)).min(100)
+        };
+
+        (line_index + 1, percent)
+    }
+
+    /// Whether each row of the loaded window is past the last line of file 1, and of file 2.
+    /// Rows before that without a line of the file are gaps the alignment left
+    fn past_end_rows(&self) -> (Vec<bool>, Vec<bool>) {
+        let (file1_count, file2_count) = self.line_counts();
+
+        let past_end = |line_count: usize, file1: bool| {
+            let mut past_end = vec![false; self.rows.len()];
+            // Whether the window reaches the end of the file, so the rows after its last line
+            // really are past it
+            let mut at_end = self.rows.is_empty() || line_count == 0;
+
+            for row in (0..self.rows.len()).rev() {
+                let (line1, line2) = self.row_lines(row);
+
+                match if file1 { line1 } else { line2 } {
+                    Some(line_index) => {
+                        at_end = line_index + 1 >= line_count;
+                        break;
+                    }
+                    // The hidden lines may run to the end of the file
+                    None if self.separator_rows.contains(&row) => {
+                        at_end = false;
+                        break;
+                    }
+                    None => past_end[row] = true,
+                }
+            }
+
+            if !at_end {
+                past_end.fill(false);
+            }
+
+            past_end
+        };
+
+        (past_end(file1_count, true), past_end(file2_count, false))
+    }
+
+    /// Lines in file 1 and in file 2
+    pub fn line_counts(&self) -> (usize, usize) {
+        (
+            self.file1_line_positions.len(),
+            self.file2_line_positions.len(),
+        )
+    }
+
+    /// Calls `visit` with the 1-based line number of each changed row of the whole files, in
+    /// order. Uses file 1's number, or file 2's for rows only file 2 has a line in. Aligns the
+    /// files a chunk at a time, so the whole of neither is held at once
+    pub fn visit_changed_line_numbers(
+        &mut self,
+        mut visit: impl FnMut(usize) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.visit_rows(|state, row| {
+            if !row
+                .line_diffs
+                .sections()
+                .iter()
+                .any(|diff| diff.is_change())
+            {
+                return Ok(());
+            }
+
+            match row.file1_index.or(row.file2_index) {
+                Some(index) => visit(state.line_number(index)),
+                None => Ok(()),
+            }
+        })?;
+
+        Ok(())
+    }
+
+    /// Every changed row of the loaded window, with only its changed sections
+    pub fn change_index(&self) -> Vec<IndexEntry<'a>> {
+        let dim = Style::default().add_modifier(Modifier::DIM);
+        let (file1_numbers, file2_numbers) = self.line_numbers();
+
+        self.line_diffs
+            .iter()
+            .enumerate()
+            .filter_map(|(row, line_diffs)| {
+                let (file1_range, _) = change_region(line_diffs, 0)?;
+
+                let (mut file1_spans, mut file2_spans) =
+                    build_change_spans(slice::from_ref(line_diffs), &self.style);
+
+                let number = file1_numbers[row]
+                    .or(file2_numbers[row])
+                    .map_or(String::new(), |number| number.to_string());
+
+                let mut spans = vec![Span::styled(format!("{:>8} ", number), dim)];
+                spans.extend(file1_spans.pop()?.0);
+                spans.push(Span::styled(" → ", dim));
+                spans.extend(file2_spans.pop()?.0);
+
+                Some(IndexEntry {
+                    row,
+                    offset: file1_range.start,
+                    spans: Spans::from(spans),
+                })
+            })
+            .collect()
+    }
+
+    /// Renders the changed section of `row` at `horizontal_offset`, or the closest one, in
+    /// reverse video. The emphasis lasts until the next `build_lines`. Returns whether there was
+    /// a change to emphasize
+    pub fn invert_region(&mut self, row: usize, horizontal_offset: usize) -> bool {
+        self.emphasize_change(
+            row,
+            horizontal_offset,
+            horizontal_offset,
+            Modifier::REVERSED,
+        )
+    }
+
+    /// Brightens the changed section of `row` at file 1 column `offset`, or the closest one, in
+    /// both panes, so the text it replaced stands out from the rest of the line next to it. Like
+    /// `invert_region`, the emphasis lasts until the next `build_lines`. Returns whether there
+    /// was a change to link
+    pub fn link_change(&mut self, row: usize, offset: usize, horizontal_offset: usize) -> bool {
+        self.emphasize_change(
+            row,
+            offset,
+            horizontal_offset,
+            Modifier::BOLD | Modifier::UNDERLINED,
+        )
+    }
+
+    /// Adds `modifier` to the changed section of `row` at file 1 column `offset`, or the closest
+    /// one, in both panes, redrawing the row at `horizontal_offset`
+    fn emphasize_change(
+        &mut self,
+        row: usize,
+        offset: usize,
+        horizontal_offset: usize,
+        modifier: Modifier,
+    ) -> bool {
+        let Some((file1_range, file2_range)) = self
+            .line_diffs
+            .get(row)
+            // Expanded tabs move the characters past them out of the sections' columns
+            .filter(|line_diffs| {
+                !line_diffs.left_text().contains('\t') && !line_diffs.right_text().contains('\t')
+            })
+            .and_then(|line_diffs| change_region(line_diffs, offset))
+        else {
+            return false;
+        };
+
+        let (file1_numbers, file2_numbers) = self.line_numbers();
+
+        let file1_spans = spans_with_modifier(self.file1_spans[row].clone(), file1_range, modifier);
+        let file2_spans = spans_with_modifier(self.file2_spans[row].clone(), file2_range, modifier);
+
+        let numbers = (file1_numbers[row], file2_numbers[row]);
+        let (file1_offset, file2_offset) = self.row_offsets(row);
+        let (file1_past_end, file2_past_end) = self.past_end_rows();
+
+        let (mut file1_lines, mut file2_lines) = build_lines(
+            slice::from_ref(&file1_spans),
+            slice::from_ref(&file2_spans),
+            &[self.gutter_text(&self.line_diffs[row], numbers, file1_offset, true)],
+            &[self.gutter_text(&self.line_diffs[row], numbers, file2_offset, false)],
+            (&file1_past_end[row..=row], &file2_past_end[row..=row]),
+            &[],
+            self.shown_columns(horizontal_offset),
+        );
+
+        if let (Some(file1_line), Some(file2_line)) = (file1_lines.pop(), file2_lines.pop()) {
+            self.file1_list_lines[row] = file1_line;
+            self.file2_list_lines[row] = file2_line;
+        }
+
+        true
+    }
+
+    /// The gutter of file 1's (or file 2's) pane for a row with `line_diffs`, the line `numbers`
+    /// of both files, and the `offset` of the pane's file's line, including any enabled
+    /// `GutterOptions`
+    fn gutter_text(
+        &self,
+        line_diffs: &LineDiff,
+        numbers: (Option<usize>, Option<usize>),
+        offset: Option<usize>,
+        file1: bool,
+    ) -> String {
+        if self.gutter.hidden {
+            return String::new();
+        }
+
+        let sections = line_diffs.sections();
+
+        let line_number = if file1 { numbers.0 } else { numbers.1 };
+
+        // Rows a file has no line in keep its column blank
+        let number_text = |number: Option<usize>| {
+            number.map_or(String::new(), |n| self.gutter.number_base.format(n))
+        };
+
+        let mut number = if self.gutter.both_numbers {
+            format!(
+                "{:>6} | {:<6}",
+                number_text(numbers.0),
+                number_text(numbers.1)
+            )
+        } else {
+            number_text(line_number)
+        };
+
+        if self.gutter.change_counts && line_number.is_some() {
+            let count = sections.iter().filter(|diff| diff.is_change()).count();
+
+            if count > 0 {
+                number.push_str(&format!("({})", count));
+            }
+        }
+
+        let full_sized_number_string = format!("{} ", number);
+        let width = self.gutter.number_base.width();
+
+        let mut number_string = if full_sized_number_string.len() <= width + 1 {
+            format!("{:>width$} ", number)
+        } else {
+            full_sized_number_string
+        };
+
+        if self.gutter.offsets {
+            // As wide as the largest offset in either file, so the gutter doesn't change width
+            // while scrolling
+            let width = self.offset_width();
+
+            match offset {
+                Some(offset) => number_string.push_str(&format!("{:0width$x} ", offset)),
+                None => number_string.push_str(&" ".repeat(width + 1)),
+            }
+        }
+
+        let is_only = |predicate: fn(&DiffSection) -> bool| sections.iter().all(predicate);
+
+        if self.gutter.markers {
+            let marker = if is_only(|diff| !diff.is_change()) {
+                ' '
+            } else if is_only(|diff| {
+                !matches!(diff, DiffSection::Removed(_) | DiffSection::Modified { .. })
+            }) {
+                '+'
+            } else if is_only(|diff| {
+                !matches!(diff, DiffSection::Added(_) | DiffSection::Modified { .. })
+            }) {
+                '-'
+            } else {
+                '~'
+            };
+
+            number_string.insert(0, marker);
+        }
+
+        if self.gutter.origin {
+            let tag = if is_only(|diff| !diff.is_change()) {
+                "  "
+            } else if is_only(|diff| matches!(diff, DiffSection::Removed(_))) {
+                "1>"
+            } else if is_only(|diff| matches!(diff, DiffSection::Added(_))) {
+                "<2"
+            } else {
+                "<>"
+            };
+
+            format!("{} {}", tag, number_string)
+        } else {
+            number_string
+        }
+    }
+
+    pub fn get_lines_around_line(
+        &mut self,
+        line_index: usize,
+        line_count: usize,
+    ) -> (Vec<String>, Vec<String>) {
+        let total_line_count = self
+            .file1_line_positions
+            .len()
+            .max(self.file2_line_positions.len());
+
+        // Center the window on `line_index`, but slide it back from EOF so it still covers
+        // `line_count` lines when the file has that many
+        let bottom_line_index = line_index
+            .saturating_sub(line_count / 2)
+            .min(total_line_count.saturating_sub(line_count));
+
+        let top_line_index = bottom_line_index + line_count;
+
+        debug!(
+            "Loading lines {}..{} around line {}",
+            bottom_line_index, top_line_index, line_index
+        );
+
+        let mut file1_lines = vec![];
+        let mut file2_lines = vec![];
+
+        for i in bottom_line_index..top_line_index {
+            if let Some(line1_offset) = self.file1_line_positions.get(i) {
+                match self.read_window_line_at_offset(true, line1_offset as u64) {
+                    Ok(line1) => file1_lines.push(line1),
+                    Err(err) => {
+                        self.record_read_error(true, line1_offset, err);
+                        break;
+                    }
+                }
+            }
+
+            if let Some(line2_offset) = self.file2_line_positions.get(i) {
+                match self.read_window_line_at_offset(false, line2_offset as u64) {
+                    Ok(line2) => file2_lines.push(line2),
+                    Err(err) => {
+                        self.record_read_error(false, line2_offset, err);
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.first_line_index = bottom_line_index;
+
+        (file1_lines, file2_lines)
+    }
+
+    /// Keeps the first error reading a window line, for `take_read_error` to report, as the
+    /// window is loaded by every scroll with nowhere to return it
+    fn record_read_error(&mut self, file1: bool, offset: usize, err: io::Error) {
+        let file_number = if file1 { 1 } else { 2 };
+
+        warn!(
+            "Could not read file {} at offset {}: {}",
+            file_number, offset, err
+        );
+
+        self.read_error.get_or_insert_with(|| {
+            io::Error::new(
+                err.kind(),
+                format!(
+                    "Could not read file {} at byte {}: {}",
+                    file_number, offset, err
+                ),
+            )
+        });
+    }
+
+    /// The first error reading the loaded windows since the last call, if any. The lines past it
+    /// were left out of its window
+    pub fn take_read_error(&mut self) -> Option<io::Error> {
+        self.read_error.take()
+    }
+
+    /// Reads the raw bytes of row `row` of the loaded window from each file, including the line
+    /// ending. `None` if that file has no line there
+    pub fn row_bytes(&mut self, row: usize) -> io::Result<RowBytes> {
+        let (file1_offset, file2_offset) = self.row_offsets(row);
+
+        let file1_bytes = file1_offset
+            .map(|offset| self.read_bytes_at_offset(true, offset as u64))
+            .transpose()?;
+        let file2_bytes = file2_offset
+            .map(|offset| self.read_bytes_at_offset(false, offset as u64))
+            .transpose()?;
+
+        Ok((file1_bytes, file2_bytes))
+    }
+
+    /// Reads the text of row `row` of the loaded window from each file, without the line ending.
+    /// `None` if that file has no line there
+    pub fn row_text(&mut self, row: usize) -> io::Result<(Option<String>, Option<String>)> {
+        let (file1_offset, file2_offset) = self.row_offsets(row);
+
+        let mut read = |file1: bool, offset: Option<usize>| {
+            offset
+                .map(|offset| -> io::Result<String> {
+                    let mut line = self.read_line_at_offset(file1, offset as u64)?;
+                    line.truncate(line.trim_end_matches(['\r', '\n']).len());
+
+                    Ok(line)
+                })
+                .transpose()
+        };
+
+        Ok((read(true, file1_offset)?, read(false, file2_offset)?))
+    }
+
+    /// Byte offsets in each file of the lines in row `row` of the loaded window
+    fn row_offsets(&self, row: usize) -> (Option<usize>, Option<usize>) {
+        let (file1_index, file2_index) = self
+            .rows
+            .get(row)
+            .map_or((None, None), |row| self.row_window_lines(row));
+
+        let file1_offset = file1_index
+            .and_then(|index| self.file1_line_positions.get(self.first_line_index + index));
+        let file2_offset = file2_index
+            .and_then(|index| self.file2_line_positions.get(self.first_line_index + index));
+
+        (file1_offset, file2_offset)
+    }
+
+    /// Like `read_line_at_offset`, but cut off after `max_line_width` characters with `…`. Only
+    /// the bytes those characters can take up are read, so a runaway line isn't read whole
+    fn read_window_line_at_offset(&mut self, file1: bool, offset: u64) -> io::Result<String> {
+        let max_line_width = match self.max_line_width {
+            Some(max_line_width) if !self.hex && self.record_width.is_none() => max_line_width,
+            _ => return self.read_line_at_offset(file1, offset),
+        };
+
+        let mut buffer = Vec::new();
+
+        // A character takes up to 4 bytes, and a line ending up to 2
+        self.read_at(file1, offset, |reader| {
+            reader
+                .take(max_line_width as u64 * 4 + 2)
+                .read_until(b'\n', &mut buffer)
+        })?;
+
+        let line = match String::from_utf8(buffer) {
+            Ok(line) => line,
+            // The read can stop partway through a character, which isn't shown anyway
+            Err(err) if err.utf8_error().error_len().is_none() => {
+                let valid_length = err.utf8_error().valid_up_to();
+                let mut bytes = err.into_bytes();
+                bytes.truncate(valid_length);
+
+                String::from_utf8_lossy(&bytes).into_owned()
+            }
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+
+        let text = line.trim_end_matches(['\r', '\n']);
+
+        Ok(match text.char_indices().nth(max_line_width) {
+            Some((cut, _)) => format!("{}…", &text[..cut]),
+            None => line,
+        })
+    }
+
+    fn read_line_at_offset(&mut self, file1: bool, offset: u64) -> io::Result<String> {
+        let buffer = self.read_bytes_at_offset(file1, offset)?;
+
+        if self.hex {
+            return Ok(hex_record_text(offset as usize, &buffer));
+        }
+
+        if self.record_width.is_some() {
+            // Fixed-width records may be binary
+            return Ok(String::from_utf8_lossy(&buffer).into_owned());
+        }
+
+        String::from_utf8(buffer).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn read_bytes_at_offset(&mut self, file1: bool, offset: u64) -> io::Result<Vec<u8>> {
+        let record_width = self.record_width;
+        let mut buffer = Vec::new();
+
+        let start = Instant::now();
+
+        self.read_at(file1, offset, |reader| {
+            read_record_bytes(reader, &mut buffer, record_width)
+        })?;
+
+        trace!(
+            "Read {} bytes from file {} at offset {} in {:?}",
+            buffer.len(),
+            if file1 { 1 } else { 2 },
+            offset,
+            start.elapsed()
+        );
+
+        Ok(buffer)
+    }
+
+    /// Runs `read` on the reader of file 1 or 2 moved to `offset`, seeking only if the last read
+    /// didn't leave it there. `read` returns how many bytes it read, which moves the reader on
+    fn read_at(
+        &mut self,
+        file1: bool,
+        offset: u64,
+        read: impl FnOnce(&mut BufReader<R>) -> io::Result<usize>,
+    ) -> io::Result<()> {
+        let (reader, position) = if file1 {
+            (&mut self.file1_reader, &mut self.file1_reader_position)
+        } else {
+            (&mut self.file2_reader, &mut self.file2_reader_position)
+        };
+
+        if *position != Some(offset) {
+            // Unknown until the seek succeeds
+            *position = None;
+            reader.seek(SeekFrom::Start(offset))?;
+        }
+
+        match read(reader) {
+            Ok(length) => {
+                *position = Some(offset + length as u64);
+
+                Ok(())
+            }
+            Err(err) => {
+                *position = None;
+
+                Err(err)
+            }
+        }
+    }
+
+    /// Columns `section` of `row` moves diff offsets along: its file 1 columns, or its file 2
+    /// columns on rows where only file 2 has a line, as those are shown in file 2's pane alone
+    fn offset_len(&self, row: usize, section: &DiffSection) -> usize {
+        if self.rows.get(row).is_none_or(|row| row.file1.is_some()) {
+            section.left_len()
+        } else {
+            section.right_len()
+        }
+    }
+
+    pub fn find_next_diff(&self, match_line: usize, match_offset: usize) -> Option<(usize, usize)> {
+        // The selection can sit past the last row when one file is longer than the window
+        let line_diffs = self.line_diffs.get(match_line..)?;
+
+        for (line_number, line_diffs) in line_diffs.iter().enumerate() {
+            // Make sure index is actually to the start of the lines
+            let line_number = line_number + match_line;
+            let mut line_offset = 0;
+
+            if !self.row_differs(line_number) {
+                continue;
+            }
+
+            for diff in line_diffs.sections() {
+                match diff {
+                    DiffSection::Added(_)
+                    | DiffSection::Modified { left: _, right: _ }
+                    | DiffSection::Removed(_)
+                        if line_offset > match_offset || line_number > match_line =>
+                    {
+                        // This is the next diff
+                        return Some((line_number, line_offset));
+                    }
+                    _ => {}
+                }
+
+                line_offset += self.offset_len(line_number, diff);
+            }
+        }
+
+        None
+    }
+
+    pub fn find_prev_diff(&self, match_line: usize, match_offset: usize) -> Option<(usize, usize)> {
+        let last_line = match_line.min(self.line_diffs.len().checked_sub(1)?);
+
+        for (line_number, line_diffs) in self.line_diffs[..=last_line].iter().enumerate().rev() {
+            if !self.row_differs(line_number) {
+                continue;
+            }
+
+            let line_width: usize = line_diffs
+                .sections()
+                .iter()
+                .map(|diff| self.offset_len(line_number, diff))
+                .sum();
+
+            let mut line_offset = line_width;
+
+            for diff in line_diffs.sections().iter().rev() {
+                line_offset = line_offset.saturating_sub(self.offset_len(line_number, diff));
+
+                match diff {
+                    DiffSection::Added(_)
+                    | DiffSection::Modified { left: _, right: _ }
+                    | DiffSection::Removed(_)
+                        if line_offset < match_offset || line_number < match_line =>
+                    {
+                        // This is the prev diff
+                        return Some((line_number, line_offset));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        None
+    }
+}
+
+fn longest_line_length(file1_lines: &[String], file2_lines: &[String], tab_width: usize) -> usize {
+    let mut longest_length = 0;
+
+    for line in file1_lines.iter().chain(file2_lines.iter()) {
+        longest_length = longest_length.max(line.tab_columns(tab_width));
+    }
+
+    longest_length
+}
+
+/// The prefix stripped from the loaded line shown at `index` of the aligned lines, if any
+fn row_prefix<'p>(index: Option<usize>, sources: &[LineSource], prefixes: &'p [String]) -> &'p str {
+    match index.and_then(|index| sources.get(index)) {
+        Some(LineSource::Line(line_index)) => prefixes.get(*line_index).map_or("", String::as_str),
+        _ => "",
+    }
+}
+
+/// Diffs each aligned row, treating lines only one file has as entirely removed or added
+pub(crate) fn calculate_diffs(
+    rows: &[Row],
+    file1_lines: &[String],
+    file2_lines: &[String],
+    options: &DiffOptions,
+) -> Vec<LineDiff> {
+    rows.iter()
+        .map(|row| match (row.file1, row.file2) {
+            (Some(index1), Some(index2)) => {
+                calculate_line_diffs(&file1_lines[index1], &file2_lines[index2], options)
+            }
+            (Some(index1), None) => DiffSection::Removed(file1_lines[index1].clone()).into(),
+            (None, Some(index2)) => DiffSection::Added(file2_lines[index2].clone()).into(),
+            (None, None) => LineDiff::default(),
+        })
+        .collect()
+}
+
+/// The column of file 1's line where its diff against file 2's line first changes, 0 if it doesn't
+pub fn first_change_column(line1: &str, line2: &str, options: &DiffOptions) -> usize {
+    let mut column = 0;
+
+    for section in calculate_line_diffs(line1, line2, options).sections() {
+        if section.is_change() {
+            return column;
+        }
+
+        column += section.left_len();
+    }
+
+    0
+}
+
+/// Diffs a single pair of lines, returning the styled text of each like the viewer shows it
+pub fn line_pair_spans<'b>(
+    line1: &str,
+    line2: &str,
+    options: &DiffOptions,
+    style: &DiffStyle,
+) -> (Spans<'b>, Spans<'b>) {
+    let diffs = calculate_line_diffs(line1, line2, options);
+    let (mut file1_spans, mut file2_spans) = build_spans(slice::from_ref(&diffs), style);
+
+    (
+        file1_spans.pop().unwrap_or_default(),
+        file2_spans.pop().unwrap_or_default(),
+    )
+}
+
+fn calculate_line_diffs(line1: &str, line2: &str, options: &DiffOptions) -> LineDiff {
+    let (text1, text2) = (options.strip_eol(line1), options.strip_eol(line2));
+    let mut diffs = calculate_content_diffs(text1, text2, options);
+
+    let (ending1, ending2) = (&line1[text1.len()..], &line2[text2.len()..]);
+    let ending = if ending1 == ending2 {
+        DiffSection::Same(ending1.to_string())
+    } else {
+        DiffSection::Equivalent {
+            left: ending1.to_string(),
+            right: ending2.to_string(),
+        }
+    };
+
+    if !ending.is_empty() {
+        diffs.push(ending);
+    }
+
+    diffs
+}
+
+/// Diffs two lines without their line endings, when those aren't compared
+fn calculate_content_diffs(line1: &str, line2: &str, options: &DiffOptions) -> LineDiff {
+    if options.mode == InputMode::Patch {
+        return calculate_patch_line_diffs(line1, line2, options);
+    }
+
+    if !options.ignores_trailing_whitespace() {
+        return calculate_text_diffs(line1, line2, options);
+    }
+
+    let (text1, text2) = (line1.trim_end(), line2.trim_end());
+    let mut diffs = calculate_text_diffs(text1, text2, options);
+
+    let trailing = DiffSection::Equivalent {
+        left: line1[text1.len()..].to_string(),
+        right: line2[text2.len()..].to_string(),
+    };
+
+    if !trailing.is_empty() {
+        diffs.push(trailing);
+    }
+
+    diffs
+}
+
+/// Diffs two lines character by character, apart from the trailing whitespace handled by
+/// `calculate_line_diffs`
+fn calculate_text_diffs(line1: &str, line2: &str, options: &DiffOptions) -> LineDiff {
+    if options.pattern {
+        if let Some(diffs) = compare::pattern_sections(line1, line2) {
+            return diffs;
+        }
+    }
+
+    let mut diffs = LineDiff::default();
+
+    let masked1 = options.masks.ranges(line1);
+    let masked2 = options.masks.ranges(line2);
+
+    let mut chars1 = line1.char_indices().peekable();
+    let mut chars2 = line2.char_indices().peekable();
+
+    // The kind of the section being built, and where it starts in each line. Each step takes the
+    // characters right after the last, so a run of steps of one kind covers one slice of each
+    // line, copied into a section once the run ends rather than a character at a time
+    let mut run: Option<(SectionKind, usize, usize)> = None;
+
+    loop {
+        let start1 = chars1.peek().map_or(line1.len(), |(index, _)| *index);
+        let start2 = chars2.peek().map_or(line2.len(), |(index, _)| *index);
+
+        let masked_at = |masked: &[Range<usize>], next: Option<&(usize, char)>| {
+            next.and_then(|(index, _)| masked.iter().find(|range| range.start == *index))
+                .cloned()
+        };
+
+        // Masked text is only set aside when both lines have some here, so it still shows up as
+        // a change against unmasked text
+        if let (Some(range1), Some(range2)) = (
+            masked_at(&masked1, chars1.peek()),
+            masked_at(&masked2, chars2.peek()),
+        ) {
+            while chars1.next_if(|(index, _)| *index < range1.end).is_some() {}
+            while chars2.next_if(|(index, _)| *index < range2.end).is_some() {}
+
+            extend_run(
+                &mut diffs,
+                &mut run,
+                SectionKind::Masked,
+                (start1, start2),
+                line1,
+                line2,
+            );
+
+            continue;
+        }
+
+        let next_char = |next: Option<&(usize, char)>| next.map(|(_, char)| *char);
+
+        let kind = match (next_char(chars1.peek()), next_char(chars2.peek())) {
+            (None, None) => break,
+            // Ignored characters are skipped over on their own side, shown but not compared
+            (Some(char1), _) if options.is_ignored(char1) => {
+                chars1.next();
+                SectionKind::Equivalent
+            }
+            (_, Some(char2)) if options.is_ignored(char2) => {
+                chars2.next();
+                SectionKind::Equivalent
+            }
+            (Some(char1), Some(char2))
+                if options.ignore_whitespace && char1.is_whitespace() && char2.is_whitespace() =>
+            {
+                while chars1.next_if(|(_, char)| char.is_whitespace()).is_some() {}
+                while chars2.next_if(|(_, char)| char.is_whitespace()).is_some() {}
+
+                let end1 = chars1.peek().map_or(line1.len(), |(index, _)| *index);
+                let end2 = chars2.peek().map_or(line2.len(), |(index, _)| *index);
+
+                if line1[start1..end1] == line2[start2..end2] {
+                    SectionKind::Same
+                } else {
+                    SectionKind::Equivalent
+                }
+            }
+            (Some(char1), Some(char2)) => {
+                chars1.next();
+                chars2.next();
+
+                if char1 == char2 {
+                    SectionKind::Same
+                } else {
+                    SectionKind::Modified
+                }
+            }
+            (Some(_), None) => {
+                chars1.next();
+                SectionKind::Removed
+            }
+            (None, Some(_)) => {
+                chars2.next();
+                SectionKind::Added
+            }
+        };
+
+        extend_run(&mut diffs, &mut run, kind, (start1, start2), line1, line2);
+    }
+
+    if let Some((kind, start1, start2)) = run {
+        diffs.push(kind.section(&line1[start1..], &line2[start2..]));
+    }
+
+    if options.char_diff == CharDiff::Substring && is_mostly_modified(&diffs) {
+        if let Some(substring_diffs) = compare::substring_sections(line1, line2) {
+            return substring_diffs;
+        }
+    }
+
+    diffs
+}
+
+/// The variant of a `DiffSection`, for `calculate_text_diffs` to build its runs with before it
+/// has their text
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SectionKind {
+    Added,
+    Modified,
+    Same,
+    Removed,
+    Equivalent,
+    Masked,
+}
+
+impl SectionKind {
+    /// The section of this kind with `left` from file 1 and `right` from file 2, of which kinds
+    /// with only one side take theirs
+    fn section(self, left: &str, right: &str) -> DiffSection {
+        let (left, right) = (left.to_string(), right.to_string());
+
+        match self {
+            SectionKind::Added => DiffSection::Added(right),
+            SectionKind::Modified => DiffSection::Modified { left, right },
+            SectionKind::Same => DiffSection::Same(left),
+            SectionKind::Removed => DiffSection::Removed(left),
+            SectionKind::Equivalent => DiffSection::Equivalent { left, right },
+            SectionKind::Masked => DiffSection::Masked { left, right },
+        }
+    }
+}
+
+/// Adds a step of `kind` starting at `starts` in each line to `run`, first pushing the run onto
+/// `diffs` if it is of another kind
+fn extend_run(
+    diffs: &mut LineDiff,
+    run: &mut Option<(SectionKind, usize, usize)>,
+    kind: SectionKind,
+    (start1, start2): (usize, usize),
+    line1: &str,
+    line2: &str,
+) {
+    if run.is_some_and(|(run_kind, _, _)| run_kind == kind) {
+        return;
+    }
+
+    if let Some((run_kind, run_start1, run_start2)) = run.replace((kind, start1, start2)) {
+        diffs.push(run_kind.section(&line1[run_start1..start1], &line2[run_start2..start2]));
+    }
+}
+
+/// Diffs two lines of unified diffs: hunk headers are equivalent, and the markers of content
+/// lines are compared on their own so a changed marker doesn't shift the content diff
+fn calculate_patch_line_diffs(line1: &str, line2: &str, options: &DiffOptions) -> LineDiff {
+    let content_options = DiffOptions {
+        mode: InputMode::Trace,
+        ..options.clone()
+    };
+
+    match (PatchLine::parse(line1), PatchLine::parse(line2)) {
+        (PatchLine::HunkHeader, PatchLine::HunkHeader) => DiffSection::Equivalent {
+            left: line1.to_string(),
+            right: line2.to_string(),
+        }
+        .into(),
+        (
+            PatchLine::Content {
+                marker: marker1,
+                content: content1,
+            },
+            PatchLine::Content {
+                marker: marker2,
+                content: content2,
+            },
+        ) => {
+            let mut diffs = LineDiff::from(if marker1 == marker2 {
+                DiffSection::Same(marker1.to_string())
+            } else {
+                DiffSection::Modified {
+                    left: marker1.to_string(),
+                    right: marker2.to_string(),
+                }
+            });
+
+            for section in calculate_line_diffs(content1, content2, &content_options).sections {
+                diffs.push(section);
+            }
+
+            diffs
+        }
+        _ => calculate_line_diffs(line1, line2, &content_options),
+    }
+}
+
+/// Whether more than half of the columns of the line pair are `Modified`
+fn is_mostly_modified(line_diffs: &LineDiff) -> bool {
+    let (modified, total) =
+        line_diffs
+            .sections()
+            .iter()
+            .fold((0, 0), |(modified, total), section| {
+                let length = section.left_len().max(section.right_len());
+
+                match section {
+                    DiffSection::Modified { .. } => (modified + length, total + length),
+                    _ => (modified, total + length),
+                }
+            });
+
+    modified * 2 > total
+}
+
+/// Shortens the `Same` text at the start and end of a changed line to `…`, keeping
+/// `FOLD_CONTEXT` characters next to the changes
+fn fold_same(line_diffs: &LineDiff) -> LineDiff {
+    let sections = line_diffs.sections();
+
+    if !sections.iter().any(|diff| diff.is_change()) {
+        return line_diffs.clone();
+    }
+
+    let last_index = sections.len() - 1;
+    let mut folded = LineDiff::default();
+
+    for (index, diff) in sections.iter().enumerate() {
+        let text = match diff {
+            DiffSection::Same(text) if text.chars().count() > FOLD_CONTEXT + 1 => text,
+            _ => {
+                folded.push(diff.clone());
+                continue;
+            }
+        };
+
+        let length = text.chars().count();
+
+        let folded_text = if index == 0 {
+            format!("…{}", text.slice(length - FOLD_CONTEXT..))
+        } else if index == last_index {
+            format!("{}…", text.slice(..FOLD_CONTEXT))
+        } else {
+            text.clone()
+        };
+
+        folded.push(DiffSection::Same(folded_text));
+    }
+
+    folded
+}
+
+fn build_spans<'b>(diffs: &[LineDiff], style: &DiffStyle) -> (Vec<Spans<'b>>, Vec<Spans<'b>>) {
+    diffs
+        .iter()
+        .map(|line_diffs| {
+            let mut line1 = Spans::default();
+            let mut line2 = Spans::default();
+
+            let sections = line_diffs.sections();
+
+            for (index, diff) in sections.iter().enumerate() {
+                let diff_style = |default_style: Style| {
+                    if is_trailing_whitespace(&sections[index..]) {
+                        style.trailing_whitespace
+                    } else {
+                        default_style
+                    }
+                };
+
+                match diff {
+                    DiffSection::Added(string) => line2
+                        .0
+                        .push(Span::styled(string.clone(), diff_style(style.added))),
+                    DiffSection::Modified { left, right } => {
+                        let modified_style = diff_style(style.modified);
+
+                        line1.0.push(Span::styled(left.clone(), modified_style));
+                        line2.0.push(Span::styled(right.clone(), modified_style));
+                    }
+                    DiffSection::Same(string) => {
+                        let span = Span::styled(string.clone(), style.same);
+
+                        line1.0.push(span.clone());
+                        line2.0.push(span);
+                    }
+                    DiffSection::Equivalent { left, right } => {
+                        line1.0.push(Span::styled(left.clone(), style.same));
+                        line2.0.push(Span::styled(right.clone(), style.same));
+                    }
+                    DiffSection::Masked { left, right } => {
+                        let masked_style = style.same.add_modifier(Modifier::DIM);
+
+                        line1.0.push(Span::styled(left.clone(), masked_style));
+                        line2.0.push(Span::styled(right.clone(), masked_style));
+                    }
+                    DiffSection::Removed(string) => line1
+                        .0
+                        .push(Span::styled(string.clone(), diff_style(style.removed))),
+                }
+            }
+
+            (line1, line2)
+        })
+        .unzip()
+}
+
+/// Like `build_spans`, but drops the `Same` sections, joining the changed ones with a separator.
+/// Identical lines are rendered as a dim `=`
+fn build_change_spans<'b>(
+    diffs: &[LineDiff],
+    style: &DiffStyle,
+) -> (Vec<Spans<'b>>, Vec<Spans<'b>>) {
+    let separator = Span::styled(" | ", Style::default().add_modifier(Modifier::DIM));
+
+    diffs
+        .iter()
+        .map(|line_diffs| {
+            let mut line1 = Spans::default();
+            let mut line2 = Spans::default();
+
+            // Line endings are left out, as they can't be seen
+            let text = |string: &str| string.trim_end_matches(['\r', '\n']).to_string();
+
+            // Pair each change with `∅` on the side without it, so both sides stay in step
+            let changes = line_diffs
+                .sections()
+                .iter()
+                .filter_map(|diff| match diff {
+                    DiffSection::Added(string) => Some((String::new(), text(string), style.added)),
+                    DiffSection::Modified { left, right } => {
+                        Some((text(left), text(right), style.modified))
+                    }
+                    DiffSection::Removed(string) => {
+                        Some((text(string), String::new(), style.removed))
+                    }
+                    DiffSection::Same(_)
+                    | DiffSection::Equivalent { .. }
+                    | DiffSection::Masked { .. } => None,
+                })
+                .filter(|(left, right, _)| !(left.is_empty() && right.is_empty()))
+                .map(|(left, right, style)| {
+                    let placeholder = |text: String| {
+                        if text.is_empty() {
+                            "∅".to_string()
+                        } else {
+                            text
+                        }
+                    };
+
+                    (
+                        Span::styled(placeholder(left), style),
+                        Span::styled(placeholder(right), style),
+                    )
+                });
+
+            for (index, (left, right)) in changes.enumerate() {
+                if index > 0 {
+                    line1.0.push(separator.clone());
+                    line2.0.push(separator.clone());
+                }
+
+                line1.0.push(left);
+                line2.0.push(right);
+            }
+
+            if line1.0.is_empty() {
+                let same = Span::styled("=", Style::default().add_modifier(Modifier::DIM));
+
+                line1.0.push(same.clone());
+                line2.0.push(same);
+            }
+
+            (line1, line2)
+        })
+        .unzip()
+}
+
+/// Whether the first of `sections` and everything after it on the line is only whitespace
+fn is_trailing_whitespace(sections: &[DiffSection]) -> bool {
+    sections.iter().all(|section| match section {
+        DiffSection::Added(string) | DiffSection::Same(string) | DiffSection::Removed(string) => {
+            string.trim().is_empty()
+        }
+        DiffSection::Modified { left, right }
+        | DiffSection::Equivalent { left, right }
+        | DiffSection::Masked { left, right } => left.trim().is_empty() && right.trim().is_empty(),
+    })
+}
+
+/// Which columns of the lines the panes show
+#[derive(Clone, Copy)]
+struct ShownColumns {
+    horizontal_offset: usize,
+    max_columns: Option<usize>,
+    /// Columns of text per row, when wrapping
+    wrap_columns: Option<usize>,
+}
+
+fn build_lines<'a>(
+    file1_spans: &[Spans<'a>],
+    file2_spans: &[Spans<'a>],
+    file1_gutters: &[String],
+    file2_gutters: &[String],
+    past_end: (&[bool], &[bool]),
+    unscrolled_rows: &[usize],
+    columns: ShownColumns,
+) -> (Vec<ListItem<'a>>, Vec<ListItem<'a>>) {
+    let ShownColumns {
+        horizontal_offset,
+        max_columns,
+        wrap_columns,
+    } = columns;
+
+    // Only the first row past the end reads `EOF`, and the rest are left blank
+    let add_left_placeholder = |spans: Spans<'a>, original_length: usize, first_past_end: bool| {
+        if original_length == 0 && first_past_end {
+            Spans::from(Span::styled(
+                "EOF",
+                Style::default().add_modifier(Modifier::DIM),
+            ))
+        } else if original_length == 0 {
+            // An empty line, a gap in the alignment, or a later row past the end
+            spans
+        } else if spans.width() == 0 {
+            Spans::from(Span::styled(
+                "<==",
+                Style::default().add_modifier(Modifier::DIM),
+            ))
+        } else {
+            spans
+        }
+    };
+
+    // Each line as the rows it takes up, more than one when wrapped
+    let process_spans_into_lines =
+        |spans: &[Spans<'a>], gutters: &[String], past_end: &[bool]| -> Vec<Vec<Spans<'a>>> {
+            spans
+                .iter()
+                .enumerate()
+                .map(|(index, spans)| {
+                    let original_length = spans.width();
+
+                    let mut spans = if unscrolled_rows.contains(&index) {
+                        // Not part of the lines, so not scrolled with them
+                        spans.clone()
+                    } else {
+                        spans_substring(spans.clone(), horizontal_offset)
+                    };
+
+                    if let Some(max_columns) = max_columns {
+                        spans = spans_truncate(spans, max_columns);
+                    }
+
+                    let is_past_end = |index: usize| past_end.get(index).copied().unwrap_or(false);
+
+                    let spans = add_left_placeholder(
+                        spans,
+                        original_length,
+                        is_past_end(index) && (index == 0 || !is_past_end(index - 1)),
+                    );
+
+                    let mut number_string = gutters.get(index).cloned().unwrap_or_default();
+
+                    if is_past_end(index) && !number_string.is_empty() {
+                        // Like vim's end of buffer, so rows past the end don't read as blank lines
+                        number_string = format!("{:<1$}", "~", number_string.columns());
+                    }
+                    // Later rows of a wrapped line are indented past the gutter
+                    let indent = " ".repeat(number_string.columns());
+
+                    let mut rows = match wrap_columns {
+                        Some(columns) => spans_wrap(spans, columns),
+                        None => vec![spans],
+                    };
+
+                    for (row_index, row) in rows.iter_mut().enumerate() {
+                        let gutter = if row_index == 0 {
+                            number_string.clone()
+                        } else {
+                            indent.clone()
+                        };
+
+                        row.0.insert(
+                            0,
+                            Span::styled(gutter, Style::default().add_modifier(Modifier::DIM)),
+                        );
+                    }
+
+                    rows
+                })
+                .collect()
+        };
+
+    let mut file1_rows = process_spans_into_lines(file1_spans, file1_gutters, past_end.0);
+    let mut file2_rows = process_spans_into_lines(file2_spans, file2_gutters, past_end.1);
+
+    // Pad the shorter side of each pair, so the panes stay level
+    for (rows1, rows2) in file1_rows.iter_mut().zip(file2_rows.iter_mut()) {
+        let height = rows1.len().max(rows2.len());
+
+        rows1.resize_with(height, Spans::default);
+        rows2.resize_with(height, Spans::default);
+    }
+
+    let into_items = |rows: Vec<Vec<Spans<'a>>>| -> Vec<ListItem<'a>> {
+        rows.into_iter()
+            .map(|rows| ListItem::new(Text::from(rows)))
+            .collect()
+    };
+
+    (into_items(file1_rows), into_items(file2_rows))
+}
+
+/// Splits `spans` into rows of `columns` columns, keeping the styles. A wide character that would
+/// be cut at the end of a row starts the next one instead
+fn spans_wrap(spans: Spans<'_>, columns: usize) -> Vec<Spans<'_>> {
+    let mut rows = vec![];
+    let mut row: Vec<Span<'_>> = vec![];
+    let mut remaining = columns;
+
+    for span in spans.0 {
+        let mut text = span.content.trim_end_matches(['\r', '\n']);
+
+        while !text.is_empty() {
+            let head = text.slice_columns(..remaining);
+
+            if head.is_empty() {
+                // The row is full, or too short for the next character
+                rows.push(Spans::from(std::mem::take(&mut row)));
+                remaining = columns;
+
+                if text.slice_columns(..remaining).is_empty() {
+                    // Wider than a whole row, so it gets one to itself
+                    let width = text.chars().next().map_or(0, char::len_utf8);
+                    row.push(Span::styled(text[..width].to_string(), span.style));
+                    text = &text[width..];
+                    remaining = 0;
+                }
+
+                continue;
+            }
+
+            remaining -= head.columns();
+            row.push(Span::styled(head.to_string(), span.style));
+            text = &text[head.len()..];
+        }
+    }
+
+    rows.push(Spans::from(row));
+
+    rows
+}
+
+/// The file 1 and file 2 columns of the changed section containing file 1 column `offset`. Falls
+/// back to the first change after `offset`, then the last change before it
+fn change_region(line_diffs: &LineDiff, offset: usize) -> Option<(Range<usize>, Range<usize>)> {
+    let mut left_position = 0;
+    let mut right_position = 0;
+    let mut last_change = None;
+
+    for section in line_diffs.sections() {
+        let left_end = left_position + section.left_len();
+        let right_end = right_position + section.right_len();
+
+        if section.is_change() {
+            let region = (left_position..left_end, right_position..right_end);
+
+            if left_end > offset || left_position >= offset {
+                return Some(region);
+            }
+
+            last_change = Some(region);
+        }
+
+        left_position = left_end;
+        right_position = right_end;
+    }
+
+    last_change
+}
+
+/// Adds `modifier` to the characters of `spans` within `range`
+fn spans_with_modifier<'a>(spans: Spans<'a>, range: Range<usize>, modifier: Modifier) -> Spans<'a> {
+    let mut position = 0;
+    let mut result = vec![];
+
+    for span in spans.0 {
+        let length = span.content.columns();
+        let start = range.start.clamp(position, position + length) - position;
+        let end = range.end.clamp(position, position + length) - position;
+        position += length;
+
+        if start == end {
+            result.push(span);
+            continue;
+        }
+
+        let emphasized_style = span.style.add_modifier(modifier);
+
+        for (text, style) in [
+            (span.content.slice_columns(..start), span.style),
+            (span.content.slice_columns(start..end), emphasized_style),
+            (span.content.slice_columns(end..), span.style),
+        ] {
+            if !text.is_empty() {
+                result.push(Span::styled(text.to_string(), style));
+            }
+        }
+    }
+
+    Spans::from(result)
+}
+
+/// Cuts `spans` down to `max_columns` columns, ending in a `›` when text was cut off. Line
+/// endings don't take up a column
+fn spans_truncate(spans: Spans<'_>, max_columns: usize) -> Spans<'_> {
+    let width: usize = spans
+        .0
+        .iter()
+        .map(|span| span.content.trim_end_matches(['\r', '\n']).columns())
+        .sum();
+
+    if width <= max_columns {
+        return spans;
+    }
+
+    // Leave a column for the marker
+    let mut remaining = max_columns.saturating_sub(1);
+
+    let mut truncated: Vec<Span<'_>> = spans
+        .0
+        .into_iter()
+        .map_while(|span| {
+            if remaining == 0 {
+                return None;
+            }
+
+            let length = span.content.columns();
+
+            if length <= remaining {
+                remaining -= length;
+                Some(span)
+            } else {
+                let text = span.content.slice_columns(..remaining).to_string();
+                remaining = 0;
+                Some(Span::styled(text, span.style))
+            }
+        })
+        .collect();
+
+    truncated.push(Span::styled(
+        "›",
+        Style::default().add_modifier(Modifier::DIM),
+    ));
+
+    Spans::from(truncated)
+}
+
+/// Expands the tabs of `spans` with spaces out to the next multiple of `tab_width`. With a
+/// `shown` style, tabs start with a dim `→` and spaces are drawn as a dim `·` too, while the
+/// whitespace ending the line is drawn in the `shown` style instead
+fn expand_whitespace(spans: Spans<'_>, tab_width: usize, shown: Option<Style>) -> Spans<'_> {
+    if shown.is_none() && !spans.0.iter().any(|span| span.content.contains('\t')) {
+        return spans;
+    }
+
+    let text: String = spans.0.iter().map(|span| span.content.as_ref()).collect();
+    let trailing_start = text
+        .trim_end_matches(['\r', '\n'])
+        .trim_end_matches([' ', '\t'])
+        .len();
+
+    let mut expanded = vec![];
+    let mut column = 0;
+    let mut position = 0;
+
+    for span in spans.0 {
+        let mut run = String::new();
+
+        for char in span.content.chars() {
+            let glyph = match (char, shown) {
+                (' ', Some(_)) => "·".to_string(),
+                ('\t', Some(_)) => format!("→{}", " ".repeat(tab_width - 1 - column % tab_width)),
+                ('\t', None) => " ".repeat(tab_width - column % tab_width),
+                _ => {
+                    run.push(char);
+                    column += char.width().unwrap_or(0);
+                    position += char.len_utf8();
+                    continue;
+                }
+            };
+
+            if !run.is_empty() {
+                expanded.push(Span::styled(std::mem::take(&mut run), span.style));
+            }
+
+            let glyph_style = match shown {
+                Some(trailing_style) if position >= trailing_start => trailing_style,
+                Some(_) => span.style.add_modifier(Modifier::DIM),
+                None => span.style,
+            };
+
+            column += glyph.columns();
+            position += char.len_utf8();
+            expanded.push(Span::styled(glyph, glyph_style));
+        }
+
+        if !run.is_empty() {
+            expanded.push(Span::styled(run, span.style));
+        }
+    }
+
+    Spans::from(expanded)
+}
+
+fn spans_substring<'a>(spans: Spans<'a>, horizontal_offset: usize) -> Spans<'a> {
+    let mut required_offset = horizontal_offset;
+
+    let spans: Vec<Span<'_>> = spans
+        .0
+        .into_iter()
+        .filter_map(|span| {
+            if required_offset == 0 {
+                // Consume this span
+                Some(span)
+            } else if required_offset < span.width() {
+                // Offset is within this span
+                let text = span.content.skip_columns(required_offset);
+                required_offset = 0;
+                Some(Span::styled(text, span.style))
+            } else {
+                // Offset is not within this span. Skip it
+                required_offset -= span.width();
+                None
+            }
+        })
+        .collect();
+
+    Spans::from(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, iter};
+
+    use super::*;
+    use crate::mask::Masks;
+
+    fn same(text: &str) -> DiffSection {
+        DiffSection::Same(text.to_string())
+    }
+
+    fn modified(left: &str, right: &str) -> DiffSection {
+        DiffSection::Modified {
+            left: left.to_string(),
+            right: right.to_string(),
+        }
+    }
+
+    fn line_diffs(line1: &str, line2: &str) -> Vec<DiffSection> {
+        calculate_line_diffs(line1, line2, &DiffOptions::default()).sections
+    }
+
+    /// A state reading `text1` and `text2` from memory, with every line indexed like the scan
+    /// indexes files that differ
+    fn memory_state(text1: &str, text2: &str) -> State<'static, Cursor<Vec<u8>>> {
+        let positions = |text: &str| {
+            let mut positions = LinePositions::default();
+            let mut offset = 0;
+
+            for line in text.split_inclusive('\n') {
+                positions.push(offset);
+                offset += line.len();
+            }
+
+            positions
+        };
+        let reader = |text: &str| BufReader::new(Cursor::new(text.as_bytes().to_vec()));
+
+        State::new(
+            None,
+            positions(text1),
+            positions(text2),
+            reader(text1),
+            reader(text2),
+        )
+    }
+
+    /// Indices of the lines differing from the other file's line with the same index, as the
+    /// scan finds them
+    fn positional_diffs(text1: &str, text2: &str) -> Vec<usize> {
+        let (mut lines1, mut lines2) = (text1.lines(), text2.lines());
+
+        iter::from_fn(|| match (lines1.next(), lines2.next()) {
+            (None, None) => None,
+            lines => Some(lines.0 != lines.1),
+        })
+        .enumerate()
+        .filter_map(|(index, differs)| differs.then_some(index))
+        .collect()
+    }
+
+    /// `count` lines numbered from 1, with `edit` applied to the list of them
+    fn numbered_lines(count: usize, edit: impl FnOnce(&mut Vec<String>)) -> String {
+        let mut lines: Vec<String> = (1..=count).map(|i| format!("line {}\n", i)).collect();
+        edit(&mut lines);
+
+        lines.concat()
+    }
+
+    /// The text and style of each span of `spans`
+    fn styled_text(spans: &Spans) -> Vec<(String, Style)> {
+        spans
+            .0
+            .iter()
+            .map(|span| (span.content.to_string(), span.style))
+            .collect()
+    }
+
+    #[test]
+    fn identical_lines_are_one_same_section() {
+        assert_eq!(
+            line_diffs("pc=1 r1=0x0f", "pc=1 r1=0x0f"),
+            [same("pc=1 r1=0x0f")]
+        );
+    }
+
+    #[test]
+    fn substituted_character_is_modified() {
+        assert_eq!(
+            line_diffs("pc=1 r1=0x0f", "pc=1 r1=0x1f"),
+            [same("pc=1 r1=0x"), modified("0", "1"), same("f")]
+        );
+    }
+
+    #[test]
+    fn text_inserted_at_end_is_added() {
+        assert_eq!(
+            line_diffs("pc=1", "pc=1 r1=0x0f"),
+            [same("pc=1"), DiffSection::Added(" r1=0x0f".to_string())]
+        );
+    }
+
+    #[test]
+    fn text_deleted_at_start_shifts_the_columns_after_it() {
+        // Characters are compared column by column, so the rest of the line moves under the
+        // deleted text
+        assert_eq!(
+            line_diffs("xpc=1", "pc=1"),
+            [
+                modified("xpc=", "pc=1"),
+                DiffSection::Removed("1".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn replaced_line_is_one_modified_section() {
+        assert_eq!(line_diffs("pc=1", "lr:2"), [modified("pc=1", "lr:2")]);
+    }
+
+    #[test]
+    fn diff_lines_pairs_lines_along_their_common_subsequence() {
+        let file1 = ["a", "b", "c"].map(String::from);
+        let file2 = ["a", "c", "d"].map(String::from);
+
+        let sections: Vec<_> = compare::diff_lines(&file1, &file2)
+            .iter()
+            .map(|line| line.sections().to_vec())
+            .collect();
+
+        assert_eq!(
+            sections,
+            [
+                vec![same("a")],
+                vec![DiffSection::Removed("b".to_string())],
+                vec![same("c")],
+                vec![DiffSection::Added("d".to_string())],
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_of_identical_lines_are_same() {
+        let style = DiffStyle::default();
+        let (spans1, spans2) = build_spans(
+            &[calculate_line_diffs(
+                "pc=1",
+                "pc=1",
+                &DiffOptions::default(),
+            )],
+            &style,
+        );
+
+        assert_eq!(styled_text(&spans1[0]), [("pc=1".to_string(), style.same)]);
+        assert_eq!(styled_text(&spans2[0]), [("pc=1".to_string(), style.same)]);
+    }
+
+    #[test]
+    fn spans_of_substitution_style_the_changed_character() {
+        let style = DiffStyle::default();
+        let diffs = [calculate_line_diffs(
+            "r1=0x0f",
+            "r1=0x1f",
+            &DiffOptions::default(),
+        )];
+        let (spans1, spans2) = build_spans(&diffs, &style);
+
+        assert_eq!(
+            styled_text(&spans1[0]),
+            [
+                ("r1=0x".to_string(), style.same),
+                ("0".to_string(), style.modified),
+                ("f".to_string(), style.same),
+            ]
+        );
+        assert_eq!(
+            styled_text(&spans2[0]),
+            [
+                ("r1=0x".to_string(), style.same),
+                ("1".to_string(), style.modified),
+                ("f".to_string(), style.same),
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_of_insertion_only_show_in_file_2() {
+        let style = DiffStyle::default();
+        let diffs = [calculate_line_diffs(
+            "pc=1",
+            "pc=1 r1",
+            &DiffOptions::default(),
+        )];
+        let (spans1, spans2) = build_spans(&diffs, &style);
+
+        assert_eq!(styled_text(&spans1[0]), [("pc=1".to_string(), style.same)]);
+        assert_eq!(
+            styled_text(&spans2[0]),
+            [
+                ("pc=1".to_string(), style.same),
+                (" r1".to_string(), style.added),
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_of_deletion_at_start_show_the_shift() {
+        let style = DiffStyle::default();
+        let diffs = [calculate_line_diffs(
+            "xpc=1",
+            "pc=1",
+            &DiffOptions::default(),
+        )];
+        let (spans1, spans2) = build_spans(&diffs, &style);
+
+        assert_eq!(
+            styled_text(&spans1[0]),
+            [
+                ("xpc=".to_string(), style.modified),
+                ("1".to_string(), style.removed),
+            ]
+        );
+        assert_eq!(
+            styled_text(&spans2[0]),
+            [("pc=1".to_string(), style.modified)]
+        );
+    }
+
+    #[test]
+    fn spans_of_replaced_line_are_modified() {
+        let style = DiffStyle::default();
+        let diffs = [calculate_line_diffs(
+            "pc=1",
+            "lr:2",
+            &DiffOptions::default(),
+        )];
+        let (spans1, spans2) = build_spans(&diffs, &style);
+
+        assert_eq!(
+            styled_text(&spans1[0]),
+            [("pc=1".to_string(), style.modified)]
+        );
+        assert_eq!(
+            styled_text(&spans2[0]),
+            [("lr:2".to_string(), style.modified)]
+        );
+    }
+
+    #[test]
+    fn unified_diff_carries_hunks_across_chunks() {
+        let count = DIFF_CHUNK_LINES + 2_000;
+        let text1 = numbered_lines(count, |_| {});
+        let text2 = numbered_lines(count, |lines| {
+            lines[4] = "line five\n".to_string();
+            lines.insert(11_000, "extra\n".to_string());
+        });
+
+        let patch = memory_state(&text1, &text2)
+            .unified_diff(Path::new("a"), Path::new("b"), 1)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            patch,
+            "--- a\n+++ b\n\
+             @@ -4,3 +4,3 @@\n line 4\n-line 5\n+line five\n line 6\n\
+             @@ -11000,2 +11000,3 @@\n line 11000\n+extra\n line 11001\n"
+        );
+    }
+
+    #[test]
+    fn unified_diff_of_identical_files_is_none() {
+        let text = numbered_lines(20, |_| {});
+
+        assert_eq!(
+            memory_state(&text, &text)
+                .unified_diff(Path::new("a"), Path::new("b"), 3)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn aligned_files_count_an_inserted_line_once() {
+        let text1 = numbered_lines(100, |_| {});
+        let text2 = numbered_lines(100, |lines| lines.insert(10, "inserted\n".to_string()));
+
+        let mut state = memory_state(&text1, &text2);
+        state.diff_lines = positional_diffs(&text1, &text2);
+        state.align_files(0).unwrap();
+
+        assert_eq!(state.diff_summary.differing(), 1);
+    }
+
+    #[test]
+    fn aligned_files_classify_rows_by_the_files_with_a_line_in_them() {
+        let text1 = numbered_lines(100, |_| {});
+        let text2 = numbered_lines(100, |lines| {
+            lines[80] = "changed\n".to_string();
+            lines.remove(50);
+            lines.insert(10, "inserted\n".to_string());
+        });
+
+        let mut state = memory_state(&text1, &text2);
+        state.diff_lines = positional_diffs(&text1, &text2);
+        state.align_files(0).unwrap();
+
+        let summary = &state.diff_summary;
+        assert_eq!(
+            (summary.modified, summary.added, summary.removed),
+            (1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn start_diff_opens_at_the_row_of_an_aligned_diff() {
+        let text1 = numbered_lines(100, |_| {});
+        let text2 = numbered_lines(100, |lines| {
+            lines[60] = "changed\n".to_string();
+            lines.insert(10, "inserted\n".to_string());
+        });
+
+        let mut state = memory_state(&text1, &text2);
+        state.diff_lines = positional_diffs(&text1, &text2);
+        state.align_files(0).unwrap();
+
+        assert_eq!(state.diff_marks(), [10, 61]);
+
+        state.start_diff = Some(2);
+        state.build_state(11);
+
+        assert_eq!(state.row_lines(state.selected_line), (Some(60), Some(61)));
+    }
+
+    #[test]
+    fn next_diff_past_the_window_skips_lines_the_window_leaves_unpaired() {
+        let text1 = numbered_lines(3000, |_| {});
+        let text2 = numbered_lines(3000, |lines| {
+            lines[2000] = "changed\n".to_string();
+            lines.insert(10, "inserted\n".to_string());
+        });
+
+        let mut state = memory_state(&text1, &text2);
+        state.diff_lines = positional_diffs(&text1, &text2);
+        state.align_files(0).unwrap();
+        state.start_diff = Some(1);
+        state.build_state(20);
+
+        assert_eq!(state.row_lines(state.selected_line), (None, Some(10)));
+
+        let mut diffs = vec![];
+
+        while let Some((row, _)) = state.jump_to_next_diff(state.selected_line, usize::MAX) {
+            state.selected_line = row;
+            diffs.push(state.row_lines(row));
+        }
+
+        assert_eq!(diffs, [(Some(2000), Some(2001))]);
+    }
+
+    #[test]
+    fn changed_line_numbers_run_to_the_end_of_the_longer_file() {
+        let text1 = numbered_lines(5, |_| {});
+        let text2 = numbered_lines(100, |_| {});
+
+        let mut line_numbers = vec![];
+        memory_state(&text1, &text2)
+            .visit_changed_line_numbers(|line_number| {
+                line_numbers.push(line_number);
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(line_numbers, (6..=100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn html_page_has_a_row_for_every_line_of_the_longer_file() {
+        let text1 = numbered_lines(5, |_| {});
+        let text2 = numbered_lines(100, |_| {});
+
+        let mut page = vec![];
+        let identical = memory_state(&text1, &text2)
+            .write_html(&mut page, Path::new("a"), Path::new("b"))
+            .unwrap();
+        let page = String::from_utf8(page).unwrap();
+
+        assert!(!identical);
+        assert_eq!(page.matches("<tr>").count(), 1 + 100);
+        assert!(page.contains(r#"id="file2-L100""#));
+        assert!(!page.contains(r#"id="file1-L6""#));
+    }
+
+    #[test]
+    fn window_slides_back_from_the_end_of_the_files() {
+        let text = numbered_lines(100, |_| {});
+        let mut state = memory_state(&text, &text);
+
+        // The first, a middle, and the last line, and the line each window starts at
+        for (line_index, first_line_index) in [(0, 0), (50, 45), (99, 90)] {
+            let (file1_lines, file2_lines) = state.get_lines_around_line(line_index, 10);
+
+            assert_eq!(state.first_line_index, first_line_index);
+            assert_eq!(file1_lines.len(), 10);
+            assert_eq!(file1_lines, file2_lines);
+            assert_eq!(file1_lines[0], format!("line {}\n", first_line_index + 1));
+            assert!(file1_lines.contains(&format!("line {}\n", line_index + 1)));
+        }
+    }
+
+    #[test]
+    fn pushed_sections_merge_into_the_last_of_the_same_kind() {
+        let pair = |left: &str, right: &str| (left.to_string(), right.to_string());
+        let sections = |(left, right): (String, String)| {
+            [
+                DiffSection::Same(left.clone()),
+                DiffSection::Added(right.clone()),
+                DiffSection::Removed(left.clone()),
+                DiffSection::Modified {
+                    left: left.clone(),
+                    right: right.clone(),
+                },
+                DiffSection::Equivalent {
+                    left: left.clone(),
+                    right: right.clone(),
+                },
+                DiffSection::Masked { left, right },
+            ]
+        };
+
+        let mut line_diffs = LineDiff::default();
+
+        for (first, second) in sections(pair("a", "b"))
+            .into_iter()
+            .zip(sections(pair("c", "d")))
+        {
+            line_diffs.push(first);
+            line_diffs.push(second);
+        }
+
+        assert_eq!(line_diffs.sections(), sections(pair("ac", "bd")));
+    }
+
+    #[test]
+    fn diffed_lines_never_have_adjacent_sections_of_the_same_kind() {
+        let options = lenient_options();
+
+        for (line1, line2) in [
+            ("t=1:2 pc=1 #x r1", "t=3:45 pc=2 #y r1"),
+            ("pc=1 r1=0x0f", "pc=2 r1=0x0F r2=7"),
+            ("  mov r1, r2", "mov r1,  r3"),
+            ("abcdef", "xbcdefgh"),
+            ("", "added"),
+            ("removed", ""),
+        ] {
+            let sections = calculate_line_diffs(line1, line2, &options).sections;
+
+            for adjacent in sections.windows(2) {
+                assert_ne!(
+                    std::mem::discriminant(&adjacent[0]),
+                    std::mem::discriminant(&adjacent[1]),
+                    "{:?}",
+                    sections
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn first_diff_past_the_indexed_lines_selects_a_loaded_row() {
+        let text = numbered_lines(100, |_| {});
+
+        let mut state = memory_state(&text, &text);
+        // As a checkpoint cut short can leave it
+        state.first_diff = Some(DiffPosition {
+            line_index: 500,
+            line_offset: 3,
+            file1_offset: 5000,
+            file2_offset: 5000,
+        });
+        state.build_state(10);
+
+        assert_eq!(state.rows.len(), 10);
+        assert_eq!(state.selected_line, 9);
+        assert_eq!(state.row_lines(state.selected_line), (Some(99), Some(99)));
+    }
+
+    #[test]
+    fn left_len_counts_only_file_1_columns_between_added_text() {
+        let sections = [
+            same("ab"),
+            DiffSection::Added("X".to_string()),
+            same("cd"),
+            modified("e", "f"),
+            DiffSection::Added("YY".to_string()),
+            same("g"),
+        ];
+
+        let columns: Vec<_> = sections.iter().map(DiffSection::left_len).collect();
+        assert_eq!(columns, [2, 0, 2, 1, 0, 1]);
+
+        let mut state = memory_state("abcdeg\n", "abXcdfYYg\n");
+        state.build_state(10);
+
+        let mut line_diffs = LineDiff::default();
+        sections
+            .into_iter()
+            .for_each(|section| line_diffs.push(section));
+        state.line_diffs = vec![line_diffs];
+
+        // Stepping back from the end of the line goes through each change's file 1 column
+        let mut offsets = vec![];
+        let mut offset = usize::MAX;
+
+        while let Some((_, prev)) = state.find_prev_diff(0, offset) {
+            offsets.push(prev);
+            offset = prev;
+        }
+
+        assert_eq!(offsets, [5, 4, 2]);
+    }
+
+    #[test]
+    fn files_of_different_lengths_load_only_the_lines_each_has() {
+        let long = numbered_lines(10, |_| {});
+        let short = numbered_lines(3, |_| {});
+
+        // Either file can be the shorter one, and each is read only up to its own last line
+        for (text1, text2) in [(&long, &short), (&short, &long)] {
+            let mut state = memory_state(text1, text2);
+            let (file1_lines, file2_lines) = state.get_lines_around_line(9, 10);
+
+            assert_eq!(file1_lines, text1.split_inclusive('\n').collect::<Vec<_>>());
+            assert_eq!(file2_lines, text2.split_inclusive('\n').collect::<Vec<_>>());
+
+            state.build_state(10);
+            let (numbers1, numbers2) = state.line_numbers();
+            let numbers = |count| (1..=10).map(move |number| (number <= count).then_some(number));
+
+            assert_eq!(numbers1, numbers(text1.lines().count()).collect::<Vec<_>>());
+            assert_eq!(numbers2, numbers(text2.lines().count()).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn lines_are_read_at_their_offsets_in_any_order() {
+        let text1 = "pc=1\npc=22\npc=333";
+        let text2 = "r1=0\nr1=1\n";
+        let mut state = memory_state(text1, text2);
+
+        // Forwards from where the last read left the reader, then back, and from the other file
+        // in between
+        for (file1, offset, line) in [
+            (true, 0, "pc=1\n"),
+            (true, 5, "pc=22\n"),
+            (false, 5, "r1=1\n"),
+            (true, 11, "pc=333"),
+            (true, 5, "pc=22\n"),
+            (false, 0, "r1=0\n"),
+        ] {
+            assert_eq!(state.read_line_at_offset(file1, offset).unwrap(), line);
+        }
+    }
+
+    #[test]
+    fn wide_characters_count_as_two_columns_but_one_character() {
+        let line = "pc=1 名前=値 ok".to_string();
+
+        assert_eq!(line.chars().count(), 12);
+        assert_eq!(
+            longest_line_length(
+                std::slice::from_ref(&line),
+                &["pc=1".to_string()],
+                DEFAULT_TAB_WIDTH
+            ),
+            15
+        );
+
+        // Characters are taken by index, columns by width with any cut wide character left out
+        assert_eq!(line.substring(5, 2), "名前");
+        assert_eq!(line.slice_columns(5..9), "名前");
+        assert_eq!(line.slice_columns(6..9), "前");
+        assert_eq!(line.skip_columns(6), " 前=値 ok");
+    }
+
+    #[test]
+    fn scrolling_down_stops_at_the_last_line() {
+        let text = numbered_lines(30, |_| {});
+
+        // One line at a time, and in steps that overshoot the end
+        for step in [1, 7, 100] {
+            let mut state = memory_state(&text, &text);
+            state.build_state(10);
+
+            for _ in 0..50 {
+                state.scroll(true, step);
+
+                assert!(state.selected_line < state.rows.len());
+            }
+
+            assert_eq!(state.selected_line, state.rows.len() - 1);
+            assert_eq!(state.selected_line_index(), 29);
+        }
+    }
+
+    #[test]
+    fn tabs_are_expanded_for_display_but_compared_raw() {
+        let mut state = memory_state("a\tb=1\n", "a\tb=2\n");
+        state.tab_width = 4;
+        state.build_state(10);
+
+        // The comparison sees the tab as one character, which moves diff offsets no columns
+        assert_eq!(state.line_diffs[0].sections()[0], same("a\tb="));
+        assert_eq!(state.find_next_diff(0, 0), Some((0, 3)));
+
+        // Shown, the tab runs to column 4
+        assert_eq!(state.longest_line_length, 7);
+        assert_eq!(state.diff_column(0, 3), 6);
+
+        let text: String = state.spans().0[0]
+            .0
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect();
+        assert_eq!(text.trim_end(), "a   b=1");
+
+        // Spaces that look the same as a tab still differ from it
+        assert!(!DiffOptions::default().lines_equal("a\tb=1", "a   b=1"));
+    }
+
+    #[test]
+    fn crlf_line_shows_no_change_from_the_same_lf_line() {
+        let mut state = memory_state("pc=1\r\npc=2\r\n", "pc=1\npc=2\n");
+        state.build_state(10);
+
+        assert_eq!(state.find_next_diff(0, 0), None);
+        assert!(state
+            .line_diffs
+            .iter()
+            .all(|line| !line.sections().iter().any(DiffSection::is_change)));
+    }
+
+    #[test]
+    fn added_and_removed_text_is_placed_by_its_own_pane() {
+        let cases = [
+            // Added text only shows in file 2's pane, after its narrow `b`
+            (
+                [modified("名", "b"), DiffSection::Added("X".to_string())],
+                1,
+            ),
+            // Removed text only shows in file 1's pane, after its narrow `b`
+            (
+                [modified("b", "名"), DiffSection::Removed("X".to_string())],
+                1,
+            ),
+            // and after its wide character
+            (
+                [modified("名", "b"), DiffSection::Removed("X".to_string())],
+                2,
+            ),
+        ];
+
+        for (sections, column) in cases {
+            let mut state = memory_state("bX\n", "bX\n");
+            state.build_state(10);
+
+            let mut line_diffs = LineDiff::default();
+            sections
+                .into_iter()
+                .for_each(|section| line_diffs.push(section));
+            state.line_diffs = vec![line_diffs];
+
+            let (row, offset) = state.find_next_diff(0, 0).unwrap();
+            assert_eq!(state.diff_column(row, offset), column);
+        }
+    }
+
+    #[test]
+    fn wholly_added_and_removed_lines_are_navigated_in_their_own_pane() {
+        let added = DiffSection::Added("pc=2".to_string());
+        let removed = DiffSection::Removed("pc=2".to_string());
+
+        assert_eq!((added.left_len(), added.right_len()), (0, 4));
+        assert_eq!((removed.left_len(), removed.right_len()), (4, 0));
+
+        // A line only file 2 has, then one only file 1 has
+        for (text1, text2) in [("pc=1\n", "pc=1\npc=2\n"), ("pc=1\npc=2\n", "pc=1\n")] {
+            let mut state = memory_state(text1, text2);
+            state.build_state(10);
+
+            assert_eq!(state.find_next_diff(0, 0), Some((1, 0)));
+            assert_eq!(state.find_next_diff(1, 0), None);
+            assert_eq!(state.find_prev_diff(1, usize::MAX), Some((1, 0)));
+            assert_eq!(state.find_prev_diff(1, 0), None);
+            assert_eq!(state.diff_column(1, 0), 0);
+        }
+    }
+
+    #[test]
+    fn json_rows_tell_equal_lines_from_equivalent_ones() {
+        let mut state = memory_state("a\nb\r\nc\nd\n", "a\nb\nx\n");
+        let mut out = vec![];
+
+        state
+            .write_json(&mut out, Path::new("1.log"), Path::new("2.log"))
+            .unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let rows = json["lines"].as_array().unwrap();
+        let kinds: Vec<_> = rows
+            .iter()
+            .map(|row| row["kind"].as_str().unwrap())
+            .collect();
+
+        assert_eq!(kinds, ["equal", "equivalent", "modified", "removed"]);
+
+        // The sections leave out the line endings
+        let sections: Vec<_> = rows.iter().map(|row| row["sections"].clone()).collect();
+        assert_eq!(
+            sections,
+            [
+                serde_json::json!([{"kind": "same", "text": "a"}]),
+                serde_json::json!([{"kind": "same", "text": "b"}]),
+                serde_json::json!([{"kind": "modified", "left": "c", "right": "x"}]),
+                serde_json::json!([{"kind": "removed", "text": "d"}]),
+            ]
+        );
+    }
+
+    #[test]
+    fn line_endings_come_off_whichever_sections_end_the_lines() {
+        let mut ending_diffs = LineDiff::default();
+        [
+            same("b"),
+            modified("\r", "\n"),
+            DiffSection::Removed("\n".to_string()),
+        ]
+        .into_iter()
+        .for_each(|section| ending_diffs.push(section));
+
+        assert_eq!(ending_diffs.without_line_endings().sections(), [same("b")]);
+        assert_eq!(
+            calculate_line_diffs("x=1\n", "x=2\n", &DiffOptions::default())
+                .without_line_endings()
+                .sections(),
+            [same("x="), modified("1", "2")]
+        );
+    }
+
+    /// The character loop of `calculate_text_diffs` as it was, pushing a section per character for
+    /// `LineDiff::push` to merge, to check the runs it builds in place against
+    fn per_character_text_diffs(line1: &str, line2: &str, options: &DiffOptions) -> LineDiff {
+        let mut diffs = LineDiff::default();
+
+        let masked1 = options.masks.ranges(line1);
+        let masked2 = options.masks.ranges(line2);
+
+        let mut chars1 = line1.char_indices().peekable();
+        let mut chars2 = line2.char_indices().peekable();
+
+        loop {
+            let masked_at = |masked: &[Range<usize>], next: Option<&(usize, char)>| {
+                next.and_then(|(index, _)| masked.iter().find(|range| range.start == *index))
+                    .cloned()
+            };
+
+            // Masked text is only set aside when both lines have some here, so it still shows up as
+            // a change against unmasked text
+            if let (Some(range1), Some(range2)) = (
+                masked_at(&masked1, chars1.peek()),
+                masked_at(&masked2, chars2.peek()),
+            ) {
+                while chars1.next_if(|(index, _)| *index < range1.end).is_some() {}
+                while chars2.next_if(|(index, _)| *index < range2.end).is_some() {}
+
+                diffs.push(DiffSection::Masked {
+                    left: line1[range1].to_string(),
+                    right: line2[range2].to_string(),
+                });
+
+                continue;
+            }
+
+            let next_char = |next: Option<&(usize, char)>| next.map(|(_, char)| *char);
+
+            let section = match (next_char(chars1.peek()), next_char(chars2.peek())) {
+                (None, None) => break,
+                // Ignored characters are skipped over on their own side, shown but not compared
+                (Some(char1), _) if options.is_ignored(char1) => {
+                    chars1.next();
+                    DiffSection::Equivalent {
+                        left: char1.to_string(),
+                        right: String::new(),
+                    }
+                }
+                (_, Some(char2)) if options.is_ignored(char2) => {
+                    chars2.next();
+                    DiffSection::Equivalent {
+                        left: String::new(),
+                        right: char2.to_string(),
+                    }
+                }
+                (Some(char1), Some(char2))
+                    if options.ignore_whitespace
+                        && char1.is_whitespace()
+                        && char2.is_whitespace() =>
+                {
+                    let left: String =
+                        iter::from_fn(|| chars1.next_if(|(_, char)| char.is_whitespace()))
+                            .map(|(_, char)| char)
+                            .collect();
+                    let right: String =
+                        iter::from_fn(|| chars2.next_if(|(_, char)| char.is_whitespace()))
+                            .map(|(_, char)| char)
+                            .collect();
+
+                    if left == right {
+                        DiffSection::Same(left)
+                    } else {
+                        DiffSection::Equivalent { left, right }
+                    }
+                }
+                (Some(char1), Some(char2)) => {
+                    chars1.next();
+                    chars2.next();
+
+                    if char1 == char2 {
+                        DiffSection::Same(char1.to_string())
+                    } else {
+                        DiffSection::Modified {
+                            left: char1.to_string(),
+                            right: char2.to_string(),
+                        }
+                    }
+                }
+                (Some(char1), None) => {
+                    chars1.next();
+                    DiffSection::Removed(char1.to_string())
+                }
+                (None, Some(char2)) => {
+                    chars2.next();
+                    DiffSection::Added(char2.to_string())
+                }
+            };
+
+            diffs.push(section);
+        }
+
+        diffs
+    }
+
+    /// A pair of 10 KB lines with every kind of step: changed and same runs, spaces, ignored and
+    /// masked text, and text only file 2 has at the end
+    fn long_line_pair() -> (String, String) {
+        let line1: String = (0..550)
+            .map(|i| format!("id={} t=12:{:02} v={}  ", i, i % 60, i * 7 % 13))
+            .collect();
+        let line2: String = (0..570)
+            .map(|i| {
+                let gap = if i % 5 == 0 { "\t " } else { "  " };
+                let mark = if i % 7 == 0 { "#" } else { "" };
+
+                format!(
+                    "id={}{} t=13:{:02} v={}{}",
+                    i,
+                    mark,
+                    i % 50,
+                    i * 5 % 13,
+                    gap
+                )
+            })
+            .collect();
+
+        assert!(line1.len() >= 10_000);
+
+        (line1, line2)
+    }
+
+    fn lenient_options() -> DiffOptions {
+        DiffOptions {
+            ignore_whitespace: true,
+            ignore_chars: "#".to_string(),
+            masks: Masks(vec![regex::Regex::new(r"t=\d+:\d+").unwrap()]),
+            ..DiffOptions::default()
+        }
+    }
+
+    #[test]
+    fn text_diff_runs_match_the_per_character_diff() {
+        let (line1, line2) = long_line_pair();
+
+        for options in [DiffOptions::default(), lenient_options()] {
+            for (line1, line2) in [(&line1, &line2), (&line2, &line1)] {
+                assert_eq!(
+                    calculate_text_diffs(line1, line2, &options),
+                    per_character_text_diffs(line1, line2, &options)
+                );
+            }
+        }
+    }
+
+    /// Times both on the long lines. Run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_text_diff_runs_against_the_per_character_diff() {
+        let (line1, line2) = long_line_pair();
+        let options = lenient_options();
+        let rounds = 200;
+
+        let time = |diff: fn(&str, &str, &DiffOptions) -> LineDiff| {
+            let start = Instant::now();
+
+            for _ in 0..rounds {
+                std::hint::black_box(diff(&line1, &line2, &options));
+            }
+
+            start.elapsed() / rounds
+        };
+
+        println!(
+            "runs {:?}, per character {:?} per diff of {} and {} bytes",
+            time(calculate_text_diffs),
+            time(per_character_text_diffs),
+            line1.len(),
+            line2.len()
+        );
+    }
 }
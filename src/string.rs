@@ -1,8 +1,25 @@
 use std::ops::{Bound, RangeBounds};
 
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
 pub trait StringUtils {
     fn substring(&self, start: usize, len: usize) -> &str;
     fn slice(&self, range: impl RangeBounds<usize>) -> &str;
+    /// Columns the text takes up in a terminal. Wide characters take two, combining marks none
+    fn columns(&self) -> usize;
+    /// The column the character at `char_index` starts at
+    fn column_of(&self, char_index: usize) -> usize;
+    /// Like `column_of`, but with each tab taking the columns up to the next multiple of
+    /// `tab_width`, as it does once expanded
+    fn tab_column_of(&self, char_index: usize, tab_width: usize) -> usize;
+    /// Columns the text takes up with its tabs expanded
+    fn tab_columns(&self, tab_width: usize) -> usize;
+    /// The characters within the `range` of columns. A wide character cut in half by either end
+    /// is left out, and combining marks go with the character before them
+    fn slice_columns(&self, range: impl RangeBounds<usize>) -> &str;
+    /// The text from column `start` on, with a space in place of the half of a wide character
+    /// cut at `start`, so the rest stays in the same columns
+    fn skip_columns(&self, start: usize) -> String;
 }
 
 // Taken from https://stackoverflow.com/a/54494217
@@ -49,4 +66,72 @@ impl StringUtils for str {
         } - start;
         self.substring(start, len)
     }
+    fn columns(&self) -> usize {
+        self.width()
+    }
+    fn column_of(&self, char_index: usize) -> usize {
+        self.chars()
+            .take(char_index)
+            .map(|c| c.width().unwrap_or(0))
+            .sum()
+    }
+    fn tab_column_of(&self, char_index: usize, tab_width: usize) -> usize {
+        self.chars().take(char_index).fold(0, |column, c| match c {
+            '\t' => column + tab_width - column % tab_width,
+            _ => column + c.width().unwrap_or(0),
+        })
+    }
+    fn tab_columns(&self, tab_width: usize) -> usize {
+        self.tab_column_of(usize::MAX, tab_width)
+    }
+    fn slice_columns(&self, range: impl RangeBounds<usize>) -> &str {
+        let start = match range.start_bound() {
+            Bound::Included(bound) => *bound,
+            Bound::Excluded(bound) => *bound + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(bound) => *bound + 1,
+            Bound::Excluded(bound) => *bound,
+            Bound::Unbounded => usize::MAX,
+        };
+
+        let mut column = 0;
+        let mut byte_range: Option<(usize, usize)> = None;
+        let mut previous_included = false;
+
+        for (index, c) in self.char_indices() {
+            let width = c.width().unwrap_or(0);
+
+            let included = if width == 0 && index > 0 {
+                previous_included
+            } else {
+                column >= start && column + width <= end
+            };
+
+            if included {
+                let (byte_start, _) = byte_range.get_or_insert((index, index));
+                byte_range = Some((*byte_start, index + c.len_utf8()));
+            } else if column >= end {
+                break;
+            }
+
+            previous_included = included;
+            column += width;
+        }
+
+        match byte_range {
+            Some((byte_start, byte_end)) => &self[byte_start..byte_end],
+            None => "",
+        }
+    }
+    fn skip_columns(&self, start: usize) -> String {
+        let rest = self.slice_columns(start..);
+        let padding = self
+            .columns()
+            .saturating_sub(start)
+            .saturating_sub(rest.columns());
+
+        format!("{}{}", " ".repeat(padding), rest)
+    }
 }
@@ -0,0 +1,344 @@
+use std::env;
+
+use clap::ValueEnum;
+use tui::style::{Color, Modifier, Style};
+
+use crate::theme::Theme;
+
+/// How added, removed, and modified text stands out from the same text
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Emphasis {
+    /// Fill the background with the kind's color
+    #[default]
+    Fill,
+    /// Color the text instead, and tell the kinds apart by line style: added text is underlined,
+    /// removed text struck through, and modified text bold, italic, and underlined
+    Outline,
+}
+
+/// How many colors the terminal can show
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ColorDepth {
+    /// Any 24-bit color
+    Truecolor,
+    /// The xterm 256 color palette
+    Ansi256,
+    /// The 16 standard colors
+    Ansi16,
+}
+
+impl ColorDepth {
+    /// Guesses the depth from `COLORTERM`, which truecolor terminals set to `truecolor` or
+    /// `24bit`, and a `TERM` like `xterm-256color`
+    pub fn detect() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::Truecolor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+}
+
+/// The usual RGB values of the 16 standard colors, as xterm shows them
+const ANSI_16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// Channel values of the 6×6×6 color cube in the 256 color palette, from index 16
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Styles applied to each kind of `DiffSection` when rendering
+pub struct DiffStyle {
+    pub added: Style,
+    pub removed: Style,
+    pub modified: Style,
+    pub same: Style,
+    /// Differences made only of whitespace at the end of the line, which are usually
+    /// insignificant
+    pub trailing_whitespace: Style,
+    /// Background filling every line with a change, when highlighting whole lines
+    pub changed_line: Style,
+    /// The selected row
+    pub selected: Style,
+}
+
+impl DiffStyle {
+    /// Colors from the Okabe-Ito palette, told apart under deuteranopia and protanopia: blue for
+    /// added text, orange for removed, and sky blue for modified
+    pub fn colorblind() -> Self {
+        DiffStyle {
+            added: Style::default()
+                .fg(Color::White)
+                .bg(Color::Rgb(0, 114, 178)),
+            removed: Style::default()
+                .fg(Color::Black)
+                .bg(Color::Rgb(230, 159, 0)),
+            modified: Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(Color::Black)
+                .bg(Color::Rgb(86, 180, 233)),
+            same: Style::default(),
+            trailing_whitespace: Style::default()
+                .fg(Color::Rgb(240, 228, 66))
+                .add_modifier(Modifier::UNDERLINED),
+            changed_line: Style::default().bg(Color::Rgb(48, 48, 48)),
+            selected: Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        }
+    }
+
+    /// Replaces the colors the theme gives, keeping the rest of each style
+    pub fn with_theme(self, theme: &Theme) -> Self {
+        let mut style = self.with_colors(theme.added, theme.removed, theme.modified);
+
+        for (style, color) in [
+            (&mut style.same, theme.same),
+            (&mut style.selected, theme.selected),
+        ] {
+            if let Some(color) = color {
+                style.fg = Some(color);
+            }
+        }
+
+        style
+    }
+
+    /// Replaces the color of each kind that is given, keeping the rest of its style
+    pub fn with_colors(
+        mut self,
+        added: Option<Color>,
+        removed: Option<Color>,
+        modified: Option<Color>,
+    ) -> Self {
+        for (style, color) in [
+            (&mut self.added, added),
+            (&mut self.removed, removed),
+            (&mut self.modified, modified),
+        ] {
+            if let Some(color) = color {
+                style.bg = Some(color);
+            }
+        }
+
+        self
+    }
+
+    /// Replaces the colors the terminal can't show with the nearest ones it can
+    pub fn for_color_depth(mut self, depth: ColorDepth) -> Self {
+        for style in [
+            &mut self.added,
+            &mut self.removed,
+            &mut self.modified,
+            &mut self.same,
+            &mut self.trailing_whitespace,
+            &mut self.changed_line,
+            &mut self.selected,
+        ] {
+            style.fg = style.fg.map(|color| reduce_color(color, depth));
+            style.bg = style.bg.map(|color| reduce_color(color, depth));
+        }
+
+        self
+    }
+
+    pub fn with_emphasis(mut self, emphasis: Emphasis) -> Self {
+        if emphasis == Emphasis::Outline {
+            for (style, modifier) in [
+                (&mut self.added, Modifier::UNDERLINED),
+                (&mut self.removed, Modifier::CROSSED_OUT),
+                (
+                    &mut self.modified,
+                    Modifier::BOLD | Modifier::ITALIC | Modifier::UNDERLINED,
+                ),
+            ] {
+                let color = style.bg.or(style.fg).unwrap_or(Color::Reset);
+
+                *style = Style::default().fg(color).add_modifier(modifier);
+            }
+        }
+
+        self
+    }
+}
+
+fn reduce_color(color: Color, depth: ColorDepth) -> Color {
+    match (color, depth) {
+        (_, ColorDepth::Truecolor) => color,
+        (Color::Rgb(r, g, b), ColorDepth::Ansi256) => Color::Indexed(nearest_256(r, g, b)),
+        (Color::Rgb(r, g, b), ColorDepth::Ansi16) => nearest_16(r, g, b),
+        (Color::Indexed(index), ColorDepth::Ansi16) if index >= 16 => {
+            let (r, g, b) = indexed_rgb(index);
+            nearest_16(r, g, b)
+        }
+        _ => color,
+    }
+}
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    [(r1, r2), (g1, g2), (b1, b2)]
+        .into_iter()
+        .map(|(a, b)| (a.abs_diff(b) as u32).pow(2))
+        .sum()
+}
+
+/// The closest of the 6×6×6 cube and the grayscale ramp of the 256 color palette
+fn nearest_256(r: u8, g: u8, b: u8) -> u8 {
+    let level = |channel: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&level| CUBE_LEVELS[level].abs_diff(channel))
+            .unwrap_or(0)
+    };
+
+    let (r_level, g_level, b_level) = (level(r), level(g), level(b));
+    let cube_index = 16 + 36 * r_level + 6 * g_level + b_level;
+
+    // The ramp goes from 8 to 238 in steps of 10
+    let average = (r as usize + g as usize + b as usize) / 3;
+    let gray_step = (average.saturating_sub(3) / 10).min(23);
+    let gray = (8 + 10 * gray_step) as u8;
+
+    let cube_color = (
+        CUBE_LEVELS[r_level],
+        CUBE_LEVELS[g_level],
+        CUBE_LEVELS[b_level],
+    );
+
+    if distance((gray, gray, gray), (r, g, b)) < distance(cube_color, (r, g, b)) {
+        (232 + gray_step) as u8
+    } else {
+        cube_index as u8
+    }
+}
+
+/// The closest of the 16 standard colors
+fn nearest_16(r: u8, g: u8, b: u8) -> Color {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+
+    // Match dark colors by hue rather than brightness, or they would all become black
+    let (r, g, b) = if max - min >= 32 && max < 205 {
+        let scale = |channel: u8| (channel as u32 * 205 / max as u32) as u8;
+        (scale(r), scale(g), scale(b))
+    } else {
+        (r, g, b)
+    };
+
+    ANSI_16
+        .iter()
+        .min_by_key(|(_, rgb)| distance(*rgb, (r, g, b)))
+        .map_or(Color::Reset, |(color, _)| *color)
+}
+
+/// The RGB value of a 256 color palette index past the 16 standard colors
+fn indexed_rgb(index: u8) -> (u8, u8, u8) {
+    if index >= 232 {
+        let gray = 8 + 10 * (index - 232);
+        return (gray, gray, gray);
+    }
+
+    let cube_index = (index - 16) as usize;
+
+    (
+        CUBE_LEVELS[cube_index / 36],
+        CUBE_LEVELS[cube_index / 6 % 6],
+        CUBE_LEVELS[cube_index % 6],
+    )
+}
+
+/// Parses a color name like `red` or `lightblue`, a `#rrggbb` hex color, an `rgb(r, g, b)` color,
+/// or a 0-255 palette index
+pub fn parse_color(value: &str) -> Result<Color, String> {
+    if let Some(channels) = value
+        .strip_prefix("rgb(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        let channels: Vec<_> = channels
+            .split(',')
+            .map(|channel| channel.trim().parse::<u8>())
+            .collect();
+
+        return match channels[..] {
+            [Ok(r), Ok(g), Ok(b)] => Ok(Color::Rgb(r, g, b)),
+            _ => Err(format!("`{}` is not an rgb(r, g, b) color", value)),
+        };
+    }
+
+    if let Some(hex) = value.strip_prefix('#') {
+        let channel = |start: usize| {
+            hex.get(start..start + 2)
+                .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+        };
+
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Color::Rgb(r, g, b)),
+            _ => Err(format!("`{}` is not a #rrggbb color", value)),
+        };
+    }
+
+    if let Ok(index) = value.parse::<u8>() {
+        return Ok(Color::Indexed(index));
+    }
+
+    let color = match value.to_lowercase().as_str() {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" => Color::Gray,
+        "darkgray" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return Err(format!("unknown color `{}`", value)),
+    };
+
+    Ok(color)
+}
+
+impl Default for DiffStyle {
+    fn default() -> Self {
+        DiffStyle {
+            added: Style::default().bg(Color::Rgb(0, 100, 0)),
+            removed: Style::default().bg(Color::Red),
+            modified: Style::default()
+                .add_modifier(Modifier::BOLD)
+                .bg(Color::Blue),
+            same: Style::default(),
+            trailing_whitespace: Style::default()
+                .fg(Color::Magenta)
+                .add_modifier(Modifier::UNDERLINED),
+            changed_line: Style::default().bg(Color::Rgb(48, 48, 48)),
+            selected: Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        }
+    }
+}
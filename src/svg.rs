@@ -0,0 +1,230 @@
+use std::fmt::Write;
+
+use tui::{
+    style::{Color, Modifier, Style},
+    text::Spans,
+};
+
+use crate::string::StringUtils;
+
+const FONT_SIZE: f32 = 14.0;
+const CHAR_WIDTH: f32 = FONT_SIZE * 0.6;
+const LINE_HEIGHT: f32 = FONT_SIZE * 1.3;
+const PADDING: f32 = 8.0;
+
+pub(crate) const BACKGROUND: &str = "#1e1e1e";
+pub(crate) const FOREGROUND: &str = "#d4d4d4";
+
+/// Renders the two panes of styled lines side by side as a standalone SVG image. Each row is
+/// labelled with its pane's entry in `line_numbers`, or left blank for `None`
+pub fn render_svg(panes: [&[Spans]; 2], line_numbers: [&[Option<usize>]; 2]) -> String {
+    let line_count = panes[0].len().max(panes[1].len());
+    let last_line_number = line_numbers
+        .iter()
+        .flat_map(|numbers| numbers.iter().flatten())
+        .max()
+        .copied()
+        .unwrap_or(0);
+
+    let gutter_width = last_line_number.to_string().len() + 1;
+    let longest_line = panes[0]
+        .iter()
+        .chain(panes[1].iter())
+        .map(|spans| displayed_text(&spans_text(spans)).columns())
+        .max()
+        .unwrap_or(0);
+
+    let pane_width = (gutter_width + longest_line) as f32 * CHAR_WIDTH + PADDING * 2.0;
+    let width = pane_width * 2.0;
+    let height = line_count as f32 * LINE_HEIGHT + PADDING * 2.0;
+
+    let mut svg = String::new();
+
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width:.1}" height="{height:.1}" font-family="monospace" font-size="{FONT_SIZE}" xml:space="preserve">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="100%" height="100%" fill="{BACKGROUND}"/>"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<line x1="{pane_width:.1}" y1="0" x2="{pane_width:.1}" y2="{height:.1}" stroke="{FOREGROUND}" stroke-opacity="0.3"/>"#
+    );
+
+    for (pane_index, (pane, numbers)) in panes.iter().zip(line_numbers).enumerate() {
+        let pane_x = pane_index as f32 * pane_width + PADDING;
+
+        for (index, spans) in pane.iter().enumerate() {
+            let number = numbers
+                .get(index)
+                .copied()
+                .flatten()
+                .map_or(String::new(), |number| number.to_string());
+
+            let y = PADDING + index as f32 * LINE_HEIGHT;
+            let baseline = y + FONT_SIZE;
+
+            let _ = writeln!(
+                svg,
+                r#"<text x="{pane_x:.1}" y="{baseline:.1}" fill="{FOREGROUND}" fill-opacity="0.5">{:>width$}</text>"#,
+                number,
+                width = gutter_width - 1
+            );
+
+            let mut column = gutter_width;
+
+            for span in &spans.0 {
+                let text = displayed_text(&span.content);
+                let text_width = text.columns();
+
+                if text_width == 0 {
+                    continue;
+                }
+
+                let x = pane_x + column as f32 * CHAR_WIDTH;
+
+                if let Some(bg) = span.style.bg.and_then(color_to_hex) {
+                    let _ = writeln!(
+                        svg,
+                        r#"<rect x="{x:.1}" y="{y:.1}" width="{:.1}" height="{LINE_HEIGHT:.1}" fill="{bg}"/>"#,
+                        text_width as f32 * CHAR_WIDTH
+                    );
+                }
+
+                let _ = writeln!(
+                    svg,
+                    r#"<text x="{x:.1}" y="{baseline:.1}"{}>{}</text>"#,
+                    text_attributes(&span.style),
+                    escape(&text)
+                );
+
+                column += text_width;
+            }
+        }
+    }
+
+    svg.push_str("</svg>\n");
+
+    svg
+}
+
+fn spans_text(spans: &Spans) -> String {
+    spans.0.iter().map(|span| span.content.as_ref()).collect()
+}
+
+/// Line terminators are kept in the loaded lines, but have no place in a rendered row
+fn displayed_text(text: &str) -> String {
+    text.chars().filter(|char| !char.is_control()).collect()
+}
+
+fn text_attributes(style: &Style) -> String {
+    let mut attributes = format!(
+        r#" fill="{}""#,
+        style
+            .fg
+            .and_then(color_to_hex)
+            .unwrap_or_else(|| FOREGROUND.to_string())
+    );
+
+    if style.add_modifier.contains(Modifier::BOLD) {
+        attributes.push_str(r#" font-weight="bold""#);
+    }
+
+    if style.add_modifier.contains(Modifier::DIM) {
+        attributes.push_str(r#" fill-opacity="0.5""#);
+    }
+
+    if style.add_modifier.contains(Modifier::ITALIC) {
+        attributes.push_str(r#" font-style="italic""#);
+    }
+
+    let decorations: Vec<&str> = [
+        (Modifier::UNDERLINED, "underline"),
+        (Modifier::CROSSED_OUT, "line-through"),
+    ]
+    .into_iter()
+    .filter(|(modifier, _)| style.add_modifier.contains(*modifier))
+    .map(|(_, decoration)| decoration)
+    .collect();
+
+    if !decorations.is_empty() {
+        attributes.push_str(&format!(r#" text-decoration="{}""#, decorations.join(" ")));
+    }
+
+    attributes
+}
+
+/// Maps terminal colors to the xterm default palette
+pub(crate) fn color_to_hex(color: Color) -> Option<String> {
+    let (r, g, b) = match color {
+        Color::Reset => return None,
+        Color::Black => (0x00, 0x00, 0x00),
+        Color::Red => (0xcd, 0x00, 0x00),
+        Color::Green => (0x00, 0xcd, 0x00),
+        Color::Yellow => (0xcd, 0xcd, 0x00),
+        Color::Blue => (0x00, 0x00, 0xee),
+        Color::Magenta => (0xcd, 0x00, 0xcd),
+        Color::Cyan => (0x00, 0xcd, 0xcd),
+        Color::Gray => (0xe5, 0xe5, 0xe5),
+        Color::DarkGray => (0x7f, 0x7f, 0x7f),
+        Color::LightRed => (0xff, 0x00, 0x00),
+        Color::LightGreen => (0x00, 0xff, 0x00),
+        Color::LightYellow => (0xff, 0xff, 0x00),
+        Color::LightBlue => (0x5c, 0x5c, 0xff),
+        Color::LightMagenta => (0xff, 0x00, 0xff),
+        Color::LightCyan => (0x00, 0xff, 0xff),
+        Color::White => (0xff, 0xff, 0xff),
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::Indexed(index) => indexed_to_rgb(index),
+    };
+
+    Some(format!("#{:02x}{:02x}{:02x}", r, g, b))
+}
+
+fn indexed_to_rgb(index: u8) -> (u8, u8, u8) {
+    const NAMED: [Color; 16] = [
+        Color::Black,
+        Color::Red,
+        Color::Green,
+        Color::Yellow,
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+        Color::Gray,
+        Color::DarkGray,
+        Color::LightRed,
+        Color::LightGreen,
+        Color::LightYellow,
+        Color::LightBlue,
+        Color::LightMagenta,
+        Color::LightCyan,
+        Color::White,
+    ];
+
+    match index {
+        0..=15 => {
+            let hex = color_to_hex(NAMED[index as usize]).unwrap_or_default();
+            let channel =
+                |start: usize| u8::from_str_radix(&hex[start..start + 2], 16).unwrap_or(0);
+            (channel(1), channel(3), channel(5))
+        }
+        16..=231 => {
+            // 6x6x6 color cube
+            let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+            let index = index - 16;
+            (level(index / 36), level((index / 6) % 6), level(index % 6))
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
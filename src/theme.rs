@@ -0,0 +1,92 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use tui::style::Color;
+
+use crate::style;
+
+/// Colors read from a theme file, each replacing the default of its kind when given. Added,
+/// removed, and modified text is filled with its color, while same text and the selected row
+/// are drawn in theirs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Theme {
+    pub added: Option<Color>,
+    pub removed: Option<Color>,
+    pub modified: Option<Color>,
+    pub same: Option<Color>,
+    pub selected: Option<Color>,
+}
+
+impl Theme {
+    /// Parses `kind = "color"` lines, one per kind, with `#` comments. Colors are whatever
+    /// `style::parse_color` takes. Malformed lines and unknown kinds are skipped, with a message
+    /// for each
+    pub fn parse(contents: &str) -> (Theme, Vec<String>) {
+        let mut theme = Theme::default();
+        let mut problems = vec![];
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                problems.push(format!("line {}: expected `kind = \"color\"`", index + 1));
+                continue;
+            };
+
+            let slot = match key.trim() {
+                "added" => &mut theme.added,
+                "removed" => &mut theme.removed,
+                "modified" => &mut theme.modified,
+                "same" => &mut theme.same,
+                "selected" => &mut theme.selected,
+                key => {
+                    problems.push(format!("line {}: unknown kind `{}`", index + 1, key));
+                    continue;
+                }
+            };
+
+            match style::parse_color(strip_quotes(value)) {
+                Ok(color) => *slot = Some(color),
+                Err(err) => problems.push(format!("line {}: {}", index + 1, err)),
+            }
+        }
+
+        (theme, problems)
+    }
+}
+
+/// The value of a line, without its quotes and trailing comment
+fn strip_quotes(value: &str) -> &str {
+    let value = value.trim();
+
+    match value.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next().unwrap_or_default(),
+        // A bare `#` value is a hex color rather than a comment
+        None => value.split(" #").next().unwrap_or_default().trim(),
+    }
+}
+
+/// `trace-log-comparer/theme.toml` in `$XDG_CONFIG_HOME`, or in `~/.config` when that isn't set
+pub fn default_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+    Some(config_dir.join("trace-log-comparer").join("theme.toml"))
+}
+
+/// Reads and parses the theme at `path`. `None` when there is no file there
+pub fn read_theme(path: &Path) -> io::Result<Option<(Theme, Vec<String>)>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(Theme::parse(&contents))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    io::{self, stdout},
+    path::Path,
+    time::Duration,
+};
+
+use crossterm::{
+    event::{self, Event},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use log::info;
+use tui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout},
+    style::{Modifier, Style},
+    text::Span,
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    Terminal,
+};
+
+use trace_log_comparer::{input::Input, state::State};
+
+use crate::{
+    acceleration::KeyRepeat,
+    action::{Action, Context},
+    ui::{lines_to_load, max_horizontal_offset, Controls},
+};
+
+/// A base log compared against two variants at once. Each variant has its own `State` against
+/// the base, and the second one follows the first's selection by base line
+struct ThreeWay {
+    first: State<'static, Input>,
+    second: State<'static, Input>,
+    horizontal_offset: usize,
+}
+
+impl ThreeWay {
+    /// Reloads the second variant around the first one's selected base line, and restyles both
+    fn sync(&mut self) {
+        let (line_number, _) = self.first.position();
+
+        self.second.jump_to_line(line_number - 1);
+
+        self.first
+            .build_lines(self.horizontal_offset, self.first.first_line_index + 1);
+        self.second
+            .build_lines(self.horizontal_offset, self.second.first_line_index + 1);
+    }
+
+    /// The second variant's pane, one item per row of the first variant's panes so the three
+    /// line up. Rows without a base line are left blank
+    fn second_items(&self) -> Vec<ListItem<'static>> {
+        (0..self.first.file1_list_lines.len())
+            .map(|row| {
+                let (line_index, _) = self.first.row_lines(row);
+
+                (0..self.second.file2_list_lines.len())
+                    .find(|&second_row| {
+                        line_index.is_some() && self.second.row_lines(second_row).0 == line_index
+                    })
+                    .map_or_else(
+                        || ListItem::new(""),
+                        |second_row| self.second.file2_list_lines[second_row].clone(),
+                    )
+            })
+            .collect()
+    }
+
+    /// Jumps to the nearest line after (or before) the selected one that differs in either
+    /// variant
+    fn step_diff(&mut self, forward: bool) {
+        let (line_number, _) = self.first.position();
+        let line_index = line_number - 1;
+
+        let nearest = [&self.first.diff_lines, &self.second.diff_lines]
+            .into_iter()
+            .filter_map(|diff_lines| {
+                if forward {
+                    let next = diff_lines.partition_point(|&line| line <= line_index);
+                    diff_lines.get(next).copied()
+                } else {
+                    let prev = diff_lines.partition_point(|&line| line < line_index);
+                    prev.checked_sub(1).map(|prev| diff_lines[prev])
+                }
+            })
+            .reduce(|a, b| if forward { a.min(b) } else { a.max(b) });
+
+        if let Some(diff_line) = nearest {
+            self.first.jump_to_line(diff_line);
+        }
+    }
+}
+
+/// Runs the viewer for `base` against two variants, from the states comparing `base` with each
+pub fn run_three_way(
+    first: State<'static, Input>,
+    second: State<'static, Input>,
+    paths: [&Path; 3],
+    controls: Controls,
+) -> io::Result<()> {
+    enable_raw_mode()?;
+    let mut stdout = stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+
+    let result = run(&mut terminal, first, second, paths, controls);
+
+    // Left on the way out of an error too, so it prints to the normal screen
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+
+    result
+}
+
+fn run<B: Backend>(
+    terminal: &mut Terminal<B>,
+    mut first: State<'static, Input>,
+    mut second: State<'static, Input>,
+    paths: [&Path; 3],
+    controls: Controls,
+) -> io::Result<()> {
+    let size = terminal.get_frame().size();
+
+    info!(
+        "Starting three-way UI with terminal size {}x{}",
+        size.width, size.height
+    );
+
+    first.build_state(lines_to_load(size.height, false));
+    second.build_state(lines_to_load(size.height, false));
+
+    let mut view = ThreeWay {
+        horizontal_offset: first.initial_horizontal_offset,
+        first,
+        second,
+    };
+    view.sync();
+
+    let mut list_state = ListState::default();
+    let mut key_repeat = KeyRepeat::default();
+
+    let titles = paths.map(|path| path.display().to_string());
+
+    // Inner width of each pane at the last draw
+    let mut pane_width = 0;
+
+    // Shown in place of the status until the next key
+    let mut read_error = None;
+
+    // Only the navigation applies, with a single pair and nothing toggled
+    let context = Context {
+        pair_count: 1,
+        collapse_repeats: false,
+        folding: false,
+        range_marked: false,
+        full_lines: true,
+        change_index_shown: false,
+        searched: false,
+        independent_scroll: false,
+        wrapped: false,
+    };
+
+    loop {
+        if let Some(err) = view.first.take_read_error() {
+            read_error = Some(err.to_string());
+        }
+        if let Some(err) = view.second.take_read_error() {
+            read_error = Some(err.to_string());
+        }
+
+        terminal.draw(|f| {
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
+                .split(f.size());
+
+            let chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Ratio(1, 3); 3].as_ref())
+                .split(rows[0]);
+
+            pane_width = (Block::default()
+                .borders(Borders::ALL)
+                .inner(chunks[0])
+                .width as usize)
+                .saturating_sub(controls.selected_symbol_width());
+
+            let panes = [
+                view.first.file1_list_lines.clone(),
+                view.first.file2_list_lines.clone(),
+                view.second_items(),
+            ];
+
+            list_state.select(Some(view.first.selected_line));
+
+            for ((title, lines), area) in titles.iter().zip(panes).zip(chunks.iter()) {
+                let mut list = List::new(lines)
+                    .block(Block::default().borders(Borders::ALL).title(title.as_str()))
+                    .highlight_style(view.first.style.selected);
+
+                if let Some(symbol) = &controls.selected_symbol {
+                    list = list.highlight_symbol(symbol);
+                }
+
+                f.render_stateful_widget(list, *area, &mut list_state);
+            }
+
+            let (line_number, percent) = view.first.position();
+            let status = read_error.clone().unwrap_or_else(|| {
+                format!(
+                    "line {}/{} {}% col {} | {} and {} lines differ from the base",
+                    line_number + view.first.skipped_lines,
+                    view.first.line_count() + view.first.skipped_lines,
+                    percent,
+                    view.horizontal_offset + 1,
+                    view.first.diff_lines.len(),
+                    view.second.diff_lines.len()
+                )
+            });
+
+            f.render_widget(
+                Paragraph::new(Span::styled(
+                    status,
+                    Style::default().add_modifier(Modifier::REVERSED),
+                )),
+                rows[1],
+            );
+        })?;
+
+        if !crossterm::event::poll(Duration::from_millis(100))? {
+            key_repeat.reset();
+            continue;
+        }
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+
+        read_error = None;
+
+        let step_size = controls.acceleration.step(key_repeat.press(key.code));
+
+        // Both variants' lines scroll together
+        let max_offset = max_horizontal_offset(&view.first, pane_width)
+            .max(max_horizontal_offset(&view.second, pane_width));
+
+        match controls.keymap.action(key.code, &context) {
+            Some(action @ (Action::Down | Action::Up)) => {
+                view.first.scroll(action == Action::Down, step_size);
+            }
+            Some(action @ (Action::PageDown | Action::PageUp)) => {
+                let page = (terminal.size()?.height as usize).saturating_sub(4).max(1);
+
+                view.first.scroll(action == Action::PageDown, page);
+            }
+            Some(action @ (Action::Top | Action::Bottom)) => {
+                let line_index = if action == Action::Top {
+                    0
+                } else {
+                    view.first.line_count().saturating_sub(1)
+                };
+
+                view.first.jump_to_line(line_index);
+            }
+            Some(action @ (Action::NextDiff | Action::PrevDiff)) => {
+                view.step_diff(action == Action::NextDiff);
+            }
+            Some(Action::ScrollRight) => {
+                view.horizontal_offset = (view.horizontal_offset + step_size)
+                    .min(max_offset)
+                    .max(view.horizontal_offset);
+            }
+            Some(Action::ScrollLeft) => {
+                view.horizontal_offset = view.horizontal_offset.saturating_sub(step_size);
+            }
+            Some(Action::LineStart) => view.horizontal_offset = 0,
+            Some(Action::LineEnd) => view.horizontal_offset = max_offset,
+            Some(Action::Quit) => break,
+            _ => continue,
+        }
+
+        view.sync();
+    }
+
+    Ok(())
+}
@@ -1,222 +1,2151 @@
 use std::{
+    collections::BTreeMap,
+    fs,
     io::{self, stdout},
+    path::{Path, PathBuf},
     time::Duration,
 };
 
 use crossterm::{
-    event::{self, Event, KeyCode},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, MouseButton, MouseEventKind,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use log::{debug, info};
+use serde::Serialize;
 use tui::{
     backend::{Backend, CrosstermBackend},
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
-    widgets::{Block, Borders, List, ListState},
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    text::Span,
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
     Terminal,
 };
+use unicode_width::UnicodeWidthStr;
 
-use crate::state::State;
+use trace_log_comparer::{
+    compare::DiffOptions,
+    hex::hex_dump,
+    input::Input,
+    pairs::FilePair,
+    state::State,
+    view::{SavedView, SavedViews},
+};
+
+use crate::{
+    acceleration::{Acceleration, KeyRepeat},
+    action::{Action, Context, Keymap},
+    clipboard,
+    minimap::Minimap,
+    watch::FileWatcher,
+};
+
+/// How keys control the viewer
+pub struct Controls {
+    pub acceleration: Acceleration,
+    pub keymap: Keymap,
+    /// Ask before quitting
+    pub confirm_quit: bool,
+    /// Show the status line below the panes
+    pub status: bool,
+    /// `Action::NextDiff` after the last diff jumps to the first, and `Action::PrevDiff` before
+    /// the first to the last
+    pub diff_wrap: bool,
+    /// Reload the files when they change on disk
+    pub watch: bool,
+    /// File the view of each pair is saved to on quit, and restored from on launch
+    pub saved_views: Option<PathBuf>,
+    /// Brighten the selected change in both panes
+    pub link_changes: bool,
+    /// Drawn before the selected row of each pane, with every other row moved over to match
+    pub selected_symbol: Option<String>,
+}
+
+impl Controls {
+    /// Columns the selected symbol takes up at the start of every row
+    pub fn selected_symbol_width(&self) -> usize {
+        self.selected_symbol
+            .as_deref()
+            .map_or(0, UnicodeWidthStr::width)
+    }
+}
 
 struct UIState {
     list_state: ListState,
+    /// First row `list_state` showed at the last draw, which tui keeps to itself
+    list_offset: usize,
+    /// Where each pane's list was drawn, to tell which row a click is on
+    list_areas: [Rect; 2],
+    horizontal_offset: usize,
+    /// Inner width of each pane at the last draw
+    pane_width: usize,
+    status_message: Option<String>,
+    /// Shown on top of the panes until the next key press
+    overlay: Option<Overlay>,
+    /// File 1 and file 2 line indexes of the rows marked as the ends of the export range
+    range_start: Option<(Option<usize>, Option<usize>)>,
+    range_end: Option<(Option<usize>, Option<usize>)>,
+    /// The selected line's change is shown in reverse video
+    region_inverted: bool,
+    /// The selected change is brightened in both panes, so what each file has there can be
+    /// matched up
+    link_changes: bool,
+    /// The third pane listing the changed lines, when shown
+    change_index: Option<ChangeIndex>,
+    /// Show column numbers above the panes
+    ruler: bool,
+    /// Show where the diffs are in the whole files beside the panes
+    minimap: bool,
+    /// File 1 line index waiting for the file 2 line to anchor it to
+    anchor_start: Option<usize>,
+    /// What is being typed in the status line, if anything
+    prompt: Option<Prompt>,
+    /// Text of the last search, repeated by `Action::SearchNext`
+    last_search: Option<String>,
+    /// The panes scroll apart, when enabled
+    independent_scroll: Option<IndependentScroll>,
+}
+
+/// How far each pane is scrolled from the selected row, to line up their content by eye
+struct IndependentScroll {
+    /// Rows file 1's and file 2's panes are scrolled past the selected row
+    offsets: [isize; 2],
+    /// `Action::PaneDown` and `Action::PaneUp` scroll file 2's pane rather than file 1's
+    file2_focused: bool,
+    /// File 2's pane selection, as file 1's is `UIState::list_state`
+    file2_list_state: ListState,
+    file2_list_offset: usize,
+}
+
+impl IndependentScroll {
+    /// The row pane `file2` (or file 1) selects, of its `row_count` rows
+    fn pane_row(&self, selected_line: usize, file2: bool, row_count: usize) -> usize {
+        let row = selected_line as isize + self.offsets[file2 as usize];
+
+        row.clamp(0, row_count.saturating_sub(1) as isize) as usize
+    }
+
+    /// Scrolls the focused pane `step` rows down, or up
+    fn scroll(&mut self, selected_line: usize, down: bool, step: usize, row_count: usize) {
+        let file2 = self.file2_focused;
+        let row = self.pane_row(selected_line, file2, row_count);
+
+        let row = if down {
+            (row + step).min(row_count.saturating_sub(1))
+        } else {
+            row.saturating_sub(step)
+        };
+
+        self.offsets[file2 as usize] = row as isize - selected_line as isize;
+    }
+}
+
+/// Text typed into the status line, confirmed with Enter or canceled with Esc
+struct Prompt {
+    kind: PromptKind,
+    text: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PromptKind {
+    /// A line number to jump to
+    Line,
+    /// Text to search the files for
+    Search,
+}
+
+impl PromptKind {
+    fn symbol(&self) -> char {
+        match self {
+            PromptKind::Line => ':',
+            PromptKind::Search => '/',
+        }
+    }
+}
+
+struct ChangeIndex {
+    list_state: ListState,
+    /// Up and Down move through the changes instead of the lines
+    focused: bool,
+}
+
+/// File the view state is written to for bug reports
+const VIEW_STATE_PATH: &str = "view-state.json";
+
+/// What the viewer was showing, written out to reproduce reported issues
+#[derive(Serialize)]
+struct ViewState<'s> {
+    version: &'static str,
+    file1: &'s Path,
+    file2: &'s Path,
+    /// 1-based position of the pair among `pair_count`
+    pair: usize,
+    pair_count: usize,
+    terminal_size: (u16, u16),
+    /// Row of the loaded window, and the 1-based line number each file shows in it
+    selected_row: usize,
+    selected_lines: (Option<usize>, Option<usize>),
+    first_line_index: usize,
     horizontal_offset: usize,
+    diff_options: &'s DiffOptions,
+    alignment: String,
+    file2_shift: Option<isize>,
+    toggles: BTreeMap<&'static str, bool>,
+}
+
+enum Overlay {
+    /// The help, scrolled `scroll` lines down. `page` is how many lines it showed at the last
+    /// draw
+    Help { scroll: usize, page: usize },
+    /// Asks whether to quit, which `y` confirms
+    ConfirmQuit,
+    /// Hex dumps of the selected line from file 1 and file 2
+    HexDump(Vec<String>, Vec<String>),
+}
+
+impl UIState {
+    fn new(state: &State<Input>) -> Self {
+        let mut list_state = ListState::default();
+        list_state.select(Some(state.selected_line));
+
+        UIState {
+            list_state,
+            list_offset: 0,
+            list_areas: [Rect::default(); 2],
+            horizontal_offset: state.initial_horizontal_offset,
+            pane_width: 0,
+            status_message: None,
+            overlay: None,
+            range_start: None,
+            range_end: None,
+            region_inverted: false,
+            link_changes: false,
+            change_index: None,
+            ruler: false,
+            minimap: false,
+            anchor_start: None,
+            prompt: None,
+            last_search: None,
+            independent_scroll: None,
+        }
+    }
 }
 
-pub fn build_app(state: State) -> Result<(), io::Error> {
+/// Runs the viewer until it's quit, returning the state of the pair shown last
+pub fn build_app<F>(
+    state: State<'static, Input>,
+    pairs: &[FilePair],
+    controls: Controls,
+    load_pair: F,
+) -> Result<State<'static, Input>, io::Error>
+where
+    F: Fn(&FilePair) -> io::Result<State<'static, Input>>,
+{
     enable_raw_mode()?;
     let mut stdout = stdout();
-    execute!(stdout, EnterAlternateScreen)?;
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    run_app(&mut terminal, state)?;
+    let state = run_app(&mut terminal, state, pairs, controls, load_pair);
 
+    // Left on the way out of an error too, so it prints to the normal screen
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen,)?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture
+    )?;
     terminal.show_cursor()?;
 
-    Ok(())
+    state
 }
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut state: State) -> Result<(), io::Error> {
-    let size = terminal.get_frame().size();
+fn run_app<B: Backend, F>(
+    terminal: &mut Terminal<B>,
+    mut state: State<'static, Input>,
+    pairs: &[FilePair],
+    controls: Controls,
+    load_pair: F,
+) -> Result<State<'static, Input>, io::Error>
+where
+    F: Fn(&FilePair) -> io::Result<State<'static, Input>>,
+{
+    let mut size = terminal.get_frame().size();
+
+    info!(
+        "Starting UI with terminal size {}x{}",
+        size.width, size.height
+    );
 
-    state.build_state((size.height as usize) * 2);
+    // Borderless panes without a gutter
+    let mut compact = false;
 
+    state.build_state(lines_to_load(size.height, compact));
+
+    let mut pair_index = 0;
     let mut selected_diff_offset = 0;
 
-    let mut ui_state = UIState {
-        list_state: ListState::default(),
-        horizontal_offset: state.initial_horizontal_offset,
-    };
+    let mut ui_state = UIState::new(&state);
+    ui_state.link_changes = controls.link_changes;
 
-    ui_state.list_state.select(Some(state.selected_line));
+    if let Some(path) = &controls.saved_views {
+        load_view(path, &pairs[0], &mut state, &mut ui_state);
+    }
+
+    let mut key_repeat = KeyRepeat::default();
 
-    let mut last_keycode: Option<KeyCode> = None;
-    let mut key_repeat_count = 0;
+    let mut watcher = controls.watch.then(|| FileWatcher::new(&pairs[pair_index]));
+
+    // Set until the panes are drawn at the size of the terminal's last resize
+    let mut resized = false;
 
     loop {
+        if ui_state.link_changes && !ui_state.region_inverted && state.shows_full_lines() {
+            // Again before every draw, as rebuilding the lines drops it
+            state.link_change(
+                state.selected_line,
+                selected_diff_offset,
+                ui_state.horizontal_offset,
+            );
+        }
+
+        if let Some(err) = state.take_read_error() {
+            ui_state.status_message = Some(err.to_string());
+        }
+
+        let context = action_context(&state, &ui_state, pairs);
+
         terminal.draw(|f| {
+            let ruler_height = if ui_state.ruler { 1 } else { 0 };
+            let status_height = if controls.status || ui_state.prompt.is_some() {
+                1
+            } else {
+                0
+            };
+
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(
+                    [
+                        Constraint::Length(ruler_height),
+                        Constraint::Min(0),
+                        Constraint::Length(status_height),
+                    ]
+                    .as_ref(),
+                )
+                .split(f.size());
+
+            let pane_constraints = if ui_state.change_index.is_some() {
+                vec![
+                    Constraint::Percentage(35),
+                    Constraint::Percentage(35),
+                    Constraint::Percentage(30),
+                ]
+            } else {
+                vec![Constraint::Percentage(50), Constraint::Percentage(50)]
+            };
+
+            let minimap_width = if ui_state.minimap { 1 } else { 0 };
+
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(0), Constraint::Length(minimap_width)].as_ref())
+                .split(rows[1]);
+
             let chunks = Layout::default()
                 .direction(Direction::Horizontal)
-                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-                .split(f.size());
+                .constraints(pane_constraints)
+                .split(columns[0]);
 
-            let list1 = List::new(state.file1_list_lines.clone())
-                .block(Block::default().borders(Borders::ALL).title("File 1"))
-                .highlight_style(
-                    Style::default()
-                        .fg(Color::Yellow)
-                        .add_modifier(Modifier::BOLD),
+            let pane_block = |title| {
+                if compact {
+                    Block::default()
+                } else {
+                    Block::default().borders(Borders::ALL).title(title)
+                }
+            };
+
+            // Less the block borders and the selected symbol
+            ui_state.pane_width = (pane_block("").inner(chunks[0]).width as usize)
+                .saturating_sub(controls.selected_symbol_width());
+
+            if ui_state.minimap {
+                // Level with the text of the panes, inside their borders
+                let pane_inner = pane_block("").inner(chunks[0]);
+                let minimap_area = Rect::new(
+                    columns[1].x,
+                    pane_inner.y,
+                    columns[1].width,
+                    pane_inner.height,
                 );
 
-            f.render_stateful_widget(list1, chunks[0], &mut ui_state.list_state);
+                // The selected line, with the lines around it that fit in the panes
+                let (line_number, _) = state.position();
+                let viewport_start =
+                    (line_number - 1).saturating_sub(pane_inner.height as usize / 2);
 
-            let list2 = List::new(state.file2_list_lines.clone())
-                .block(Block::default().borders(Borders::ALL).title("File 2"))
-                .highlight_style(
-                    Style::default()
-                        .fg(Color::Yellow)
-                        .add_modifier(Modifier::BOLD),
+                f.render_widget(
+                    Minimap {
+                        diff_lines: state.diff_marks(),
+                        line_count: state.line_count(),
+                        viewport: viewport_start..viewport_start + pane_inner.height as usize,
+                    },
+                    minimap_area,
                 );
+            }
 
-            f.render_stateful_widget(list2, chunks[1], &mut ui_state.list_state);
-        })?;
+            for (file2, area, (title, focused_title), lines, pinned_line) in [
+                (
+                    false,
+                    chunks[0],
+                    ("File 1", "File 1 (focused)"),
+                    &state.file1_list_lines,
+                    &state.file1_pinned_line,
+                ),
+                (
+                    true,
+                    chunks[1],
+                    ("File 2", "File 2 (focused)"),
+                    &state.file2_list_lines,
+                    &state.file2_pinned_line,
+                ),
+            ] {
+                let focused = ui_state
+                    .independent_scroll
+                    .as_ref()
+                    .map(|scroll| scroll.file2_focused == file2);
 
-        if crossterm::event::poll(Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
-                let mut repeat = false;
+                let title = if focused == Some(true) {
+                    focused_title
+                } else {
+                    title
+                };
 
-                if let Some(code) = last_keycode {
-                    if code == key.code {
-                        if key_repeat_count < 5 {
-                            key_repeat_count += 1;
-                        } else {
-                            repeat = true;
-                        }
+                let block = pane_block(title);
+                let inner = block.inner(area);
+                f.render_widget(block, area);
+
+                if ui_state.ruler {
+                    // Over the text of the pane, past the selected symbol and the gutter
+                    let gutter_width = ((controls.selected_symbol_width() + state.gutter_width)
+                        as u16)
+                        .min(inner.width);
+                    let ruler_area = Rect::new(
+                        inner.x + gutter_width,
+                        rows[0].y,
+                        inner.width - gutter_width,
+                        1,
+                    );
+
+                    f.render_widget(
+                        Paragraph::new(ruler_text(
+                            if state.wrap_width.is_some() {
+                                0
+                            } else {
+                                ui_state.horizontal_offset
+                            },
+                            ruler_area.width as usize,
+                        ))
+                        .style(Style::default().add_modifier(Modifier::DIM)),
+                        ruler_area,
+                    );
+                }
+
+                // The pinned line takes the first row of the pane, above the scrolling list
+                let list_area = match pinned_line {
+                    Some(pinned_line) => {
+                        let pane_rows = Layout::default()
+                            .direction(Direction::Vertical)
+                            .constraints([Constraint::Length(1), Constraint::Min(0)].as_ref())
+                            .split(inner);
+
+                        // Level with the rows below, past the selected symbol
+                        let symbol_width =
+                            (controls.selected_symbol_width() as u16).min(pane_rows[0].width);
+                        let pinned_area = Rect {
+                            x: pane_rows[0].x + symbol_width,
+                            width: pane_rows[0].width - symbol_width,
+                            ..pane_rows[0]
+                        };
+
+                        let pinned = List::new(vec![pinned_line.clone()])
+                            .style(Style::default().add_modifier(Modifier::UNDERLINED));
+                        f.render_widget(pinned, pinned_area);
+
+                        pane_rows[1]
                     }
+                    None => inner,
+                };
+
+                let highlight_style = match focused {
+                    // The other pane's selection is only for reference
+                    Some(false) => Style::default().add_modifier(Modifier::BOLD),
+                    _ => state.style.selected,
+                };
+
+                let mut list = List::new(lines.clone()).highlight_style(highlight_style);
+
+                if let Some(symbol) = &controls.selected_symbol {
+                    // On every line of a wrapped row
+                    list = list.highlight_symbol(symbol).repeat_highlight_symbol(true);
                 }
 
-                let horizontal_step_size = if repeat { 5 } else { 1 };
+                let (list_state, list_offset) = match &mut ui_state.independent_scroll {
+                    Some(scroll) => {
+                        let row = scroll.pane_row(state.selected_line, file2, lines.len());
 
-                match key.code {
-                    KeyCode::Right => {
-                        let min_line_length = if state.longest_line_length > 10 {
-                            state.longest_line_length - 10
+                        let (list_state, list_offset) = if file2 {
+                            (&mut scroll.file2_list_state, &mut scroll.file2_list_offset)
                         } else {
-                            0
+                            (&mut ui_state.list_state, &mut ui_state.list_offset)
                         };
 
-                        if ui_state.horizontal_offset + horizontal_step_size < min_line_length {
-                            ui_state.horizontal_offset += horizontal_step_size;
+                        list_state.select(Some(row));
+                        (list_state, list_offset)
+                    }
+                    None => (&mut ui_state.list_state, &mut ui_state.list_offset),
+                };
+
+                *list_offset = list_offset_after_draw(
+                    lines,
+                    list_state.selected(),
+                    *list_offset,
+                    list_area.height as usize,
+                );
+
+                f.render_stateful_widget(list, list_area, list_state);
+
+                ui_state.list_areas[file2 as usize] = list_area;
+            }
+
+            if let Some(change_index) = &mut ui_state.change_index {
+                let entries = state.change_index();
+
+                if !change_index.focused {
+                    // Follow the selection in the main panes
+                    change_index.list_state.select(
+                        entries
+                            .iter()
+                            .position(|entry| entry.row == state.selected_line),
+                    );
+                }
+
+                let title = if change_index.focused {
+                    "Changes (focused)"
+                } else {
+                    "Changes"
+                };
+
+                let items: Vec<ListItem> = entries
+                    .into_iter()
+                    .map(|entry| ListItem::new(entry.spans))
+                    .collect();
+
+                let list = List::new(items)
+                    .block(pane_block(title))
+                    .highlight_style(state.style.selected);
+
+                f.render_stateful_widget(list, chunks[2], &mut change_index.list_state);
+            }
+
+            let status = status_line(&state, &ui_state, pairs, pair_index);
+
+            f.render_widget(Paragraph::new(status), rows[2]);
+
+            let help = matches!(ui_state.overlay, Some(Overlay::Help { .. })).then(|| {
+                help_text(
+                    &state,
+                    &ui_state,
+                    &state.diff_options,
+                    &controls.keymap,
+                    &context,
+                )
+            });
+
+            match &mut ui_state.overlay {
+                Some(Overlay::Help { scroll, page }) => {
+                    let area = centered_rect(rows[1]);
+                    let help = help.unwrap_or_default();
+
+                    let block = Block::default().borders(Borders::ALL);
+                    *page = block.inner(area).height as usize;
+                    *scroll = (*scroll).min(help.len().saturating_sub(*page));
+
+                    // Says where it is when the help doesn't all fit
+                    let title = if help.len() > *page {
+                        format!(
+                            "Help, lines {}-{} of {}",
+                            *scroll + 1,
+                            (*scroll + *page).min(help.len()),
+                            help.len()
+                        )
+                    } else {
+                        "Help".to_string()
+                    };
+
+                    f.render_widget(Clear, area);
+                    f.render_widget(
+                        Paragraph::new(help.join("\n"))
+                            .block(block.title(title))
+                            .scroll((*scroll as u16, 0)),
+                        area,
+                    );
+                }
+                Some(Overlay::ConfirmQuit) => {
+                    let area = confirm_rect(rows[1]);
+
+                    f.render_widget(Clear, area);
+                    f.render_widget(
+                        Paragraph::new("Quit? (y/n)")
+                            .block(Block::default().borders(Borders::ALL).title("Quit")),
+                        area,
+                    );
+                }
+                Some(Overlay::HexDump(file1_dump, file2_dump)) => {
+                    let area = centered_rect(rows[1]);
+
+                    let panes = Layout::default()
+                        .direction(Direction::Vertical)
+                        .constraints(
+                            [Constraint::Percentage(50), Constraint::Percentage(50)].as_ref(),
+                        )
+                        .split(area);
+
+                    f.render_widget(Clear, area);
+
+                    for (dump, pane, title) in [
+                        (file1_dump, panes[0], "File 1 bytes"),
+                        (file2_dump, panes[1], "File 2 bytes"),
+                    ] {
+                        let paragraph = Paragraph::new(dump.join("\n"))
+                            .block(Block::default().borders(Borders::ALL).title(title));
+
+                        f.render_widget(paragraph, pane);
+                    }
+                }
+                None => {}
+            }
+        })?;
+
+        if state
+            .wrap_width
+            .is_some_and(|width| width != ui_state.pane_width)
+        {
+            // The panes were resized, so rewrap to their new width
+            state.wrap_width = Some(ui_state.pane_width);
+            state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+        }
+
+        if resized {
+            resized = false;
+
+            // The panes' new width is only known once they are drawn, so the offset is kept from
+            // scrolling further than the end of the longest line after that
+            let max_offset = max_horizontal_offset(&state, ui_state.pane_width);
+
+            if ui_state.horizontal_offset > max_offset {
+                ui_state.horizontal_offset = max_offset;
+                state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+
+                continue;
+            }
+        }
+
+        if let Some(watcher) = &mut watcher {
+            watcher.follow(&pairs[pair_index]);
+
+            if watcher.poll() {
+                info!("Pair {} changed on disk", pair_index + 1);
+
+                let indexed = load_pair(&pairs[pair_index]);
+                reload(
+                    &mut state,
+                    &mut ui_state,
+                    indexed,
+                    "Files changed, reloaded",
+                );
+            } else if let Some(path) = watcher.missing() {
+                ui_state.status_message = Some(format!(
+                    "{} is gone, reloading once it is back",
+                    path.display()
+                ));
+            }
+        }
+
+        if crossterm::event::poll(Duration::from_millis(100))? {
+            let event = event::read()?;
+
+            if let Event::Resize(width, height) = event {
+                debug!("Resized to {}x{}", width, height);
+
+                size = Rect::new(0, 0, width, height);
+
+                // Fill the new height, keeping the selected line selected
+                state.resize_window(lines_to_load(size.height, compact));
+                resized = true;
+
+                ui_state.list_state.select(Some(state.selected_line));
+                state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+
+                continue;
+            }
+
+            if let Event::Mouse(mouse) = event {
+                if ui_state.overlay.is_some() || ui_state.prompt.is_some() {
+                    continue;
+                }
+
+                match mouse.kind {
+                    MouseEventKind::ScrollDown | MouseEventKind::ScrollUp => {
+                        state.scroll(mouse.kind == MouseEventKind::ScrollDown, 1);
+
+                        ui_state.list_state.select(Some(state.selected_line));
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    MouseEventKind::Down(MouseButton::Left) => {
+                        if let Some(row) = clicked_row(&state, &ui_state, mouse.column, mouse.row) {
+                            state.selected_line = row;
 
+                            ui_state.list_state.select(Some(state.selected_line));
                             state.build_lines(
                                 ui_state.horizontal_offset,
                                 state.first_line_index + 1,
                             );
                         }
                     }
-                    KeyCode::Left => {
-                        if ui_state.horizontal_offset >= horizontal_step_size {
-                            ui_state.horizontal_offset -= horizontal_step_size;
-                        } else {
-                            ui_state.horizontal_offset = 0;
-                        }
+                    _ => {}
+                }
 
-                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                continue;
+            }
+
+            if let Event::Key(key) = event {
+                debug!("Key {:?} (modifiers {:?})", key.code, key.modifiers);
+
+                let step_size = controls.acceleration.step(key_repeat.press(key.code));
+
+                if let Some(Overlay::Help { scroll, page }) = &mut ui_state.overlay {
+                    // The help scrolls with the keys that move the selection, and is clamped to
+                    // its length when drawn
+                    let scrolled = match controls.keymap.action(key.code, &context) {
+                        Some(Action::Down) => Some(*scroll + step_size),
+                        Some(Action::Up) => Some(scroll.saturating_sub(step_size)),
+                        Some(Action::PageDown) => Some(*scroll + *page),
+                        Some(Action::PageUp) => Some(scroll.saturating_sub(*page)),
+                        Some(Action::Top) => Some(0),
+                        Some(Action::Bottom) => Some(usize::MAX),
+                        _ => None,
+                    };
+
+                    if let Some(scrolled) = scrolled {
+                        *scroll = scrolled;
+                        continue;
                     }
-                    KeyCode::Down => {
-                        if state.selected_line < state.file1_list_lines.len()
-                            || state.selected_line < state.file2_list_lines.len()
+                }
+
+                if let Some(overlay) = ui_state.overlay.take() {
+                    if matches!(overlay, Overlay::ConfirmQuit)
+                        && matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y'))
+                    {
+                        break;
+                    }
+
+                    // Any other key dismisses the overlay
+                    key_repeat.reset();
+                    continue;
+                }
+
+                if let Some(prompt) = ui_state.prompt.as_mut() {
+                    match key.code {
+                        KeyCode::Char(digit)
+                            if prompt.kind == PromptKind::Search || digit.is_ascii_digit() =>
                         {
-                            state.selected_line += 1;
+                            prompt.text.push(digit)
                         }
+                        KeyCode::Backspace => {
+                            prompt.text.pop();
+                        }
+                        KeyCode::Enter if prompt.kind == PromptKind::Search => {
+                            let needle = std::mem::take(&mut prompt.text);
+                            ui_state.prompt = None;
 
-                        ui_state.list_state.select(Some(state.selected_line));
-                    }
-                    KeyCode::Up => {
-                        if state.selected_line > 0 {
-                            state.selected_line -= 1;
+                            if !needle.is_empty() {
+                                if let Some(offset) = search(&mut state, &mut ui_state, &needle) {
+                                    selected_diff_offset = offset;
+                                }
+
+                                ui_state.last_search = Some(needle);
+                            }
                         }
+                        KeyCode::Enter => {
+                            if let Ok(line_number) = prompt.text.parse::<usize>() {
+                                let line_index = state
+                                    .jump_to_line(line_number.saturating_sub(state.line_number(0)));
 
-                        ui_state.list_state.select(Some(state.selected_line));
+                                ui_state.list_state.select(Some(state.selected_line));
+                                state.build_lines(
+                                    ui_state.horizontal_offset,
+                                    state.first_line_index + 1,
+                                );
+
+                                ui_state.status_message = Some(format!(
+                                    "Jumped to line {}",
+                                    state.line_number(line_index)
+                                ));
+                            }
+
+                            ui_state.prompt = None;
+                        }
+                        KeyCode::Esc => ui_state.prompt = None,
+                        _ => {}
                     }
-                    KeyCode::Char('N') => {
-                        // Prev diff
-                        if let Some((prev_diff_line, prev_diff_offset)) =
-                            state.find_prev_diff(state.selected_line, selected_diff_offset)
-                        {
-                            selected_diff_offset = select_diff(
-                                &mut state,
-                                &mut ui_state,
-                                prev_diff_line,
-                                prev_diff_offset,
-                            );
+
+                    key_repeat.reset();
+                    continue;
+                }
+
+                if ui_state.region_inverted {
+                    // The emphasis only lasts until the next key
+                    ui_state.region_inverted = false;
+                    state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                }
+
+                let context = action_context(&state, &ui_state, pairs);
+
+                let action = controls.keymap.action(key.code, &context);
+
+                let change_index_focused = ui_state
+                    .change_index
+                    .as_ref()
+                    .is_some_and(|change_index| change_index.focused);
+
+                match action {
+                    Some(Action::Down) | Some(Action::Up) if change_index_focused => {
+                        if let Some(offset) = step_change_index(
+                            &mut state,
+                            &mut ui_state,
+                            action == Some(Action::Down),
+                        ) {
+                            selected_diff_offset = offset;
                         }
                     }
-                    KeyCode::Char('n') => {
-                        // Next diff
-                        if let Some((next_diff_line, next_diff_offset)) =
-                            state.find_next_diff(state.selected_line, selected_diff_offset)
-                        {
-                            selected_diff_offset = select_diff(
-                                &mut state,
-                                &mut ui_state,
-                                next_diff_line,
-                                next_diff_offset,
+                    Some(Action::ScrollRight) => {
+                        let max_offset = max_horizontal_offset(&state, ui_state.pane_width);
+
+                        if ui_state.horizontal_offset < max_offset {
+                            // Large steps stop at the last offset rather than not moving at all
+                            ui_state.horizontal_offset =
+                                (ui_state.horizontal_offset + step_size).min(max_offset);
+
+                            state.build_lines(
+                                ui_state.horizontal_offset,
+                                state.first_line_index + 1,
                             );
                         }
                     }
-                    KeyCode::Char('$') => {
-                        // End of line
-                        let min_line_length = if state.longest_line_length > 10 {
-                            state.longest_line_length - 10
-                        } else {
+                    Some(Action::ScrollLeft) => {
+                        ui_state.horizontal_offset =
+                            ui_state.horizontal_offset.saturating_sub(step_size);
+
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(action @ (Action::Down | Action::Up)) => {
+                        state.scroll(action == Action::Down, step_size);
+
+                        ui_state.list_state.select(Some(state.selected_line));
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(action @ (Action::PageDown | Action::PageUp)) => {
+                        // A row short of the panes' height, so the edge row stays in sight
+                        let page = (ui_state.list_areas[0].height as usize)
+                            .saturating_sub(1)
+                            .max(1);
+
+                        state.scroll(action == Action::PageDown, page);
+
+                        ui_state.list_state.select(Some(state.selected_line));
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(action @ (Action::Top | Action::Bottom)) => {
+                        let line_index = if action == Action::Top {
                             0
+                        } else {
+                            state.line_count().saturating_sub(1)
+                        };
+
+                        state.jump_to_line(line_index);
+
+                        ui_state.list_state.select(Some(state.selected_line));
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(action @ (Action::NextDiff | Action::PrevDiff)) => {
+                        if let Some((diff_line, diff_offset)) = step_diff(
+                            &mut state,
+                            &mut ui_state,
+                            action == Action::NextDiff,
+                            selected_diff_offset,
+                            controls.diff_wrap,
+                        ) {
+                            selected_diff_offset =
+                                select_diff(&mut state, &mut ui_state, diff_line, diff_offset);
+                        }
+                    }
+                    Some(action @ (Action::FirstDiff | Action::LastDiff)) => {
+                        let diff = if action == Action::FirstDiff {
+                            state.jump_to_first_diff()
+                        } else {
+                            state.jump_to_last_diff()
                         };
 
-                        ui_state.horizontal_offset = min_line_length;
+                        if let Some((diff_line, diff_offset)) = diff {
+                            selected_diff_offset =
+                                select_diff(&mut state, &mut ui_state, diff_line, diff_offset);
+                        }
+                    }
+                    Some(Action::LineEnd) => {
+                        ui_state.horizontal_offset =
+                            max_horizontal_offset(&state, ui_state.pane_width);
 
                         state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
                     }
-                    KeyCode::Char('^') => {
+                    Some(Action::LineStart) => {
                         // Start of line
                         ui_state.horizontal_offset = 0;
 
                         state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
                     }
-                    KeyCode::Esc => break,
-                    _ => {}
-                }
+                    Some(Action::ToggleChangeCounts) => {
+                        // Toggle per-line change counts in the gutter
+                        state.gutter.change_counts = !state.gutter.change_counts;
 
-                last_keycode = Some(key.code.clone());
-            } else {
-                last_keycode = None;
-                key_repeat_count = 0;
-            }
-        } else {
-            // No event, kill repeat
-            last_keycode = None;
-            key_repeat_count = 0;
-        }
-    }
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::ToggleOrigin) => {
+                        // Toggle the origin tag column in the gutter
+                        state.gutter.origin = !state.gutter.origin;
 
-    Ok(())
-}
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::ToggleBothNumbers) => {
+                        state.gutter.both_numbers = !state.gutter.both_numbers;
 
-fn select_diff(
-    state: &mut State,
-    ui_state: &mut UIState,
-    diff_line: usize,
-    diff_offset: usize,
-) -> usize {
-    state.selected_line = diff_line;
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::ToggleOffsets) => {
+                        state.gutter.offsets = !state.gutter.offsets;
 
-    ui_state.horizontal_offset = if diff_offset > 5 { diff_offset - 5 } else { 0 };
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::HexDump) => {
+                        // Hex dump of the selected line
+                        match state.row_bytes(state.selected_line) {
+                            Ok((file1_bytes, file2_bytes)) => {
+                                let dump = |bytes: Option<Vec<u8>>| match bytes {
+                                    Some(bytes) => hex_dump(&bytes),
+                                    None => vec!["EOF".to_string()],
+                                };
 
-    ui_state.list_state.select(Some(state.selected_line));
-    state.build_lines(ui_state.horizontal_offset, state.first_line_index);
+                                ui_state.overlay =
+                                    Some(Overlay::HexDump(dump(file1_bytes), dump(file2_bytes)));
+                            }
+                            Err(err) => {
+                                ui_state.status_message = Some(err.to_string());
+                            }
+                        }
+                    }
+                    Some(Action::ToggleCompact) => {
+                        compact = !compact;
+                        state.gutter.hidden = compact;
 
-    diff_offset
+                        state.resize_window(lines_to_load(size.height, compact));
+                        ui_state.list_state.select(Some(state.selected_line));
+
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::ToggleChangesOnly) => {
+                        state.changes_only = !state.changes_only;
+                        state.rebuild_spans();
+
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::ToggleFold) => {
+                        state.fold_same = !state.fold_same;
+                        state.rebuild_spans();
+
+                        if state.fold_same {
+                            // The changes are now near the start of the line
+                            ui_state.horizontal_offset = 0;
+                        }
+
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::ToggleOnlyDiffs) => {
+                        state.toggle_only_diffs();
+
+                        ui_state.list_state.select(Some(state.selected_line));
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::ToggleWrap) => {
+                        state.wrap_width = match state.wrap_width {
+                            Some(_) => None,
+                            None => Some(ui_state.pane_width),
+                        };
+
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::ToggleRuler) => {
+                        ui_state.ruler = !ui_state.ruler;
+                    }
+                    Some(Action::ToggleMinimap) => {
+                        ui_state.minimap = !ui_state.minimap;
+                    }
+                    Some(Action::ToggleLinkedChanges) => {
+                        ui_state.link_changes = !ui_state.link_changes;
+
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::ToggleLineHighlight) => {
+                        state.highlight_changed_lines = !state.highlight_changed_lines;
+
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::ToggleWhitespace) => {
+                        state.show_whitespace = !state.show_whitespace;
+                        state.rebuild_spans();
+
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::ExpandRepeat) => {
+                        state.expand_repeats(state.selected_line);
+
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(action @ (Action::ExpandFold | Action::CollapseFold)) => {
+                        let changed = if action == Action::ExpandFold {
+                            state.expand_fold(state.selected_line)
+                        } else {
+                            state.collapse_fold(state.selected_line)
+                        };
+
+                        if changed {
+                            ui_state.list_state.select(Some(state.selected_line));
+                        }
+
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::InvertRegion) => {
+                        ui_state.region_inverted =
+                            state.invert_region(state.selected_line, ui_state.horizontal_offset);
+                    }
+                    Some(Action::ToggleChangeIndex) => {
+                        ui_state.change_index = match ui_state.change_index {
+                            Some(_) => None,
+                            None => Some(ChangeIndex {
+                                list_state: ListState::default(),
+                                focused: false,
+                            }),
+                        };
+                    }
+                    Some(Action::ToggleIndependentScroll) => {
+                        ui_state.independent_scroll = match ui_state.independent_scroll {
+                            Some(_) => None,
+                            // Starts out scrolled like file 1's pane
+                            None => Some(IndependentScroll {
+                                offsets: [0, 0],
+                                file2_focused: false,
+                                file2_list_state: ui_state.list_state.clone(),
+                                file2_list_offset: ui_state.list_offset,
+                            }),
+                        };
+
+                        ui_state.list_state.select(Some(state.selected_line));
+                    }
+                    Some(Action::FocusPane) => {
+                        if let Some(scroll) = &mut ui_state.independent_scroll {
+                            scroll.file2_focused = !scroll.file2_focused;
+                        }
+                    }
+                    Some(action @ (Action::PaneDown | Action::PaneUp)) => {
+                        if let Some(scroll) = &mut ui_state.independent_scroll {
+                            let row_count = if scroll.file2_focused {
+                                state.file2_list_lines.len()
+                            } else {
+                                state.file1_list_lines.len()
+                            };
+
+                            scroll.scroll(
+                                state.selected_line,
+                                action == Action::PaneDown,
+                                step_size,
+                                row_count,
+                            );
+                        }
+                    }
+                    Some(Action::FocusChangeIndex) => {
+                        if let Some(change_index) = &mut ui_state.change_index {
+                            change_index.focused = !change_index.focused;
+                        }
+                    }
+                    Some(Action::SyncToSimilar) => {
+                        ui_state.status_message = state.sync_to_similar(state.selected_line).map(
+                            |(score, line_number)| {
+                                let line = line_number.map_or("a repeat".to_string(), |number| {
+                                    format!("line {}", number)
+                                });
+
+                                format!("Synced to file 2 {} ({:.0}% similar)", line, score * 100.0)
+                            },
+                        );
+
+                        ui_state.list_state.select(Some(state.selected_line));
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::Anchor) => {
+                        let (file1_index, file2_index) = state.row_lines(state.selected_line);
+
+                        let message = match (ui_state.anchor_start.take(), file1_index, file2_index)
+                        {
+                            (None, Some(file1_index), _) => {
+                                ui_state.anchor_start = Some(file1_index);
+
+                                format!(
+                                    "Anchoring file 1 line {}, select its file 2 line and press a",
+                                    state.line_number(file1_index)
+                                )
+                            }
+                            (None, None, _) => "No file 1 line to anchor here".to_string(),
+                            (Some(file1_index), _, Some(file2_index)) => {
+                                state.add_anchor(file1_index, file2_index);
+
+                                ui_state.list_state.select(Some(state.selected_line));
+                                state.build_lines(
+                                    ui_state.horizontal_offset,
+                                    state.first_line_index + 1,
+                                );
+
+                                format!(
+                                    "Anchored file 1 line {} to file 2 line {}",
+                                    state.line_number(file1_index),
+                                    state.line_number(file2_index)
+                                )
+                            }
+                            (Some(_), _, None) => {
+                                "No file 2 line to anchor to here, anchoring canceled".to_string()
+                            }
+                        };
+
+                        ui_state.status_message = Some(message);
+                    }
+                    Some(Action::ClearAnchors) => {
+                        ui_state.anchor_start = None;
+
+                        let count = state.clear_anchors();
+
+                        ui_state.list_state.select(Some(state.selected_line));
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+
+                        ui_state.status_message = Some(format!("Dropped {} anchors", count));
+                    }
+                    Some(Action::TogglePin) => {
+                        state.toggle_pin(state.selected_line);
+
+                        state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+                    }
+                    Some(Action::MarkRangeStart) | Some(Action::MarkRangeEnd) => {
+                        let lines = state.row_lines(state.selected_line);
+
+                        let end = if action == Some(Action::MarkRangeStart) {
+                            ui_state.range_start = Some(lines);
+                            "start"
+                        } else {
+                            ui_state.range_end = Some(lines);
+                            "end"
+                        };
+
+                        ui_state.status_message = Some(format!("Marked range {}", end));
+                    }
+                    Some(Action::ExportRange) => {
+                        if let (Some(start), Some(end)) = (ui_state.range_start, ui_state.range_end)
+                        {
+                            let pair = &pairs[pair_index];
+
+                            ui_state.status_message =
+                                Some(match export_range(&mut state, pair, start, end) {
+                                    Ok(path) => format!("Wrote {}", path),
+                                    Err(err) => err.to_string(),
+                                });
+                        }
+                    }
+                    Some(Action::CopyLinePatch) => {
+                        if let Some(patch) = state.row_patch(state.selected_line) {
+                            ui_state.status_message = Some(match clipboard::copy(&patch) {
+                                Ok(()) => "Copied the line pair to the clipboard".to_string(),
+                                Err(err) => err.to_string(),
+                            });
+                        }
+                    }
+                    Some(Action::CopyLinePair) => {
+                        let copied = state.row_text(state.selected_line).and_then(|texts| {
+                            clipboard::copy(&labeled_lines(&state, state.selected_line, texts))
+                        });
+
+                        ui_state.status_message = Some(match copied {
+                            Ok(()) => "Copied".to_string(),
+                            Err(err) => err.to_string(),
+                        });
+                    }
+                    Some(Action::DumpViewState) => {
+                        let written = terminal.size().and_then(|size| {
+                            write_view_state(&state, &ui_state, pairs, pair_index, size, compact)
+                        });
+
+                        ui_state.status_message = Some(match written {
+                            Ok(()) => format!("Wrote {}", VIEW_STATE_PATH),
+                            Err(err) => err.to_string(),
+                        });
+                    }
+                    Some(Action::Reload) => {
+                        let indexed = load_pair(&pairs[pair_index]);
+
+                        reload(&mut state, &mut ui_state, indexed, "Reloaded");
+                    }
+                    Some(Action::NextPair) | Some(Action::PrevPair) => {
+                        // Next/prev file pair
+                        let new_index = if action == Some(Action::NextPair) {
+                            pair_index + 1
+                        } else {
+                            pair_index.wrapping_sub(1)
+                        };
+
+                        if let Some(pair) = pairs.get(new_index) {
+                            match load_pair(pair) {
+                                Ok(mut new_state) => {
+                                    info!("Switched to pair {}", new_index + 1);
+
+                                    let saved = match &controls.saved_views {
+                                        Some(path) => {
+                                            save_view(path, &pairs[pair_index], &state, &ui_state)
+                                        }
+                                        None => Ok(()),
+                                    };
+
+                                    carry_state_toggles(&state, &mut new_state);
+                                    new_state.build_state(lines_to_load(size.height, compact));
+
+                                    let mut new_ui_state = UIState::new(&new_state);
+                                    carry_ui_toggles(&ui_state, &mut new_ui_state);
+
+                                    if let Some(path) = &controls.saved_views {
+                                        load_view(path, pair, &mut new_state, &mut new_ui_state);
+                                    }
+                                    if let Err(err) = saved {
+                                        new_ui_state.status_message = Some(err.to_string());
+                                    }
+
+                                    state = new_state;
+                                    ui_state = new_ui_state;
+
+                                    pair_index = new_index;
+                                    selected_diff_offset = 0;
+                                }
+                                Err(err) => {
+                                    ui_state.status_message = Some(err.to_string());
+                                }
+                            }
+                        }
+                    }
+                    Some(Action::Help) => {
+                        ui_state.overlay = Some(Overlay::Help { scroll: 0, page: 0 });
+                    }
+                    Some(Action::JumpToLine) => {
+                        ui_state.prompt = Some(Prompt {
+                            kind: PromptKind::Line,
+                            text: String::new(),
+                        });
+                    }
+                    Some(Action::Search) => {
+                        ui_state.prompt = Some(Prompt {
+                            kind: PromptKind::Search,
+                            text: String::new(),
+                        });
+                    }
+                    Some(Action::SearchNext) => {
+                        if let Some(needle) = ui_state.last_search.clone() {
+                            if let Some(offset) = search(&mut state, &mut ui_state, &needle) {
+                                selected_diff_offset = offset;
+                            }
+                        }
+                    }
+                    Some(Action::Quit) => {
+                        if controls.confirm_quit {
+                            ui_state.overlay = Some(Overlay::ConfirmQuit);
+                        } else {
+                            break;
+                        }
+                    }
+                    None => {}
+                }
+            } else {
+                key_repeat.reset();
+            }
+        } else {
+            // No event, kill repeat
+            key_repeat.reset();
+        }
+    }
+
+    if let Some(path) = &controls.saved_views {
+        save_view(path, &pairs[pair_index], &state, &ui_state)?;
+    }
+
+    Ok(state)
+}
+
+/// Records where `pair` was left in the saved views at `path`
+fn save_view(
+    path: &Path,
+    pair: &FilePair,
+    state: &State<Input>,
+    ui_state: &UIState,
+) -> io::Result<()> {
+    let mut views = SavedViews::read(path);
+
+    views.insert(
+        &pair.file1,
+        &pair.file2,
+        SavedView {
+            line_index: state.position().0 - 1,
+            horizontal_offset: ui_state.horizontal_offset,
+        },
+    );
+    views.write(path)
+}
+
+/// Goes back to where `pair` was left, if the saved views at `path` have it
+fn load_view(path: &Path, pair: &FilePair, state: &mut State<Input>, ui_state: &mut UIState) {
+    if let Some(view) = SavedViews::read(path).get(&pair.file1, &pair.file2) {
+        restore_view(state, ui_state, view);
+    }
+}
+
+/// Gives `new_state`, of the pair being switched to, the view toggles of `state`. Set before it
+/// loads its window, as some of them change the rows shown
+fn carry_state_toggles(state: &State<Input>, new_state: &mut State<Input>) {
+    new_state.gutter = state.gutter;
+    new_state.changes_only = state.changes_only;
+    new_state.fold_same = state.fold_same;
+    new_state.highlight_changed_lines = state.highlight_changed_lines;
+    new_state.show_whitespace = state.show_whitespace;
+    new_state.only_diffs = state.only_diffs;
+    new_state.wrap_width = state.wrap_width;
+}
+
+/// Gives `new_ui_state`, of the pair being switched to, the view toggles of `ui_state`. Where
+/// the panes were scrolled apart only lines up the old pair, so they start together again
+fn carry_ui_toggles(ui_state: &UIState, new_ui_state: &mut UIState) {
+    new_ui_state.pane_width = ui_state.pane_width;
+    new_ui_state.link_changes = ui_state.link_changes;
+    new_ui_state.ruler = ui_state.ruler;
+    new_ui_state.minimap = ui_state.minimap;
+    new_ui_state.change_index = ui_state.change_index.as_ref().map(|_| ChangeIndex {
+        list_state: ListState::default(),
+        focused: false,
+    });
+}
+
+/// Selects the saved line and scrolls to the saved column, clamped to the files as they are now
+fn restore_view(state: &mut State<Input>, ui_state: &mut UIState, view: SavedView) {
+    state.jump_to_line(view.line_index);
+
+    ui_state.horizontal_offset = view
+        .horizontal_offset
+        .min(max_horizontal_offset(state, ui_state.pane_width));
+    ui_state.list_state.select(Some(state.selected_line));
+
+    state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+}
+
+fn select_diff(
+    state: &mut State<Input>,
+    ui_state: &mut UIState,
+    diff_line: usize,
+    diff_offset: usize,
+) -> usize {
+    state.selected_line = diff_line;
+
+    let text_width = ui_state.pane_width.saturating_sub(state.gutter_width);
+
+    ui_state.horizontal_offset = if !state.shows_full_lines() {
+        // Offsets are into the full lines, which aren't shown
+        0
+    } else {
+        let column = state.diff_column(diff_line, diff_offset);
+
+        diff_scroll_offset(column, text_width, state.context_chars)
+    };
+
+    ui_state.list_state.select(Some(state.selected_line));
+    state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+
+    diff_offset
+}
+
+/// Swaps the lines of `state` for those of `indexed`, a fresh scan of the same files, keeping the
+/// selected line and the export range ends the files still have
+fn reload(
+    state: &mut State<'static, Input>,
+    ui_state: &mut UIState,
+    indexed: io::Result<State<'static, Input>>,
+    message: &str,
+) {
+    let indexed = match indexed {
+        Ok(indexed) => indexed,
+        Err(err) => {
+            ui_state.status_message = Some(err.to_string());
+            return;
+        }
+    };
+
+    state.reload(indexed);
+
+    let (file1_count, file2_count) = state.line_counts();
+    let exists = |(file1_index, file2_index): (Option<usize>, Option<usize>)| {
+        file1_index.is_none_or(|index| index < file1_count)
+            && file2_index.is_none_or(|index| index < file2_count)
+    };
+
+    ui_state.range_start = ui_state.range_start.filter(|&lines| exists(lines));
+    ui_state.range_end = ui_state.range_end.filter(|&lines| exists(lines));
+
+    ui_state.horizontal_offset = ui_state
+        .horizontal_offset
+        .min(max_horizontal_offset(state, ui_state.pane_width));
+
+    ui_state.list_state.select(Some(state.selected_line));
+    state.build_lines(ui_state.horizontal_offset, state.first_line_index + 1);
+
+    ui_state.status_message = Some(message.to_string());
+}
+
+/// Finds the diff after (or before) column `offset` of the selected row anywhere in the files,
+/// or with `wrap` the first (or last) diff when there is none, telling which in the status line
+fn step_diff(
+    state: &mut State<Input>,
+    ui_state: &mut UIState,
+    forward: bool,
+    offset: usize,
+    wrap: bool,
+) -> Option<(usize, usize)> {
+    let diff = if forward {
+        state.jump_to_next_diff(state.selected_line, offset)
+    } else {
+        state.jump_to_prev_diff(state.selected_line, offset)
+    };
+
+    if diff.is_some() {
+        return diff;
+    }
+
+    if !wrap {
+        let direction = if forward { "after" } else { "before" };
+
+        ui_state.status_message = Some(format!("No diffs {} this one", direction));
+        return None;
+    }
+
+    let diff = if forward {
+        state.jump_to_first_diff()
+    } else {
+        state.jump_to_last_diff()
+    };
+
+    ui_state.status_message = Some(match diff {
+        Some(_) if forward => "Wrapped to the first diff".to_string(),
+        Some(_) => "Wrapped to the last diff".to_string(),
+        None => "The files have no diffs".to_string(),
+    });
+
+    diff
+}
+
+/// Selects the next line after the selected one containing `needle` in either file, scrolled to
+/// the match, returning the column of the match
+fn search(state: &mut State<Input>, ui_state: &mut UIState, needle: &str) -> Option<usize> {
+    // The 1-based number of the selected line is the index of the line after it
+    let (start, _) = state.position();
+
+    let Some((line_index, column)) = state.find_line_matching(start, needle) else {
+        ui_state.status_message = Some(format!("No line after this one contains `{}`", needle));
+        return None;
+    };
+
+    state.jump_to_line(line_index);
+
+    ui_state.status_message = Some(format!(
+        "Found `{}` on line {}",
+        needle,
+        state.line_number(line_index)
+    ));
+
+    Some(select_diff(state, ui_state, state.selected_line, column))
+}
+
+/// Moves the change index selection to the next or previous change and selects it in the main
+/// panes, returning its offset
+fn step_change_index(
+    state: &mut State<Input>,
+    ui_state: &mut UIState,
+    forward: bool,
+) -> Option<usize> {
+    let entries = state.change_index();
+    let change_index = ui_state.change_index.as_mut()?;
+
+    let position = match change_index.list_state.selected() {
+        None => 0,
+        Some(position) if forward => (position + 1).min(entries.len().saturating_sub(1)),
+        Some(position) => position.saturating_sub(1),
+    };
+
+    let entry = entries.get(position)?;
+    change_index.list_state.select(Some(position));
+
+    Some(select_diff(state, ui_state, entry.row, entry.offset))
+}
+
+/// The first of `items` a `List` `height` rows tall shows with `selected` selected, when it
+/// showed `offset` first the last time. Works it out the way tui does
+fn list_offset_after_draw(
+    items: &[ListItem],
+    selected: Option<usize>,
+    offset: usize,
+    height: usize,
+) -> usize {
+    if items.is_empty() || height == 0 {
+        // Nothing is drawn, so tui leaves the offset alone
+        return offset;
+    }
+
+    let mut start = offset.min(items.len() - 1);
+    let mut end = start;
+    let mut shown_height = 0;
+
+    for item in &items[start..] {
+        if shown_height + item.height() > height {
+            break;
+        }
+
+        shown_height += item.height();
+        end += 1;
+    }
+
+    let selected = selected.unwrap_or(0).min(items.len() - 1);
+
+    while selected >= end {
+        shown_height += items[end].height();
+        end += 1;
+
+        while shown_height > height {
+            shown_height -= items[start].height();
+            start += 1;
+        }
+    }
+
+    while selected < start {
+        start -= 1;
+        shown_height += items[start].height();
+
+        while shown_height > height {
+            end -= 1;
+            shown_height -= items[end].height();
+        }
+    }
+
+    start
+}
+
+/// The row of `state` shown at terminal cell `column`, `row` of either pane, if any
+fn clicked_row(state: &State<Input>, ui_state: &UIState, column: u16, row: u16) -> Option<usize> {
+    let position = Rect::new(column, row, 1, 1);
+
+    let file2 = (0..2).find(|&pane| ui_state.list_areas[pane].intersects(position))? == 1;
+
+    let lines = if file2 {
+        &state.file2_list_lines
+    } else {
+        &state.file1_list_lines
+    };
+
+    let (pane_offset, list_offset) = match &ui_state.independent_scroll {
+        Some(scroll) if file2 => (scroll.offsets[1], scroll.file2_list_offset),
+        Some(scroll) => (scroll.offsets[0], ui_state.list_offset),
+        None => (0, ui_state.list_offset),
+    };
+
+    // Wrapped lines take more than a row each
+    let clicked_height = (row - ui_state.list_areas[file2 as usize].y) as usize;
+    let mut bottom = 0;
+
+    let pane_row = lines
+        .iter()
+        .enumerate()
+        .skip(list_offset)
+        .find_map(|(index, item)| {
+            bottom += item.height();
+
+            (clicked_height < bottom).then_some(index)
+        })?;
+
+    // Back from the pane's own scrolling to the row both panes select
+    let selected_line = pane_row as isize - pane_offset;
+
+    Some(selected_line.clamp(0, lines.len().saturating_sub(1) as isize) as usize)
+}
+
+fn action_context(state: &State<Input>, ui_state: &UIState, pairs: &[FilePair]) -> Context {
+    Context {
+        pair_count: pairs.len(),
+        collapse_repeats: state.collapse_repeats,
+        folding: state.fold_identical.is_some(),
+        range_marked: ui_state.range_start.is_some() && ui_state.range_end.is_some(),
+        full_lines: state.shows_full_lines(),
+        change_index_shown: ui_state.change_index.is_some(),
+        searched: ui_state.last_search.is_some(),
+        independent_scroll: ui_state.independent_scroll.is_some(),
+        wrapped: state.wrap_width.is_some(),
+    }
+}
+
+/// The text of each file's line in `row`, labeled with the file and line number, one per line.
+/// A file past its end has no line to label
+fn labeled_lines(
+    state: &State<Input>,
+    row: usize,
+    (file1_text, file2_text): (Option<String>, Option<String>),
+) -> String {
+    let (file1_index, file2_index) = state.row_lines(row);
+
+    [(1, file1_index, file1_text), (2, file2_index, file2_text)]
+        .into_iter()
+        .filter_map(|(file, index, text)| {
+            Some(format!(
+                "File {} line {}: {}\n",
+                file,
+                state.line_number(index?),
+                text?
+            ))
+        })
+        .collect()
+}
+
+/// Writes the lines between two marked rows to a patch file in the working directory, returning
+/// its name
+fn export_range(
+    state: &mut State<Input>,
+    pair: &FilePair,
+    start: (Option<usize>, Option<usize>),
+    end: (Option<usize>, Option<usize>),
+) -> io::Result<String> {
+    // A row without a line from one file uses the line index from the other file
+    let range = |start: usize, end: usize| start.min(end)..=start.max(end);
+
+    let file1_range = range(
+        start.0.or(start.1).unwrap_or(0),
+        end.0.or(end.1).unwrap_or(0),
+    );
+    let file2_range = range(
+        start.1.or(start.0).unwrap_or(0),
+        end.1.or(end.0).unwrap_or(0),
+    );
+
+    let patch = state.range_patch(file1_range.clone(), file2_range, &pair.file1, &pair.file2)?;
+
+    let path = format!(
+        "lines-{}-{}.patch",
+        file1_range.start() + 1,
+        file1_range.end() + 1
+    );
+
+    fs::write(&path, patch)?;
+
+    Ok(path)
+}
+
+/// Writes what the viewer is showing to `VIEW_STATE_PATH` as JSON
+fn write_view_state(
+    state: &State<Input>,
+    ui_state: &UIState,
+    pairs: &[FilePair],
+    pair_index: usize,
+    terminal_size: Rect,
+    compact: bool,
+) -> io::Result<()> {
+    let (file1_index, file2_index) = state.row_lines(state.selected_line);
+
+    let view_state = ViewState {
+        version: env!("CARGO_PKG_VERSION"),
+        file1: &pairs[pair_index].file1,
+        file2: &pairs[pair_index].file2,
+        pair: pair_index + 1,
+        pair_count: pairs.len(),
+        terminal_size: (terminal_size.width, terminal_size.height),
+        selected_row: state.selected_line,
+        selected_lines: (file1_index.map(|i| i + 1), file2_index.map(|i| i + 1)),
+        first_line_index: state.first_line_index,
+        horizontal_offset: ui_state.horizontal_offset,
+        diff_options: &state.diff_options,
+        alignment: format!("{:?}", state.alignment).to_lowercase(),
+        file2_shift: state.file2_shift,
+        toggles: BTreeMap::from([
+            ("change_counts", state.gutter.change_counts),
+            ("origin", state.gutter.origin),
+            ("markers", state.gutter.markers),
+            ("both_numbers", state.gutter.both_numbers),
+            ("offsets", state.gutter.offsets),
+            ("compact", compact),
+            ("changes_only", state.changes_only),
+            ("fold_same", state.fold_same),
+            ("highlight_changed_lines", state.highlight_changed_lines),
+            ("show_whitespace", state.show_whitespace),
+            ("collapse_repeats", state.collapse_repeats),
+            ("only_diffs", state.only_diffs),
+            ("fold_identical", state.fold_identical.is_some()),
+            ("pinned", state.is_pinned()),
+            ("region_inverted", ui_state.region_inverted),
+            ("link_changes", ui_state.link_changes),
+            ("change_index", ui_state.change_index.is_some()),
+            ("ruler", ui_state.ruler),
+            ("minimap", ui_state.minimap),
+            ("wrap", state.wrap_width.is_some()),
+            ("independent_scroll", ui_state.independent_scroll.is_some()),
+        ]),
+    };
+
+    fs::write(VIEW_STATE_PATH, serde_json::to_string_pretty(&view_state)?)
+}
+
+/// The largest horizontal offset, which scrolls the end of the longest loaded line flush against
+/// the right edge of panes `pane_width` columns wide inside their borders
+pub(crate) fn max_horizontal_offset(state: &State<Input>, pane_width: usize) -> usize {
+    let text_width = pane_width.saturating_sub(state.gutter_width);
+    let text_width = state
+        .max_columns
+        .map_or(text_width, |max_columns| max_columns.min(text_width));
+
+    state.longest_line_length.saturating_sub(text_width)
+}
+
+/// Lines to keep loaded, double the rows the panes can show
+pub(crate) fn lines_to_load(terminal_height: u16, compact: bool) -> usize {
+    // The status line, and the top and bottom borders
+    let chrome_height = if compact { 1 } else { 3 };
+
+    (terminal_height.saturating_sub(chrome_height) as usize) * 2
+}
+
+/// The comparison options in effect and the state of each toggle, followed by the bindings that
+/// apply in `context`, a line each
+fn help_text(
+    state: &State<Input>,
+    ui_state: &UIState,
+    diff_options: &DiffOptions,
+    keymap: &Keymap,
+    context: &Context,
+) -> Vec<String> {
+    let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+    let ignored = |ignored: bool| if ignored { "ignored" } else { "compared" };
+
+    let whitespace = if diff_options.ignore_whitespace {
+        "ignored"
+    } else if diff_options.ignore_trailing_whitespace {
+        "ignored at line ends"
+    } else {
+        "compared"
+    };
+    let masks = diff_options
+        .masks
+        .0
+        .iter()
+        .map(|mask| mask.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let none_if_empty = |text: &str| {
+        if text.is_empty() {
+            "none".to_string()
+        } else {
+            text.to_string()
+        }
+    };
+
+    let mut lines = vec![
+        format!("alignment: {:?}", state.alignment).to_lowercase(),
+        format!("input: {:?}", diff_options.mode).to_lowercase(),
+        format!("character diff: {:?}", diff_options.char_diff).to_lowercase(),
+        format!("file 2 as a pattern: {}", on_off(diff_options.pattern)),
+        format!("whitespace: {}", whitespace),
+        format!("line endings: {}", ignored(!diff_options.preserve_eol)),
+        format!(
+            "ignored characters: {}",
+            none_if_empty(&diff_options.ignore_chars)
+        ),
+        format!("masks: {}", none_if_empty(&masks)),
+        format!(
+            "compared width: {}",
+            diff_options
+                .compare_width
+                .map_or("whole lines".to_string(), |width| format!(
+                    "{} characters",
+                    width
+                ))
+        ),
+        String::new(),
+    ];
+
+    for (name, enabled) in [
+        ("change counts", state.gutter.change_counts),
+        ("origin tags", state.gutter.origin),
+        ("both line numbers", state.gutter.both_numbers),
+        ("byte offsets", state.gutter.offsets),
+        ("compact", state.gutter.hidden),
+        ("changes only", state.changes_only),
+        ("folded", state.fold_same),
+        ("only diffs", state.only_diffs),
+        ("identical folds", state.fold_identical.is_some()),
+        ("wrapped", state.wrap_width.is_some()),
+        ("line highlight", state.highlight_changed_lines),
+        ("shown whitespace", state.show_whitespace),
+        ("pinned line", state.is_pinned()),
+        ("ruler", ui_state.ruler),
+        ("minimap", ui_state.minimap),
+        ("linked changes", ui_state.link_changes),
+        ("change list", ui_state.change_index.is_some()),
+        ("independent scroll", ui_state.independent_scroll.is_some()),
+    ] {
+        lines.push(format!("{}: {}", name, on_off(enabled)));
+    }
+
+    lines.push(String::new());
+
+    let bindings = keymap.bindings(context);
+    let key_width = bindings
+        .iter()
+        .map(|(key, _)| key.width())
+        .max()
+        .unwrap_or(0);
+
+    lines.extend(
+        bindings.into_iter().map(|(key, description)| {
+            format!("{:>width$}  {}", key, description, width = key_width)
+        }),
+    );
+
+    lines
+}
+
+/// The area covered by overlays, inset from `area` on every side
+fn centered_rect(area: Rect) -> Rect {
+    let horizontal_margin = area.width / 20;
+    let vertical_margin = area.height / 10;
+
+    Rect::new(
+        area.x + horizontal_margin,
+        area.y + vertical_margin,
+        area.width - horizontal_margin * 2,
+        area.height - vertical_margin * 2,
+    )
+}
+
+/// Marks every fifth column of the `width` columns from `offset` with `+`, and every tenth with
+/// its 1-based column number
+fn ruler_text(offset: usize, width: usize) -> String {
+    let mut ruler: Vec<char> = (offset + 1..=offset + width)
+        .map(|column| if column % 5 == 0 { '+' } else { '.' })
+        .collect();
+
+    for (index, column) in (offset + 1..=offset + width).enumerate() {
+        if column % 10 == 0 {
+            for (char_index, char) in column.to_string().chars().enumerate() {
+                if let Some(slot) = ruler.get_mut(index + char_index) {
+                    *slot = char;
+                }
+            }
+        }
+    }
+
+    ruler.into_iter().collect()
+}
+
+/// A small box in the middle of `area` for a one line prompt
+fn confirm_rect(area: Rect) -> Rect {
+    let width = area.width.min(20);
+    let height = area.height.min(3);
+
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// The horizontal offset that shows `context_chars` columns left of column `offset`, or places it
+/// in the middle of `text_width` columns
+fn diff_scroll_offset(offset: usize, text_width: usize, context_chars: Option<usize>) -> usize {
+    offset.saturating_sub(context_chars.unwrap_or(text_width / 2))
+}
+
+fn status_line<'a>(
+    state: &State<Input>,
+    ui_state: &UIState,
+    pairs: &[FilePair],
+    pair_index: usize,
+) -> Span<'a> {
+    let pair = &pairs[pair_index];
+
+    let mut text = if pairs.len() > 1 {
+        format!("[{}/{}] ", pair_index + 1, pairs.len())
+    } else {
+        String::new()
+    };
+
+    // Comparison options that make differing text compare equal, so they're easy to forget
+    for label in state.diff_options.active_labels() {
+        text.push_str(&format!("[{}]", label));
+    }
+
+    if !text.ends_with(' ') && !text.is_empty() {
+        text.push(' ');
+    }
+
+    text.push_str(&format!(
+        "{} <> {}",
+        pair.file1.display(),
+        pair.file2.display()
+    ));
+
+    let (line_number, percent) = state.position();
+    text.push_str(&format!(
+        " | line {}/{} {}% col {}",
+        line_number + state.skipped_lines,
+        state.line_count() + state.skipped_lines,
+        percent,
+        ui_state.horizontal_offset + 1
+    ));
+
+    let (file1_count, file2_count) = state.line_counts();
+    text.push_str(&format!(
+        " | {} and {} lines, {} differ",
+        file1_count,
+        file2_count,
+        state.diff_summary.differing()
+    ));
+
+    if let Some(prompt) = &ui_state.prompt {
+        text.push_str(" | ");
+        text.push(prompt.kind.symbol());
+        text.push_str(&prompt.text);
+    } else if let Some(message) = &ui_state.status_message {
+        text.push_str(" | ");
+        text.push_str(message);
+    }
+
+    Span::styled(text, Style::default().add_modifier(Modifier::REVERSED))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Cursor};
+
+    use trace_log_comparer::positions::LinePositions;
+    use tui::backend::TestBackend;
+
+    use super::*;
+
+    /// A state comparing `lines` against themselves
+    fn identical_state(lines: &[&str]) -> State<'static, Input> {
+        memory_state(lines, lines)
+    }
+
+    /// A state comparing `lines1` against `lines2`, read from memory
+    fn memory_state(lines1: &[&str], lines2: &[&str]) -> State<'static, Input> {
+        let file = |lines: &[&str]| {
+            let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+
+            let mut positions = LinePositions::default();
+            let mut offset = 0;
+
+            for line in lines {
+                positions.push(offset);
+                offset += line.len() + 1;
+            }
+
+            let reader = BufReader::new(Input::Memory(Cursor::new(text.into_bytes())));
+
+            (positions, reader)
+        };
+
+        let (file1_positions, file1_reader) = file(lines1);
+        let (file2_positions, file2_reader) = file(lines2);
+
+        State::new(
+            None,
+            file1_positions,
+            file2_positions,
+            file1_reader,
+            file2_reader,
+        )
+    }
+
+    #[test]
+    fn selected_diff_is_centered_in_the_pane() {
+        let line1 = "x".repeat(200);
+        let mut line2 = line1.clone();
+        line2.replace_range(120..121, "y");
+
+        let mut state = memory_state(&[&line1], &[&line2]);
+        state.build_state(10);
+
+        for pane_width in [40, 100] {
+            let mut ui_state = UIState::new(&state);
+            ui_state.pane_width = pane_width;
+
+            let (row, offset) = state.find_next_diff(0, 0).unwrap();
+            select_diff(&mut state, &mut ui_state, row, offset);
+
+            let text_width = pane_width - state.gutter_width;
+            let column = state.diff_column(row, offset);
+
+            assert_eq!(column, 120);
+            assert_eq!(column - ui_state.horizontal_offset, text_width / 2);
+        }
+    }
+
+    #[test]
+    fn line_end_scrolls_the_end_of_the_longest_line_flush_against_the_border() {
+        let mut state = identical_state(&["short", "0123456789abcdefghijklmnopqrstuvwxyz"]);
+        state.build_state(10);
+
+        let pane_width = 20;
+        let offset = max_horizontal_offset(&state, pane_width);
+        state.build_lines(offset, state.first_line_index + 1);
+
+        let mut terminal = Terminal::new(TestBackend::new(pane_width as u16 + 2, 4)).unwrap();
+        terminal
+            .draw(|f| {
+                let list = List::new(state.file1_list_lines.clone())
+                    .block(Block::default().borders(Borders::ALL));
+
+                f.render_widget(list, f.size());
+            })
+            .unwrap();
+
+        let buffer = terminal.backend().buffer();
+        let row: String = (0..buffer.area.width)
+            .map(|x| buffer.get(x, 2).symbol.as_str())
+            .collect();
+
+        assert!(row.ends_with("uvwxyz│"), "{:?}", row);
+    }
+
+    #[test]
+    fn help_opens_with_the_modes_and_lines_up_the_descriptions() {
+        let mut state = identical_state(&["pc=1"]);
+        state.diff_options.ignore_whitespace = true;
+        state.build_state(10);
+
+        let mut ui_state = UIState::new(&state);
+        ui_state.ruler = true;
+
+        let keymap = Keymap::new(KeyCode::Esc);
+        let context = action_context(&state, &ui_state, &[]);
+        let help = help_text(&state, &ui_state, &state.diff_options, &keymap, &context);
+
+        assert_eq!(help[0], "alignment: lcs");
+        assert!(help[..10].contains(&"whitespace: ignored".to_string()));
+        assert!(help.contains(&"ruler: on".to_string()));
+
+        // Every description starts in the column after the widest key, `PageDown`
+        let bindings: Vec<_> = keymap.bindings(&context);
+        let descriptions = &help[help.len() - bindings.len()..];
+
+        for (line, (_, description)) in descriptions.iter().zip(&bindings) {
+            assert_eq!(
+                line.find(description),
+                Some("PageDown".len() + 2),
+                "{:?}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn view_toggles_carry_over_to_the_next_pair() {
+        let mut state = identical_state(&["pc=1"]);
+        state.gutter.change_counts = true;
+        state.gutter.hidden = true;
+        state.show_whitespace = true;
+        state.wrap_width = Some(40);
+
+        let mut ui_state = UIState::new(&state);
+        ui_state.ruler = true;
+        ui_state.link_changes = true;
+        ui_state.horizontal_offset = 12;
+
+        let mut new_state = identical_state(&["pc=2"]);
+        carry_state_toggles(&state, &mut new_state);
+        new_state.build_state(10);
+
+        let mut new_ui_state = UIState::new(&new_state);
+        carry_ui_toggles(&ui_state, &mut new_ui_state);
+
+        assert!(new_state.gutter.change_counts && new_state.gutter.hidden);
+        assert!(new_state.show_whitespace);
+        assert_eq!(new_state.wrap_width, Some(40));
+        assert!(new_ui_state.ruler && new_ui_state.link_changes);
+        assert!(!new_ui_state.minimap);
+        // Where the old pair was scrolled to isn't carried
+        assert_eq!(new_ui_state.horizontal_offset, 0);
+    }
+
+    #[test]
+    fn each_pair_goes_back_to_its_own_saved_view() {
+        let path = std::env::temp_dir().join(format!("tlc-views-{}.json", std::process::id()));
+        let pair = |name: &str| FilePair {
+            file1: PathBuf::from(format!("1/{}", name)),
+            file2: PathBuf::from(format!("2/{}", name)),
+        };
+        let (pair_a, pair_b) = (pair("a"), pair("b"));
+        let lines: Vec<String> = (1..=50).map(|line| line.to_string()).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+
+        let open = || {
+            let mut state = identical_state(&lines);
+            state.build_state(10);
+            let ui_state = UIState::new(&state);
+
+            (state, ui_state)
+        };
+
+        // Leave pair a on line 20 and switch to pair b, leaving it on line 40
+        let (mut state, ui_state) = open();
+        state.jump_to_line(19);
+        save_view(&path, &pair_a, &state, &ui_state).unwrap();
+
+        let (mut state, ui_state) = open();
+        state.jump_to_line(39);
+        save_view(&path, &pair_b, &state, &ui_state).unwrap();
+
+        // Switching back to pair a finds it where it was left, not where pair b was
+        let (mut state, mut ui_state) = open();
+        load_view(&path, &pair_a, &mut state, &mut ui_state);
+        assert_eq!(state.position().0, 20);
+
+        let (mut state, mut ui_state) = open();
+        load_view(&path, &pair_b, &mut state, &mut ui_state);
+        assert_eq!(state.position().0, 40);
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,88 @@
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// Where the viewer was left on a pair of files, to open there on the next run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedView {
+    /// 0-based index of the selected line
+    pub line_index: usize,
+    pub horizontal_offset: usize,
+}
+
+/// `trace-log-comparer/views.json` in `$XDG_CACHE_HOME`, or in `~/.cache` when that isn't set
+pub fn default_path() -> Option<PathBuf> {
+    let cache_dir = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+
+    Some(cache_dir.join("trace-log-comparer").join("views.json"))
+}
+
+/// Views saved for every pair of files, keyed by their paths
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SavedViews {
+    views: BTreeMap<String, SavedView>,
+}
+
+impl SavedViews {
+    /// Reads the views saved at `path`. Empty when there is no file there, or it is malformed
+    pub fn read(path: &Path) -> SavedViews {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    warn!("Could not read saved views {}: {}", path.display(), err);
+                }
+
+                return SavedViews::default();
+            }
+        };
+
+        serde_json::from_slice(&contents).unwrap_or_else(|err| {
+            warn!("Ignoring malformed saved views {}: {}", path.display(), err);
+            SavedViews::default()
+        })
+    }
+
+    pub fn get(&self, file1_path: &Path, file2_path: &Path) -> Option<SavedView> {
+        self.views.get(&key(file1_path, file2_path)).copied()
+    }
+
+    pub fn insert(&mut self, file1_path: &Path, file2_path: &Path, view: SavedView) {
+        self.views.insert(key(file1_path, file2_path), view);
+    }
+
+    /// Writes the views to `path`, creating its directory if needed
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // Write next to the file and rename over it, so two viewers exiting at once can't leave
+        // it half written
+        let temp_path = path.with_extension("tmp");
+
+        fs::write(&temp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&temp_path, path)
+    }
+}
+
+/// The key of a pair of files, from their absolute paths so runs from other directories share
+/// it
+fn key(file1_path: &Path, file2_path: &Path) -> String {
+    let absolute = |path: &Path| {
+        fs::canonicalize(path)
+            .unwrap_or_else(|_| path.to_path_buf())
+            .display()
+            .to_string()
+    };
+
+    format!("{}\t{}", absolute(file1_path), absolute(file2_path))
+}
@@ -0,0 +1,96 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use trace_log_comparer::{input, pairs::FilePair};
+
+/// How long the files have to stay unchanged after a change before they are reloaded, so a file
+/// being written isn't read halfway
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+/// Notices the files of a pair being rewritten, by polling their modification times and lengths
+pub struct FileWatcher {
+    paths: [PathBuf; 2],
+    /// What each file looked like the last time it was checked, `None` while it is missing
+    stamps: [Option<Stamp>; 2],
+    /// When the files were last seen changing, while a reload is waiting for them to settle
+    changed_at: Option<Instant>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    length: u64,
+}
+
+impl FileWatcher {
+    pub fn new(pair: &FilePair) -> Self {
+        let paths = [pair.file1.clone(), pair.file2.clone()];
+
+        FileWatcher {
+            stamps: [stamp(&paths[0]), stamp(&paths[1])],
+            paths,
+            changed_at: None,
+        }
+    }
+
+    /// Starts watching `pair` instead, when it isn't the pair being watched
+    pub fn follow(&mut self, pair: &FilePair) {
+        if self.paths[0] != pair.file1 || self.paths[1] != pair.file2 {
+            *self = FileWatcher::new(pair);
+        }
+    }
+
+    /// Whether the files changed and have since settled, so they should be reloaded. A file that
+    /// is missing, as it is while some tools rewrite it, holds the reload off until it is back
+    pub fn poll(&mut self) -> bool {
+        let stamps = [stamp(&self.paths[0]), stamp(&self.paths[1])];
+
+        if stamps != self.stamps {
+            self.stamps = stamps;
+            self.changed_at = Some(Instant::now());
+
+            return false;
+        }
+
+        let settled = self
+            .changed_at
+            .is_some_and(|changed_at| changed_at.elapsed() >= SETTLE_TIME);
+
+        if !settled || stamps.iter().any(Option::is_none) {
+            return false;
+        }
+
+        self.changed_at = None;
+
+        true
+    }
+
+    /// The first file of the pair that was missing when last polled
+    pub fn missing(&self) -> Option<&Path> {
+        self.paths
+            .iter()
+            .zip(&self.stamps)
+            .find_map(|(path, stamp)| stamp.is_none().then_some(path.as_path()))
+    }
+}
+
+/// The modification time and length of the file at `path`, `None` if it is missing. Stdin never
+/// changes
+fn stamp(path: &Path) -> Option<Stamp> {
+    if input::is_stdin(path) {
+        return Some(Stamp {
+            modified: None,
+            length: 0,
+        });
+    }
+
+    let metadata = fs::metadata(path).ok()?;
+
+    Some(Stamp {
+        modified: metadata.modified().ok(),
+        length: metadata.len(),
+    })
+}
@@ -2,62 +2,142 @@ use std::{
     env,
     fs::{File, OpenOptions},
     io::{self, BufRead, BufReader, Write},
+    process::ExitCode,
 };
 
-fn main() -> Result<(), io::Error> {
+const USAGE: &str =
+    "Usage: trim-to-line [input_file.log] [output_file.log] [line_number] [end_line_number]";
+
+/// Path that reads from stdin, or writes to stdout
+const STANDARD_STREAM_PATH: &str = "-";
+
+fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 3 {
+    if args.len() < 4 || args.len() > 5 {
         println!(
-            "trim-to-line expects three arguments, received {}:",
+            "trim-to-line expects three or four arguments, received {}:",
             args.len() - 1
         );
-        println!("Usage: trim-to-line [input_file.log] [output_file.log] [line_number]");
-        return Ok(());
+        println!("{}", USAGE);
+        return ExitCode::FAILURE;
+    }
+
+    let (trim_line_number, end_line_number) = match parse_lines(&args[3], args.get(4)) {
+        Ok(lines) => lines,
+        Err(message) => {
+            eprintln!("trim-to-line: {}", message);
+            println!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match trim(&args[1], &args[2], trim_line_number, end_line_number) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("trim-to-line: {}", message);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    let input_file_path = &args[1];
-    let output_file_path = &args[2];
-    let trim_line_number = &args[3]
-        .parse::<usize>()
-        .expect("Could not parse line number");
+/// Parses the first line to keep, and the last one if given
+fn parse_lines(
+    trim_line_number: &str,
+    end_line_number: Option<&String>,
+) -> Result<(usize, Option<usize>), String> {
+    let parse = |number: &str| {
+        number
+            .parse::<usize>()
+            .map_err(|err| format!("invalid line number '{}': {}", number, err))
+    };
 
-    let input_file = File::open(input_file_path)?;
-    let mut input_reader = BufReader::new(input_file);
+    let trim_line_number = parse(trim_line_number)?;
+    let end_line_number = end_line_number.map(|number| parse(number)).transpose()?;
 
-    let mut output_file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(output_file_path)
-        .unwrap();
+    if let Some(end_line_number) = end_line_number {
+        if end_line_number < trim_line_number {
+            return Err(format!(
+                "end line {} is before start line {}",
+                end_line_number, trim_line_number
+            ));
+        }
+    }
+
+    Ok((trim_line_number, end_line_number))
+}
+
+fn trim(
+    input_file_path: &str,
+    output_file_path: &str,
+    trim_line_number: usize,
+    end_line_number: Option<usize>,
+) -> Result<(), String> {
+    let read_error = |err: io::Error| format!("cannot read '{}': {}", input_file_path, err.kind());
+    let write_error =
+        |err: io::Error| format!("cannot write '{}': {}", output_file_path, err.kind());
+
+    let mut input_reader: Box<dyn BufRead> = if input_file_path == STANDARD_STREAM_PATH {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(
+            File::open(input_file_path).map_err(read_error)?,
+        ))
+    };
+
+    let to_stdout = output_file_path == STANDARD_STREAM_PATH;
+
+    let mut output_file: Box<dyn Write> = if to_stdout {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(output_file_path)
+                .map_err(write_error)?,
+        )
+    };
 
     let mut line = String::new();
     let mut line_number = 1;
     let mut start_saving = false;
+    let mut written_count = 0;
 
-    let mut input_file_result = input_reader.read_line(&mut line);
-
-    while let Ok(length) = input_file_result.as_ref() {
-        if *length == 0 {
+    while input_reader.read_line(&mut line).map_err(read_error)? > 0 {
+        if end_line_number.is_some_and(|end_line_number| line_number > end_line_number) {
+            // Past the end of the range, so the rest of the file isn't needed
             break;
         }
 
-        if !start_saving && line_number == *trim_line_number {
+        if !start_saving && line_number == trim_line_number {
             // This line onwards should be saved
             start_saving = true;
         }
 
         if start_saving {
-            output_file.write(line.as_bytes())?;
+            match output_file.write_all(line.as_bytes()) {
+                Ok(()) => written_count += 1,
+                // Whatever reads the output, like `head`, has all it wants
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                Err(err) => return Err(write_error(err)),
+            }
         }
 
         line_number += 1;
 
         line.clear();
-        input_file_result = input_reader.read_line(&mut line);
     }
 
-    println!("Wrote {} lines", line_number - trim_line_number);
+    output_file.flush().map_err(write_error)?;
+
+    if to_stdout {
+        // Keep the summary out of the piped lines
+        eprintln!("Wrote {} lines", written_count);
+    } else {
+        println!("Wrote {} lines", written_count);
+    }
 
     Ok(())
 }